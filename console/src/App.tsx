@@ -1,17 +1,27 @@
 import {useEffect, useState} from 'react'
 import {ServerStatus} from './ServerStatus'
 import {LoginButton} from './LoginButton'
+import {LogoutButton} from './LogoutButton'
 import {ToggleButton} from './ToggleButton'
 import {StatusTable} from "./StatusTable";
+import {DownloadEvent} from './DownloadEvent'
+import {EventLog} from './EventLog'
 
 // This app is delivered by the same Rust server that exposes the endpoints.
 // In dev mode, requests are passed through a proxy, see vite.config.js.
-const LOGIN_URL = '/authorize'
-const TOGGLE_URL = '/toggle'
-const STATUS_URL = '/status'
+// After authorization, the server redirects to the console with the athlete as query parameter.
+// Without that parameter, the endpoints address the default athlete.
+const ATHLETE = new URLSearchParams(window.location.search).get('athlete')
+const LOGIN_URL = ATHLETE ? `/authorize?athlete=${ATHLETE}` : '/authorize'
+const LOGOUT_URL = ATHLETE ? `/athletes/${ATHLETE}/logout` : '/logout'
+const DOWNLOAD_URL = ATHLETE ? `/athletes/${ATHLETE}/download` : '/download'
+const STATUS_URL = ATHLETE ? `/athletes/${ATHLETE}/status` : '/status'
+const MAX_EVENTS = 10
 
 export const App = () => {
     const [status, setStatus] = useState<ServerStatus | null>(null)
+    const [rejected, setRejected] = useState(false)
+    const [events, setEvents] = useState<DownloadEvent[]>([])
 
     const setDownloadState = (download_state: string) => {
         setStatus(Object.assign({}, status, { download_state }))
@@ -26,11 +36,28 @@ export const App = () => {
        */
         const es = new EventSource(STATUS_URL)
         es.onopen = () => console.log('SSE connection opened')
-        es.onerror = (e) => console.warn('SSE error:', e)
+        es.onerror = (e) => {
+            console.warn('SSE error:', e)
+            // The browser closes the connection if the server rejects it, e.g. without session cookie
+            setRejected(es.readyState === EventSource.CLOSED)
+        }
         es.onmessage = (e) => setStatus(JSON.parse(e.data))
+        es.addEventListener('download_event', (e) => {
+            const event: DownloadEvent = JSON.parse(e.data)
+            setEvents(events => [event, ...events].slice(0, MAX_EVENTS))
+        })
         return () => es.close();
     }, [])
 
+    if (status == null && rejected) {
+        return (
+            <div>
+                <b>Access denied by server, please connect with Strava</b>
+                <LoginButton loginUrl={LOGIN_URL} authorized={false} />
+            </div>
+        )
+    }
+
     if (status == null) {
         return <b>Waiting for data from server...</b>
     }
@@ -39,7 +66,9 @@ export const App = () => {
         <div>
             <StatusTable status={status} />
             <LoginButton loginUrl={LOGIN_URL} authorized={ status.authorized } />
-            <ToggleButton toggleUrl={TOGGLE_URL} disabled={ !status.authorized } downloadState={ status.download_state } setDownloadState={setDownloadState} />
+            <LogoutButton logoutUrl={LOGOUT_URL} authorized={ status.authorized } setDownloadState={setDownloadState} />
+            <ToggleButton downloadUrl={DOWNLOAD_URL} disabled={ !status.authorized } downloadState={ status.download_state } setDownloadState={setDownloadState} />
+            {events.length > 0 && <EventLog events={events} />}
         </div>
     )
 }
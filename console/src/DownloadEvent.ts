@@ -0,0 +1,8 @@
+export type DownloadEvent = {
+    id: number,
+    athlete_id: number,
+    time: string,
+    kind: string,
+    activity_id: number | null,
+    details: string
+}
@@ -0,0 +1,24 @@
+import { ReactElement } from 'react'
+import { DownloadEvent } from './DownloadEvent'
+
+type EventLogProps = {
+    events: DownloadEvent[]
+}
+
+// Shows the latest events of the download scheduler received since the console was opened
+export const EventLog = ({ events }: EventLogProps): ReactElement => (
+    <table>
+        <tbody>
+        <tr>
+            <th colSpan={3}>Latest download events</th>
+        </tr>
+        {events.map(event => (
+            <tr key={event.id}>
+                <td>{event.time.substring(11, 19)}</td>
+                <td style={event.kind === 'Error' ? {color: 'darkred'} : {}}>{event.kind}</td>
+                <td>{event.details}</td>
+            </tr>
+        ))}
+        </tbody>
+    </table>
+)
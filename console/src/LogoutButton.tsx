@@ -0,0 +1,18 @@
+type LogoutButtonProps = {
+    logoutUrl: string
+    authorized: boolean,
+    setDownloadState (state: string): void
+}
+
+export const LogoutButton = ({ logoutUrl, authorized, setDownloadState }: LogoutButtonProps) => {
+    const logout = () => fetch(logoutUrl, { method: 'POST' })
+        .then(res => res.text())
+        .then(result => setDownloadState(JSON.parse(result)))
+        .catch(error => console.warn(error))
+
+    return (
+        <button disabled={!authorized} onClick={logout}>
+            Disconnect from Strava
+        </button>
+    )
+}
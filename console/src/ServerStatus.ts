@@ -6,8 +6,42 @@ type ActivityStats = {
     trk_max_time: string | null
 }
 
+type CurrentActivity = {
+    id: number,
+    name: string,
+    start_date: string,
+    job: string
+}
+
+type DownloadError = {
+    message: string,
+    time: string
+}
+
+type RateLimit = {
+    limit_15min: number,
+    limit_daily: number,
+    usage_15min: number,
+    usage_daily: number
+}
+
+export type DownloadProgress = {
+    current_activity: CurrentActivity | null,
+    remaining_tracks: number,
+    missing_tracks: number,
+    eta: string | null,
+    last_error: DownloadError | null,
+    next_request: string | null,
+    rate_limit: RateLimit | null
+}
+
 export type ServerStatus = {
+    athlete_id: number | null,
     authorized: boolean,
     download_state: string,
-    activity_stats: ActivityStats
+    activity_stats: ActivityStats,
+    progress: DownloadProgress,
+    next_scheduled_run: string | null,
+    scheduler_restarts: number,
+    scheduler_error: string | null
 }
@@ -1,5 +1,5 @@
 import { ReactElement } from 'react'
-import { ServerStatus } from './ServerStatus'
+import { DownloadProgress, ServerStatus } from './ServerStatus'
 
 type StatusTableProps = {
     status: ServerStatus
@@ -15,10 +15,46 @@ export const StatusTable = ({ status }: StatusTableProps): ReactElement => (
             <td>Connected with Strava:</td>
             <td>{connectionText(status.authorized)}</td>
         </tr>
+        <tr>
+            <td>Strava athlete:</td>
+            <td>{status.athlete_id ?? ''}</td>
+        </tr>
         <tr>
             <td>Download scheduler status:</td>
             <td>{downloaderText(status.download_state)}</td>
         </tr>
+        <tr>
+            <td>Current activity:</td>
+            <td>{currentActivityText(status.progress)}</td>
+        </tr>
+        <tr>
+            <td>Remaining / missing tracks:</td>
+            <td>{status.progress.remaining_tracks} / {status.progress.missing_tracks}</td>
+        </tr>
+        <tr>
+            <td>Estimated end of track download:</td>
+            <td>{extractDateTime(status.progress.eta)}</td>
+        </tr>
+        <tr>
+            <td>Next request:</td>
+            <td>{extractDateTime(status.progress.next_request)}</td>
+        </tr>
+        <tr>
+            <td>Strava API usage:</td>
+            <td>{rateLimitText(status.progress)}</td>
+        </tr>
+        <tr>
+            <td>Last download error:</td>
+            <td>{lastErrorText(status.progress)}</td>
+        </tr>
+        <tr>
+            <td>Scheduler restarts:</td>
+            <td>{schedulerText(status.scheduler_restarts, status.scheduler_error)}</td>
+        </tr>
+        <tr>
+            <td>Next scheduled download:</td>
+            <td>{extractDateTime(status.next_scheduled_run)}</td>
+        </tr>
         <tr>
             <td>Number of downloaded activities:</td>
             <td><b>{status.activity_stats.act_count}</b></td>
@@ -51,6 +87,39 @@ function connectionText(connected: boolean): ReactElement {
 const extractDate = (datetime: string | null): string => {
     return datetime ? datetime.substring(0, 10) : ''
 }
+const extractDateTime = (datetime: string | null): string => {
+    return datetime ? datetime.substring(0, 16).replace('T', ' ') + ' UTC' : ''
+}
+
+function currentActivityText({ current_activity }: DownloadProgress): string {
+    return current_activity
+        ? `${current_activity.job} of ${current_activity.name} (${extractDate(current_activity.start_date)})`
+        : ''
+}
+
+function rateLimitText({ rate_limit }: DownloadProgress): string {
+    return rate_limit
+        ? `${rate_limit.usage_15min}/${rate_limit.limit_15min} (15 min), ${rate_limit.usage_daily}/${rate_limit.limit_daily} (daily)`
+        : ''
+}
+
+function lastErrorText({ last_error }: DownloadProgress): ReactElement {
+    return (
+        <>
+            {last_error && <div style={{color: 'darkred'}}>{last_error.message}</div>}
+            {last_error && <div>{extractDateTime(last_error.time)}</div>}
+        </>
+    )
+}
+
+function schedulerText(restarts: number, error: string | null): ReactElement {
+    return (
+        <>
+            <b style={restarts > 0 ? {color: 'darkred'} : {}}>{restarts}</b>
+            {error && <div>Last error: {error}</div>}
+        </>
+    )
+}
 
 function downloaderText(status: string): ReactElement {
     switch (status) {
@@ -72,12 +141,33 @@ function downloaderText(status: string): ReactElement {
                 <div>Please inspect the server log</div>
             </>
         )
+        case 'Unauthorized': return (
+            <>
+                <b style={{ color: 'darkred' }}>Access revoked by Strava</b>
+                <div>Please connect with Strava again</div>
+            </>
+        )
+        case 'Profile': return (
+            <b style={{color: 'darkgreen'}}>Profile download</b>
+        )
         case 'Activities': return (
             <b style={{color: 'darkgreen'}}>Activity download</b>
         )
         case 'Tracks': return (
             <b style={{color: 'darkgreen'}}>Track download</b>
         )
+        case 'Laps': return (
+            <b style={{color: 'darkgreen'}}>Lap download</b>
+        )
+        case 'Efforts': return (
+            <b style={{color: 'darkgreen'}}>Effort download</b>
+        )
+        case 'Photos': return (
+            <b style={{color: 'darkgreen'}}>Photo download</b>
+        )
+        case 'Social': return (
+            <b style={{color: 'darkgreen'}}>Comment and kudos download</b>
+        )
         default: throw new Error('Illegal state')
     }
 }
@@ -1,12 +1,13 @@
 type ToggleButtonProps = {
-    toggleUrl: string
+    downloadUrl: string // Prefix of the start and stop endpoints
     disabled: boolean,
     downloadState: string,
     setDownloadState (state: string): void
 }
 
-export const ToggleButton = ({ toggleUrl, disabled, downloadState, setDownloadState }: ToggleButtonProps) => {
-    const toggle = () => fetch(toggleUrl)
+// Sends an explicit start or stop request, so that several browser tabs cannot stop each other's start
+export const ToggleButton = ({ downloadUrl, disabled, downloadState, setDownloadState }: ToggleButtonProps) => {
+    const toggle = () => fetch(`${downloadUrl}/${isActive(downloadState) ? 'stop' : 'start'}`, { method: 'POST' })
         .then(res => res.text())
         .then(result => setDownloadState(JSON.parse(result)))
         .catch(error => console.warn(error))
@@ -24,8 +25,14 @@ function isActive(status: string): boolean {
         case 'NoResults': return false
         case 'LimitReached': return false
         case 'RequestError': return false
+        case 'Unauthorized': return false
+        case 'Profile': return true
         case 'Activities': return true
         case 'Tracks': return true
+        case 'Laps': return true
+        case 'Efforts': return true
+        case 'Photos': return true
+        case 'Social': return true
         default: throw new Error('Illegal state')
     }
 }
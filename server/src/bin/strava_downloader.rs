@@ -1,18 +1,23 @@
 use std::{env, fs};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use axum::BoxError;
 use config::{Config, File};
 use log::info;
 use tokio::join;
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
+use strava_activity_downloader::domain::download_filter::DownloadFilter;
+use strava_activity_downloader::domain::download_schedule::{CronSchedule, DownloadSchedule};
+use strava_activity_downloader::domain::retry_policy::RetryPolicy;
 use strava_activity_downloader::domain::server_status::ServerStatus;
 use strava_activity_downloader::oauth::oauth_client::OAuthClient;
+use strava_activity_downloader::rest::access_control::{AccessControl, AccessMode};
 use strava_activity_downloader::rest::http_server::spawn_http_server;
 use strava_activity_downloader::rest::rest_paths::{AUTH_CALLBACK, STATUS};
 use strava_activity_downloader::service::activity_service::ActivityService;
 use strava_activity_downloader::service::download_scheduler::spawn_download_scheduler;
 use strava_activity_downloader::state::shared_state::SharedState;
+use strava_activity_downloader::strava::strava_client::{StravaClient, StravaClientConfig};
 use strava_activity_downloader::track::track_storage::TrackStorage;
 use strava_activity_downloader::util::shutdown_signal::shutdown_signal;
 
@@ -23,6 +28,10 @@ const DEFAULT_HOST: &str = "localhost";
 const DEFAULT_PORT: u16 = 2525;
 const DEFAULT_DATA_DIR: &str = "data";
 
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
 #[tokio::main]
 async fn main() -> Result<(), BoxError>  {
     env_logger::init();
@@ -41,9 +50,57 @@ async fn main() -> Result<(), BoxError>  {
         .parse::<u16>()
         .expect("The port must be numeric");
 
-    let strava_url = config.get_string("strava.api_url").unwrap_or("https://www.strava.com/api/v3".to_string());
+    let default_http = StravaClientConfig::default();
+    let strava = StravaClient::new(&StravaClientConfig {
+        base_url: config.get_string("strava.api_url").unwrap_or(default_http.base_url),
+        connect_timeout: config.get_int("strava.connect_timeout").map(|s| Duration::from_secs(s as u64)).unwrap_or(default_http.connect_timeout),
+        timeout: config.get_int("strava.timeout").map(|s| Duration::from_secs(s as u64)).unwrap_or(default_http.timeout),
+        proxy: config.get_string("strava.proxy").ok(),
+        user_agent: config.get_string("strava.user_agent").unwrap_or(default_http.user_agent),
+        ca_certificate: config.get_string("strava.ca_certificate").ok(),
+        accept_invalid_certs: config.get_bool("strava.accept_invalid_certs").unwrap_or(default_http.accept_invalid_certs)
+    }).expect(CONFIG_YAML);
     let request_period = config.get_int("strava.request_period").unwrap_or(10) as u64;
     let activities_per_page = config.get_int("strava.activities_per_page").unwrap_or(30) as u16;
+    let default_retry = RetryPolicy::default();
+    let retry = RetryPolicy::new(
+        config.get_int("strava.max_attempts").map(|n| n as u32).unwrap_or(default_retry.max_attempts),
+        config.get_int("strava.retry_delay").map(|s| Duration::from_secs(s as u64)).unwrap_or(default_retry.base_delay),
+        config.get_int("strava.max_retry_delay").map(|s| Duration::from_secs(s as u64)).unwrap_or(default_retry.max_delay));
+
+    let filter = DownloadFilter {
+        after: config.get_string("filter.after").ok(),
+        before: config.get_string("filter.before").ok(),
+        sport_types: config.get_array("filter.sport_types").unwrap_or(Vec::new())
+            .iter().map(|v| v.clone().into_string().expect(CONFIG_YAML)).collect()
+    }.normalized().expect(CONFIG_YAML);
+
+    let schedule = config.get_string("schedule.cron").ok().map(|cron| {
+        let cron = CronSchedule::try_from(cron.as_str()).expect(CONFIG_YAML);
+        let max_requests = config.get_int("schedule.max_requests").ok().map(|n| n as u32);
+        info!("Download schedule: {} (max. requests: {max_requests:?})", cron.expression());
+        DownloadSchedule::new(cron, max_requests, now_secs())
+    });
+
+    let access_mode = match env::var("ACCESS_MODE") // Environment precedes config
+        .unwrap_or_else(|_| config.get_string("access.mode").unwrap_or("none".to_string())).as_str() {
+        "none" => AccessMode::None,
+        "api_key" => AccessMode::ApiKey(config.get_string("access.api_key").expect(CONFIG_YAML)),
+        "basic" => AccessMode::Basic {
+            username: config.get_string("access.username").expect(CONFIG_YAML),
+            password: config.get_string("access.password").expect(CONFIG_YAML)
+        },
+        "session" => AccessMode::Session,
+        mode => panic!("Unknown access mode '{mode}' in {CONFIG_YAML}")
+    };
+    let secure_cookie = config.get_bool("access.secure_cookie").unwrap_or(true);
+    let metrics_token = config.get_string("access.metrics_token").ok();
+    info!("Access mode: {}", match access_mode {
+        AccessMode::None => "none",
+        AccessMode::ApiKey(_) => "api_key",
+        AccessMode::Basic { .. } => "basic",
+        AccessMode::Session => "session"
+    });
 
     let redirect_url = env::var("REDIRECT_URL")
         .unwrap_or_else(|_| config.get_string("oauth.redirect_url")
@@ -59,7 +116,8 @@ async fn main() -> Result<(), BoxError>  {
         config.get_string("oauth.token_url").expect(CONFIG_YAML),
         config.get_string("oauth.target_url").unwrap_or(STATUS.to_string()),
         format!("{redirect_url}{AUTH_CALLBACK}"),
-        scopes);
+        scopes,
+        strava.clone());
 
     let base_path = env::var("DATA_DIR") // Environment precedes config
         .unwrap_or_else(|_| config.get_string("service.data_dir")
@@ -69,6 +127,8 @@ async fn main() -> Result<(), BoxError>  {
 
     let db_path = format!("{base_path}/{ACTIVITY_DB}");
     let store_tiles = config.get_bool("service.store_tiles").unwrap_or(false);
+    let download_photos = config.get_bool("service.download_photos").unwrap_or(false);
+    let legacy_athlete = config.get_int("service.legacy_athlete").ok().map(|id| id as u64);
     let service = ActivityService::new(db_path.as_str(), store_tiles).await?;
 
     let tracks = TrackStorage::new(base_path.as_str());
@@ -80,10 +140,15 @@ async fn main() -> Result<(), BoxError>  {
     // Channel for sending data from the producer to the SSE handler
     let (tx_data, _rx_data) = broadcast::channel::<ServerStatus>(3);
 
-    let state = SharedState::new(client, service, tracks, tx_data, tx_term.clone(), activities_per_page);
+    let state = SharedState::new(client, strava, service, tracks, tx_data, tx_term.clone(), activities_per_page, download_photos);
+    state.lock().await.filter = filter;
+    state.lock().await.schedule = schedule;
+    state.lock().await.legacy_athlete = legacy_athlete;
+    state.lock().await.restore_download_states().await?;
+    state.lock().await.access = AccessControl::new(access_mode, secure_cookie).with_metrics_token(metrics_token);
 
     let request_period = Duration::from_secs(request_period);
-    let downloader = spawn_download_scheduler(state.clone(), rx_term1, strava_url, request_period);
+    let downloader = spawn_download_scheduler(state.clone(), rx_term1, request_period, retry);
 
     let addr = format!("{host}:{port}");
     info!("Server listening on http://{addr}");
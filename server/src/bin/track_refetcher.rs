@@ -0,0 +1,64 @@
+use std::env;
+use axum::BoxError;
+use config::{Config, File};
+use strava_activity_downloader::domain::missing_reason::MissingReason;
+use strava_activity_downloader::service::activity_service::ActivityService;
+use strava_activity_downloader::track::track_storage::TrackStorage;
+
+const CONFIG_YAML : &str = "conf/application.yaml";
+const DEFAULT_DATA_DIR: &str = "data";
+
+const ACTIVITY_DB: &str = "activity.db";
+
+const MISSING_FLAG: &str = "--missing";
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    // --missing retries all missing tracks, --missing=<reason> those missing for the reason
+    let missing = match args.first().map(|arg| arg.strip_prefix(MISSING_FLAG)) {
+        Some(Some("")) => Some(None),
+        Some(Some(reason)) => match reason.strip_prefix('=').map(MissingReason::try_from) {
+            Some(Ok(reason)) => Some(Some(reason)),
+            _ => return Err(format!("Invalid option '{}'", args[0]).into())
+        }
+        _ => None
+    };
+    let activity_ids = match missing {
+        Some(_) => Vec::new(),
+        None => args.iter()
+            .map(|arg| arg.parse::<u64>().map_err(|_| format!("Invalid activity id '{arg}'")))
+            .collect::<Result<Vec<u64>, String>>()?
+    };
+    if missing.is_none() && activity_ids.is_empty() {
+        let reasons: Vec<&str> = MissingReason::VALUES.iter().map(|reason| reason.name()).collect();
+        println!("Usage: track_refetcher <activity id>...");
+        println!("       track_refetcher {MISSING_FLAG}[=<reason>] (reason is one of {})", reasons.join(", "));
+        return Ok(())
+    }
+    let config = Config::builder()
+        .add_source(File::with_name(CONFIG_YAML).required(false))
+        .build()?;
+    let base_path = env::var("DATA_DIR") // Environment precedes config
+        .unwrap_or_else(|_| config.get_string("service.data_dir")
+            .unwrap_or(DEFAULT_DATA_DIR.to_string()));
+    let store_tiles = config.get_bool("service.store_tiles").unwrap_or(false);
+    let tracks = TrackStorage::new(base_path.as_str());
+    let mut service = ActivityService::new(format!("{base_path}/{ACTIVITY_DB}").as_str(), store_tiles).await?;
+    if let Some(reason) = missing {
+        let count = service.retry_missing_tracks(None, reason).await?;
+        println!("Missing tracks reset: {count}");
+    }
+    for activity_id in activity_ids {
+        match service.get_by_id(activity_id).await? {
+            Some(activity) => {
+                service.refetch_track(&tracks, &activity).await?;
+                println!("Activity {activity_id}: track enqueued");
+            }
+            None => println!("Activity {activity_id}: not found")
+        }
+    }
+    println!("The tracks are downloaded with the next download of the tracks (like POST /download/start?phase=tracks)");
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use std::env;
+use axum::BoxError;
+use config::{Config, File};
+use strava_activity_downloader::service::activity_service::ActivityService;
+use strava_activity_downloader::service::track_verifier::verify_tracks;
+use strava_activity_downloader::track::track_storage::TrackStorage;
+
+const CONFIG_YAML : &str = "conf/application.yaml";
+const DEFAULT_DATA_DIR: &str = "data";
+
+const ACTIVITY_DB: &str = "activity.db";
+
+const REPAIR_FLAG: &str = "--repair";
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    env_logger::init();
+    let repair = env::args().any(|arg| arg == REPAIR_FLAG);
+    let config = Config::builder()
+        .add_source(File::with_name(CONFIG_YAML).required(false))
+        .build()?;
+    let base_path = env::var("DATA_DIR") // Environment precedes config
+        .unwrap_or_else(|_| config.get_string("service.data_dir")
+            .unwrap_or(DEFAULT_DATA_DIR.to_string()));
+    let store_tiles = config.get_bool("service.store_tiles").unwrap_or(false);
+    println!("Verify tracks in {base_path} (use {REPAIR_FLAG} to fix problems, RUST_LOG=info for details)");
+    let tracks = TrackStorage::new(base_path.as_str());
+    let mut service = ActivityService::new(format!("{base_path}/{ACTIVITY_DB}").as_str(), store_tiles).await?;
+    let report = verify_tracks(&mut service, &tracks, repair).await?;
+    println!("Temporary files:            {}", report.temp_files.len());
+    println!("GPX files without activity: {}", report.orphan_files.len());
+    println!("GPX files not marked:       {}", report.unmarked_files.len());
+    println!("Missing GPX files:          {}", report.missing_files.len());
+    println!("Corrupt GPX files:          {}", report.corrupt_files.len());
+    println!("Unsynced tile zoom levels:  {}", report.unsynced_tiles.len());
+    match (report.is_consistent(), repair) {
+        (true, _) => println!("Tracks and database are consistent"),
+        (false, true) => println!("Problems repaired"),
+        (false, false) => println!("Problems found, run again with {REPAIR_FLAG} to repair")
+    }
+    Ok(())
+}
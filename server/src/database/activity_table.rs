@@ -1,16 +1,20 @@
-use const_format::concatcp;
-use log::debug;
-use sqlx::{query, Result, Row};
+use const_format::{concatcp, str_replace};
+use log::{debug, info};
+use sqlx::{query, Acquire, Database, Result, Row};
+use sqlx::query::Query;
 use crate::database::db_executor::DbExecutor;
-use crate::database::db_types::DBRow;
+use crate::database::db_types::{DBConnection, DBPool, DBRow, DbType};
 use crate::domain::activity::{Activity, ActivityVec};
 use crate::domain::activity_stats::ActivityStats;
+use crate::domain::download_filter::DownloadFilter;
+use crate::domain::missing_reason::MissingReason;
 use crate::domain::track_store_state::TrackStoreState;
 
-/// See [TrackStoreState] for the meaning of gpx_fetched values
+/// See [TrackStoreState] for the meaning of gpx_fetched values, and [MissingReason] for missing_reason
 const CREATE_ACTIVITY_TABLE : &str =
     "CREATE TABLE IF NOT EXISTS activity (
         id INTEGER NOT NULL PRIMARY KEY,
+        athlete_id INTEGER DEFAULT 0 NOT NULL,
         name TEXT NOT NULL,
         sport_type TEXT NOT NULL,
         start_date TEXT NOT NULL,
@@ -19,12 +23,68 @@ const CREATE_ACTIVITY_TABLE : &str =
         total_elevation_gain INTEGER NOT NULL,
         average_speed INTEGER NOT NULL,
         kudos_count INTEGER NOT NULL,
-        gpx_fetched INTEGER DEFAULT 0 NOT NULL CHECK (gpx_fetched IN (0, 1, 2))
+        comment_count INTEGER,
+        total_photo_count INTEGER,
+        gear_id TEXT,
+        gpx_fetched INTEGER DEFAULT 0 NOT NULL CHECK (gpx_fetched IN (0, 1, 2, 3)),
+        laps_fetched INTEGER DEFAULT 0 NOT NULL CHECK (laps_fetched IN (0, 1)),
+        efforts_fetched INTEGER DEFAULT 0 NOT NULL CHECK (efforts_fetched IN (0, 1)),
+        photos_fetched INTEGER DEFAULT 0 NOT NULL CHECK (photos_fetched IN (0, 1)),
+        social_fetched INTEGER DEFAULT 0 NOT NULL CHECK (social_fetched IN (0, 1)),
+        missing_reason TEXT
     )";
 
+/// Condition matching the activities of a [DownloadFilter], see [ActivityTable::bind_filter].
+/// The condition must be placed behind all other parameters of a statement.
+pub const FILTER_CONDITION : &str =
+    "(? IS NULL OR start_date >= ?) AND (? IS NULL OR start_date < ?) \
+     AND (json_array_length(?) = 0 OR sport_type IN (SELECT value FROM json_each(?)))";
+
+const SELECT_COLUMN : &str =
+    "SELECT COUNT(*) FROM pragma_table_info('activity') WHERE name = ?";
+
+// Columns missing in databases created by older versions
+const ADDED_COLUMNS : [(&str, &str); 9] = [
+    ("athlete_id", "ALTER TABLE activity ADD COLUMN athlete_id INTEGER DEFAULT 0 NOT NULL"), // Multi-athlete support
+    ("gear_id", "ALTER TABLE activity ADD COLUMN gear_id TEXT"),
+    ("laps_fetched", "ALTER TABLE activity ADD COLUMN laps_fetched INTEGER DEFAULT 0 NOT NULL CHECK (laps_fetched IN (0, 1))"),
+    ("efforts_fetched", "ALTER TABLE activity ADD COLUMN efforts_fetched INTEGER DEFAULT 0 NOT NULL CHECK (efforts_fetched IN (0, 1))"),
+    ("photos_fetched", "ALTER TABLE activity ADD COLUMN photos_fetched INTEGER DEFAULT 0 NOT NULL CHECK (photos_fetched IN (0, 1))"),
+    ("comment_count", "ALTER TABLE activity ADD COLUMN comment_count INTEGER"),
+    ("social_fetched", "ALTER TABLE activity ADD COLUMN social_fetched INTEGER DEFAULT 0 NOT NULL CHECK (social_fetched IN (0, 1))"),
+    ("missing_reason", "ALTER TABLE activity ADD COLUMN missing_reason TEXT"), // Unknown for tracks marked missing before
+    ("total_photo_count", "ALTER TABLE activity ADD COLUMN total_photo_count INTEGER")
+];
+
+// Tables created before sensor streams were stored only allow the gpx_fetched values 0 to 2. As sqlite
+// cannot change a CHECK constraint, these tables are rebuilt (with foreign keys disabled, see upgrade_table).
+const SELECT_TABLE_SQL : &str =
+    "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'activity'";
+
+const OUTDATED_FETCHED_CHECK : &str = "gpx_fetched IN (0, 1, 2))";
+
+const ACTIVITY_COLUMNS : &str =
+    "id, athlete_id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, \
+     kudos_count, comment_count, total_photo_count, gear_id, gpx_fetched, laps_fetched, efforts_fetched, photos_fetched, social_fetched, missing_reason";
+
+const CREATE_NEW_ACTIVITY_TABLE : &str =
+    str_replace!(CREATE_ACTIVITY_TABLE, "IF NOT EXISTS activity (", "activity_new (");
+
+const COPY_ACTIVITY_TABLE : &str =
+    concatcp!("INSERT INTO activity_new (", ACTIVITY_COLUMNS, ") SELECT ", ACTIVITY_COLUMNS, " FROM activity");
+
+const DROP_ACTIVITY_TABLE : &str =
+    "DROP TABLE activity";
+
+const RENAME_NEW_ACTIVITY_TABLE : &str =
+    "ALTER TABLE activity_new RENAME TO activity";
+
+const CREATE_ATHLETE_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS activity_athlete ON activity (athlete_id, start_date)";
+
 const INSERT_ACTIVITY : &str =
-    "INSERT INTO activity (id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, kudos_count) \
-     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
+    "INSERT INTO activity (id, athlete_id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, kudos_count, comment_count, total_photo_count, gear_id) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
 
 const UPSERT_ACTIVITY : &str =
     concatcp!(INSERT_ACTIVITY, " \
@@ -37,34 +97,73 @@ const UPSERT_ACTIVITY : &str =
        moving_time = excluded.moving_time, \
        total_elevation_gain = excluded.total_elevation_gain, \
        average_speed = excluded.average_speed, \
-       kudos_count = excluded.kudos_count"); // Do NOT update column gpx_fetched
+       kudos_count = excluded.kudos_count, \
+       comment_count = excluded.comment_count, \
+       total_photo_count = excluded.total_photo_count, \
+       gear_id = excluded.gear_id"); // Do NOT update column gpx_fetched
 
 const DELETE_ACTIVITY : &str =
     "DELETE FROM activity WHERE id = ?";
 
 const UPDATE_FETCHED_COLUMN: &str =
-    "UPDATE activity SET gpx_fetched = ? WHERE id = ?";
+    "UPDATE activity SET gpx_fetched = ?, missing_reason = NULL WHERE id = ?";
+
+const UPDATE_MISSING_COLUMNS: &str =
+    "UPDATE activity SET gpx_fetched = 2, missing_reason = ? WHERE id = ?";
+
+// Without athlete, the tracks of all athletes are reset. Without reason, also those of unknown reason.
+const RESET_MISSING_TRACKS: &str =
+    "UPDATE activity SET gpx_fetched = 0, missing_reason = NULL \
+     WHERE gpx_fetched = 2 AND (? IS NULL OR athlete_id = ?) AND (? IS NULL OR missing_reason = ?)";
+
+const UPDATE_LAPS_FETCHED_COLUMN: &str =
+    "UPDATE activity SET laps_fetched = ? WHERE id = ?";
+
+const UPDATE_EFFORTS_FETCHED_COLUMN: &str =
+    "UPDATE activity SET efforts_fetched = ? WHERE id = ?";
+
+const UPDATE_PHOTOS_FETCHED_COLUMN: &str =
+    "UPDATE activity SET photos_fetched = ? WHERE id = ?";
+
+const UPDATE_SOCIAL_FETCHED_COLUMN: &str =
+    "UPDATE activity SET social_fetched = ? WHERE id = ?";
+
+const UPDATE_ATHLETE_COLUMN: &str =
+    "UPDATE activity SET athlete_id = ? WHERE athlete_id = ?";
+
+const SELECT_ATHLETES: &str =
+    "SELECT DISTINCT athlete_id FROM activity ORDER BY athlete_id";
 
 const SELECT_ACTIVITIES : &str =
-    "SELECT id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, kudos_count FROM activity";
+    "SELECT id, athlete_id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, kudos_count, comment_count, total_photo_count, gear_id FROM activity";
 
 const SELECT_ACTIVITY : &str =
     concatcp!(SELECT_ACTIVITIES, " WHERE id = ?");
 
-const SELECT_EARLIEST_ACTIVITY_WITHOUT_TRACK: &str =
-    concatcp!(SELECT_ACTIVITIES, " WHERE gpx_fetched = 0 and start_date = (SELECT MIN(start_date) from activity WHERE gpx_fetched = 0)");
-
 const SELECT_ACTIVITIES_WITH_TRACK: &str =
     concatcp!(SELECT_ACTIVITIES, " WHERE gpx_fetched = 1 ORDER BY start_date ASC");
 
+const SELECT_ACTIVITIES_WITH_SENSORS: &str =
+    concatcp!(SELECT_ACTIVITIES, " WHERE gpx_fetched = 3 ORDER BY start_date ASC");
+
+const SELECT_FETCHED_COLUMN: &str =
+    "SELECT gpx_fetched FROM activity WHERE id = ?";
+
+/// Tracks stored as sensor data (gpx_fetched = 3) count as tracks
 const SELECT_ACTIVITY_STATS: &str =
     "SELECT \
       COUNT(id), \
       MIN(start_date), \
       MAX(start_date), \
-      COUNT(id) FILTER (where gpx_fetched = 1), \
-      MAX(start_date) FILTER (where gpx_fetched = 1) \
-    FROM activity";
+      COUNT(id) FILTER (where gpx_fetched IN (1, 3)), \
+      MAX(start_date) FILTER (where gpx_fetched IN (1, 3)) \
+    FROM activity WHERE athlete_id = ?";
+
+/// Missing tracks and remaining tracks (matching the filter) of an athlete
+const SELECT_TRACK_COUNTS: &str =
+    concatcp!("SELECT \
+      (SELECT COUNT(id) FROM activity WHERE athlete_id = ? AND gpx_fetched = 2), \
+      (SELECT COUNT(id) FROM activity WHERE athlete_id = ? AND gpx_fetched = 0 AND ", FILTER_CONDITION, ")");
 
 pub struct ActivityTable;
 
@@ -77,6 +176,60 @@ impl ActivityTable {
         Ok(())
     }
 
+    /// Adds the columns missing in tables created by older versions and creates the athlete index.
+    /// The activities of such tables belong to [crate::domain::activity::LEGACY_ATHLETE].
+    pub async fn upgrade_table(pool: &DBPool) -> Result<()> {
+        for (column, add_column) in ADDED_COLUMNS {
+            debug!("Execute\n{} with: {}", SELECT_COLUMN, column);
+            let count: i64 = query(SELECT_COLUMN)
+                .bind(column)
+                .map(|row: DBRow| row.get(0))
+                .fetch_one(pool)
+                .await?;
+            if count == 0 {
+                info!("Add column {column} to activity table");
+                query(add_column).execute(pool).await?;
+            }
+        }
+        debug!("Execute\n{}", SELECT_TABLE_SQL);
+        let table_sql: String = query(SELECT_TABLE_SQL)
+            .map(|row: DBRow| row.get(0))
+            .fetch_one(pool)
+            .await?;
+        if table_sql.contains(OUTDATED_FETCHED_CHECK) {
+            info!("Rebuild activity table to allow storing sensor streams");
+            Self::rebuild_table(pool).await?;
+        }
+        debug!("Execute\n{}", CREATE_ATHLETE_INDEX);
+        query(CREATE_ATHLETE_INDEX).execute(pool).await?;
+        Ok(())
+    }
+
+    /// Replaces the table by a new one with the current layout. Dropping the table would delete
+    /// all rows referring to it (like the laps), so foreign keys are disabled meanwhile.
+    /// This is only possible outside a transaction, hence a dedicated connection is used.
+    /// Foreign keys are enabled again even if the replacement fails, otherwise the connection
+    /// is closed instead of being returned to the pool.
+    async fn rebuild_table(pool: &DBPool) -> Result<()> {
+        let mut conn = pool.acquire().await?;
+        query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+        let result = Self::replace_table(&mut conn).await;
+        if let Err(error) = query("PRAGMA foreign_keys = ON").execute(&mut *conn).await {
+            conn.detach(); // Dropping the detached connection closes it
+            return Err(error)
+        }
+        result
+    }
+
+    async fn replace_table(conn: &mut DBConnection) -> Result<()> {
+        let mut tx = conn.begin().await?;
+        for sql in [CREATE_NEW_ACTIVITY_TABLE, COPY_ACTIVITY_TABLE, DROP_ACTIVITY_TABLE, RENAME_NEW_ACTIVITY_TABLE] {
+            debug!("Execute\n{sql}");
+            query(sql).execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+
     pub async fn insert<'e, E>(executor: E, activity: &Activity) -> Result<()>
         where E: DbExecutor<'e> {
         Self::execute_for_activity(executor, INSERT_ACTIVITY, activity).await
@@ -109,6 +262,100 @@ impl ActivityTable {
         Ok(result.rows_affected() == 1)
     }
 
+    /// Marks the track of the activity as missing for the given reason
+    pub async fn update_missing_columns<'e, E>(executor: E, id: u64, reason: MissingReason) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", UPDATE_MISSING_COLUMNS, id, reason);
+        let result = query(UPDATE_MISSING_COLUMNS)
+            .bind(reason.name())
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Resets missing tracks (of the athlete and for the reason, if given) to pending and returns their number
+    pub async fn reset_missing_tracks<'e, E>(executor: E, athlete_id: Option<u64>, reason: Option<MissingReason>) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {:?} {:?}", RESET_MISSING_TRACKS, athlete_id, reason);
+        let athlete_id = athlete_id.map(|id| id as i64);
+        let reason = reason.map(|reason| reason.name());
+        let result = query(RESET_MISSING_TRACKS)
+            .bind(athlete_id)
+            .bind(athlete_id)
+            .bind(reason)
+            .bind(reason)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn update_laps_fetched_column<'e, E>(executor: E, id: u64, fetched: bool) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_LAPS_FETCHED_COLUMN, id, fetched);
+        let result = query(UPDATE_LAPS_FETCHED_COLUMN)
+            .bind(fetched)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn update_efforts_fetched_column<'e, E>(executor: E, id: u64, fetched: bool) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_EFFORTS_FETCHED_COLUMN, id, fetched);
+        let result = query(UPDATE_EFFORTS_FETCHED_COLUMN)
+            .bind(fetched)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn update_photos_fetched_column<'e, E>(executor: E, id: u64, fetched: bool) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_PHOTOS_FETCHED_COLUMN, id, fetched);
+        let result = query(UPDATE_PHOTOS_FETCHED_COLUMN)
+            .bind(fetched)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn update_social_fetched_column<'e, E>(executor: E, id: u64, fetched: bool) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_SOCIAL_FETCHED_COLUMN, id, fetched);
+        let result = query(UPDATE_SOCIAL_FETCHED_COLUMN)
+            .bind(fetched)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Moves all activities of one athlete to another athlete
+    pub async fn update_athlete_column<'e, E>(executor: E, old_id: u64, new_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_ATHLETE_COLUMN, new_id, old_id);
+        let result = query(UPDATE_ATHLETE_COLUMN)
+            .bind(new_id as i64)
+            .bind(old_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns the ids of all athletes owning activities
+    pub async fn select_athletes<'e, E>(executor: E) -> Result<Vec<u64>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", SELECT_ATHLETES);
+        query(SELECT_ATHLETES)
+            .map(|row: DBRow| row.get::<i64, _>(0) as u64)
+            .fetch_all(executor)
+            .await
+    }
+
     pub async fn select_all<'e, E>(executor: E) -> Result<ActivityVec>
         where E: DbExecutor<'e> {
         debug!("Execute\n{}", SELECT_ACTIVITIES);
@@ -137,19 +384,31 @@ impl ActivityTable {
             .await
     }
 
-    pub async fn select_earliest_without_track<'e, E>(executor: E) -> Result<Option<Activity>>
+    pub async fn select_all_with_sensors<'e, E>(executor: E) -> Result<ActivityVec>
         where E: DbExecutor<'e> {
-        debug!("Execute\n{}", SELECT_EARLIEST_ACTIVITY_WITHOUT_TRACK);
-        query(SELECT_EARLIEST_ACTIVITY_WITHOUT_TRACK)
+        debug!("Execute\n{}", SELECT_ACTIVITIES_WITH_SENSORS);
+        query(SELECT_ACTIVITIES_WITH_SENSORS)
             .map(|row: DBRow| Self::row_to_activity(&row))
-            .fetch_optional(executor)
+            .fetch_all(executor)
             .await
     }
 
-     pub async fn select_stats<'e, E>(executor: E) -> Result<ActivityStats>
+    pub async fn select_fetched_column<'e, E>(executor: E, id: u64) -> Result<Option<TrackStoreState>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_FETCHED_COLUMN, id);
+        let value: Option<i32> = query(SELECT_FETCHED_COLUMN)
+            .bind(id as i64)
+            .map(|row: DBRow| row.get(0))
+            .fetch_optional(executor)
+            .await?;
+        value.map(|v| TrackStoreState::try_from(v).map_err(|e| sqlx::Error::Decode(e.into()))).transpose()
+    }
+
+     pub async fn select_stats<'e, E>(executor: E, athlete_id: u64) -> Result<ActivityStats>
          where E: DbExecutor<'e> {
-         debug!("Execute\n{}", SELECT_ACTIVITY_STATS);
+         debug!("Execute\n{} with: {}", SELECT_ACTIVITY_STATS, athlete_id);
          query(SELECT_ACTIVITY_STATS)
+             .bind(athlete_id as i64)
              .map(|row: DBRow| {
                  let act_cnt : u32 = row.get(0);
                  let act_min : Option<String> = row.get(1);
@@ -162,6 +421,32 @@ impl ActivityTable {
              .await
     }
 
+    /// Returns the numbers of remaining tracks (matching the filter) and of tracks missing at Strava
+    pub async fn select_track_counts<'e, E>(executor: E, athlete_id: u64, filter: &DownloadFilter) -> Result<(u64, u64)>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", SELECT_TRACK_COUNTS, athlete_id, filter);
+        let query = query(SELECT_TRACK_COUNTS)
+            .bind(athlete_id as i64)
+            .bind(athlete_id as i64);
+        Self::bind_filter(query, filter)
+            .map(|row: DBRow| (row.get::<i64, _>(1) as u64, row.get::<i64, _>(0) as u64))
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Binds the parameters of [FILTER_CONDITION] (each of them twice, as sqlx does not support named parameters)
+    pub fn bind_filter<'q>(query: Query<'q, DbType, <DbType as Database>::Arguments<'q>>, filter: &DownloadFilter)
+        -> Query<'q, DbType, <DbType as Database>::Arguments<'q>> {
+        let sport_types = filter.sport_types_json();
+        query
+            .bind(filter.after.clone())
+            .bind(filter.after.clone())
+            .bind(filter.before.clone())
+            .bind(filter.before.clone())
+            .bind(sport_types.clone())
+            .bind(sport_types)
+    }
+
     async fn execute_for_activity<'e, E>(executor: E, sql: &str, activity: &Activity) -> Result<()>
         where E: DbExecutor<'e> {
         debug!("Execute\n{}\nwith: {:?}", sql, activity);
@@ -170,6 +455,7 @@ impl ActivityTable {
         // The inverse operations are done by row_to_activity() below.
         query(sql)
             .bind(activity.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(activity.athlete_id as i64)
             .bind(activity.name.clone())
             .bind(activity.sport_type.clone())
             .bind(activity.start_date.clone())
@@ -178,6 +464,9 @@ impl ActivityTable {
             .bind((activity.total_elevation_gain * 10.0) as i64)
             .bind((activity.average_speed * 1000.0) as i64)
             .bind(activity.kudos_count)
+            .bind(activity.comment_count)
+            .bind(activity.total_photo_count)
+            .bind(activity.gear_id.clone())
             .execute(executor)
             .await
             .map(|_| ()) // Ignore returned row count
@@ -187,24 +476,31 @@ impl ActivityTable {
         // Reverse the conversion of floats to integers done in function upsert:
         Activity {
             id: row.get(0),
-            name: row.get(1),
-            sport_type: row.get(2),
-            start_date: row.get(3),
-            distance: (row.get::<i64, _>(4) as f32 / 10.0),
-            moving_time: row.get(5),
-            total_elevation_gain: (row.get::<i64, _>(6) as f32 / 10.0),
-            average_speed: (row.get::<i64, _>(7) as f32 / 1000.0),
-            kudos_count: row.get(8)
+            athlete_id: row.get::<i64, _>(1) as u64,
+            name: row.get(2),
+            sport_type: row.get(3),
+            start_date: row.get(4),
+            distance: (row.get::<i64, _>(5) as f32 / 10.0),
+            moving_time: row.get(6),
+            total_elevation_gain: (row.get::<i64, _>(7) as f32 / 10.0),
+            average_speed: (row.get::<i64, _>(8) as f32 / 1000.0),
+            kudos_count: row.get(9),
+            comment_count: row.get(10),
+            total_photo_count: row.get(11),
+            gear_id: row.get(12)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use sqlx::{query, Row};
     use crate::database::activity_table::ActivityTable;
-    use crate::database::db_types::DBPool;
-    use crate::domain::activity::Activity;
+    use crate::database::db_types::{DBPool, DBRow};
+    use crate::domain::activity::{Activity, LEGACY_ATHLETE};
     use crate::domain::activity_stats::ActivityStats;
+    use crate::domain::download_filter::DownloadFilter;
+    use crate::domain::missing_reason::MissingReason;
     use crate::domain::track_store_state::TrackStoreState;
 
     #[tokio::test]
@@ -249,7 +545,7 @@ mod tests {
         assert!(ActivityTable::upsert(&pool, &activity).await.is_ok());
         let result = ActivityTable::delete(&pool, 1).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), true);
+        assert!(result.unwrap());
 
         check_results(&pool, &[]).await;
     }
@@ -259,7 +555,7 @@ mod tests {
         let pool = create_connection_and_table().await;
         let result = ActivityTable::delete(&pool, 1).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), false);
+        assert!(!result.unwrap());
     }
 
     #[tokio::test]
@@ -268,7 +564,7 @@ mod tests {
         assert!(ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.is_ok());
         let result = ActivityTable::update_fetched_column(&pool, 1, TrackStoreState::Stored).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), true);
+        assert!(result.unwrap());
     }
 
     #[tokio::test]
@@ -276,33 +572,45 @@ mod tests {
         let pool = create_connection_and_table().await;
         let result = ActivityTable::update_fetched_column(&pool, 1, TrackStoreState::Stored).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), false);
+        assert!(!result.unwrap());
     }
 
     #[tokio::test]
-    async fn test_earliest_without_track() {
-        let activity1 = Activity::dummy(1, "2018-02-20T18:02:13Z");
-        let activity2 = Activity::dummy(2, "2018-02-20T18:02:12Z");
-        let activity3 = Activity::dummy(3, "2018-02-20T18:02:11Z");
-
+    async fn test_select_fetched_column() {
         let pool = create_connection_and_table().await;
-        ActivityTable::upsert(&pool, &activity1).await.unwrap();
-        ActivityTable::upsert(&pool, &activity2).await.unwrap();
-        ActivityTable::upsert(&pool, &activity3).await.unwrap();
-        ActivityTable::update_fetched_column(&pool, 3, TrackStoreState::Stored).await.unwrap(); // Earliest activity already has a track
-        ActivityTable::update_fetched_column(&pool, 2, TrackStoreState::Missing).await.unwrap(); // Same for missing track
+        ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "bar")).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 2, TrackStoreState::Missing).await.unwrap();
 
-        let result = ActivityTable::select_earliest_without_track(&pool).await;
+        let result = ActivityTable::select_fetched_column(&pool, 1).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(TrackStoreState::Pending));
+        let result = ActivityTable::select_fetched_column(&pool, 2).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), Some(activity1));
+        assert_eq!(result.unwrap(), Some(TrackStoreState::Missing));
+        let result = ActivityTable::select_fetched_column(&pool, 3).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), None);
     }
 
     #[tokio::test]
-    async fn test_earliest_without_track_missing() {
+    async fn test_reset_missing_tracks() {
         let pool = create_connection_and_table().await;
-        let result = ActivityTable::select_earliest_without_track(&pool).await;
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), None);
+        for (athlete_id, id) in [(7, 1), (7, 2), (7, 3), (8, 4), (7, 5)] {
+            ActivityTable::insert(&pool, &Activity::dummy_for(athlete_id, id, "foo")).await.unwrap();
+        }
+        ActivityTable::update_missing_columns(&pool, 1, MissingReason::NotFound).await.unwrap();
+        ActivityTable::update_missing_columns(&pool, 2, MissingReason::NoLatlng).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 3, TrackStoreState::Missing).await.unwrap(); // Unknown reason
+        ActivityTable::update_missing_columns(&pool, 4, MissingReason::NoLatlng).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 5, TrackStoreState::Stored).await.unwrap();
+
+        assert_eq!(ActivityTable::reset_missing_tracks(&pool, Some(7), Some(MissingReason::NoLatlng)).await.unwrap(), 1);
+        assert_eq!(ActivityTable::select_fetched_column(&pool, 2).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(ActivityTable::select_fetched_column(&pool, 4).await.unwrap(), Some(TrackStoreState::Missing));
+        assert_eq!(ActivityTable::reset_missing_tracks(&pool, Some(7), Some(MissingReason::ParseError)).await.unwrap(), 0);
+        assert_eq!(ActivityTable::reset_missing_tracks(&pool, None, None).await.unwrap(), 3);
+        assert_eq!(ActivityTable::select_fetched_column(&pool, 5).await.unwrap(), Some(TrackStoreState::Stored));
     }
 
     #[tokio::test]
@@ -318,10 +626,12 @@ mod tests {
         ActivityTable::upsert(&pool, &activity3).await.unwrap();
         ActivityTable::update_fetched_column(&pool, 3, TrackStoreState::Stored).await.unwrap();
         ActivityTable::update_fetched_column(&pool, 7, TrackStoreState::Stored).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 5, TrackStoreState::Sensors).await.unwrap();
 
         let result = ActivityTable::select_all_with_track(&pool).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), vec![activity3, activity1]);
+        assert_eq!(ActivityTable::select_all_with_sensors(&pool).await.unwrap(), vec![activity2]);
     }
 
     #[tokio::test]
@@ -333,24 +643,83 @@ mod tests {
         ActivityTable::upsert(&pool, &Activity::dummy(2, "2018-02-20T18:02:12Z")).await.unwrap();
         ActivityTable::upsert(&pool, &Activity::dummy(1, "2018-02-20T18:02:11Z")).await.unwrap(); // Note: ID overwrite
         ActivityTable::update_fetched_column(&pool, 1, TrackStoreState::Stored).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 2, TrackStoreState::Sensors).await.unwrap();
 
-        let result = ActivityTable::select_stats(&mut *tx).await;
+        let result = ActivityTable::select_stats(&mut *tx, Activity::DUMMY_ATHLETE).await;
         assert!(result.is_ok());
-        let reference = ActivityStats::new(3, Some("2018-02-20T18:02:11Z".to_string()), Some("2018-02-20T18:02:15Z".to_string()), 1, Some("2018-02-20T18:02:11Z".to_string()));
+        let reference = ActivityStats::new(3, Some("2018-02-20T18:02:11Z".to_string()), Some("2018-02-20T18:02:15Z".to_string()), 2, Some("2018-02-20T18:02:12Z".to_string()));
         assert_eq!(result.unwrap(), reference);
     }
 
+    #[tokio::test]
+    async fn test_select_track_counts() {
+        let mut activity3 = Activity::dummy(3, "2018-01-02T00:00:00Z");
+        activity3.sport_type = "Yoga".to_string();
+        let pool = create_connection_and_table().await;
+        for activity in [&Activity::dummy(1, "2018-01-01T00:00:00Z"), &Activity::dummy(2, "2018-01-01T00:00:00Z"), &activity3] {
+            ActivityTable::upsert(&pool, activity).await.unwrap();
+        }
+        ActivityTable::update_fetched_column(&pool, 1, TrackStoreState::Missing).await.unwrap();
+
+        let result = ActivityTable::select_track_counts(&pool, Activity::DUMMY_ATHLETE, &DownloadFilter::default()).await;
+        assert_eq!(result.unwrap(), (2, 1));
+        let filter = DownloadFilter::new(None, None, &["Yoga"]);
+        let result = ActivityTable::select_track_counts(&pool, Activity::DUMMY_ATHLETE, &filter).await;
+        assert_eq!(result.unwrap(), (1, 1));
+    }
+
     #[tokio::test]
     async fn test_select_stats_missing() {
         let pool = create_connection_and_table().await;
-        let result = ActivityTable::select_stats(&pool).await;
+        let result = ActivityTable::select_stats(&pool, Activity::DUMMY_ATHLETE).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), ActivityStats::new(0, None, None, 0, None));
     }
 
+    #[tokio::test]
+    async fn test_upgrade_table() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        // Table layout before multi-athlete support
+        query("CREATE TABLE activity (id INTEGER NOT NULL PRIMARY KEY, name TEXT NOT NULL, sport_type TEXT NOT NULL, \
+            start_date TEXT NOT NULL, distance INTEGER NOT NULL, moving_time INTEGER NOT NULL, \
+            total_elevation_gain INTEGER NOT NULL, average_speed INTEGER NOT NULL, kudos_count INTEGER NOT NULL, \
+            gpx_fetched INTEGER DEFAULT 0 NOT NULL CHECK (gpx_fetched IN (0, 1, 2)))").execute(&pool).await.unwrap();
+        query("INSERT INTO activity (id, name, sport_type, start_date, distance, moving_time, total_elevation_gain, average_speed, kudos_count) \
+            VALUES (1, 'foo', 'walk', 'bar', 3104, 1005, 1009, 3558, 3)").execute(&pool).await.unwrap();
+
+        query("CREATE TABLE child (activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE)").execute(&pool).await.unwrap();
+        query("INSERT INTO child VALUES (1)").execute(&pool).await.unwrap();
+
+        assert!(ActivityTable::create_table(&pool).await.is_ok());
+        assert!(ActivityTable::upgrade_table(&pool).await.is_ok());
+        assert!(ActivityTable::upgrade_table(&pool).await.is_ok()); // Second upgrade does nothing
+        check_results(&pool, &[&Activity::dummy_for(LEGACY_ATHLETE, 1, "bar")]).await;
+        assert!(ActivityTable::update_fetched_column(&pool, 1, TrackStoreState::Sensors).await.unwrap()); // Rebuilt with new CHECK
+        let children: i64 = query("SELECT COUNT(*) FROM child").map(|row: DBRow| row.get(0)).fetch_one(&pool).await.unwrap();
+        assert_eq!(children, 1); // Not deleted by the rebuild
+        ActivityTable::delete(&pool, 1).await.unwrap();
+        let children: i64 = query("SELECT COUNT(*) FROM child").map(|row: DBRow| row.get(0)).fetch_one(&pool).await.unwrap();
+        assert_eq!(children, 0); // Foreign keys enabled again
+    }
+
+    #[tokio::test]
+    async fn test_update_athlete_column() {
+        let pool = create_connection_and_table().await;
+        ActivityTable::insert(&pool, &Activity::dummy_for(LEGACY_ATHLETE, 1, "foo")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(LEGACY_ATHLETE, 2, "bar")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(7, 3, "baz")).await.unwrap();
+        assert_eq!(ActivityTable::select_athletes(&pool).await.unwrap(), vec![LEGACY_ATHLETE, 7]);
+
+        let result = ActivityTable::update_athlete_column(&pool, LEGACY_ATHLETE, 5).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(ActivityTable::select_athletes(&pool).await.unwrap(), vec![5, 7]);
+    }
+
     async fn create_connection_and_table() -> DBPool {
         let pool = DBPool::connect("sqlite::memory:").await.unwrap();
         ActivityTable::create_table(&pool).await.unwrap();
+        ActivityTable::upgrade_table(&pool).await.unwrap();
         pool
     }
 
@@ -0,0 +1,185 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::athlete::Athlete;
+use crate::domain::athlete_stats::AthleteStats;
+
+const CREATE_ATHLETE_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS athlete (
+        id INTEGER NOT NULL PRIMARY KEY,
+        username TEXT,
+        firstname TEXT,
+        lastname TEXT,
+        city TEXT,
+        state TEXT,
+        country TEXT,
+        sex TEXT,
+        weight INTEGER,
+        ftp INTEGER,
+        measurement_preference TEXT,
+        created_at TEXT,
+        biggest_ride_distance INTEGER,
+        biggest_climb_elevation_gain INTEGER
+    )";
+
+// The biggest_* columns are provided by the athlete stats and therefore not overwritten
+const UPSERT_ATHLETE : &str =
+    "INSERT INTO athlete (id, username, firstname, lastname, city, state, country, sex, weight, ftp, measurement_preference, created_at) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+     ON CONFLICT(id) DO \
+     UPDATE SET \
+       username = excluded.username, \
+       firstname = excluded.firstname, \
+       lastname = excluded.lastname, \
+       city = excluded.city, \
+       state = excluded.state, \
+       country = excluded.country, \
+       sex = excluded.sex, \
+       weight = excluded.weight, \
+       ftp = excluded.ftp, \
+       measurement_preference = excluded.measurement_preference, \
+       created_at = excluded.created_at";
+
+const UPDATE_BIGGEST_COLUMNS : &str =
+    "UPDATE athlete SET biggest_ride_distance = ?, biggest_climb_elevation_gain = ? WHERE id = ?";
+
+const SELECT_ATHLETE : &str =
+    "SELECT id, username, firstname, lastname, city, state, country, sex, weight, ftp, measurement_preference, created_at \
+     FROM athlete WHERE id = ?";
+
+pub struct AthleteTable;
+
+#[allow(dead_code)]
+impl AthleteTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ATHLETE_TABLE);
+        query(CREATE_ATHLETE_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    /// Stores the profile without the gear lists, which are stored by [crate::database::gear_table::GearTable]
+    pub async fn upsert<'e, E>(executor: E, athlete: &Athlete) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {:?}", UPSERT_ATHLETE, athlete);
+        // Floats are stored as int, see ActivityTable
+        query(UPSERT_ATHLETE)
+            .bind(athlete.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(athlete.username.clone())
+            .bind(athlete.firstname.clone())
+            .bind(athlete.lastname.clone())
+            .bind(athlete.city.clone())
+            .bind(athlete.state.clone())
+            .bind(athlete.country.clone())
+            .bind(athlete.sex.clone())
+            .bind(athlete.weight.map(|w| (w * 10.0) as i64))
+            .bind(athlete.ftp)
+            .bind(athlete.measurement_preference.clone())
+            .bind(athlete.created_at.clone())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Stores the record values of the stats. Returns false if the athlete does not exist.
+    pub async fn update_biggest_columns<'e, E>(executor: E, athlete_id: u64, stats: &AthleteStats) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", UPDATE_BIGGEST_COLUMNS, athlete_id, stats);
+        let result = query(UPDATE_BIGGEST_COLUMNS)
+            .bind(stats.biggest_ride_distance.map(|d| (d * 10.0) as i64))
+            .bind(stats.biggest_climb_elevation_gain.map(|e| (e * 10.0) as i64))
+            .bind(athlete_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Returns the athlete with empty gear lists
+    pub async fn select_by_id<'e, E>(executor: E, id: u64) -> Result<Option<Athlete>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_ATHLETE, id);
+        query(SELECT_ATHLETE)
+            .bind(id as i64)
+            .map(|row: DBRow| Self::row_to_athlete(&row))
+            .fetch_optional(executor)
+            .await
+    }
+
+    fn row_to_athlete(row: &DBRow) -> Athlete {
+        Athlete {
+            id: row.get::<i64, _>(0) as u64,
+            username: row.get(1),
+            firstname: row.get(2),
+            lastname: row.get(3),
+            city: row.get(4),
+            state: row.get(5),
+            country: row.get(6),
+            sex: row.get(7),
+            weight: row.get::<Option<i64>, _>(8).map(|w| w as f32 / 10.0),
+            ftp: row.get(9),
+            measurement_preference: row.get(10),
+            created_at: row.get(11),
+            bikes: Vec::new(),
+            shoes: Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::athlete_table::AthleteTable;
+    use crate::database::db_types::DBPool;
+    use crate::domain::athlete::Athlete;
+    use crate::domain::athlete_stats::AthleteStats;
+
+    impl Athlete {
+        pub fn dummy(id: u64) -> Self {
+            Self {
+                id,
+                username: Some("foo".to_string()),
+                firstname: Some("Foo".to_string()),
+                lastname: Some("Bar".to_string()),
+                city: None,
+                state: None,
+                country: Some("Germany".to_string()),
+                sex: None,
+                weight: Some(70.5),
+                ftp: Some(250),
+                measurement_preference: Some("meters".to_string()),
+                created_at: Some("2010-04-01T15:13:08Z".to_string()),
+                bikes: Vec::new(),
+                shoes: Vec::new()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upsert() {
+        let pool = create_connection_and_table().await;
+        let mut athlete = Athlete::dummy(7);
+        assert!(AthleteTable::upsert(&pool, &athlete).await.is_ok());
+        athlete.city = Some("Leipzig".to_string());
+        assert!(AthleteTable::upsert(&pool, &athlete).await.is_ok());
+
+        let result = AthleteTable::select_by_id(&pool, 7).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(athlete));
+        assert_eq!(AthleteTable::select_by_id(&pool, 8).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_update_biggest_columns() {
+        let pool = create_connection_and_table().await;
+        let stats: AthleteStats = serde_json::from_str(r#"{"biggest_ride_distance":123.4,"biggest_climb_elevation_gain":null}"#).unwrap();
+        assert!(!AthleteTable::update_biggest_columns(&pool, 7, &stats).await.unwrap());
+        AthleteTable::upsert(&pool, &Athlete::dummy(7)).await.unwrap();
+        assert!(AthleteTable::update_biggest_columns(&pool, 7, &stats).await.unwrap());
+    }
+
+    async fn create_connection_and_table() -> DBPool {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        AthleteTable::create_table(&pool).await.unwrap();
+        pool
+    }
+}
@@ -0,0 +1,102 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::athlete_stats::ActivityTotal;
+
+/// period is "recent", "ytd" or "all", sport is "ride", "run" or "swim", see [crate::domain::athlete_stats::AthleteStats]
+const CREATE_ATHLETE_TOTALS_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS athlete_totals (
+        athlete_id INTEGER NOT NULL,
+        period TEXT NOT NULL,
+        sport TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        distance INTEGER NOT NULL,
+        moving_time INTEGER NOT NULL,
+        elapsed_time INTEGER NOT NULL,
+        elevation_gain INTEGER NOT NULL,
+        PRIMARY KEY (athlete_id, period, sport)
+    )";
+
+const UPSERT_ATHLETE_TOTAL : &str =
+    "INSERT INTO athlete_totals (athlete_id, period, sport, count, distance, moving_time, elapsed_time, elevation_gain) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?) \
+     ON CONFLICT(athlete_id, period, sport) DO \
+     UPDATE SET \
+       count = excluded.count, \
+       distance = excluded.distance, \
+       moving_time = excluded.moving_time, \
+       elapsed_time = excluded.elapsed_time, \
+       elevation_gain = excluded.elevation_gain";
+
+const SELECT_ATHLETE_TOTAL : &str =
+    "SELECT count, distance, moving_time, elapsed_time, elevation_gain FROM athlete_totals \
+     WHERE athlete_id = ? AND period = ? AND sport = ?";
+
+pub struct AthleteTotalsTable;
+
+#[allow(dead_code)]
+impl AthleteTotalsTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ATHLETE_TOTALS_TABLE);
+        query(CREATE_ATHLETE_TOTALS_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn upsert<'e, E>(executor: E, athlete_id: u64, period: &str, sport: &str, total: &ActivityTotal) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {} {} {:?}", UPSERT_ATHLETE_TOTAL, athlete_id, period, sport, total);
+        // Floats are stored as int, see ActivityTable
+        query(UPSERT_ATHLETE_TOTAL)
+            .bind(athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(period)
+            .bind(sport)
+            .bind(total.count)
+            .bind((total.distance * 10.0) as i64)
+            .bind(total.moving_time as i64)
+            .bind(total.elapsed_time as i64)
+            .bind((total.elevation_gain * 10.0) as i64)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    pub async fn select<'e, E>(executor: E, athlete_id: u64, period: &str, sport: &str) -> Result<Option<ActivityTotal>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {} {}", SELECT_ATHLETE_TOTAL, athlete_id, period, sport);
+        query(SELECT_ATHLETE_TOTAL)
+            .bind(athlete_id as i64)
+            .bind(period)
+            .bind(sport)
+            .map(|row: DBRow| ActivityTotal {
+                count: row.get(0),
+                distance: row.get::<i64, _>(1) as f32 / 10.0,
+                moving_time: row.get::<i64, _>(2) as u64,
+                elapsed_time: row.get::<i64, _>(3) as u64,
+                elevation_gain: row.get::<i64, _>(4) as f32 / 10.0
+            })
+            .fetch_optional(executor)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::athlete_totals_table::AthleteTotalsTable;
+    use crate::database::db_types::DBPool;
+    use crate::domain::athlete_stats::ActivityTotal;
+
+    #[tokio::test]
+    async fn test_upsert() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        AthleteTotalsTable::create_table(&pool).await.unwrap();
+        let mut total = ActivityTotal { count: 3, distance: 1234.5, moving_time: 100, elapsed_time: 120, elevation_gain: 10.2 };
+        assert!(AthleteTotalsTable::upsert(&pool, 7, "ytd", "ride", &total).await.is_ok());
+        total.count = 4;
+        assert!(AthleteTotalsTable::upsert(&pool, 7, "ytd", "ride", &total).await.is_ok());
+
+        assert_eq!(AthleteTotalsTable::select(&pool, 7, "ytd", "ride").await.unwrap(), Some(total));
+        assert_eq!(AthleteTotalsTable::select(&pool, 7, "all", "ride").await.unwrap(), None);
+    }
+}
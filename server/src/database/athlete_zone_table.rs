@@ -0,0 +1,103 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::athlete_zones::ZoneRange;
+
+/// zone_type is "heart_rate" or "power", zone_index is the position within the zones of that type
+const CREATE_ATHLETE_ZONE_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS athlete_zone (
+        athlete_id INTEGER NOT NULL,
+        zone_type TEXT NOT NULL,
+        zone_index INTEGER NOT NULL,
+        min INTEGER NOT NULL,
+        max INTEGER NOT NULL,
+        PRIMARY KEY (athlete_id, zone_type, zone_index)
+    )";
+
+const INSERT_ATHLETE_ZONE : &str =
+    "INSERT INTO athlete_zone (athlete_id, zone_type, zone_index, min, max) VALUES (?, ?, ?, ?, ?)";
+
+const DELETE_ATHLETE_ZONES : &str =
+    "DELETE FROM athlete_zone WHERE athlete_id = ?";
+
+const SELECT_ATHLETE_ZONES : &str =
+    "SELECT zone_type, min, max FROM athlete_zone WHERE athlete_id = ? ORDER BY zone_type, zone_index";
+
+pub struct AthleteZoneTable;
+
+#[allow(dead_code)]
+impl AthleteZoneTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ATHLETE_ZONE_TABLE);
+        query(CREATE_ATHLETE_ZONE_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, athlete_id: u64, zone_type: &str, zone_index: usize, zone: &ZoneRange) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {} {} {:?}", INSERT_ATHLETE_ZONE, athlete_id, zone_type, zone_index, zone);
+        query(INSERT_ATHLETE_ZONE)
+            .bind(athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(zone_type)
+            .bind(zone_index as i64)
+            .bind(zone.min)
+            .bind(zone.max)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all zones of the athlete and returns their number
+    pub async fn delete_for_athlete<'e, E>(executor: E, athlete_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_ATHLETE_ZONES, athlete_id);
+        let result = query(DELETE_ATHLETE_ZONES)
+            .bind(athlete_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns the zones of the athlete together with their zone type, ordered by type and index
+    pub async fn select_for_athlete<'e, E>(executor: E, athlete_id: u64) -> Result<Vec<(String, ZoneRange)>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_ATHLETE_ZONES, athlete_id);
+        query(SELECT_ATHLETE_ZONES)
+            .bind(athlete_id as i64)
+            .map(|row: DBRow| (row.get(0), ZoneRange { min: row.get(1), max: row.get(2) }))
+            .fetch_all(executor)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::athlete_zone_table::AthleteZoneTable;
+    use crate::database::db_types::DBPool;
+    use crate::domain::athlete_zones::ZoneRange;
+
+    #[tokio::test]
+    async fn test_insert_and_delete() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        AthleteZoneTable::create_table(&pool).await.unwrap();
+        let zone1 = ZoneRange { min: 0, max: 120 };
+        let zone2 = ZoneRange { min: 120, max: -1 };
+        assert!(AthleteZoneTable::insert(&pool, 7, "power", 0, &zone1).await.is_ok());
+        assert!(AthleteZoneTable::insert(&pool, 7, "heart_rate", 1, &zone2).await.is_ok());
+        assert!(AthleteZoneTable::insert(&pool, 7, "heart_rate", 0, &zone1).await.is_ok());
+        assert!(AthleteZoneTable::insert(&pool, 7, "heart_rate", 0, &zone1).await.is_err()); // Duplicate key
+        assert!(AthleteZoneTable::insert(&pool, 8, "power", 0, &zone1).await.is_ok());
+
+        let expected = vec![
+            ("heart_rate".to_string(), zone1.clone()),
+            ("heart_rate".to_string(), zone2.clone()),
+            ("power".to_string(), zone1.clone())
+        ];
+        assert_eq!(AthleteZoneTable::select_for_athlete(&pool, 7).await.unwrap(), expected);
+        assert_eq!(AthleteZoneTable::delete_for_athlete(&pool, 7).await.unwrap(), 3);
+        assert!(AthleteZoneTable::select_for_athlete(&pool, 7).await.unwrap().is_empty());
+        assert_eq!(AthleteZoneTable::select_for_athlete(&pool, 8).await.unwrap().len(), 1);
+    }
+}
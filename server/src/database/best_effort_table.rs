@@ -0,0 +1,152 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::effort::BestEffort;
+
+const CREATE_BEST_EFFORT_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS best_effort (
+        id INTEGER NOT NULL PRIMARY KEY,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        start_date TEXT NOT NULL,
+        elapsed_time INTEGER NOT NULL,
+        moving_time INTEGER NOT NULL,
+        distance INTEGER NOT NULL,
+        start_index INTEGER NOT NULL,
+        end_index INTEGER NOT NULL,
+        pr_rank INTEGER
+    )";
+
+const CREATE_INDEXES : [&str; 2] = [
+    "CREATE INDEX IF NOT EXISTS best_effort_activity ON best_effort (activity_id)",
+    "CREATE INDEX IF NOT EXISTS best_effort_name ON best_effort (name, elapsed_time)"
+];
+
+const INSERT_BEST_EFFORT : &str =
+    "INSERT INTO best_effort (id, activity_id, name, start_date, elapsed_time, moving_time, distance, start_index, end_index, pr_rank) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+const DELETE_BEST_EFFORTS : &str =
+    "DELETE FROM best_effort WHERE activity_id = ?";
+
+// SQLite takes the bare columns from the row with the minimal elapsed time
+const SELECT_BEST_EFFORTS : &str =
+    "SELECT e.id, e.activity_id, e.name, e.start_date, MIN(e.elapsed_time), e.moving_time, \
+       e.distance, e.start_index, e.end_index, e.pr_rank \
+     FROM best_effort e JOIN activity a ON a.id = e.activity_id \
+     WHERE a.athlete_id = ? \
+     GROUP BY e.name ORDER BY e.distance";
+
+pub struct BestEffortTable;
+
+#[allow(dead_code)]
+impl BestEffortTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_BEST_EFFORT_TABLE);
+        query(CREATE_BEST_EFFORT_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_indexes<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> + Copy {
+        for sql in CREATE_INDEXES {
+            debug!("Execute\n{}", sql);
+            query(sql).execute(executor).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, effort: &BestEffort) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {:?}", INSERT_BEST_EFFORT, effort);
+        // Floats are stored as int, see ActivityTable
+        query(INSERT_BEST_EFFORT)
+            .bind(effort.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(effort.activity_id as i64)
+            .bind(effort.name.clone())
+            .bind(effort.start_date.clone())
+            .bind(effort.elapsed_time as i64)
+            .bind(effort.moving_time as i64)
+            .bind((effort.distance * 10.0) as i64)
+            .bind(effort.start_index)
+            .bind(effort.end_index)
+            .bind(effort.pr_rank)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all best efforts of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_BEST_EFFORTS, activity_id);
+        let result = query(DELETE_BEST_EFFORTS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns the fastest effort of the athlete for each distance (like "5k"), ordered by distance
+    pub async fn select_best<'e, E>(executor: E, athlete_id: u64) -> Result<Vec<BestEffort>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_BEST_EFFORTS, athlete_id);
+        query(SELECT_BEST_EFFORTS)
+            .bind(athlete_id as i64)
+            .map(|row: DBRow| Self::row_to_effort(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_effort(row: &DBRow) -> BestEffort {
+        BestEffort {
+            id: row.get::<i64, _>(0) as u64,
+            activity_id: row.get::<i64, _>(1) as u64,
+            name: row.get(2),
+            start_date: row.get(3),
+            elapsed_time: row.get::<i64, _>(4) as u64,
+            moving_time: row.get::<i64, _>(5) as u64,
+            distance: row.get::<i64, _>(6) as f32 / 10.0,
+            start_index: row.get(7),
+            end_index: row.get(8),
+            pr_rank: row.get(9)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::best_effort_table::BestEffortTable;
+    use crate::database::db_types::DBPool;
+    use crate::domain::activity::Activity;
+    use crate::domain::effort::BestEffort;
+
+    #[tokio::test]
+    async fn test_select_best() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        BestEffortTable::create_table(&pool).await.unwrap();
+        BestEffortTable::create_indexes(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "2020-01-02T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(9, 3, "2020-01-03T00:00:00Z")).await.unwrap();
+
+        let best_5k = BestEffort::dummy(21, 2, "5k", "2020-01-02T00:00:00Z", 1400);
+        let mut best_1k = BestEffort::dummy(22, 1, "1k", "2020-01-01T00:00:00Z", 250);
+        best_1k.distance = 1000.0;
+        assert!(BestEffortTable::insert(&pool, &BestEffort::dummy(11, 1, "5k", "2020-01-01T00:00:00Z", 1500)).await.is_ok());
+        assert!(BestEffortTable::insert(&pool, &best_1k).await.is_ok());
+        assert!(BestEffortTable::insert(&pool, &best_5k).await.is_ok());
+        assert!(BestEffortTable::insert(&pool, &BestEffort::dummy(31, 3, "5k", "2020-01-03T00:00:00Z", 1000)).await.is_ok());
+
+        let result = BestEffortTable::select_best(&pool, Activity::DUMMY_ATHLETE).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![best_1k, best_5k]); // Effort 31 belongs to another athlete
+
+        assert_eq!(BestEffortTable::delete_for_activity(&pool, 2).await.unwrap(), 1);
+        assert_eq!(BestEffortTable::select_best(&pool, Activity::DUMMY_ATHLETE).await.unwrap()[1].id, 11);
+    }
+}
@@ -0,0 +1,116 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::social::{Comment, CommentVec, PersonName};
+
+const CREATE_COMMENT_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS comment (
+        id INTEGER NOT NULL PRIMARY KEY,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        firstname TEXT NOT NULL,
+        lastname TEXT NOT NULL,
+        text TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    )";
+
+const CREATE_ACTIVITY_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS comment_activity ON comment (activity_id, created_at)";
+
+const INSERT_COMMENT : &str =
+    "INSERT INTO comment (id, activity_id, firstname, lastname, text, created_at) VALUES (?, ?, ?, ?, ?, ?)";
+
+const DELETE_COMMENTS : &str =
+    "DELETE FROM comment WHERE activity_id = ?";
+
+const SELECT_COMMENTS : &str =
+    "SELECT id, firstname, lastname, text, created_at FROM comment WHERE activity_id = ? ORDER BY created_at, id";
+
+pub struct CommentTable;
+
+#[allow(dead_code)]
+impl CommentTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_COMMENT_TABLE);
+        query(CREATE_COMMENT_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_index<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ACTIVITY_INDEX);
+        query(CREATE_ACTIVITY_INDEX).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, activity_id: u64, comment: &Comment) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {} {:?}", INSERT_COMMENT, activity_id, comment);
+        query(INSERT_COMMENT)
+            .bind(comment.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(activity_id as i64)
+            .bind(comment.author.firstname.clone())
+            .bind(comment.author.lastname.clone())
+            .bind(comment.text.clone())
+            .bind(comment.created_at.clone())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all comments of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_COMMENTS, activity_id);
+        let result = query(DELETE_COMMENTS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn select_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<CommentVec>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_COMMENTS, activity_id);
+        query(SELECT_COMMENTS)
+            .bind(activity_id as i64)
+            .map(|row: DBRow| Comment {
+                id: row.get::<i64, _>(0) as u64,
+                author: PersonName { firstname: row.get(1), lastname: row.get(2) },
+                text: row.get(3),
+                created_at: row.get(4)
+            })
+            .fetch_all(executor)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::comment_table::CommentTable;
+    use crate::database::db_types::DBPool;
+    use crate::domain::activity::Activity;
+    use crate::domain::social::Comment;
+
+    #[tokio::test]
+    async fn test_insert_and_delete() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        CommentTable::create_table(&pool).await.unwrap();
+        CommentTable::create_index(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-01T00:00:00Z")).await.unwrap();
+
+        let comments = vec![Comment::dummy(11, "Nice"), Comment::dummy(12, "Great & <fast>")];
+        for comment in &comments {
+            assert!(CommentTable::insert(&pool, 1, comment).await.is_ok());
+        }
+        assert!(CommentTable::insert(&pool, 2, &Comment::dummy(13, "Foo")).await.is_err()); // Unknown activity
+
+        let result = CommentTable::select_for_activity(&pool, 1).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), comments);
+        assert_eq!(CommentTable::delete_for_activity(&pool, 1).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,140 @@
+use const_format::concatcp;
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::download_event::{DownloadEvent, EventKind};
+
+/// Append-only log of the actions of the download scheduler. The events are kept when
+/// their activity is deleted, so there is no foreign key on the activity.
+const CREATE_DOWNLOAD_EVENT_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS download_event (
+        id INTEGER NOT NULL PRIMARY KEY,
+        athlete_id INTEGER NOT NULL,
+        time TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        activity_id INTEGER,
+        details TEXT NOT NULL
+    )";
+
+const CREATE_TIME_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS download_event_time ON download_event (time)";
+
+const INSERT_EVENT : &str =
+    "INSERT INTO download_event (athlete_id, time, kind, activity_id, details) VALUES (?, ?, ?, ?, ?)";
+
+const SELECT_EVENTS : &str =
+    "SELECT id, athlete_id, time, kind, activity_id, details FROM download_event WHERE time >= ?";
+
+const ORDER_EVENTS : &str =
+    " ORDER BY id ASC LIMIT ?";
+
+const SELECT_ALL_EVENTS : &str =
+    concatcp!(SELECT_EVENTS, ORDER_EVENTS);
+
+const SELECT_ATHLETE_EVENTS : &str =
+    concatcp!(SELECT_EVENTS, " AND athlete_id = ?", ORDER_EVENTS);
+
+pub struct DownloadEventTable;
+
+#[allow(dead_code)]
+impl DownloadEventTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_DOWNLOAD_EVENT_TABLE);
+        query(CREATE_DOWNLOAD_EVENT_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_index<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_TIME_INDEX);
+        query(CREATE_TIME_INDEX).execute(executor).await?;
+        Ok(())
+    }
+
+    /// Appends the event (ignoring its id) and returns the id of the new row
+    pub async fn insert<'e, E>(executor: E, event: &DownloadEvent) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {:?}", INSERT_EVENT, event);
+        let result = query(INSERT_EVENT)
+            .bind(event.athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(event.time.clone())
+            .bind(event.kind.name())
+            .bind(event.activity_id.map(|id| id as i64))
+            .bind(event.details.clone())
+            .execute(executor)
+            .await?;
+        Ok(result.last_insert_rowid() as u64)
+    }
+
+    /// Returns at most `limit` events at or after the given time (ISO 8601) in the order of their creation,
+    /// of the given athlete or of all athletes
+    pub async fn select_since<'e, E>(executor: E, athlete_id: Option<u64>, since: &str, limit: u32) -> Result<Vec<DownloadEvent>>
+        where E: DbExecutor<'e> {
+        let sql = match athlete_id {
+            Some(_) => SELECT_ATHLETE_EVENTS,
+            None => SELECT_ALL_EVENTS
+        };
+        debug!("Execute\n{} with: {} {:?} {}", sql, since, athlete_id, limit);
+        let query = query(sql).bind(since);
+        let query = match athlete_id {
+            Some(athlete_id) => query.bind(athlete_id as i64),
+            None => query
+        };
+        query.bind(limit)
+            .try_map(|row: DBRow| Self::row_to_event(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_event(row: &DBRow) -> Result<DownloadEvent> {
+        let kind: String = row.get(3);
+        Ok(DownloadEvent {
+            id: row.get::<i64, _>(0) as u64,
+            athlete_id: row.get::<i64, _>(1) as u64,
+            time: row.get(2),
+            kind: EventKind::try_from(kind.as_str()).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            activity_id: row.get::<Option<i64>, _>(4).map(|id| id as u64),
+            details: row.get(5)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::db_types::DBPool;
+    use crate::database::download_event_table::DownloadEventTable;
+    use crate::domain::download_event::{DownloadEvent, EventKind};
+
+    fn event(athlete_id: u64, time: &str, kind: EventKind, activity_id: Option<u64>) -> DownloadEvent {
+        DownloadEvent { id: 0, athlete_id, time: time.to_string(), kind, activity_id, details: "Foo".to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_select() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        DownloadEventTable::create_table(&pool).await.unwrap();
+        DownloadEventTable::create_index(&pool).await.unwrap();
+
+        let events = [
+            event(1, "2020-01-01T10:00:00Z", EventKind::PageFetched, None),
+            event(1, "2020-01-01T10:00:10Z", EventKind::TrackStored, Some(5)),
+            event(2, "2020-01-01T10:00:20Z", EventKind::RateLimited, None),
+            event(1, "2020-01-01T10:00:30Z", EventKind::TrackMissing, Some(6))
+        ];
+        for (index, event) in events.iter().enumerate() {
+            assert_eq!(DownloadEventTable::insert(&pool, event).await.unwrap(), index as u64 + 1);
+        }
+
+        let result = DownloadEventTable::select_since(&pool, None, "2020-01-01T10:00:10Z", 10).await.unwrap();
+        let ids: Vec<u64> = result.iter().map(|event| event.id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(result[0], DownloadEvent { id: 2, ..events[1].clone() });
+
+        let result = DownloadEventTable::select_since(&pool, Some(1), "2020-01-01T10:00:10Z", 10).await.unwrap();
+        let ids: Vec<u64> = result.iter().map(|event| event.id).collect();
+        assert_eq!(ids, vec![2, 4]);
+        assert_eq!(DownloadEventTable::select_since(&pool, None, "2020-01-01T00:00:00Z", 2).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,337 @@
+use const_format::concatcp;
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::activity_table::{ActivityTable, FILTER_CONDITION};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::download_filter::DownloadFilter;
+use crate::domain::download_job::{DownloadJob, JobType, REFETCH_PRIORITY};
+
+/// Persistent queue of the per-activity downloads. A job is deleted when it was executed
+/// successfully. A job of a given type exists at most once per activity. Jobs that failed
+/// permanently are marked as poisoned and are not executed anymore.
+const CREATE_DOWNLOAD_JOB_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS download_job (
+        id INTEGER NOT NULL PRIMARY KEY,
+        job_type TEXT NOT NULL,
+        athlete_id INTEGER NOT NULL,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        priority INTEGER NOT NULL,
+        attempts INTEGER DEFAULT 0 NOT NULL,
+        next_run_at INTEGER DEFAULT 0 NOT NULL,
+        last_error TEXT,
+        poisoned INTEGER DEFAULT 0 NOT NULL CHECK (poisoned IN (0, 1)),
+        UNIQUE (job_type, activity_id)
+    )";
+
+const CREATE_ATHLETE_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS download_job_athlete ON download_job (athlete_id, priority, next_run_at)";
+
+// Enqueues a job for each activity of the athlete matching the filter and the condition per job type
+const INSERT_JOBS : &str =
+    concatcp!("INSERT OR IGNORE INTO download_job (job_type, athlete_id, activity_id, priority) \
+     SELECT ?, athlete_id, id, ? FROM activity WHERE athlete_id = ? AND ", FILTER_CONDITION, " AND ");
+
+const INSERT_TRACK_JOBS : &str = concatcp!(INSERT_JOBS, "gpx_fetched = 0");
+const INSERT_LAPS_JOBS : &str = concatcp!(INSERT_JOBS, "laps_fetched = 0");
+const INSERT_EFFORTS_JOBS : &str = concatcp!(INSERT_JOBS, "efforts_fetched = 0");
+// Activities without photos, kudos and comments need no requests
+const INSERT_PHOTOS_JOBS : &str =
+    concatcp!(INSERT_JOBS, "photos_fetched = 0 AND (total_photo_count IS NULL OR total_photo_count > 0)");
+const INSERT_SOCIAL_JOBS : &str =
+    concatcp!(INSERT_JOBS, "social_fetched = 0 AND (kudos_count > 0 OR comment_count IS NULL OR comment_count > 0)");
+
+// A refetch replaces a pending (or poisoned) track job of the activity
+const UPSERT_REFETCH_JOB : &str =
+    "INSERT INTO download_job (job_type, athlete_id, activity_id, priority) VALUES (?, ?, ?, ?) \
+     ON CONFLICT (job_type, activity_id) DO \
+     UPDATE SET priority = excluded.priority, attempts = 0, next_run_at = 0, last_error = NULL, poisoned = 0";
+
+const UPDATE_FAILED_JOB : &str =
+    "UPDATE download_job SET attempts = attempts + 1, next_run_at = ?, last_error = ? WHERE id = ?";
+
+const UPDATE_POISONED_JOB : &str =
+    "UPDATE download_job SET attempts = attempts + 1, last_error = ?, poisoned = 1 WHERE id = ?";
+
+const DELETE_JOB : &str =
+    "DELETE FROM download_job WHERE id = ?";
+
+const DELETE_JOBS_OF_TYPE : &str =
+    "DELETE FROM download_job WHERE athlete_id = ? AND job_type = ?";
+
+// Jobs of the same priority are executed from the oldest to the youngest activity
+const SELECT_JOBS : &str =
+    "SELECT j.id, j.job_type, j.athlete_id, j.activity_id, j.priority, j.attempts, j.next_run_at, j.last_error, j.poisoned \
+     FROM download_job j JOIN activity a ON a.id = j.activity_id \
+     WHERE j.athlete_id = ?";
+
+const ORDER_JOBS : &str =
+    " ORDER BY j.priority DESC, a.start_date ASC, j.id ASC";
+
+const SELECT_ALL_JOBS : &str =
+    concatcp!(SELECT_JOBS, ORDER_JOBS);
+
+// Jobs of activities not matching the filter (anymore) are skipped
+const SELECT_NEXT_JOB : &str =
+    concatcp!(SELECT_JOBS, " AND j.poisoned = 0 AND j.next_run_at <= ? AND ", FILTER_CONDITION, ORDER_JOBS, " LIMIT 1");
+
+const SELECT_PENDING_COUNT : &str =
+    concatcp!("SELECT COUNT(*) FROM download_job j JOIN activity a ON a.id = j.activity_id \
+     WHERE j.athlete_id = ? AND j.poisoned = 0 AND ", FILTER_CONDITION);
+
+pub struct DownloadJobTable;
+
+#[allow(dead_code)]
+impl DownloadJobTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_DOWNLOAD_JOB_TABLE);
+        query(CREATE_DOWNLOAD_JOB_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_index<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ATHLETE_INDEX);
+        query(CREATE_ATHLETE_INDEX).execute(executor).await?;
+        Ok(())
+    }
+
+    /// Enqueues a job of the given type for all activities of the athlete that match the filter
+    /// and still miss the downloaded data. Existing jobs are kept. Returns the number of new jobs.
+    pub async fn insert_missing<'e, E>(executor: E, athlete_id: u64, job_type: JobType, filter: &DownloadFilter) -> Result<u64>
+        where E: DbExecutor<'e> {
+        let sql = match job_type {
+            JobType::Track => INSERT_TRACK_JOBS,
+            JobType::Laps => INSERT_LAPS_JOBS,
+            JobType::Efforts => INSERT_EFFORTS_JOBS,
+            JobType::Photos => INSERT_PHOTOS_JOBS,
+            JobType::Social => INSERT_SOCIAL_JOBS
+        };
+        debug!("Execute\n{} with: {:?} {} {:?}", sql, job_type, athlete_id, filter);
+        let query = query(sql)
+            .bind(job_type.name())
+            .bind(job_type.priority())
+            .bind(athlete_id as i64); // sqlx::sqlite cannot encode u64
+        let result = ActivityTable::bind_filter(query, filter)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Enqueues a track job for the activity at [REFETCH_PRIORITY], replacing an existing one
+    pub async fn upsert_refetch<'e, E>(executor: E, athlete_id: u64, activity_id: u64) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPSERT_REFETCH_JOB, athlete_id, activity_id);
+        query(UPSERT_REFETCH_JOB)
+            .bind(JobType::Track.name())
+            .bind(athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(activity_id as i64)
+            .bind(REFETCH_PRIORITY)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Increases the attempts of a failed job and postpones it
+    pub async fn update_failed<'e, E>(executor: E, id: u64, next_run_at: i64, error: &str) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {} {}", UPDATE_FAILED_JOB, next_run_at, error, id);
+        let result = query(UPDATE_FAILED_JOB)
+            .bind(next_run_at)
+            .bind(error)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Increases the attempts of a permanently failed job and excludes it from execution
+    pub async fn update_poisoned<'e, E>(executor: E, id: u64, error: &str) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", UPDATE_POISONED_JOB, error, id);
+        let result = query(UPDATE_POISONED_JOB)
+            .bind(error)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn delete<'e, E>(executor: E, id: u64) -> Result<bool>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_JOB, id);
+        let result = query(DELETE_JOB)
+            .bind(id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Deletes all jobs of the given type of the athlete and returns their number
+    pub async fn delete_for_type<'e, E>(executor: E, athlete_id: u64, job_type: JobType) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", DELETE_JOBS_OF_TYPE, athlete_id, job_type);
+        let result = query(DELETE_JOBS_OF_TYPE)
+            .bind(athlete_id as i64)
+            .bind(job_type.name())
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns all jobs of the athlete in the order of execution
+    pub async fn select_for_athlete<'e, E>(executor: E, athlete_id: u64) -> Result<Vec<DownloadJob>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_ALL_JOBS, athlete_id);
+        query(SELECT_ALL_JOBS)
+            .bind(athlete_id as i64)
+            .try_map(|row: DBRow| Self::row_to_job(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    /// Returns the job of the athlete to be executed next, if any matching the filter is due at the given time
+    pub async fn select_next<'e, E>(executor: E, athlete_id: u64, now: i64, filter: &DownloadFilter) -> Result<Option<DownloadJob>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {} {:?}", SELECT_NEXT_JOB, athlete_id, now, filter);
+        let query = query(SELECT_NEXT_JOB)
+            .bind(athlete_id as i64)
+            .bind(now);
+        ActivityTable::bind_filter(query, filter)
+            .try_map(|row: DBRow| Self::row_to_job(&row))
+            .fetch_optional(executor)
+            .await
+    }
+
+    /// Returns the number of jobs of the athlete matching the filter that are not poisoned (including the postponed jobs)
+    pub async fn select_pending_count<'e, E>(executor: E, athlete_id: u64, filter: &DownloadFilter) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", SELECT_PENDING_COUNT, athlete_id, filter);
+        let query = query(SELECT_PENDING_COUNT)
+            .bind(athlete_id as i64);
+        ActivityTable::bind_filter(query, filter)
+            .map(|row: DBRow| row.get::<i64, _>(0) as u64)
+            .fetch_one(executor)
+            .await
+    }
+
+    fn row_to_job(row: &DBRow) -> Result<DownloadJob> {
+        let job_type: String = row.get(1);
+        Ok(DownloadJob {
+            id: row.get::<i64, _>(0) as u64,
+            job_type: JobType::try_from(job_type.as_str()).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            athlete_id: row.get::<i64, _>(2) as u64,
+            activity_id: row.get::<i64, _>(3) as u64,
+            priority: row.get(4),
+            attempts: row.get(5),
+            next_run_at: row.get(6),
+            last_error: row.get(7),
+            poisoned: row.get(8)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::download_job_table::DownloadJobTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::download_filter::DownloadFilter;
+    use crate::domain::download_job::JobType;
+    use crate::domain::track_store_state::TrackStoreState;
+
+    async fn init_pool() -> DBPool {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        DownloadJobTable::create_table(&pool).await.unwrap();
+        DownloadJobTable::create_index(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_insert_missing() {
+        let pool = init_pool().await;
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-02-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "2020-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(7, 3, "2020-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::update_fetched_column(&pool, 2, TrackStoreState::Stored).await.unwrap();
+
+        for job_type in [JobType::Track, JobType::Laps] {
+            assert!(DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, job_type, &DownloadFilter::default()).await.is_ok());
+        }
+        assert_eq!(DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, JobType::Laps, &DownloadFilter::default()).await.unwrap(), 0); // Already enqueued
+
+        let jobs = DownloadJobTable::select_for_athlete(&pool, Activity::DUMMY_ATHLETE).await.unwrap();
+        let order: Vec<(JobType, u64)> = jobs.iter().map(|job| (job.job_type, job.activity_id)).collect();
+        assert_eq!(order, vec![(JobType::Track, 1), (JobType::Laps, 2), (JobType::Laps, 1)]);
+        assert!(jobs.iter().all(|job| job.attempts == 0 && job.last_error.is_none()));
+
+        assert_eq!(DownloadJobTable::delete_for_type(&pool, Activity::DUMMY_ATHLETE, JobType::Laps).await.unwrap(), 2);
+        assert!(DownloadJobTable::delete(&pool, jobs[0].id).await.unwrap());
+        assert!(DownloadJobTable::select_for_athlete(&pool, Activity::DUMMY_ATHLETE).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_insert_photos() {
+        let pool = init_pool().await;
+        for (id, total_photo_count) in [(1, Some(0)), (2, Some(2)), (3, None)] {
+            let mut activity = Activity::dummy(id, "2020-01-01T00:00:00Z");
+            activity.total_photo_count = total_photo_count;
+            ActivityTable::insert(&pool, &activity).await.unwrap();
+        }
+
+        let result = DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, JobType::Photos, &DownloadFilter::default()).await;
+        assert_eq!(result.unwrap(), 2); // None without photos, unknown count of older activities
+        let jobs = DownloadJobTable::select_for_athlete(&pool, Activity::DUMMY_ATHLETE).await.unwrap();
+        let ids: Vec<u64> = jobs.iter().map(|job| job.activity_id).collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_select_next() {
+        let pool = init_pool().await;
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "2020-02-01T00:00:00Z")).await.unwrap();
+        DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, JobType::Track, &DownloadFilter::default()).await.unwrap();
+
+        let job = DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 100, &DownloadFilter::default()).await.unwrap().unwrap();
+        assert_eq!(job.activity_id, 1);
+        assert!(DownloadJobTable::update_failed(&pool, job.id, 200, "Timeout").await.unwrap());
+
+        let job = DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 100, &DownloadFilter::default()).await.unwrap().unwrap();
+        assert_eq!(job.activity_id, 2); // First job postponed
+        let job = DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 200, &DownloadFilter::default()).await.unwrap().unwrap();
+        assert_eq!((job.activity_id, job.attempts, job.last_error), (1, 1, Some("Timeout".to_string())));
+
+        ActivityTable::delete(&pool, 1).await.unwrap(); // Deletes the job as well
+        let job = DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 200, &DownloadFilter::default()).await.unwrap().unwrap();
+        assert_eq!(job.activity_id, 2);
+        assert_eq!(DownloadJobTable::select_next(&pool, 7, 200, &DownloadFilter::default()).await.unwrap(), None);
+
+        assert!(DownloadJobTable::update_poisoned(&pool, job.id, "Bad request").await.unwrap());
+        assert_eq!(DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 200, &DownloadFilter::default()).await.unwrap(), None);
+        assert_eq!(DownloadJobTable::select_pending_count(&pool, Activity::DUMMY_ATHLETE, &DownloadFilter::default()).await.unwrap(), 0);
+        let jobs = DownloadJobTable::select_for_athlete(&pool, Activity::DUMMY_ATHLETE).await.unwrap();
+        assert!(jobs[0].poisoned);
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let pool = init_pool().await;
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2017-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "2018-01-01T00:00:00Z")).await.unwrap();
+        let filter = DownloadFilter::new(Some("2018-01-01T00:00:00Z"), None, &[]);
+        assert_eq!(DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, JobType::Track, &filter).await.unwrap(), 1);
+        assert_eq!(DownloadJobTable::insert_missing(&pool, Activity::DUMMY_ATHLETE, JobType::Laps, &DownloadFilter::default()).await.unwrap(), 2);
+
+        let filter = DownloadFilter::new(None, None, &["Ride"]);
+        assert_eq!(DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap(), None);
+        assert_eq!(DownloadJobTable::select_pending_count(&pool, Activity::DUMMY_ATHLETE, &filter).await.unwrap(), 0);
+        let filter = DownloadFilter::new(None, Some("2018-01-01T00:00:00Z"), &["walk"]);
+        let job = DownloadJobTable::select_next(&pool, Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap().unwrap();
+        assert_eq!((job.job_type, job.activity_id), (JobType::Laps, 1));
+        assert_eq!(DownloadJobTable::select_pending_count(&pool, Activity::DUMMY_ATHLETE, &filter).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,75 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::download_state::DownloadState;
+
+/// Download state per athlete (by [DownloadState::name]), so that downloads resume after a restart
+const CREATE_DOWNLOAD_STATE_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS download_state (
+        athlete_id INTEGER NOT NULL PRIMARY KEY,
+        state TEXT NOT NULL
+    )";
+
+const UPSERT_DOWNLOAD_STATE : &str =
+    "INSERT INTO download_state (athlete_id, state) VALUES (?, ?) \
+     ON CONFLICT(athlete_id) DO UPDATE SET state = excluded.state";
+
+const SELECT_DOWNLOAD_STATES : &str =
+    "SELECT athlete_id, state FROM download_state ORDER BY athlete_id ASC";
+
+pub struct DownloadStateTable;
+
+impl DownloadStateTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_DOWNLOAD_STATE_TABLE);
+        query(CREATE_DOWNLOAD_STATE_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn upsert<'e, E>(executor: E, athlete_id: u64, download_state: &DownloadState) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {:?}", UPSERT_DOWNLOAD_STATE, athlete_id, download_state);
+        query(UPSERT_DOWNLOAD_STATE)
+            .bind(athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(download_state.name())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Returns the download states of all athletes in ascending order of the athlete ids
+    pub async fn select_all<'e, E>(executor: E) -> Result<Vec<(u64, DownloadState)>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", SELECT_DOWNLOAD_STATES);
+        let rows: Vec<(i64, String)> = query(SELECT_DOWNLOAD_STATES)
+            .map(|row: DBRow| (row.get(0), row.get(1)))
+            .fetch_all(executor)
+            .await?;
+        rows.into_iter()
+            .map(|(athlete_id, state)| DownloadState::try_from(state.as_str())
+                .map(|state| (athlete_id as u64, state))
+                .map_err(|e| sqlx::Error::Decode(e.into())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::db_types::DBPool;
+    use crate::database::download_state_table::DownloadStateTable;
+    use crate::domain::download_state::DownloadState;
+
+    #[tokio::test]
+    async fn test_upsert() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        DownloadStateTable::create_table(&pool).await.unwrap();
+        assert!(DownloadStateTable::select_all(&pool).await.unwrap().is_empty());
+
+        DownloadStateTable::upsert(&pool, 7, &DownloadState::Tracks).await.unwrap();
+        DownloadStateTable::upsert(&pool, 3, &DownloadState::Profile).await.unwrap();
+        DownloadStateTable::upsert(&pool, 7, &DownloadState::Laps).await.unwrap();
+        assert_eq!(DownloadStateTable::select_all(&pool).await.unwrap(), vec![(3, DownloadState::Profile), (7, DownloadState::Laps)]);
+    }
+}
@@ -0,0 +1,154 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::gear::{Gear, GearUsage};
+
+const CREATE_GEAR_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS gear (
+        id TEXT NOT NULL PRIMARY KEY,
+        athlete_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        distance INTEGER NOT NULL,
+        is_primary INTEGER NOT NULL,
+        retired INTEGER NOT NULL,
+        brand_name TEXT,
+        model_name TEXT,
+        description TEXT
+    )";
+
+// Gear summaries lack the details, so existing details are kept
+const UPSERT_GEAR : &str =
+    "INSERT INTO gear (id, athlete_id, name, distance, is_primary, retired, brand_name, model_name, description) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) \
+     ON CONFLICT(id) DO \
+     UPDATE SET \
+       athlete_id = excluded.athlete_id, \
+       name = excluded.name, \
+       distance = excluded.distance, \
+       is_primary = excluded.is_primary, \
+       retired = excluded.retired, \
+       brand_name = COALESCE(excluded.brand_name, brand_name), \
+       model_name = COALESCE(excluded.model_name, model_name), \
+       description = COALESCE(excluded.description, description)";
+
+const SELECT_GEAR_USAGE : &str =
+    "SELECT g.id, g.name, g.distance, g.is_primary, g.retired, g.brand_name, g.model_name, g.description, \
+       COUNT(a.id), COALESCE(SUM(a.distance), 0) \
+     FROM gear g LEFT JOIN activity a ON a.gear_id = g.id \
+     WHERE g.athlete_id = ? \
+     GROUP BY g.id ORDER BY g.id";
+
+pub struct GearTable;
+
+#[allow(dead_code)]
+impl GearTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_GEAR_TABLE);
+        query(CREATE_GEAR_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn upsert<'e, E>(executor: E, athlete_id: u64, gear: &Gear) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {} {:?}", UPSERT_GEAR, athlete_id, gear);
+        // Floats are stored as int, see ActivityTable
+        query(UPSERT_GEAR)
+            .bind(gear.id.clone())
+            .bind(athlete_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(gear.name.clone())
+            .bind((gear.distance * 10.0) as i64)
+            .bind(gear.primary)
+            .bind(gear.retired)
+            .bind(gear.brand_name.clone())
+            .bind(gear.model_name.clone())
+            .bind(gear.description.clone())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Returns the gear of the athlete together with the number and total distance of the
+    /// activities (in the activity table) done with it
+    pub async fn select_usage<'e, E>(executor: E, athlete_id: u64) -> Result<Vec<GearUsage>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_GEAR_USAGE, athlete_id);
+        query(SELECT_GEAR_USAGE)
+            .bind(athlete_id as i64)
+            .map(|row: DBRow| Self::row_to_usage(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_usage(row: &DBRow) -> GearUsage {
+        let gear = Gear {
+            id: row.get(0),
+            name: row.get(1),
+            distance: row.get::<i64, _>(2) as f32 / 10.0,
+            primary: row.get(3),
+            retired: row.get(4),
+            brand_name: row.get(5),
+            model_name: row.get(6),
+            description: row.get(7)
+        };
+        GearUsage {
+            gear_type: gear.gear_type().to_string(),
+            gear,
+            activity_count: row.get(8),
+            activity_distance: row.get::<i64, _>(9) as f32 / 10.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::gear_table::GearTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::gear::Gear;
+
+    #[tokio::test]
+    async fn test_upsert_keeps_details() {
+        let pool = create_connection_and_tables().await;
+        let mut gear = Gear::dummy("b1", 100.0);
+        gear.brand_name = Some("BMC".to_string());
+        assert!(GearTable::upsert(&pool, 7, &gear).await.is_ok());
+        let summary = Gear::dummy("b1", 200.0);
+        assert!(GearTable::upsert(&pool, 7, &summary).await.is_ok());
+
+        let usage = GearTable::select_usage(&pool, 7).await.unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].gear.distance, 200.0);
+        assert_eq!(usage[0].gear.brand_name, Some("BMC".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_select_usage() {
+        let pool = create_connection_and_tables().await;
+        GearTable::upsert(&pool, 7, &Gear::dummy("b1", 100.0)).await.unwrap();
+        GearTable::upsert(&pool, 7, &Gear::dummy("g2", 100.0)).await.unwrap();
+        GearTable::upsert(&pool, 8, &Gear::dummy("b3", 100.0)).await.unwrap();
+        for id in 1..=3 {
+            let mut activity = Activity::dummy_for(7, id, "2020-01-01T00:00:00Z");
+            activity.gear_id = Some("b1".to_string());
+            ActivityTable::insert(&pool, &activity).await.unwrap();
+        }
+        ActivityTable::insert(&pool, &Activity::dummy_for(7, 4, "2020-01-01T00:00:00Z")).await.unwrap();
+
+        let result = GearTable::select_usage(&pool, 7).await;
+        assert!(result.is_ok());
+        let usage = result.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!((usage[0].gear_type.as_str(), usage[0].activity_count, usage[0].activity_distance), ("bike", 3, 931.2));
+        assert_eq!((usage[1].gear_type.as_str(), usage[1].activity_count, usage[1].activity_distance), ("shoe", 0, 0.0));
+    }
+
+    async fn create_connection_and_tables() -> DBPool {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        GearTable::create_table(&pool).await.unwrap();
+        pool
+    }
+}
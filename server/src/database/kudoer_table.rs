@@ -0,0 +1,97 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::social::{KudoerVec, PersonName};
+
+/// Strava does not reveal the ids of the kudoers, so they are identified by their position in the list
+const CREATE_KUDOER_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS kudoer (
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        firstname TEXT NOT NULL,
+        lastname TEXT NOT NULL,
+        PRIMARY KEY (activity_id, position)
+    )";
+
+const INSERT_KUDOER : &str =
+    "INSERT INTO kudoer (activity_id, position, firstname, lastname) VALUES (?, ?, ?, ?)";
+
+const DELETE_KUDOERS : &str =
+    "DELETE FROM kudoer WHERE activity_id = ?";
+
+const SELECT_KUDOERS : &str =
+    "SELECT firstname, lastname FROM kudoer WHERE activity_id = ? ORDER BY position";
+
+pub struct KudoerTable;
+
+#[allow(dead_code)]
+impl KudoerTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_KUDOER_TABLE);
+        query(CREATE_KUDOER_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, activity_id: u64, position: usize, kudoer: &PersonName) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {} {} {:?}", INSERT_KUDOER, activity_id, position, kudoer);
+        query(INSERT_KUDOER)
+            .bind(activity_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(position as i64)
+            .bind(kudoer.firstname.clone())
+            .bind(kudoer.lastname.clone())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all kudoers of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_KUDOERS, activity_id);
+        let result = query(DELETE_KUDOERS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn select_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<KudoerVec>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_KUDOERS, activity_id);
+        query(SELECT_KUDOERS)
+            .bind(activity_id as i64)
+            .map(|row: DBRow| PersonName { firstname: row.get(0), lastname: row.get(1) })
+            .fetch_all(executor)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::kudoer_table::KudoerTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::social::PersonName;
+
+    #[tokio::test]
+    async fn test_insert_and_delete() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        KudoerTable::create_table(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-01T00:00:00Z")).await.unwrap();
+
+        let kudoers = vec![PersonName::new("Foo", "B."), PersonName::new("Foo", "B.")]; // Same names are possible
+        for (position, kudoer) in kudoers.iter().enumerate() {
+            assert!(KudoerTable::insert(&pool, 1, position, kudoer).await.is_ok());
+        }
+
+        let result = KudoerTable::select_for_activity(&pool, 1).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), kudoers);
+        assert_eq!(KudoerTable::delete_for_activity(&pool, 1).await.unwrap(), 2);
+    }
+}
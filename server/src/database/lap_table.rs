@@ -0,0 +1,166 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::lap::{Lap, LapVec};
+
+const CREATE_LAP_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS lap (
+        id INTEGER NOT NULL PRIMARY KEY,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        lap_index INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        start_date TEXT NOT NULL,
+        start_index INTEGER NOT NULL,
+        end_index INTEGER NOT NULL,
+        elapsed_time INTEGER NOT NULL,
+        moving_time INTEGER NOT NULL,
+        distance INTEGER NOT NULL,
+        average_speed INTEGER NOT NULL,
+        average_heartrate INTEGER,
+        average_watts INTEGER
+    )";
+
+const CREATE_ACTIVITY_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS lap_activity ON lap (activity_id, lap_index)";
+
+const INSERT_LAP : &str =
+    "INSERT INTO lap (id, activity_id, lap_index, name, start_date, start_index, end_index, elapsed_time, moving_time, \
+       distance, average_speed, average_heartrate, average_watts) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+const DELETE_LAPS : &str =
+    "DELETE FROM lap WHERE activity_id = ?";
+
+const SELECT_LAPS : &str =
+    "SELECT id, lap_index, name, start_date, start_index, end_index, elapsed_time, moving_time, \
+       distance, average_speed, average_heartrate, average_watts \
+     FROM lap WHERE activity_id = ? ORDER BY lap_index";
+
+pub struct LapTable;
+
+#[allow(dead_code)]
+impl LapTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_LAP_TABLE);
+        query(CREATE_LAP_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_index<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ACTIVITY_INDEX);
+        query(CREATE_ACTIVITY_INDEX).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, activity_id: u64, lap: &Lap) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {} {:?}", INSERT_LAP, activity_id, lap);
+        // Floats are stored as int, see ActivityTable
+        query(INSERT_LAP)
+            .bind(lap.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(activity_id as i64)
+            .bind(lap.lap_index)
+            .bind(lap.name.clone())
+            .bind(lap.start_date.clone())
+            .bind(lap.start_index)
+            .bind(lap.end_index)
+            .bind(lap.elapsed_time as i64)
+            .bind(lap.moving_time as i64)
+            .bind((lap.distance * 10.0) as i64)
+            .bind((lap.average_speed * 1000.0) as i64)
+            .bind(lap.average_heartrate.map(|hr| (hr * 10.0) as i64))
+            .bind(lap.average_watts.map(|w| (w * 10.0) as i64))
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all laps of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_LAPS, activity_id);
+        let result = query(DELETE_LAPS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn select_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<LapVec>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_LAPS, activity_id);
+        query(SELECT_LAPS)
+            .bind(activity_id as i64)
+            .map(|row: DBRow| Self::row_to_lap(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_lap(row: &DBRow) -> Lap {
+        Lap {
+            id: row.get::<i64, _>(0) as u64,
+            lap_index: row.get(1),
+            name: row.get(2),
+            start_date: row.get(3),
+            start_index: row.get(4),
+            end_index: row.get(5),
+            elapsed_time: row.get::<i64, _>(6) as u64,
+            moving_time: row.get::<i64, _>(7) as u64,
+            distance: row.get::<i64, _>(8) as f32 / 10.0,
+            average_speed: row.get::<i64, _>(9) as f32 / 1000.0,
+            average_heartrate: row.get::<Option<i64>, _>(10).map(|hr| hr as f32 / 10.0),
+            average_watts: row.get::<Option<i64>, _>(11).map(|w| w as f32 / 10.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::lap_table::LapTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::lap::Lap;
+
+    #[tokio::test]
+    async fn test_insert_and_select() {
+        let pool = create_connection_and_tables().await;
+        let lap1 = Lap::dummy(11, 1, 0, 9);
+        let lap2 = Lap::dummy(12, 2, 10, 19);
+        assert!(LapTable::insert(&pool, 1, &lap2).await.is_ok());
+        assert!(LapTable::insert(&pool, 1, &lap1).await.is_ok());
+        assert!(LapTable::insert(&pool, 2, &Lap::dummy(13, 1, 0, 9)).await.is_ok());
+        assert!(LapTable::insert(&pool, 3, &Lap::dummy(14, 1, 0, 9)).await.is_err()); // Unknown activity
+
+        let result = LapTable::select_for_activity(&pool, 1).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![lap1, lap2]);
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let pool = create_connection_and_tables().await;
+        LapTable::insert(&pool, 1, &Lap::dummy(11, 1, 0, 9)).await.unwrap();
+        LapTable::insert(&pool, 1, &Lap::dummy(12, 2, 10, 19)).await.unwrap();
+        LapTable::insert(&pool, 2, &Lap::dummy(13, 1, 0, 9)).await.unwrap();
+
+        assert_eq!(LapTable::delete_for_activity(&pool, 1).await.unwrap(), 2);
+        assert!(LapTable::select_for_activity(&pool, 1).await.unwrap().is_empty());
+        assert!(ActivityTable::delete(&pool, 2).await.unwrap());
+        assert!(LapTable::select_for_activity(&pool, 2).await.unwrap().is_empty()); // Deleted by cascade
+    }
+
+    async fn create_connection_and_tables() -> DBPool {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        LapTable::create_table(&pool).await.unwrap();
+        LapTable::create_index(&pool).await.unwrap();
+        for id in 1..=2 {
+            ActivityTable::insert(&pool, &Activity::dummy(id, "2020-01-01T00:00:00Z")).await.unwrap();
+        }
+        pool
+    }
+}
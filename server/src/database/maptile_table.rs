@@ -1,29 +1,57 @@
 use const_format::str_replace;
-use log::{debug, trace};
-use sqlx::{query, Result};
+use log::{debug, info, trace};
+use sqlx::{query, Result, Row};
 use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::{DBPool, DBRow};
 use crate::domain::map_zoom::MapZoom;
 use crate::domain::map_tile::MapTile;
 
 const CREATE_TILE_TABLE : &str =
     "CREATE TABLE IF NOT EXISTS $table_name (
+        athlete_id INTEGER NOT NULL,
         x INTEGER NOT NULL,
         y INTEGER NOT NULL,
         activity_id INTEGER NOT NULL,
         activity_count INTEGER NOT NULL,
-        PRIMARY KEY (x, y)
+        PRIMARY KEY (athlete_id, x, y)
         FOREIGN KEY(activity_id) REFERENCES activity(id)
     )";
 
+// Tables created before multi-athlete support have the primary key (x, y) and must be rebuilt
+const SELECT_ATHLETE_COLUMN : &str =
+    "SELECT COUNT(*), COUNT(*) FILTER (WHERE name = 'athlete_id') FROM pragma_table_info('$table_name')";
+
+const RENAME_TILE_TABLE : &str =
+    "ALTER TABLE $table_name RENAME TO $table_name_old";
+
+const COPY_TILE_TABLE : &str =
+    "INSERT INTO $table_name (athlete_id, x, y, activity_id, activity_count) \
+     SELECT 0, x, y, activity_id, activity_count FROM $table_name_old";
+
+const DROP_OLD_TILE_TABLE : &str =
+    "DROP TABLE $table_name_old";
+
 const UPSERT_TILE: &str =
-    "INSERT INTO $table_name (x, y, activity_id, activity_count) \
-     VALUES (?, ?, ?, 1) \
-     ON CONFLICT(x, y) DO \
+    "INSERT INTO $table_name (athlete_id, x, y, activity_id, activity_count) \
+     VALUES (?, ?, ?, ?, 1) \
+     ON CONFLICT(athlete_id, x, y) DO \
      UPDATE SET activity_count = activity_count + 1";
 
+const DECREMENT_TILE: &str =
+    "UPDATE $table_name SET activity_count = activity_count - 1 WHERE athlete_id = ? AND x = ? AND y = ?";
+
+const DELETE_UNUSED_TILES: &str =
+    "DELETE FROM $table_name WHERE athlete_id = ? AND activity_count <= 0";
+
+const UPDATE_ATHLETE_COLUMN: &str =
+    "UPDATE $table_name SET athlete_id = ? WHERE athlete_id = ?";
+
 const DELETE_TILES : &str =
     "DELETE FROM $table_name";
 
+const SELECT_TILE_STATS : &str =
+    "SELECT COUNT(*), COALESCE(SUM(activity_count), 0) FROM $table_name";
+
 const TILE_TABLE_14: &str = "maptile14";
 const TILE_TABLE_17: &str = "maptile17";
 
@@ -31,12 +59,36 @@ const TILE_TABLE_17: &str = "maptile17";
 const CREATE_TILE_TABLE_14 : &str = str_replace!(CREATE_TILE_TABLE, "$table_name", TILE_TABLE_14);
 const CREATE_TILE_TABLE_17 : &str = str_replace!(CREATE_TILE_TABLE, "$table_name", TILE_TABLE_17);
 
+const SELECT_ATHLETE_COLUMN_14 : &str = str_replace!(SELECT_ATHLETE_COLUMN, "$table_name", TILE_TABLE_14);
+const SELECT_ATHLETE_COLUMN_17 : &str = str_replace!(SELECT_ATHLETE_COLUMN, "$table_name", TILE_TABLE_17);
+
+const RENAME_TILE_TABLE_14 : &str = str_replace!(RENAME_TILE_TABLE, "$table_name", TILE_TABLE_14);
+const RENAME_TILE_TABLE_17 : &str = str_replace!(RENAME_TILE_TABLE, "$table_name", TILE_TABLE_17);
+
+const COPY_TILE_TABLE_14 : &str = str_replace!(COPY_TILE_TABLE, "$table_name", TILE_TABLE_14);
+const COPY_TILE_TABLE_17 : &str = str_replace!(COPY_TILE_TABLE, "$table_name", TILE_TABLE_17);
+
+const DROP_OLD_TILE_TABLE_14 : &str = str_replace!(DROP_OLD_TILE_TABLE, "$table_name", TILE_TABLE_14);
+const DROP_OLD_TILE_TABLE_17 : &str = str_replace!(DROP_OLD_TILE_TABLE, "$table_name", TILE_TABLE_17);
+
 const UPSERT_TILE_14 : &str = str_replace!(UPSERT_TILE, "$table_name", TILE_TABLE_14);
 const UPSERT_TILE_17 : &str = str_replace!(UPSERT_TILE, "$table_name", TILE_TABLE_17);
 
+const DECREMENT_TILE_14 : &str = str_replace!(DECREMENT_TILE, "$table_name", TILE_TABLE_14);
+const DECREMENT_TILE_17 : &str = str_replace!(DECREMENT_TILE, "$table_name", TILE_TABLE_17);
+
+const DELETE_UNUSED_TILES_14 : &str = str_replace!(DELETE_UNUSED_TILES, "$table_name", TILE_TABLE_14);
+const DELETE_UNUSED_TILES_17 : &str = str_replace!(DELETE_UNUSED_TILES, "$table_name", TILE_TABLE_17);
+
+const UPDATE_ATHLETE_COLUMN_14 : &str = str_replace!(UPDATE_ATHLETE_COLUMN, "$table_name", TILE_TABLE_14);
+const UPDATE_ATHLETE_COLUMN_17 : &str = str_replace!(UPDATE_ATHLETE_COLUMN, "$table_name", TILE_TABLE_17);
+
 const DELETE_TILES_14 : &str = str_replace!(DELETE_TILES, "$table_name", TILE_TABLE_14);
 const DELETE_TILES_17 : &str = str_replace!(DELETE_TILES, "$table_name", TILE_TABLE_17);
 
+const SELECT_TILE_STATS_14 : &str = str_replace!(SELECT_TILE_STATS, "$table_name", TILE_TABLE_14);
+const SELECT_TILE_STATS_17 : &str = str_replace!(SELECT_TILE_STATS, "$table_name", TILE_TABLE_17);
+
 pub struct MapTileTable;
 
 impl MapTileTable {
@@ -52,7 +104,31 @@ impl MapTileTable {
         Ok(())
     }
 
-    pub async fn upsert<'e, E>(executor: E, zoom: MapZoom, tile: &MapTile, activity_id: u64)
+    /// Rebuilds a tile table created before multi-athlete support. The tiles of
+    /// such tables are assigned to the legacy athlete (see [crate::domain::activity::LEGACY_ATHLETE]).
+    pub async fn upgrade_table(pool: &DBPool, zoom: MapZoom) -> Result<()> {
+        let (select_sql, rename_sql, create_sql, copy_sql, drop_sql) = match zoom {
+            MapZoom::Level14 => (SELECT_ATHLETE_COLUMN_14, RENAME_TILE_TABLE_14, CREATE_TILE_TABLE_14, COPY_TILE_TABLE_14, DROP_OLD_TILE_TABLE_14),
+            MapZoom::Level17 => (SELECT_ATHLETE_COLUMN_17, RENAME_TILE_TABLE_17, CREATE_TILE_TABLE_17, COPY_TILE_TABLE_17, DROP_OLD_TILE_TABLE_17)
+        };
+        debug!("Execute\n{select_sql}");
+        let (columns, athlete_columns): (i64, i64) = query(select_sql)
+            .map(|row: DBRow| (row.get(0), row.get(1)))
+            .fetch_one(pool)
+            .await?;
+        if columns > 0 && athlete_columns == 0 {
+            info!("Add athlete column to tile table for zoom level {}", zoom.value());
+            let mut tx = pool.begin().await?;
+            for sql in [rename_sql, create_sql, copy_sql, drop_sql] {
+                debug!("Execute\n{sql}");
+                query(sql).execute(&mut *tx).await?;
+            }
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn upsert<'e, E>(executor: E, zoom: MapZoom, tile: &MapTile, athlete_id: u64, activity_id: u64)
         -> Result<()>
     where E: DbExecutor<'e>
     {
@@ -60,8 +136,9 @@ impl MapTileTable {
             MapZoom::Level14 => UPSERT_TILE_14,
             MapZoom::Level17 => UPSERT_TILE_17
         };
-        trace!("Execute\n{}\nwith {}, {}, {}", sql, tile.get_x(), tile.get_y(), activity_id);
+        trace!("Execute\n{}\nwith {}, {}, {}, {}", sql, athlete_id, tile.get_x(), tile.get_y(), activity_id);
         query(sql)
+            .bind(athlete_id as i64)
             .bind(tile.get_x() as i64) // sqlx::sqlite cannot encode u64
             .bind(tile.get_y() as i64) // see https://docs.rs/sqlx/latest/sqlx/sqlite/types
             .bind(activity_id as i64)
@@ -70,6 +147,41 @@ impl MapTileTable {
             .map(|_| ()) // Ignore returned row count
     }
 
+    /// Removes an activity from the count of the tile. The tile keeps its (first) activity id,
+    /// tiles without activities are removed by [MapTileTable::delete_unused].
+    pub async fn decrement<'e, E>(executor: E, zoom: MapZoom, tile: &MapTile, athlete_id: u64) -> Result<()>
+    where E: DbExecutor<'e>
+    {
+        let sql = match zoom {
+            MapZoom::Level14 => DECREMENT_TILE_14,
+            MapZoom::Level17 => DECREMENT_TILE_17
+        };
+        trace!("Execute\n{}\nwith {}, {}, {}", sql, athlete_id, tile.get_x(), tile.get_y());
+        query(sql)
+            .bind(athlete_id as i64)
+            .bind(tile.get_x() as i64) // sqlx::sqlite cannot encode u64
+            .bind(tile.get_y() as i64)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes the tiles of the athlete that are not visited by any activity anymore
+    pub async fn delete_unused<'e, E>(executor: E, zoom: MapZoom, athlete_id: u64) -> Result<u64>
+    where E: DbExecutor<'e>
+    {
+        let sql = match zoom {
+            MapZoom::Level14 => DELETE_UNUSED_TILES_14,
+            MapZoom::Level17 => DELETE_UNUSED_TILES_17
+        };
+        debug!("Execute\n{} with: {}", sql, athlete_id);
+        let result = query(sql)
+            .bind(athlete_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn delete_all<'e, E>(executor: E, zoom: MapZoom) -> Result<usize>
     where E: DbExecutor<'e>
     {
@@ -81,6 +193,38 @@ impl MapTileTable {
         let result = query(sql).execute(executor).await?;
         Ok(result.rows_affected() as usize)
     }
+
+    /// Moves all tiles of one athlete to another athlete
+    pub async fn update_athlete_column<'e, E>(executor: E, zoom: MapZoom, old_id: u64, new_id: u64) -> Result<u64>
+    where E: DbExecutor<'e>
+    {
+        let sql = match zoom {
+            MapZoom::Level14 => UPDATE_ATHLETE_COLUMN_14,
+            MapZoom::Level17 => UPDATE_ATHLETE_COLUMN_17
+        };
+        debug!("Execute\n{} with: {} {}", sql, new_id, old_id);
+        let result = query(sql)
+            .bind(new_id as i64)
+            .bind(old_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns the number of tiles and the sum of their activity counts for the given zoom level
+    pub async fn select_stats<'e, E>(executor: E, zoom: MapZoom) -> Result<(u64, u64)>
+    where E: DbExecutor<'e>
+    {
+        let sql = match zoom {
+            MapZoom::Level14 => SELECT_TILE_STATS_14,
+            MapZoom::Level17 => SELECT_TILE_STATS_17
+        };
+        debug!("Execute\n{sql}");
+        query(sql)
+            .map(|row: DBRow| (row.get::<i64, _>(0) as u64, row.get::<i64, _>(1) as u64))
+            .fetch_one(executor)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -94,26 +238,27 @@ mod tests {
     use crate::database::db_executor::DbExecutor;
     use crate::database::db_types::{DBPool, DBRow};
     use crate::database::maptile_table::{MapTileTable, TILE_TABLE_14, TILE_TABLE_17};
-    use crate::domain::activity::Activity;
+    use crate::domain::activity::{Activity, LEGACY_ATHLETE};
     use crate::domain::map_tile::MapTile;
     use crate::domain::map_zoom::MapZoom;
 
     const SELECT_TILES : &str =
-        "SELECT x, y, activity_id, activity_count FROM $table_name ORDER BY x, y";
+        "SELECT athlete_id, x, y, activity_id, activity_count FROM $table_name ORDER BY athlete_id, x, y";
 
     const SELECT_TILES_14 : &str = str_replace!(SELECT_TILES, "$table_name", TILE_TABLE_14);
     const SELECT_TILES_17 : &str = str_replace!(SELECT_TILES, "$table_name", TILE_TABLE_17);
 
     #[derive(Debug, PartialEq)]
     pub struct MapTileRow {
+        athlete_id: i64,
         tile: MapTile,
         activity_id: i64,
         activity_count: u32
     }
 
     impl MapTileRow {
-        pub fn new(tile: MapTile, activity_id: i64, activity_count: u32) -> Self {
-            Self { tile, activity_id, activity_count }
+        pub fn new(athlete_id: i64, tile: MapTile, activity_id: i64, activity_count: u32) -> Self {
+            Self { athlete_id, tile, activity_id, activity_count }
         }
 
         pub fn get_tile(&self) -> &MapTile {
@@ -134,9 +279,10 @@ mod tests {
             let tiles: Vec<MapTileRow> = query(sql)
                 .map(|row: DBRow| {
                     MapTileRow::new(
-                        MapTile::new(row.get(0), row.get(1)),
-                        row.get(2),
-                        row.get(3))
+                        row.get(0),
+                        MapTile::new(row.get(1), row.get(2)),
+                        row.get(3),
+                        row.get(4))
                 })
                 .fetch_all(executor)
                 .await?;
@@ -146,6 +292,7 @@ mod tests {
     }
 
     const ZOOM: MapZoom = MapZoom::Level14;
+    const ATHLETE: u64 = Activity::DUMMY_ATHLETE;
 
     #[tokio::test]
     async fn test_upsert() {
@@ -161,14 +308,14 @@ mod tests {
         ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
         ActivityTable::insert(&pool, &Activity::dummy(2, "bar")).await.unwrap();
 
-        assert!(MapTileTable::upsert(&pool, ZOOM, &tile1, 1).await.is_ok());
-        assert!(MapTileTable::upsert(&pool, ZOOM, &tile2, 2).await.is_ok());
-        assert!(MapTileTable::upsert(&pool, ZOOM, &tile3, 1).await.is_ok()); // tile3 is same as tile1
-        assert!(MapTileTable::upsert(&pool, ZOOM, &tile4, 1).await.is_ok()); // Ditto
+        assert!(MapTileTable::upsert(&pool, ZOOM, &tile1, ATHLETE, 1).await.is_ok());
+        assert!(MapTileTable::upsert(&pool, ZOOM, &tile2, ATHLETE, 2).await.is_ok());
+        assert!(MapTileTable::upsert(&pool, ZOOM, &tile3, ATHLETE, 1).await.is_ok()); // tile3 is same as tile1
+        assert!(MapTileTable::upsert(&pool, ZOOM, &tile4, ATHLETE, 1).await.is_ok()); // Ditto
 
         check_results(&pool, ZOOM, vec![
-            MapTileRow { tile: tile1, activity_id: 1, activity_count: 3 },
-            MapTileRow { tile: tile2, activity_id: 2, activity_count: 1 }
+            MapTileRow { athlete_id: ATHLETE as i64, tile: tile1, activity_id: 1, activity_count: 3 },
+            MapTileRow { athlete_id: ATHLETE as i64, tile: tile2, activity_id: 2, activity_count: 1 }
         ]).await;
     }
 
@@ -180,8 +327,8 @@ mod tests {
 
         ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
         ActivityTable::insert(&pool, &Activity::dummy(2, "bar")).await.unwrap();
-        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(1, 1), 1).await.unwrap();
-        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(2, 2), 2).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(1, 1), ATHLETE, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(2, 2), ATHLETE, 2).await.unwrap();
 
         assert!(MapTileTable::delete_all(&pool, ZOOM).await.is_ok());
 
@@ -198,15 +345,75 @@ mod tests {
         MapTileTable::create_table(&pool, ZOOM).await.unwrap();
 
         ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
-        MapTileTable::upsert(&pool, ZOOM, &tile1, 1).await.unwrap();
-        MapTileTable::upsert(&pool, ZOOM, &tile2, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &tile1, ATHLETE, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &tile2, ATHLETE, 1).await.unwrap();
 
         check_results(&pool, ZOOM, vec![
-            MapTileRow { tile: tile1, activity_id: 1, activity_count: 1 },
-            MapTileRow { tile: tile2, activity_id: 1, activity_count: 1 }
+            MapTileRow { athlete_id: ATHLETE as i64, tile: tile1, activity_id: 1, activity_count: 1 },
+            MapTileRow { athlete_id: ATHLETE as i64, tile: tile2, activity_id: 1, activity_count: 1 }
         ]).await;
     }
 
+    #[tokio::test]
+    async fn test_select_stats() {
+        let pool = create_pool().await;
+        ActivityTable::create_table(&pool).await.unwrap();
+        MapTileTable::create_table(&pool, ZOOM).await.unwrap();
+
+        assert_eq!(MapTileTable::select_stats(&pool, ZOOM).await.unwrap(), (0, 0));
+
+        ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(1, 1), ATHLETE, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(1, 1), ATHLETE, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &MapTile::new(2, 2), ATHLETE, 1).await.unwrap();
+
+        let result = MapTileTable::select_stats(&pool, ZOOM).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_other_athlete() {
+        let tile = MapTile::new(1, 1);
+
+        let pool = create_pool().await;
+        ActivityTable::create_table(&pool).await.unwrap();
+        MapTileTable::create_table(&pool, ZOOM).await.unwrap();
+
+        ActivityTable::insert(&pool, &Activity::dummy(1, "foo")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(7, 2, "bar")).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &tile, ATHLETE, 1).await.unwrap();
+        MapTileTable::upsert(&pool, ZOOM, &tile, 7, 2).await.unwrap();
+
+        check_results(&pool, ZOOM, vec![
+            MapTileRow { athlete_id: ATHLETE as i64, tile: tile.clone(), activity_id: 1, activity_count: 1 },
+            MapTileRow { athlete_id: 7, tile, activity_id: 2, activity_count: 1 }
+        ]).await;
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_table() {
+        let pool = create_pool().await;
+        ActivityTable::create_table(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(LEGACY_ATHLETE, 1, "foo")).await.unwrap();
+        // Table layout before multi-athlete support
+        query("CREATE TABLE maptile14 (x INTEGER NOT NULL, y INTEGER NOT NULL, activity_id INTEGER NOT NULL, \
+            activity_count INTEGER NOT NULL, PRIMARY KEY (x, y) FOREIGN KEY(activity_id) REFERENCES activity(id))")
+            .execute(&pool).await.unwrap();
+        query("INSERT INTO maptile14 VALUES (1, 1, 1, 3)").execute(&pool).await.unwrap();
+
+        assert!(MapTileTable::upgrade_table(&pool, ZOOM).await.is_ok());
+        assert!(MapTileTable::upgrade_table(&pool, ZOOM).await.is_ok()); // Second upgrade does nothing
+        assert!(MapTileTable::create_table(&pool, ZOOM).await.is_ok());
+
+        let expected = MapTileRow { athlete_id: LEGACY_ATHLETE as i64, tile: MapTile::new(1, 1), activity_id: 1, activity_count: 3 };
+        check_results(&pool, ZOOM, vec![expected]).await;
+
+        let result = MapTileTable::update_athlete_column(&pool, ZOOM, LEGACY_ATHLETE, ATHLETE).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+
     async fn create_pool() -> DBPool {
         DBPool::connect("sqlite::memory:").await.unwrap()
     }
@@ -1,4 +1,17 @@
 pub mod activity_table;
+pub mod athlete_table;
+pub mod athlete_totals_table;
+pub mod athlete_zone_table;
+pub mod best_effort_table;
+pub mod comment_table;
+pub mod download_event_table;
+pub mod download_job_table;
+pub mod download_state_table;
+pub mod gear_table;
+pub mod kudoer_table;
+pub mod lap_table;
 pub mod maptile_table;
+pub mod photo_table;
+pub mod segment_effort_table;
 pub mod db_types;
 mod db_executor;
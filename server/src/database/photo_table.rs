@@ -0,0 +1,132 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::photo::{Photo, PhotoVec};
+
+/// file_name is NULL if the image could not be downloaded
+const CREATE_PHOTO_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS photo (
+        unique_id TEXT NOT NULL PRIMARY KEY,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        caption TEXT,
+        created_at TEXT,
+        latitude REAL,
+        longitude REAL,
+        file_name TEXT
+    )";
+
+const CREATE_ACTIVITY_INDEX : &str =
+    "CREATE INDEX IF NOT EXISTS photo_activity ON photo (activity_id)";
+
+const INSERT_PHOTO : &str =
+    "INSERT INTO photo (unique_id, activity_id, caption, created_at, latitude, longitude, file_name) \
+     VALUES (?, ?, ?, ?, ?, ?, ?)";
+
+const DELETE_PHOTOS : &str =
+    "DELETE FROM photo WHERE activity_id = ?";
+
+const SELECT_PHOTOS : &str =
+    "SELECT unique_id, caption, created_at, latitude, longitude, file_name FROM photo \
+     WHERE activity_id = ? ORDER BY created_at, unique_id";
+
+pub struct PhotoTable;
+
+#[allow(dead_code)]
+impl PhotoTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_PHOTO_TABLE);
+        query(CREATE_PHOTO_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_index<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_ACTIVITY_INDEX);
+        query(CREATE_ACTIVITY_INDEX).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, activity_id: u64, photo: &Photo) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {} {:?}", INSERT_PHOTO, activity_id, photo);
+        query(INSERT_PHOTO)
+            .bind(photo.unique_id.clone())
+            .bind(activity_id as i64) // sqlx::sqlite cannot encode u64
+            .bind(photo.caption.clone())
+            .bind(photo.created_at.clone())
+            .bind(photo.location.map(|(lat, _)| lat))
+            .bind(photo.location.map(|(_, lon)| lon))
+            .bind(photo.file_name.clone())
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all photos of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_PHOTOS, activity_id);
+        let result = query(DELETE_PHOTOS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns the photos of the activity without image URLs
+    pub async fn select_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<PhotoVec>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", SELECT_PHOTOS, activity_id);
+        query(SELECT_PHOTOS)
+            .bind(activity_id as i64)
+            .map(|row: DBRow| Self::row_to_photo(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_photo(row: &DBRow) -> Photo {
+        let latitude: Option<f64> = row.get(3);
+        let longitude: Option<f64> = row.get(4);
+        Photo {
+            unique_id: row.get(0),
+            caption: row.get(1),
+            created_at: row.get(2),
+            location: latitude.zip(longitude),
+            urls: Default::default(),
+            file_name: row.get(5)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::photo_table::PhotoTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::photo::Photo;
+
+    #[tokio::test]
+    async fn test_insert_and_delete() {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        PhotoTable::create_table(&pool).await.unwrap();
+        PhotoTable::create_index(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-01T00:00:00Z")).await.unwrap();
+
+        let mut photo1 = Photo::dummy("a1", Some((51.3, 12.3)));
+        photo1.file_name = Some("1/a1.jpg".to_string());
+        let photo2 = Photo::dummy("b2", None);
+        assert!(PhotoTable::insert(&pool, 1, &photo1).await.is_ok());
+        assert!(PhotoTable::insert(&pool, 1, &photo2).await.is_ok());
+        assert!(PhotoTable::insert(&pool, 2, &Photo::dummy("c3", None)).await.is_err()); // Unknown activity
+
+        let result = PhotoTable::select_for_activity(&pool, 1).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![photo1, photo2]);
+        assert_eq!(PhotoTable::delete_for_activity(&pool, 1).await.unwrap(), 2);
+        assert!(PhotoTable::select_for_activity(&pool, 1).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use log::debug;
+use sqlx::{query, Result, Row};
+use crate::database::db_executor::DbExecutor;
+use crate::database::db_types::DBRow;
+use crate::domain::effort::{Segment, SegmentEffort};
+
+const CREATE_SEGMENT_EFFORT_TABLE : &str =
+    "CREATE TABLE IF NOT EXISTS segment_effort (
+        id INTEGER NOT NULL PRIMARY KEY,
+        activity_id INTEGER NOT NULL REFERENCES activity (id) ON DELETE CASCADE,
+        segment_id INTEGER NOT NULL,
+        segment_name TEXT NOT NULL,
+        name TEXT NOT NULL,
+        start_date TEXT NOT NULL,
+        elapsed_time INTEGER NOT NULL,
+        moving_time INTEGER NOT NULL,
+        distance INTEGER NOT NULL,
+        start_index INTEGER NOT NULL,
+        end_index INTEGER NOT NULL,
+        pr_rank INTEGER,
+        kom_rank INTEGER
+    )";
+
+const CREATE_INDEXES : [&str; 2] = [
+    "CREATE INDEX IF NOT EXISTS segment_effort_activity ON segment_effort (activity_id)",
+    "CREATE INDEX IF NOT EXISTS segment_effort_segment ON segment_effort (segment_id, start_date)"
+];
+
+const INSERT_SEGMENT_EFFORT : &str =
+    "INSERT INTO segment_effort (id, activity_id, segment_id, segment_name, name, start_date, elapsed_time, moving_time, \
+       distance, start_index, end_index, pr_rank, kom_rank) \
+     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+const DELETE_SEGMENT_EFFORTS : &str =
+    "DELETE FROM segment_effort WHERE activity_id = ?";
+
+const SELECT_SEGMENT_EFFORTS : &str =
+    "SELECT e.id, e.activity_id, e.segment_id, e.segment_name, e.name, e.start_date, e.elapsed_time, e.moving_time, \
+       e.distance, e.start_index, e.end_index, e.pr_rank, e.kom_rank \
+     FROM segment_effort e JOIN activity a ON a.id = e.activity_id \
+     WHERE a.athlete_id = ? AND e.segment_id = ? \
+     ORDER BY e.start_date";
+
+pub struct SegmentEffortTable;
+
+#[allow(dead_code)]
+impl SegmentEffortTable {
+    pub async fn create_table<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}", CREATE_SEGMENT_EFFORT_TABLE);
+        query(CREATE_SEGMENT_EFFORT_TABLE).execute(executor).await?;
+        Ok(())
+    }
+
+    pub async fn create_indexes<'e, E>(executor: E) -> Result<()>
+        where E: DbExecutor<'e> + Copy {
+        for sql in CREATE_INDEXES {
+            debug!("Execute\n{}", sql);
+            query(sql).execute(executor).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn insert<'e, E>(executor: E, effort: &SegmentEffort) -> Result<()>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{}\nwith: {:?}", INSERT_SEGMENT_EFFORT, effort);
+        // Floats are stored as int, see ActivityTable
+        query(INSERT_SEGMENT_EFFORT)
+            .bind(effort.id as i64) // sqlx::sqlite cannot encode u64
+            .bind(effort.activity_id as i64)
+            .bind(effort.segment.id as i64)
+            .bind(effort.segment.name.clone())
+            .bind(effort.name.clone())
+            .bind(effort.start_date.clone())
+            .bind(effort.elapsed_time as i64)
+            .bind(effort.moving_time as i64)
+            .bind((effort.distance * 10.0) as i64)
+            .bind(effort.start_index)
+            .bind(effort.end_index)
+            .bind(effort.pr_rank)
+            .bind(effort.kom_rank)
+            .execute(executor)
+            .await
+            .map(|_| ()) // Ignore returned row count
+    }
+
+    /// Deletes all segment efforts of the activity and returns their number
+    pub async fn delete_for_activity<'e, E>(executor: E, activity_id: u64) -> Result<u64>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {}", DELETE_SEGMENT_EFFORTS, activity_id);
+        let result = query(DELETE_SEGMENT_EFFORTS)
+            .bind(activity_id as i64)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns all efforts of the athlete on the segment in chronological order
+    pub async fn select_for_segment<'e, E>(executor: E, athlete_id: u64, segment_id: u64) -> Result<Vec<SegmentEffort>>
+        where E: DbExecutor<'e> {
+        debug!("Execute\n{} with: {} {}", SELECT_SEGMENT_EFFORTS, athlete_id, segment_id);
+        query(SELECT_SEGMENT_EFFORTS)
+            .bind(athlete_id as i64)
+            .bind(segment_id as i64)
+            .map(|row: DBRow| Self::row_to_effort(&row))
+            .fetch_all(executor)
+            .await
+    }
+
+    fn row_to_effort(row: &DBRow) -> SegmentEffort {
+        SegmentEffort {
+            id: row.get::<i64, _>(0) as u64,
+            activity_id: row.get::<i64, _>(1) as u64,
+            segment: Segment { id: row.get::<i64, _>(2) as u64, name: row.get(3) },
+            name: row.get(4),
+            start_date: row.get(5),
+            elapsed_time: row.get::<i64, _>(6) as u64,
+            moving_time: row.get::<i64, _>(7) as u64,
+            distance: row.get::<i64, _>(8) as f32 / 10.0,
+            start_index: row.get(9),
+            end_index: row.get(10),
+            pr_rank: row.get(11),
+            kom_rank: row.get(12)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::activity_table::ActivityTable;
+    use crate::database::db_types::DBPool;
+    use crate::database::segment_effort_table::SegmentEffortTable;
+    use crate::domain::activity::Activity;
+    use crate::domain::effort::SegmentEffort;
+
+    #[tokio::test]
+    async fn test_select_for_segment() {
+        let pool = create_connection_and_tables().await;
+        let mut effort1 = SegmentEffort::dummy(11, 1, 7, "2020-01-02T00:00:00Z", 300);
+        effort1.pr_rank = Some(1);
+        let effort2 = SegmentEffort::dummy(12, 2, 7, "2020-01-01T00:00:00Z", 310);
+        assert!(SegmentEffortTable::insert(&pool, &effort1).await.is_ok());
+        assert!(SegmentEffortTable::insert(&pool, &effort2).await.is_ok());
+        assert!(SegmentEffortTable::insert(&pool, &SegmentEffort::dummy(13, 1, 8, "2020-01-02T00:00:00Z", 100)).await.is_ok());
+        assert!(SegmentEffortTable::insert(&pool, &SegmentEffort::dummy(14, 3, 7, "2020-01-03T00:00:00Z", 200)).await.is_ok());
+
+        let result = SegmentEffortTable::select_for_segment(&pool, Activity::DUMMY_ATHLETE, 7).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![effort2, effort1]); // Effort 14 belongs to another athlete
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let pool = create_connection_and_tables().await;
+        SegmentEffortTable::insert(&pool, &SegmentEffort::dummy(11, 1, 7, "2020-01-02T00:00:00Z", 300)).await.unwrap();
+        SegmentEffortTable::insert(&pool, &SegmentEffort::dummy(12, 2, 7, "2020-01-01T00:00:00Z", 310)).await.unwrap();
+        assert_eq!(SegmentEffortTable::delete_for_activity(&pool, 1).await.unwrap(), 1);
+        assert_eq!(SegmentEffortTable::select_for_segment(&pool, Activity::DUMMY_ATHLETE, 7).await.unwrap().len(), 1);
+    }
+
+    async fn create_connection_and_tables() -> DBPool {
+        let pool = DBPool::connect("sqlite::memory:").await.unwrap();
+        ActivityTable::create_table(&pool).await.unwrap();
+        SegmentEffortTable::create_table(&pool).await.unwrap();
+        SegmentEffortTable::create_indexes(&pool).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(1, "2020-01-02T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy(2, "2020-01-01T00:00:00Z")).await.unwrap();
+        ActivityTable::insert(&pool, &Activity::dummy_for(9, 3, "2020-01-03T00:00:00Z")).await.unwrap();
+        pool
+    }
+}
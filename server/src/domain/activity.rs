@@ -1,8 +1,13 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+/// Athlete id of activities downloaded before multi-athlete support was added
+pub const LEGACY_ATHLETE: u64 = 0;
 
 #[derive(Clone, Deserialize, Debug, PartialEq)]
 pub struct Activity {
     pub id: u64,
+    #[serde(rename = "athlete", deserialize_with = "deserialize_ref_id")]
+    pub athlete_id: u64,
     pub name: String,
     pub sport_type: String,
     pub start_date: String,
@@ -10,22 +15,41 @@ pub struct Activity {
     pub moving_time: u64,
     pub total_elevation_gain: f32,
     pub average_speed: f32,
-    pub kudos_count: u32
+    pub kudos_count: u32,
+    pub comment_count: Option<u32>, // Unknown for activities downloaded by older versions
+    pub total_photo_count: Option<u32>, // Same as comment_count
+    pub gear_id: Option<String> // Bike or shoe, see [crate::domain::gear::Gear]
 }
 
 pub type ActivityVec = Vec<Activity>;
 
+/// Strava delivers references to other objects (like the owner of an activity) as
+/// object "athlete": { "id": 123, "resource_state": 1 }
+pub(crate) fn deserialize_ref_id<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where D: Deserializer<'de> {
+    #[derive(Deserialize)]
+    struct Ref {
+        id: u64
+    }
+    Ok(Ref::deserialize(deserializer)?.id)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::activity::Activity;
 
     impl Activity {
+        /// Athlete of all dummy activities
+        pub const DUMMY_ATHLETE: u64 = 1;
+
         /// Convenience function that takes &str literals
-        pub fn new(id: u64, name: &str, sport_type: &str, start_date: &str, distance: f32,
+        #[allow(clippy::too_many_arguments)]
+        pub fn new(id: u64, athlete_id: u64, name: &str, sport_type: &str, start_date: &str, distance: f32,
                    moving_time: u64, total_elevation_gain: f32, average_speed: f32,
                    kudos_count: u32) -> Self {
             Self {
                 id,
+                athlete_id,
                 name: String::from(name),
                 sport_type: String::from(sport_type),
                 start_date: String::from(start_date),
@@ -33,13 +57,33 @@ mod tests {
                 moving_time,
                 total_elevation_gain,
                 average_speed,
-                kudos_count
+                kudos_count,
+                comment_count: None,
+                total_photo_count: None,
+                gear_id: None
             }
         }
 
         /// Fills most fields with dummy values
         pub fn dummy(id: u64, start_date: &str) -> Self {
-            Self::new(id, "foo", "walk", start_date, 310.4, 1005, 100.9, 3.558, 3)
+            Self::dummy_for(Self::DUMMY_ATHLETE, id, start_date)
+        }
+
+        /// Same as [Activity::dummy], but for a given athlete
+        pub fn dummy_for(athlete_id: u64, id: u64, start_date: &str) -> Self {
+            Self::new(id, athlete_id, "foo", "walk", start_date, 310.4, 1005, 100.9, 3.558, 3)
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"id":5,"athlete":{"id":7,"resource_state":1},"name":"foo","sport_type":"walk",
+            "start_date":"2018-02-20T18:02:13Z","distance":310.4,"moving_time":1005,
+            "total_elevation_gain":100.9,"average_speed":3.558,"kudos_count":3,"type":"Walk","gear_id":"b123"}"#;
+        let result = serde_json::from_str::<Activity>(json);
+        assert!(result.is_ok());
+        let mut expected = Activity::dummy_for(7, 5, "2018-02-20T18:02:13Z");
+        expected.gear_id = Some("b123".to_string());
+        assert_eq!(result.unwrap(), expected);
+    }
+}
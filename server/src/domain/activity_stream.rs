@@ -6,8 +6,10 @@ use geo_types::Point;
 use serde::Deserialize;
 use gpx::{Gpx, GpxVersion, Link, Metadata, read, Track, TrackSegment, Waypoint};
 use iso8601_timestamp::time::OffsetDateTime;
+use crate::domain::lap::Lap;
 use crate::domain::map_tile::MapTile;
 use crate::domain::map_zoom::MapZoom;
+use crate::domain::photo::Photo;
 use crate::util::iso8601::string_to_secs;
 
 // Note: Cannot use geo_types::Point because it expects an object serialization
@@ -51,15 +53,16 @@ impl ActivityStream {
         }
     }
 
+    /// Reads the first track of the GPX file. If the track has several segments (one per lap,
+    /// see [ActivityStream::to_gpx]), they are concatenated.
     pub fn from_gpx<R: Read>(reader: R) -> Result<Self, BoxError> {
         let gpx: Gpx = read(reader)?;
         let track: &Track = &gpx.tracks[0];
-        let segment: &TrackSegment = &track.segments[0];
         let mut coords: Vec<LatLon> = vec![];
         let mut times: Vec<u32> = vec![];
         let mut altitudes: Vec<f64> = vec![];
         let mut start_time: Option<i64> = None;
-        for point in &segment.points {
+        for point in track.segments.iter().flat_map(|segment| &segment.points) {
             coords.push((point.point().y(), point.point().x()));
             altitudes.push(point.elevation.unwrap_or(0.0));
             if let Some(time) = point.time {
@@ -83,13 +86,15 @@ impl ActivityStream {
         Ok(stream)
     }
 
-    pub fn to_gpx<W: Write>(&self, writer: W, activity_id: u64, activity_name: &str, start_time: &str) -> Result<(), BoxError> {
+    /// Writes the stream as GPX track. If laps are given, the track contains one segment per lap.
+    /// Photos with location are added as waypoints linking to the image file (if downloaded).
+    pub fn to_gpx<W: Write>(&self, writer: W, activity_id: u64, activity_name: &str, start_time: &str,
+                            laps: &[Lap], photos: &[Photo]) -> Result<(), BoxError> {
         if self.latlng.data.len() != self.time.data.len() ||
             self.time.data.len() != self.altitude.data.len() {
             return Err("Streams have different lengths".into());
         }
-        // Escape name according to https://stackoverflow.com/questions/21758345/what-are-the-official-xml-reserved-characters
-        let name = activity_name.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;");
+        let name = Self::escape(activity_name);
         let start_time = string_to_secs(start_time);
         let mut points: Vec<Waypoint> = Vec::new();
         for i in 0..self.latlng.data.len() {
@@ -102,9 +107,7 @@ impl ActivityStream {
             point.time = Some(time.into());
             points.push(point);
         }
-        let track_segment = TrackSegment {
-            points
-        };
+        let segments = Self::split_into_laps(points, laps);
         let track = Track {
             name: Some(name.clone()),
             comment: None,
@@ -113,7 +116,7 @@ impl ActivityStream {
             links: vec![],
             type_: None,
             number: None,
-            segments: vec![track_segment]
+            segments
         };
         let metadata = Metadata {
             name: Some(name.clone()),
@@ -133,7 +136,7 @@ impl ActivityStream {
             version: GpxVersion::Gpx11,
             creator: Some("http://strava.com/".to_string()),
             metadata: Some(metadata),
-            waypoints: vec![],
+            waypoints: photos.iter().filter_map(Self::photo_to_waypoint).collect(),
             tracks: vec![track],
             routes: vec![],
         };
@@ -141,6 +144,42 @@ impl ActivityStream {
         Ok(())
     }
 
+    // Escape according to https://stackoverflow.com/questions/21758345/what-are-the-official-xml-reserved-characters
+    fn escape(text: &str) -> String {
+        text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+    }
+
+    fn photo_to_waypoint(photo: &Photo) -> Option<Waypoint> {
+        let (lat, lon) = photo.location?;
+        let mut waypoint = Waypoint::new(Point::new(lon, lat));
+        let name = match photo.caption.as_deref() {
+            Some(caption) if !caption.is_empty() => Self::escape(caption),
+            _ => photo.unique_id.clone()
+        };
+        waypoint.time = photo.created_at.as_deref()
+            .and_then(|time| OffsetDateTime::from_unix_timestamp(string_to_secs(time)).ok())
+            .map(|time| time.into());
+        waypoint.links = photo.file_name.iter()
+            .map(|file_name| Link { href: file_name.clone(), text: Some(name.clone()), type_: Some("image/jpeg".to_string()) })
+            .collect();
+        waypoint.name = Some(name);
+        Some(waypoint)
+    }
+
+    /// Splits the points into one segment per lap. Points not covered by any lap are dropped.
+    /// If there are no laps or the laps do not match the points, a single segment is returned.
+    fn split_into_laps(points: Vec<Waypoint>, laps: &[Lap]) -> Vec<TrackSegment> {
+        let valid = laps.iter().all(|lap| lap.start_index <= lap.end_index && (lap.end_index as usize) < points.len());
+        if laps.is_empty() || !valid {
+            return vec![TrackSegment { points }];
+        }
+        laps.iter()
+            .map(|lap| TrackSegment {
+                points: points[lap.start_index as usize..=lap.end_index as usize].to_vec()
+            })
+            .collect()
+    }
+
     /// Returns the list of unique [MapTile]s touched by this activity stream.
     /// The returned list is sorted and does not contain duplicate tiles.
     pub fn to_tiles(&self, zoom: MapZoom) -> Result<Vec<MapTile>, BoxError> {
@@ -165,8 +204,10 @@ impl fmt::Display for ActivityStream {
 mod tests {
     use std::io::Cursor;
     use crate::domain::activity_stream::ActivityStream;
+    use crate::domain::lap::Lap;
     use crate::domain::map_tile::MapTile;
     use crate::domain::map_zoom::MapZoom;
+    use crate::domain::photo::Photo;
 
     // Activity streams from java have additional fields like "series_type". They are ignored here.
     static STREAM_STR: &str = r#"{
@@ -223,12 +264,51 @@ mod tests {
     fn test_to_gpx() {
         let stream = get_stream();
         let mut buffer: Vec<u8> = Vec::new();
-        assert!(stream.to_gpx(&mut buffer, 12345, "Foo Bar", "2024-01-01T00:00:00Z").is_ok());
+        assert!(stream.to_gpx(&mut buffer, 12345, "Foo Bar", "2024-01-01T00:00:00Z", &[], &[]).is_ok());
         let result = String::from_utf8(buffer);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), GPX_STR);
     }
 
+    #[test]
+    fn test_to_gpx_with_laps() {
+        let stream = get_stream();
+        let laps = vec![Lap::dummy(1, 1, 0, 1), Lap::dummy(2, 2, 2, 2)];
+        let mut buffer: Vec<u8> = Vec::new();
+        assert!(stream.to_gpx(&mut buffer, 12345, "Foo Bar", "2024-01-01T00:00:00Z", &laps, &[]).is_ok());
+        let gpx = String::from_utf8(buffer).unwrap();
+        assert_eq!(gpx.matches("<trkseg>").count(), 2);
+
+        // Reading concatenates the segments again
+        let result = ActivityStream::from_gpx(Cursor::new(gpx.as_bytes()));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), stream);
+    }
+
+    #[test]
+    fn test_to_gpx_with_photos() {
+        let stream = get_stream();
+        let mut photo1 = Photo::dummy("a1", Some((51.3, 12.3)));
+        photo1.file_name = Some("12345/a1.jpg".to_string());
+        let photo2 = Photo::dummy("b2", None); // Without location, so no waypoint
+        let mut buffer: Vec<u8> = Vec::new();
+        assert!(stream.to_gpx(&mut buffer, 12345, "Foo Bar", "2024-01-01T00:00:00Z", &[], &[photo1, photo2]).is_ok());
+        let gpx = String::from_utf8(buffer).unwrap();
+        assert_eq!(gpx.matches("<wpt ").count(), 1);
+        assert!(gpx.contains(r#"<wpt lat="51.3" lon="12.3">"#));
+        assert!(gpx.contains(r#"<link href="12345/a1.jpg">"#));
+        assert!(gpx.contains("<name>Photo a1</name>"));
+    }
+
+    #[test]
+    fn test_to_gpx_with_invalid_laps() {
+        let stream = get_stream();
+        let laps = vec![Lap::dummy(1, 1, 0, 3)]; // End index out of range
+        let mut buffer: Vec<u8> = Vec::new();
+        assert!(stream.to_gpx(&mut buffer, 12345, "Foo Bar", "2024-01-01T00:00:00Z", &laps, &[]).is_ok());
+        assert_eq!(String::from_utf8(buffer).unwrap(), GPX_STR);
+    }
+
     #[test]
     fn test_from_gpx() {
         let reader = Cursor::new(GPX_STR.as_bytes());
@@ -0,0 +1,52 @@
+use serde::Deserialize;
+use crate::domain::gear::Gear;
+
+/// The profile of an athlete as returned by Strava https://developers.strava.com/docs/reference/#api-Athletes-getLoggedInAthlete.
+/// The gear lists only contain summaries, the details are fetched separately.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct Athlete {
+    pub id: u64,
+    pub username: Option<String>,
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+    pub sex: Option<String>,
+    pub weight: Option<f32>,
+    pub ftp: Option<u32>,
+    pub measurement_preference: Option<String>,
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub bikes: Vec<Gear>,
+    #[serde(default)]
+    pub shoes: Vec<Gear>
+}
+
+impl Athlete {
+    /// Returns the ids of all bikes and shoes
+    pub fn gear_ids(&self) -> Vec<String> {
+        self.bikes.iter().chain(self.shoes.iter()).map(|g| g.id.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::athlete::Athlete;
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"id":4711,"username":"foo","resource_state":3,"firstname":"Foo","lastname":"Bar",
+            "city":"Leipzig","state":"Sachsen","country":"Germany","sex":"M","weight":70.5,"ftp":null,
+            "created_at":"2010-04-01T15:13:08Z","measurement_preference":"meters",
+            "bikes":[{"id":"b123","primary":true,"name":"Bike","resource_state":2,"distance":12345.6}],
+            "shoes":[{"id":"g456","primary":false,"name":"Shoe","resource_state":2,"distance":678.9}]}"#;
+        let result = serde_json::from_str::<Athlete>(json);
+        assert!(result.is_ok());
+        let athlete = result.unwrap();
+        assert_eq!(athlete.id, 4711);
+        assert_eq!(athlete.firstname, Some("Foo".to_string()));
+        assert_eq!(athlete.ftp, None);
+        assert_eq!(athlete.gear_ids(), vec!["b123".to_string(), "g456".to_string()]);
+    }
+}
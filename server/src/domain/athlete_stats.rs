@@ -0,0 +1,54 @@
+use serde::Deserialize;
+
+/// Totals of one sport for one period
+#[derive(Clone, Default, Deserialize, Debug, PartialEq)]
+pub struct ActivityTotal {
+    pub count: u32,
+    pub distance: f32,
+    pub moving_time: u64,
+    pub elapsed_time: u64,
+    pub elevation_gain: f32
+}
+
+/// Statistics of an athlete as returned by Strava https://developers.strava.com/docs/reference/#api-Athletes-getStats.
+/// Not to be confused with [crate::domain::activity_stats::ActivityStats], which is computed from the database.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct AthleteStats {
+    pub biggest_ride_distance: Option<f32>,
+    pub biggest_climb_elevation_gain: Option<f32>,
+    #[serde(default)]
+    pub recent_ride_totals: ActivityTotal,
+    #[serde(default)]
+    pub recent_run_totals: ActivityTotal,
+    #[serde(default)]
+    pub recent_swim_totals: ActivityTotal,
+    #[serde(default)]
+    pub ytd_ride_totals: ActivityTotal,
+    #[serde(default)]
+    pub ytd_run_totals: ActivityTotal,
+    #[serde(default)]
+    pub ytd_swim_totals: ActivityTotal,
+    #[serde(default)]
+    pub all_ride_totals: ActivityTotal,
+    #[serde(default)]
+    pub all_run_totals: ActivityTotal,
+    #[serde(default)]
+    pub all_swim_totals: ActivityTotal
+}
+
+impl AthleteStats {
+    /// Returns all totals keyed by period ("recent", "ytd", "all") and sport ("ride", "run", "swim")
+    pub fn totals(&self) -> Vec<(&'static str, &'static str, &ActivityTotal)> {
+        vec![
+            ("recent", "ride", &self.recent_ride_totals),
+            ("recent", "run", &self.recent_run_totals),
+            ("recent", "swim", &self.recent_swim_totals),
+            ("ytd", "ride", &self.ytd_ride_totals),
+            ("ytd", "run", &self.ytd_run_totals),
+            ("ytd", "swim", &self.ytd_swim_totals),
+            ("all", "ride", &self.all_ride_totals),
+            ("all", "run", &self.all_run_totals),
+            ("all", "swim", &self.all_swim_totals)
+        ]
+    }
+}
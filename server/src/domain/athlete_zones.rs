@@ -0,0 +1,34 @@
+use serde::Deserialize;
+
+/// A single zone, where `max` is -1 for the open-ended top zone
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct ZoneRange {
+    pub min: i32,
+    pub max: i32
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct ZoneRanges {
+    pub zones: Vec<ZoneRange>
+}
+
+/// Heart-rate and power zones as returned by Strava https://developers.strava.com/docs/reference/#api-Athletes-getLoggedInAthleteZones
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct AthleteZones {
+    pub heart_rate: Option<ZoneRanges>,
+    pub power: Option<ZoneRanges>
+}
+
+impl AthleteZones {
+    /// Returns the zones keyed by zone type ("heart_rate" or "power")
+    pub fn by_type(&self) -> Vec<(&'static str, &ZoneRanges)> {
+        let mut zones = Vec::new();
+        if let Some(heart_rate) = self.heart_rate.as_ref() {
+            zones.push(("heart_rate", heart_rate));
+        }
+        if let Some(power) = self.power.as_ref() {
+            zones.push(("power", power));
+        }
+        zones
+    }
+}
@@ -0,0 +1,63 @@
+use serde::Serialize;
+
+/// Kind of an action of the download scheduler, see [DownloadEvent]
+#[derive(Clone, Copy, Serialize, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    PageFetched,
+    ActivitiesAdded,
+    TrackStored,
+    TrackMissing,
+    RateLimited,
+    Error
+}
+
+impl EventKind {
+    pub const VALUES: [EventKind; 6] = [EventKind::PageFetched, EventKind::ActivitiesAdded, EventKind::TrackStored,
+        EventKind::TrackMissing, EventKind::RateLimited, EventKind::Error];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::PageFetched => "PageFetched",
+            EventKind::ActivitiesAdded => "ActivitiesAdded",
+            EventKind::TrackStored => "TrackStored",
+            EventKind::TrackMissing => "TrackMissing",
+            EventKind::RateLimited => "RateLimited",
+            EventKind::Error => "Error"
+        }
+    }
+}
+
+impl TryFrom<&str> for EventKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, String> {
+        EventKind::VALUES.into_iter()
+            .find(|kind| kind.name() == value)
+            .ok_or_else(|| format!("Invalid event kind {value}"))
+    }
+}
+
+/// Entry of the persistent log of the download scheduler, see [crate::database::download_event_table::DownloadEventTable]
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct DownloadEvent {
+    pub id: u64,
+    pub athlete_id: u64,
+    pub time: String, // ISO 8601
+    pub kind: EventKind,
+    pub activity_id: Option<u64>,
+    pub details: String
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::download_event::EventKind;
+
+    #[test]
+    fn test_event_kind_names() {
+        for kind in EventKind::VALUES {
+            assert_eq!(EventKind::try_from(kind.name()), Ok(kind));
+            assert_eq!(serde_json::to_string(&kind).unwrap(), format!("\"{}\"", kind.name()));
+        }
+        assert!(EventKind::try_from("Foo").is_err());
+    }
+}
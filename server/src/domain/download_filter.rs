@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use crate::domain::activity::Activity;
+use crate::util::iso8601;
+
+/// Restricts the downloads to activities in a time window and of certain sport types.
+/// The activity list contains only activities of the time window, but of all sport types.
+/// The tracks and other details are downloaded only for activities matching the filter.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DownloadFilter {
+    #[serde(default)]
+    pub after: Option<String>,    // Inclusive start of the time window (ISO 8601)
+    #[serde(default)]
+    pub before: Option<String>,   // Exclusive end of the time window (ISO 8601)
+    #[serde(default)]
+    pub sport_types: Vec<String>  // Empty for all sport types
+}
+
+impl DownloadFilter {
+    /// Validates the timestamps and converts them to the format of [Activity::start_date],
+    /// so they can be compared with the start dates stored in the database.
+    pub fn normalized(self) -> Result<Self, String> {
+        Ok(Self {
+            after: Self::normalize(self.after)?,
+            before: Self::normalize(self.before)?,
+            sport_types: self.sport_types
+        })
+    }
+
+    fn normalize(timestamp: Option<String>) -> Result<Option<String>, String> {
+        timestamp.map(|str| iso8601::try_string_to_secs(&str)
+                .map(iso8601::secs_to_string)
+                .ok_or(format!("Invalid timestamp '{str}'")))
+            .transpose()
+    }
+
+    pub fn after_secs(&self) -> Option<i64> {
+        self.after.as_deref().and_then(iso8601::try_string_to_secs)
+    }
+
+    pub fn before_secs(&self) -> Option<i64> {
+        self.before.as_deref().and_then(iso8601::try_string_to_secs)
+    }
+
+    /// Returns the sport types as JSON array to be passed to SQLite's json_each
+    pub fn sport_types_json(&self) -> String {
+        serde_json::to_string(&self.sport_types).unwrap_or("[]".to_string())
+    }
+
+    pub fn matches(&self, activity: &Activity) -> bool {
+        self.after.as_ref().is_none_or(|after| activity.start_date >= *after) &&
+            self.before.as_ref().is_none_or(|before| activity.start_date < *before) &&
+            (self.sport_types.is_empty() || self.sport_types.contains(&activity.sport_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::activity::Activity;
+    use crate::domain::download_filter::DownloadFilter;
+
+    impl DownloadFilter {
+        pub fn new(after: Option<&str>, before: Option<&str>, sport_types: &[&str]) -> Self {
+            Self {
+                after: after.map(str::to_string),
+                before: before.map(str::to_string),
+                sport_types: sport_types.iter().map(|s| s.to_string()).collect()
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalized() {
+        let filter = DownloadFilter::new(Some("2018-01-01T00:00:00+01:00"), None, &["Ride"]).normalized();
+        assert_eq!(filter, Ok(DownloadFilter::new(Some("2017-12-31T23:00:00Z"), None, &["Ride"])));
+        assert_eq!(filter.unwrap().after_secs(), Some(1514761200));
+        assert!(DownloadFilter::new(None, Some("yesterday"), &[]).normalized().is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let filter = serde_json::from_str::<DownloadFilter>(r#"{"sport_types":["Run"]}"#);
+        assert!(filter.is_ok());
+        assert_eq!(filter.unwrap(), DownloadFilter::new(None, None, &["Run"]));
+        assert_eq!(DownloadFilter::new(None, None, &["Run", "Ride"]).sport_types_json(), r#"["Run","Ride"]"#);
+    }
+
+    #[test]
+    fn test_matches() {
+        let activity = Activity::dummy(1, "2018-02-20T18:02:13Z"); // A walk
+        assert!(DownloadFilter::default().matches(&activity));
+        assert!(DownloadFilter::new(Some("2018-02-20T18:02:13Z"), Some("2019-01-01T00:00:00Z"), &["walk"]).matches(&activity));
+        assert!(!DownloadFilter::new(None, Some("2018-02-20T18:02:13Z"), &[]).matches(&activity));
+        assert!(!DownloadFilter::new(None, None, &["Ride"]).matches(&activity));
+    }
+}
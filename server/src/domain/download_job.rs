@@ -0,0 +1,88 @@
+use serde::Serialize;
+use crate::domain::download_state::DownloadState;
+
+/// Priority of a track job enqueued on demand, above all job types, so it is executed next
+pub const REFETCH_PRIORITY: i32 = 100;
+
+/// Kind of a per-activity download job. The jobs of the highest priority are executed first,
+/// so all tracks are downloaded before the laps, and the comments and kudoers come last.
+#[derive(Clone, Copy, Serialize, Debug, Eq, PartialEq)]
+pub enum JobType {
+    Track,
+    Laps,
+    Efforts,
+    Photos,
+    Social
+}
+
+impl JobType {
+    pub const VALUES: [JobType; 5] = [JobType::Track, JobType::Laps, JobType::Efforts, JobType::Photos, JobType::Social];
+
+    pub fn priority(&self) -> i32 {
+        match self {
+            JobType::Track => 50,
+            JobType::Laps => 40,
+            JobType::Efforts => 30,
+            JobType::Photos => 20,
+            JobType::Social => 10
+        }
+    }
+
+    /// Download state shown while a job of this type is executed
+    pub fn download_state(&self) -> DownloadState {
+        match self {
+            JobType::Track => DownloadState::Tracks,
+            JobType::Laps => DownloadState::Laps,
+            JobType::Efforts => DownloadState::Efforts,
+            JobType::Photos => DownloadState::Photos,
+            JobType::Social => DownloadState::Social
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobType::Track => "Track",
+            JobType::Laps => "Laps",
+            JobType::Efforts => "Efforts",
+            JobType::Photos => "Photos",
+            JobType::Social => "Social"
+        }
+    }
+}
+
+impl TryFrom<&str> for JobType {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        JobType::VALUES.into_iter()
+            .find(|job_type| job_type.name() == value)
+            .ok_or_else(|| format!("Invalid job type {value}"))
+    }
+}
+
+/// Persistent download job, see [crate::database::download_job_table::DownloadJobTable]
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub job_type: JobType,
+    pub athlete_id: u64,
+    pub activity_id: u64,
+    pub priority: i32,
+    pub attempts: u32,
+    pub next_run_at: i64, // Seconds since epoch
+    pub last_error: Option<String>,
+    pub poisoned: bool // Failed permanently, not executed anymore
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::download_job::JobType;
+
+    #[test]
+    fn test_job_type_names() {
+        for job_type in JobType::VALUES {
+            assert_eq!(JobType::try_from(job_type.name()), Ok(job_type));
+        }
+        assert!(JobType::try_from("Foo").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use serde::Serialize;
+use crate::strava::rate_limit::RateLimit;
+
+/// Activity of the download job being processed
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct CurrentActivity {
+    pub id: u64,
+    pub name: String,
+    pub start_date: String,
+    pub job: String // Type of the download job, like "Track"
+}
+
+/// Latest failure of a download task of an athlete
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct DownloadError {
+    pub message: String,
+    pub time: String // ISO 8601
+}
+
+/// Live progress of the download of an athlete, part of the [crate::domain::server_status::ServerStatus].
+/// All times are ISO 8601 and only given while downloading.
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct DownloadProgress {
+    pub current_activity: Option<CurrentActivity>,
+    pub remaining_tracks: u64, // Activities matching the download filter without downloaded track
+    pub missing_tracks: u64, // Activities without track at Strava
+    pub eta: Option<String>, // Estimated end of the track download, based on the request period
+    pub last_error: Option<DownloadError>,
+    pub next_request: Option<String>,
+    pub rate_limit: Option<RateLimit> // As of the latest Strava response
+}
@@ -0,0 +1,214 @@
+/// Cron-like schedule with the five fields minute (0-59), hour (0-23), day of month (1-31),
+/// month (1-12), and day of week (0-7, with 0 and 7 meaning Sunday). Each field is either `*`
+/// or a comma-separated list of values and ranges (like `1-5`), optionally followed by a step
+/// (like `*/15`). As in cron, a time matches if the day of month *or* the day of week matches
+/// when both are restricted. All times are UTC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64, // Bit masks of the matching values
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool
+}
+
+const SECS_PER_MINUTE: i64 = 60;
+const SECS_PER_DAY: i64 = 24 * 60 * SECS_PER_MINUTE;
+const SEARCH_DAYS: i64 = 4 * 366; // Covers February 29th
+
+impl CronSchedule {
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Returns the first matching time (in seconds since epoch) after the given time
+    pub fn next_after(&self, secs: i64) -> Option<i64> {
+        let mut time = (secs.div_euclid(SECS_PER_MINUTE) + 1) * SECS_PER_MINUTE;
+        let end = time + SEARCH_DAYS * SECS_PER_DAY;
+        while time < end {
+            let day_start = time - time.rem_euclid(SECS_PER_DAY);
+            if !self.matches_day(day_start / SECS_PER_DAY) {
+                time = day_start + SECS_PER_DAY;
+                continue
+            }
+            let minute_of_day = (time - day_start) / SECS_PER_MINUTE;
+            if !Self::contains(self.hours, minute_of_day / 60) {
+                time = day_start + (minute_of_day / 60 + 1) * 60 * SECS_PER_MINUTE;
+                continue
+            }
+            if Self::contains(self.minutes, minute_of_day % 60) {
+                return Some(time)
+            }
+            time += SECS_PER_MINUTE;
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: i64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        let weekday = (days_since_epoch + 4).rem_euclid(7); // 1970-01-01 was a Thursday
+        let day_matches = Self::contains(self.days, day);
+        let weekday_matches = Self::contains(self.weekdays, weekday);
+        let day_or_weekday = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches
+        };
+        Self::contains(self.months, month) && day_or_weekday
+    }
+
+    fn contains(mask: u64, value: i64) -> bool {
+        mask & (1 << value) != 0
+    }
+
+    fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+        let mut mask = 0_u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)
+                    .ok_or(format!("Invalid step in '{part}'"))?),
+                None => (part, 1)
+            };
+            let (first, last) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((first, last)) => (Self::parse_value(first, min, max)?, Self::parse_value(last, min, max)?),
+                    None if part.contains('/') => (Self::parse_value(range, min, max)?, max),
+                    None => {
+                        let value = Self::parse_value(range, min, max)?;
+                        (value, value)
+                    }
+                }
+            };
+            if first > last {
+                return Err(format!("Invalid range '{range}'"))
+            }
+            for value in (first..=last).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        Ok(mask)
+    }
+
+    fn parse_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+        value.parse::<u32>().ok()
+            .filter(|v| (min..=max).contains(v))
+            .ok_or(format!("Value '{value}' not in {min}-{max}"))
+    }
+}
+
+impl TryFrom<&str> for CronSchedule {
+    type Error = String;
+
+    fn try_from(expression: &str) -> Result<Self, Self::Error> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Cron expression '{expression}' must have 5 fields"))
+        };
+        let weekday_mask = Self::parse_field(weekdays, 0, 7)?;
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: Self::parse_field(minutes, 0, 59)?,
+            hours: Self::parse_field(hours, 0, 23)?,
+            days: Self::parse_field(days, 1, 31)?,
+            months: Self::parse_field(months, 1, 12)?,
+            weekdays: (weekday_mask | weekday_mask >> 7) & 0x7f, // Sunday may be given as 7
+            any_day: days == "*",
+            any_weekday: weekdays == "*"
+        })
+    }
+}
+
+/// Converts days since epoch to year, month (1-12), and day (1-31) of the proleptic Gregorian calendar,
+/// see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Starts downloads automatically according to a [CronSchedule]. A scheduled download stops
+/// after the given number of requests (if any), see [crate::state::shared_state::SharedState].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadSchedule {
+    pub cron: CronSchedule,
+    pub max_requests: Option<u32>,
+    pub next_run: Option<i64> // Seconds since epoch
+}
+
+impl DownloadSchedule {
+    pub fn new(cron: CronSchedule, max_requests: Option<u32>, now: i64) -> Self {
+        let next_run = cron.next_after(now);
+        Self { cron, max_requests, next_run }
+    }
+
+    /// Returns true if the next run is due, and advances the next run then
+    pub fn take_due_run(&mut self, now: i64) -> bool {
+        match self.next_run {
+            Some(next_run) if next_run <= now => {
+                self.next_run = self.cron.next_after(now);
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::download_schedule::{civil_from_days, CronSchedule, DownloadSchedule};
+    use crate::util::iso8601::{secs_to_string, string_to_secs};
+
+    fn next_after(expression: &str, time: &str) -> Option<String> {
+        let cron = CronSchedule::try_from(expression).unwrap();
+        cron.next_after(string_to_secs(time)).map(secs_to_string)
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(CronSchedule::try_from("0 3 * * *").is_ok());
+        assert!(CronSchedule::try_from("*/15 1-5,22 1 */2 1-7").is_ok());
+        assert!(CronSchedule::try_from("0 3 * *").is_err());
+        assert!(CronSchedule::try_from("60 3 * * *").is_err());
+        assert!(CronSchedule::try_from("0 5-3 * * *").is_err());
+        assert!(CronSchedule::try_from("*/0 3 * * *").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(next_after("0 3 * * *", "2024-03-01T02:59:59Z"), Some("2024-03-01T03:00:00Z".to_string()));
+        assert_eq!(next_after("0 3 * * *", "2024-03-01T03:00:00Z"), Some("2024-03-02T03:00:00Z".to_string()));
+        assert_eq!(next_after("*/20 * * * *", "2024-03-01T10:41:00Z"), Some("2024-03-01T11:00:00Z".to_string()));
+        assert_eq!(next_after("30 22 * * 0", "2024-03-01T00:00:00Z"), Some("2024-03-03T22:30:00Z".to_string())); // Sunday
+        assert_eq!(next_after("30 22 * * 7", "2024-03-01T00:00:00Z"), Some("2024-03-03T22:30:00Z".to_string()));
+        assert_eq!(next_after("0 0 29 2 *", "2024-03-01T00:00:00Z"), Some("2028-02-29T00:00:00Z".to_string()));
+        assert_eq!(next_after("0 0 15 * 1", "2024-03-01T00:00:00Z"), Some("2024-03-04T00:00:00Z".to_string())); // Monday or 15th
+        assert_eq!(next_after("0 0 31 2 *", "2024-03-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn test_take_due_run() {
+        let cron = CronSchedule::try_from("0 3 * * *").unwrap();
+        let mut schedule = DownloadSchedule::new(cron, Some(100), string_to_secs("2024-03-01T00:00:00Z"));
+        assert_eq!(schedule.next_run, Some(string_to_secs("2024-03-01T03:00:00Z")));
+        assert!(!schedule.take_due_run(string_to_secs("2024-03-01T02:59:59Z")));
+        assert!(schedule.take_due_run(string_to_secs("2024-03-01T03:00:01Z")));
+        assert_eq!(schedule.next_run, Some(string_to_secs("2024-03-02T03:00:00Z")));
+    }
+}
@@ -1,44 +1,171 @@
 use serde::{Deserialize, Serialize};
 use crate::domain::download_delay::DownloadDelay;
 
-#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub enum DownloadState {
+    #[default]
     Inactive,     // Downloading was not started or manually stopped
     NoResults,    // Last Strava API request returned no results
     LimitReached, // Strava API rate limit was reached
     RequestError, // An error returned by the Strava API
+    Unauthorized, // The athlete revoked the access or logged out
+    Profile,      // Athlete profile, zones, stats and gear download ongoing
     Activities,   // Activity download ongoing
-    Tracks        // Track (=activity stream) download ongoing
+    Tracks,       // Track (=activity stream) download ongoing
+    Laps,         // Lap download ongoing
+    Efforts,      // Segment and best effort download ongoing
+    Photos,       // Photo download ongoing (optional)
+    Social        // Comment and kudoer download ongoing
 }
 
 impl DownloadState {
+    pub const VALUES: [DownloadState; 12] = [DownloadState::Inactive, DownloadState::NoResults, DownloadState::LimitReached,
+        DownloadState::RequestError, DownloadState::Unauthorized, DownloadState::Profile, DownloadState::Activities,
+        DownloadState::Tracks, DownloadState::Laps, DownloadState::Efforts, DownloadState::Photos, DownloadState::Social];
+
+    /// Name of the state as stored in the database (and returned by the REST API)
+    pub fn name(&self) -> &'static str {
+        match self {
+            DownloadState::Inactive => "Inactive",
+            DownloadState::NoResults => "NoResults",
+            DownloadState::LimitReached => "LimitReached",
+            DownloadState::RequestError => "RequestError",
+            DownloadState::Unauthorized => "Unauthorized",
+            DownloadState::Profile => "Profile",
+            DownloadState::Activities => "Activities",
+            DownloadState::Tracks => "Tracks",
+            DownloadState::Laps => "Laps",
+            DownloadState::Efforts => "Efforts",
+            DownloadState::Photos => "Photos",
+            DownloadState::Social => "Social"
+        }
+    }
+
     pub fn is_active(&self) -> bool {
         match self {
             DownloadState::Inactive => false,
             DownloadState::NoResults => false,
             DownloadState::LimitReached => false,
             DownloadState::RequestError => false,
+            DownloadState::Unauthorized => false,
+            DownloadState::Profile => true,
             DownloadState::Activities => true,
-            DownloadState::Tracks => true
+            DownloadState::Tracks => true,
+            DownloadState::Laps => true,
+            DownloadState::Efforts => true,
+            DownloadState::Photos => true,
+            DownloadState::Social => true
         }
     }
 
     /// Manual toggling
     pub fn toggle(&self) -> Self {
         match self {
-            DownloadState::Inactive => DownloadState::Activities,
-            DownloadState::NoResults => DownloadState::Activities,
-            DownloadState::LimitReached => DownloadState::Activities,
-            DownloadState::RequestError => DownloadState::Activities,
+            DownloadState::Inactive => DownloadState::Profile,
+            DownloadState::NoResults => DownloadState::Profile,
+            DownloadState::LimitReached => DownloadState::Profile,
+            DownloadState::RequestError => DownloadState::Profile,
+            DownloadState::Unauthorized => DownloadState::Profile,
+            DownloadState::Profile => DownloadState::Inactive,
             DownloadState::Activities => DownloadState::Inactive,
-            DownloadState::Tracks => DownloadState::Inactive
+            DownloadState::Tracks => DownloadState::Inactive,
+            DownloadState::Laps => DownloadState::Inactive,
+            DownloadState::Efforts => DownloadState::Inactive,
+            DownloadState::Photos => DownloadState::Inactive,
+            DownloadState::Social => DownloadState::Inactive
+        }
+    }
+
+    /// Manual stopping, which keeps the states that are not downloading
+    pub fn stop(&self) -> Self {
+        match self.is_active() {
+            true => DownloadState::Inactive,
+            false => self.clone()
         }
     }
 
+    /// Returns true for the phases executed by the download job queue
+    pub fn is_job_phase(&self) -> bool {
+        matches!(self, DownloadState::Tracks | DownloadState::Laps | DownloadState::Efforts |
+            DownloadState::Photos | DownloadState::Social)
+    }
+
+    /// Returns the delay before the next task. Switching between the job phases is no phase completion,
+    /// as every job sends a request (like a track refetch in the middle of the laps).
     pub fn new_delay(&self, new_state: &DownloadState) -> DownloadDelay {
-        match (*new_state == DownloadState::Activities || *new_state == DownloadState::Tracks) && new_state == self {
+        let downloading = new_state.is_active();
+        match downloading && (new_state == self || (self.is_job_phase() && new_state.is_job_phase())) {
             true => DownloadDelay::Long,
             false => DownloadDelay::Short
         }
     }
 }
+
+impl TryFrom<&str> for DownloadState {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        DownloadState::VALUES.into_iter()
+            .find(|state| state.name() == value)
+            .ok_or_else(|| format!("Invalid download state {value}"))
+    }
+}
+
+/// Phase in which a download is started manually, see [crate::state::shared_state::SharedState::start_download]
+#[derive(Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartPhase {
+    #[default]
+    Profile,    // Complete download, starting with the athlete profile
+    Activities, // Skips the profile
+    Tracks      // Skips the profile and the activity list, downloads the missing data of the known activities
+}
+
+impl StartPhase {
+    pub fn download_state(&self) -> DownloadState {
+        match self {
+            StartPhase::Profile => DownloadState::Profile,
+            StartPhase::Activities => DownloadState::Activities,
+            StartPhase::Tracks => DownloadState::Tracks
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::download_delay::DownloadDelay;
+    use crate::domain::download_state::{DownloadState, StartPhase};
+
+    #[test]
+    fn test_names() {
+        for state in DownloadState::VALUES {
+            assert_eq!(DownloadState::try_from(state.name()), Ok(state.clone()));
+            assert_eq!(serde_json::to_string(&state).unwrap(), format!("\"{}\"", state.name()));
+        }
+        assert!(DownloadState::try_from("Foo").is_err());
+    }
+
+    #[test]
+    fn test_stop() {
+        assert_eq!(DownloadState::Tracks.stop(), DownloadState::Inactive);
+        assert_eq!(DownloadState::Inactive.stop(), DownloadState::Inactive);
+        assert_eq!(DownloadState::LimitReached.stop(), DownloadState::LimitReached);
+    }
+
+    #[test]
+    fn test_new_delay() {
+        assert_eq!(DownloadState::Activities.new_delay(&DownloadState::Activities), DownloadDelay::Long);
+        assert_eq!(DownloadState::Activities.new_delay(&DownloadState::Tracks), DownloadDelay::Short);
+        assert_eq!(DownloadState::Laps.new_delay(&DownloadState::Tracks), DownloadDelay::Long);
+        assert_eq!(DownloadState::Tracks.new_delay(&DownloadState::Laps), DownloadDelay::Long);
+        assert_eq!(DownloadState::Social.new_delay(&DownloadState::NoResults), DownloadDelay::Short);
+        assert_eq!(DownloadState::Inactive.new_delay(&DownloadState::Inactive), DownloadDelay::Short);
+    }
+
+    #[test]
+    fn test_start_phase() {
+        assert_eq!(serde_json::from_str::<StartPhase>("\"tracks\"").unwrap().download_state(), DownloadState::Tracks);
+        assert_eq!(StartPhase::default().download_state(), DownloadState::Profile);
+        assert!(serde_json::from_str::<StartPhase>("\"laps\"").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use crate::domain::activity::deserialize_ref_id;
+
+/// The segment an effort was done on (only the fields needed here)
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Segment {
+    pub id: u64,
+    pub name: String
+}
+
+/// An effort on a segment, see https://developers.strava.com/docs/reference/#api-models-DetailedSegmentEffort.
+/// Start and end index refer to the points of the [crate::domain::activity_stream::ActivityStream].
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct SegmentEffort {
+    pub id: u64,
+    #[serde(rename(deserialize = "activity"), deserialize_with = "deserialize_ref_id")]
+    pub activity_id: u64,
+    pub segment: Segment,
+    pub name: String,
+    pub start_date: String,
+    pub elapsed_time: u64,
+    pub moving_time: u64,
+    pub distance: f32,
+    pub start_index: u32,
+    pub end_index: u32,
+    pub pr_rank: Option<u32>, // 1 means personal record
+    pub kom_rank: Option<u32>
+}
+
+/// The fastest time of an activity for a standard distance like "5k", see
+/// https://developers.strava.com/docs/reference/#api-models-DetailedActivity.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct BestEffort {
+    pub id: u64,
+    #[serde(rename(deserialize = "activity"), deserialize_with = "deserialize_ref_id")]
+    pub activity_id: u64,
+    pub name: String,
+    pub start_date: String,
+    pub elapsed_time: u64,
+    pub moving_time: u64,
+    pub distance: f32,
+    pub start_index: u32,
+    pub end_index: u32,
+    pub pr_rank: Option<u32>
+}
+
+/// The efforts contained in a detailed activity as returned by Strava
+/// https://developers.strava.com/docs/reference/#api-Activities-getActivityById
+#[derive(Clone, Default, Deserialize, Debug, PartialEq)]
+pub struct ActivityEfforts {
+    #[serde(default)]
+    pub segment_efforts: Vec<SegmentEffort>,
+    #[serde(default)]
+    pub best_efforts: Vec<BestEffort> // Runs only
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::effort::{ActivityEfforts, BestEffort, Segment, SegmentEffort};
+
+    impl SegmentEffort {
+        pub fn dummy(id: u64, activity_id: u64, segment_id: u64, start_date: &str, elapsed_time: u64) -> Self {
+            Self {
+                id,
+                activity_id,
+                segment: Segment { id: segment_id, name: format!("Segment {segment_id}") },
+                name: format!("Segment {segment_id}"),
+                start_date: start_date.to_string(),
+                elapsed_time,
+                moving_time: elapsed_time,
+                distance: 1234.5,
+                start_index: 0,
+                end_index: 9,
+                pr_rank: None,
+                kom_rank: None
+            }
+        }
+    }
+
+    impl BestEffort {
+        pub fn dummy(id: u64, activity_id: u64, name: &str, start_date: &str, elapsed_time: u64) -> Self {
+            Self {
+                id,
+                activity_id,
+                name: name.to_string(),
+                start_date: start_date.to_string(),
+                elapsed_time,
+                moving_time: elapsed_time,
+                distance: 5000.0,
+                start_index: 0,
+                end_index: 9,
+                pr_rank: None
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"id":1,"name":"Morning Run","segment_efforts":[{"id":11,"resource_state":2,"name":"Segment 7",
+            "activity":{"id":1,"resource_state":1},"athlete":{"id":4711,"resource_state":1},"elapsed_time":300,
+            "moving_time":300,"start_date":"2020-01-01T00:00:00Z","distance":1234.5,"start_index":0,"end_index":9,
+            "segment":{"id":7,"resource_state":2,"name":"Segment 7","activity_type":"Run"},"pr_rank":null,"kom_rank":null,
+            "hidden":false}],"best_efforts":[{"id":21,"resource_state":2,"name":"5k","activity":{"id":1,"resource_state":1},
+            "athlete":{"id":4711,"resource_state":1},"elapsed_time":1500,"moving_time":1500,"start_date":"2020-01-01T00:00:00Z",
+            "distance":5000,"start_index":0,"end_index":9,"pr_rank":null}]}"#;
+        let result = serde_json::from_str::<ActivityEfforts>(json);
+        assert!(result.is_ok());
+        let efforts = result.unwrap();
+        assert_eq!(efforts.segment_efforts, vec![SegmentEffort::dummy(11, 1, 7, "2020-01-01T00:00:00Z", 300)]);
+        assert_eq!(efforts.best_efforts, vec![BestEffort::dummy(21, 1, "5k", "2020-01-01T00:00:00Z", 1500)]);
+    }
+
+    #[test]
+    fn test_deserialize_without_efforts() {
+        let result = serde_json::from_str::<ActivityEfforts>(r#"{"id":1,"name":"Manual Ride"}"#);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), ActivityEfforts::default());
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// A bike or shoe as returned by Strava https://developers.strava.com/docs/reference/#api-Gears-getGearById.
+/// The gear summaries embedded in the athlete profile only contain id, name, distance and primary flag.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Gear {
+    pub id: String,
+    pub name: String,
+    pub distance: f32,
+    #[serde(default)]
+    pub primary: bool,
+    #[serde(default)]
+    pub retired: bool,
+    pub brand_name: Option<String>,
+    pub model_name: Option<String>,
+    pub description: Option<String>
+}
+
+impl Gear {
+    /// Strava prefixes the ids of bikes with 'b' and the ids of shoes with 'g'
+    pub fn gear_type(&self) -> &'static str {
+        match self.id.starts_with('b') {
+            true => "bike",
+            false => "shoe"
+        }
+    }
+}
+
+/// A gear plus the number and distance of the downloaded activities done with it
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct GearUsage {
+    #[serde(flatten)]
+    pub gear: Gear,
+    pub gear_type: String,
+    pub activity_count: u32,
+    pub activity_distance: f32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::gear::Gear;
+
+    impl Gear {
+        pub fn dummy(id: &str, distance: f32) -> Self {
+            Self {
+                id: id.to_string(),
+                name: format!("Gear {id}"),
+                distance,
+                primary: false,
+                retired: false,
+                brand_name: None,
+                model_name: None,
+                description: None
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"id":"b1231","primary":false,"resource_state":3,"distance":388206,
+            "brand_name":"BMC","model_name":"Teammachine","frame_type":3,"description":"My Bike","name":"BMC","retired":true}"#;
+        let result = serde_json::from_str::<Gear>(json);
+        assert!(result.is_ok());
+        let gear = result.unwrap();
+        assert_eq!(gear.gear_type(), "bike");
+        assert_eq!(gear.brand_name, Some("BMC".to_string()));
+        assert!(gear.retired);
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+/// A lap of an activity as returned by Strava https://developers.strava.com/docs/reference/#api-Activities-getLapsByActivityId.
+/// Start and end index refer to the points of the [crate::domain::activity_stream::ActivityStream].
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Lap {
+    pub id: u64,
+    pub lap_index: u32,
+    pub name: String,
+    pub start_date: String,
+    pub start_index: u32,
+    pub end_index: u32,
+    pub elapsed_time: u64,
+    pub moving_time: u64,
+    pub distance: f32,
+    pub average_speed: f32,
+    pub average_heartrate: Option<f32>,
+    pub average_watts: Option<f32>
+}
+
+pub type LapVec = Vec<Lap>;
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::lap::Lap;
+
+    impl Lap {
+        pub fn dummy(id: u64, lap_index: u32, start_index: u32, end_index: u32) -> Self {
+            Self {
+                id,
+                lap_index,
+                name: format!("Lap {lap_index}"),
+                start_date: "2020-01-01T00:00:00Z".to_string(),
+                start_index,
+                end_index,
+                elapsed_time: 60,
+                moving_time: 55,
+                distance: 500.5,
+                average_speed: 9.1,
+                average_heartrate: Some(140.2),
+                average_watts: None
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"id":12345,"resource_state":2,"name":"Lap 1","activity":{"id":1,"resource_state":1},
+            "athlete":{"id":7,"resource_state":1},"elapsed_time":60,"moving_time":55,
+            "start_date":"2020-01-01T00:00:00Z","start_date_local":"2020-01-01T01:00:00Z","distance":500.5,
+            "start_index":0,"end_index":9,"total_elevation_gain":3,"average_speed":9.1,"max_speed":12.0,
+            "average_cadence":80.0,"device_watts":false,"average_heartrate":140.2,"lap_index":1,"split":1}"#;
+        let result = serde_json::from_str::<Lap>(json);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Lap::dummy(12345, 1, 0, 9));
+    }
+}
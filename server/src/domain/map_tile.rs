@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::domain::map_zoom::MapZoom;
 
 /// Represents a slippy map tile (see https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames)
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct MapTile(u64, u64);
 
 impl MapTile {
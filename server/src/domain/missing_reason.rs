@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// Why an activity has no track, stored along with [crate::domain::track_store_state::TrackStoreState::Missing]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingReason {
+    NotFound,  // Strava returned 404 for the activity stream
+    NoLatlng,  // The activity stream has neither coordinates nor sensor data
+    ParseError // The activity stream could not be parsed
+}
+
+impl MissingReason {
+    pub const VALUES: [MissingReason; 3] = [MissingReason::NotFound, MissingReason::NoLatlng, MissingReason::ParseError];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MissingReason::NotFound => "not_found",
+            MissingReason::NoLatlng => "no_latlng",
+            MissingReason::ParseError => "parse_error"
+        }
+    }
+}
+
+impl TryFrom<&str> for MissingReason {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        MissingReason::VALUES.into_iter()
+            .find(|reason| reason.name() == value)
+            .ok_or_else(|| format!("Invalid missing reason {value}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::missing_reason::MissingReason;
+
+    #[test]
+    fn test_names() {
+        for reason in MissingReason::VALUES {
+            assert_eq!(MissingReason::try_from(reason.name()), Ok(reason));
+            assert_eq!(serde_json::to_string(&reason).unwrap(), format!("\"{}\"", reason.name()));
+        }
+        assert!(MissingReason::try_from("Foo").is_err());
+    }
+}
@@ -3,7 +3,26 @@ pub mod activity_stats;
 pub mod server_status;
 pub mod activity_stream;
 pub mod download_state;
+pub mod download_event;
+pub mod download_job;
+pub mod download_filter;
+pub mod download_progress;
+pub mod download_schedule;
 pub mod download_delay;
+pub mod effort;
 pub mod track_store_state;
 pub mod map_tile;
 pub mod map_zoom;
+pub mod photo;
+pub mod athlete;
+pub mod athlete_stats;
+pub mod athlete_zones;
+pub mod gear;
+pub mod lap;
+pub mod missing_reason;
+pub mod profile_step;
+pub mod readiness;
+pub mod retry_policy;
+pub mod scheduler_status;
+pub mod sensor_stream;
+pub mod social;
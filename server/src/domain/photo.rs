@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use serde::Deserialize;
+
+/// Requested image size, Strava scales the photos so that the longer side has this length
+pub const PHOTO_SIZE: u32 = 2048;
+
+/// A photo of an activity as returned by Strava (endpoint /activities/{id}/photos, not part of the official API docs)
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct Photo {
+    pub unique_id: String,
+    pub caption: Option<String>,
+    pub created_at: Option<String>,
+    pub location: Option<(f64, f64)>, // [lat, lon]
+    #[serde(default)]
+    pub urls: BTreeMap<String, String>, // Image URLs by size
+    #[serde(skip)]
+    pub file_name: Option<String> // Path of the downloaded image relative to the GPX file
+}
+
+pub type PhotoVec = Vec<Photo>;
+
+impl Photo {
+    /// Returns the URL of the largest image
+    pub fn url(&self) -> Option<&String> {
+        self.urls.iter()
+            .max_by_key(|(size, _)| size.parse::<u32>().unwrap_or(0))
+            .map(|(_, url)| url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::domain::photo::Photo;
+
+    impl Photo {
+        pub fn dummy(unique_id: &str, location: Option<(f64, f64)>) -> Self {
+            Self {
+                unique_id: unique_id.to_string(),
+                caption: Some(format!("Photo {unique_id}")),
+                created_at: Some("2020-01-01T00:00:05Z".to_string()),
+                location,
+                urls: BTreeMap::new(),
+                file_name: None
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"[{"unique_id":"a1b2","athlete_id":7,"activity_id":1,"resource_state":2,"caption":"Photo a1b2",
+            "type":1,"source":1,"created_at":"2020-01-01T00:00:05Z","created_at_local":"2020-01-01T01:00:05Z",
+            "urls":{"100":"https://example.com/100.jpg","2048":"https://example.com/2048.jpg"},
+            "sizes":{"2048":[2048,1536]},"default_photo":false,"location":[51.3,12.3]},
+            {"unique_id":"c3d4","caption":null,"created_at":null,"location":null}]"#;
+        let result = serde_json::from_str::<Vec<Photo>>(json);
+        assert!(result.is_ok());
+        let photos = result.unwrap();
+        assert_eq!(photos.len(), 2);
+        assert_eq!(photos[0].url(), Some(&"https://example.com/2048.jpg".to_string()));
+        assert_eq!(photos[0].location, Some((51.3, 12.3)));
+        assert_eq!(photos[1].url(), None);
+    }
+}
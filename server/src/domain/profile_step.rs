@@ -0,0 +1,15 @@
+/// The requests of the profile download phase, see [crate::domain::download_state::DownloadState::Profile]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProfileStep {
+    Athlete,     // Profile of the athlete, including the gear summaries
+    Zones,       // Heart-rate and power zones
+    Stats,       // Totals for rides, runs, and swims
+    Gear(String) // Details of a bike or shoe
+}
+
+impl ProfileStep {
+    /// The steps at the beginning of the profile phase. Gear steps are added after the athlete was fetched.
+    pub fn initial() -> Vec<Self> {
+        vec![ProfileStep::Athlete, ProfileStep::Zones, ProfileStep::Stats]
+    }
+}
@@ -0,0 +1,54 @@
+use axum::BoxError;
+use serde::Serialize;
+
+/// Result of a single readiness check
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct ReadinessCheck {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>
+}
+
+impl ReadinessCheck {
+    pub fn new(name: &'static str, result: Result<Option<String>, BoxError>) -> Self {
+        match result {
+            Ok(detail) => Self { name, ok: true, detail },
+            Err(error) => Self { name, ok: false, detail: Some(error.to_string()) }
+        }
+    }
+}
+
+/// Result returned by the /readyz endpoint. The server is ready if all checks are ok.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Readiness {
+    ready: bool,
+    checks: Vec<ReadinessCheck>
+}
+
+impl Readiness {
+    pub fn new(checks: Vec<ReadinessCheck>) -> Self {
+        let ready = checks.iter().all(|check| check.ok);
+        Self { ready, checks }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::readiness::{Readiness, ReadinessCheck};
+
+    #[test]
+    fn test_serialize() {
+        let readiness = Readiness::new(vec![
+            ReadinessCheck::new("database", Ok(None)),
+            ReadinessCheck::new("scheduler", Err("Download scheduler not running".into()))
+        ]);
+        assert!(!readiness.is_ready());
+        let json = serde_json::to_string(&readiness).unwrap();
+        assert_eq!(json, r#"{"ready":false,"checks":[{"name":"database","ok":true},{"name":"scheduler","ok":false,"detail":"Download scheduler not running"}]}"#);
+    }
+}
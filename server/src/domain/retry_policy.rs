@@ -0,0 +1,59 @@
+use std::hash::{BuildHasher, RandomState};
+use std::time::Duration;
+
+/// Exponential backoff with jitter for retrying Strava requests that failed with a transient error
+/// (like a timeout or a 5xx status). After `max_attempts` failed attempts, the request is given up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self { max_attempts, base_delay, max_delay }
+    }
+
+    pub fn is_exhausted(&self, attempts: u32) -> bool {
+        attempts >= self.max_attempts
+    }
+
+    /// Returns the delay after the given number of failed attempts: The base delay doubles with each
+    /// attempt up to the max delay, and a random jitter of up to half of it is subtracted, so that
+    /// retries of several failed requests spread out.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempts.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        delay.mul_f64(1.0 - random_fraction() / 2.0)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30), Duration::from_secs(3600))
+    }
+}
+
+/// Returns a random number in [0, 1] taken from the randomly seeded std hasher
+fn random_fraction() -> f64 {
+    RandomState::new().hash_one(0_u8) as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::domain::retry_policy::RetryPolicy;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(3, Duration::from_secs(10), Duration::from_secs(60));
+        for (attempts, max) in [(1, 10), (2, 20), (3, 40), (4, 60), (40, 60)] {
+            let delay = policy.delay(attempts);
+            assert!(delay <= Duration::from_secs(max), "{delay:?} exceeds {max}s");
+            assert!(delay >= Duration::from_secs(max) / 2, "{delay:?} below {max}s / 2");
+        }
+        assert!(!policy.is_exhausted(2));
+        assert!(policy.is_exhausted(3));
+    }
+}
@@ -0,0 +1,8 @@
+use serde::Serialize;
+
+/// Health of the download scheduler task, which is restarted by its supervisor after a panic
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SchedulerStatus {
+    pub scheduler_restarts: u32,
+    pub scheduler_error: Option<String> // Latest failure of a download task or the scheduler itself
+}
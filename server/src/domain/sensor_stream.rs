@@ -0,0 +1,133 @@
+use std::io::{BufRead, Write};
+use axum::BoxError;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::Value;
+use crate::domain::activity_stream::ActivityStream;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Series<T> {
+    data: Vec<T>
+}
+
+/// An activity stream without coordinates, as returned by Strava for indoor activities
+/// (like trainer rides or treadmill runs). Besides the time, each series is optional,
+/// and single values may be null (e.g. the watts during a dropout of the power meter).
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct SensorStream {
+    time: Series<u32>, // Seconds since the start
+    distance: Option<Series<Option<f64>>>,
+    altitude: Option<Series<Option<f64>>>,
+    heartrate: Option<Series<Option<f64>>>,
+    watts: Option<Series<Option<f64>>>,
+    cadence: Option<Series<Option<f64>>>
+}
+
+impl SensorStream {
+    /// Returns true if the stream contains any data besides the time
+    pub fn has_data(&self) -> bool {
+        self.columns().iter().any(|(_, series)| !series.data.is_empty())
+    }
+
+    /// Writes the stream as CSV with a header line and one line per point in time.
+    /// Only the columns of the series contained in the stream are written, missing values are left empty.
+    pub fn to_csv<W: Write>(&self, writer: &mut W) -> Result<(), BoxError> {
+        let columns = self.columns();
+        let header: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        writeln!(writer, "time,{}", header.join(","))?;
+        for (index, time) in self.time.data.iter().enumerate() {
+            let values: Vec<String> = columns.iter()
+                .map(|(_, series)| series.data.get(index).copied().flatten().map(|v| v.to_string()).unwrap_or_default())
+                .collect();
+            writeln!(writer, "{time},{}", values.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that a CSV file written by [Self::to_csv] is complete: the header starts with the time,
+    /// and each line has as many values as the header, each of them numeric or empty.
+    pub fn check_csv<R: BufRead>(reader: R) -> Result<(), BoxError> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or("Empty CSV file")??;
+        if !header.starts_with("time") {
+            return Err(format!("Invalid CSV header '{header}'").into())
+        }
+        let columns = header.split(',').count();
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            let values: Vec<&str> = line.split(',').collect();
+            if values.len() != columns || values.iter().any(|v| !v.is_empty() && v.parse::<f64>().is_err()) {
+                return Err(format!("Invalid CSV line {}", index + 2).into())
+            }
+        }
+        Ok(())
+    }
+
+    fn columns(&self) -> Vec<(&'static str, &Series<Option<f64>>)> {
+        [("distance", &self.distance), ("altitude", &self.altitude), ("heartrate", &self.heartrate),
+            ("watts", &self.watts), ("cadence", &self.cadence)]
+            .into_iter()
+            .filter_map(|(name, series)| series.as_ref().map(|series| (name, series)))
+            .collect()
+    }
+}
+
+/// The streams of an activity as returned by Strava: a track if the streams contain coordinates,
+/// otherwise the sensor data
+#[derive(Debug, PartialEq)]
+pub enum StreamSet {
+    Track(ActivityStream),
+    Sensors(SensorStream)
+}
+
+impl<'de> Deserialize<'de> for StreamSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.get("latlng").is_some() {
+            ActivityStream::deserialize(value).map(StreamSet::Track).map_err(D::Error::custom)
+        } else {
+            SensorStream::deserialize(value).map(StreamSet::Sensors).map_err(D::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::sensor_stream::{SensorStream, StreamSet};
+
+    static SENSOR_STR: &str = r#"{
+  "time":{"data":[0,1,2]},
+  "heartrate":{"data":[90,95,101]},
+  "watts":{"data":[150,null,180.5]}
+}"#;
+
+    #[test]
+    fn test_deserialize() {
+        let result: StreamSet = serde_json::from_str(r#"{"latlng":{"data":[[1.0,2.0]]},"altitude":{"data":[3.0]},"time":{"data":[0]}}"#).unwrap();
+        assert!(matches!(result, StreamSet::Track(_)));
+        let result: StreamSet = serde_json::from_str(SENSOR_STR).unwrap();
+        assert!(matches!(result, StreamSet::Sensors(stream) if stream.has_data()));
+        let result: SensorStream = serde_json::from_str(r#"{"time":{"data":[0,1]}}"#).unwrap();
+        assert!(!result.has_data());
+        assert!(serde_json::from_str::<StreamSet>(r#"{"latlng":{"data":[[1.0,2.0]]}}"#).is_err());
+        assert!(serde_json::from_str::<StreamSet>(r#"{"heartrate":{"data":[90]}}"#).is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let stream: SensorStream = serde_json::from_str(SENSOR_STR).unwrap();
+        let mut csv = Vec::new();
+        assert!(stream.to_csv(&mut csv).is_ok());
+        assert_eq!(String::from_utf8(csv).unwrap(), "time,heartrate,watts\n0,90,150\n1,95,\n2,101,180.5\n");
+    }
+
+    #[test]
+    fn test_check_csv() {
+        assert!(SensorStream::check_csv("time,heartrate,watts\n0,90,150\n1,95,\n".as_bytes()).is_ok());
+        assert!(SensorStream::check_csv("time\n".as_bytes()).is_ok());
+        assert!(SensorStream::check_csv("".as_bytes()).is_err());
+        assert!(SensorStream::check_csv("<gpx".as_bytes()).is_err());
+        assert!(SensorStream::check_csv("time,heartrate\n0,90\n1".as_bytes()).is_err()); // Truncated
+        assert!(SensorStream::check_csv("time,heartrate\n0,abc\n".as_bytes()).is_err());
+    }
+}
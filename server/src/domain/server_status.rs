@@ -1,17 +1,30 @@
 use serde::Serialize;
 use crate::domain::activity_stats::ActivityStats;
+use crate::domain::download_progress::DownloadProgress;
 use crate::domain::download_state::DownloadState;
+use crate::domain::scheduler_status::SchedulerStatus;
 
-/// Object passed from downloader to SSE handler and result returned by the /status endpoint
+/// Object passed from downloader to SSE handler and result returned by the /status endpoint.
+/// The athlete is [None] if no athlete has authorized the application yet.
 #[derive(Clone, Serialize, Debug, PartialEq)]
 pub struct ServerStatus {
+    athlete_id: Option<u64>,
     authorized: bool,
     download_state: DownloadState,
-    activity_stats: ActivityStats
+    activity_stats: ActivityStats,
+    progress: DownloadProgress,
+    next_scheduled_run: Option<String>, // Start of the next scheduled download (ISO 8601), if any
+    #[serde(flatten)]
+    scheduler: SchedulerStatus
 }
 
 impl ServerStatus {
-    pub fn new(authorized: bool, download_state: DownloadState, activity_stats: ActivityStats) -> Self {
-        Self { authorized, download_state, activity_stats }
+    pub fn new(athlete_id: Option<u64>, authorized: bool, download_state: DownloadState, activity_stats: ActivityStats,
+               progress: DownloadProgress, next_scheduled_run: Option<String>, scheduler: SchedulerStatus) -> Self {
+        Self { athlete_id, authorized, download_state, activity_stats, progress, next_scheduled_run, scheduler }
     }
-}
\ No newline at end of file
+
+    pub fn athlete_id(&self) -> Option<u64> {
+        self.athlete_id
+    }
+}
@@ -0,0 +1,60 @@
+use serde::Deserialize;
+
+/// Strava only reveals the names of other athletes (the last name abbreviated)
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct PersonName {
+    pub firstname: String,
+    pub lastname: String
+}
+
+/// A comment on an activity as returned by Strava https://developers.strava.com/docs/reference/#api-Activities-getCommentsByActivityId
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct Comment {
+    pub id: u64,
+    pub text: String,
+    pub created_at: String,
+    #[serde(rename(deserialize = "athlete"))]
+    pub author: PersonName
+}
+
+pub type CommentVec = Vec<Comment>;
+
+/// The kudoers of an activity as returned by Strava https://developers.strava.com/docs/reference/#api-Activities-getKudoersByActivityId
+pub type KudoerVec = Vec<PersonName>;
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::social::{Comment, CommentVec, KudoerVec, PersonName};
+
+    impl PersonName {
+        pub fn new(firstname: &str, lastname: &str) -> Self {
+            Self { firstname: firstname.to_string(), lastname: lastname.to_string() }
+        }
+    }
+
+    impl Comment {
+        pub fn dummy(id: u64, text: &str) -> Self {
+            Self {
+                id,
+                text: text.to_string(),
+                created_at: "2020-01-01T10:00:00Z".to_string(),
+                author: PersonName::new("Foo", "B.")
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let comments = r#"[{"id":11,"activity_id":1,"post_id":null,"resource_state":2,"text":"Nice",
+            "mentions_metadata":null,"created_at":"2020-01-01T10:00:00Z",
+            "athlete":{"firstname":"Foo","lastname":"B.","resource_state":2}}]"#;
+        let result = serde_json::from_str::<CommentVec>(comments);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![Comment::dummy(11, "Nice")]);
+
+        let kudoers = r#"[{"firstname":"Foo","lastname":"B.","resource_state":2}]"#;
+        let result = serde_json::from_str::<KudoerVec>(kudoers);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![PersonName::new("Foo", "B.")]);
+    }
+}
@@ -1,7 +1,21 @@
-#[derive(Clone, Debug)]
-#[allow(dead_code)] // Pending state is set implicitly by database
+#[derive(Clone, Debug, PartialEq)]
 pub enum TrackStoreState {
     Pending = 0, // Track storage pending
     Stored  = 1, // Track stored
-    Missing = 2  // Activity w/o track
-}
\ No newline at end of file
+    Missing = 2, // Activity w/o track
+    Sensors = 3  // Activity w/o coordinates, sensor data stored as CSV
+}
+
+impl TryFrom<i32> for TrackStoreState {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TrackStoreState::Pending),
+            1 => Ok(TrackStoreState::Stored),
+            2 => Ok(TrackStoreState::Missing),
+            3 => Ok(TrackStoreState::Sensors),
+            _ => Err(format!("Invalid track store state {value}"))
+        }
+    }
+}
@@ -3,6 +3,7 @@ pub mod domain;
 pub mod oauth;
 pub mod service;
 pub mod state;
+pub mod strava;
 pub mod rest;
 pub mod track;
 pub mod util;
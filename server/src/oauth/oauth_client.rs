@@ -1,11 +1,14 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
 use axum::BoxError;
 use log::{debug, info, warn};
-use oauth2::basic::{BasicClient, BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenResponse};
-use oauth2::{AuthorizationCode, AuthType, AuthUrl, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope, TokenResponse, TokenUrl, Client, StandardRevocableToken, EndpointSet, EndpointNotSet, ResourceOwnerUsername, ResourceOwnerPassword};
-use oauth2::reqwest;
+use oauth2::basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse};
+use oauth2::{AuthorizationCode, AuthType, AuthUrl, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope, TokenResponse, TokenUrl, Client, StandardRevocableToken, EndpointSet, EndpointNotSet, ResourceOwnerUsername, ResourceOwnerPassword, RequestTokenError};
 use url::Url;
 use crate::oauth::token;
-use crate::oauth::token::{Bearer, TokenHolder};
+use crate::oauth::token::{Bearer, StravaTokenResponse, TokenError, TokenHolder};
+use crate::strava::strava_client::StravaClient;
+use crate::strava::strava_error::StravaError;
 
 // About type BoxError = Box<dyn std::error::Error + Send + Sync>:
 // Send is necessary to send errors between threads (needed by axum middleware):
@@ -14,22 +17,29 @@ use crate::oauth::token::{Bearer, TokenHolder};
 // https://users.rust-lang.org/t/convert-box-dyn-error-to-box-dyn-error-send/48856
 
 type TokenResult = Result<TokenHolder, BoxError>;
-type UrlResult = Result<String, BoxError>;
+type CallbackResult = Result<(u64, String), BoxError>;
 type BearerResult = Result<Option<Bearer>, BoxError>;
 
-/// An OAuth client for the authorization of a *single* user.
-/// Configures a [BasicClient] for the given URLs.
-/// Keeps track on the state and the token, once obtained.
+/// Time an athlete has to authorize at the IdP before the state of the request expires
+const STATE_LIFETIME: Duration = Duration::from_secs(600);
+
+/// An OAuth client for the authorization of one or more athletes.
+/// Configures a [Client] for the given URLs, which sends its token requests by the shared [StravaClient].
+/// Keeps track on the pending authorizations and the tokens obtained per athlete.
+/// The tokens are kept in memory only, so the athletes have to authorize again after a restart.
 pub struct OAuthClient {
     // This extreme ugliness follows https://github.com/ramosbugs/oauth2-rs/blob/main/UPGRADE.md:
-    client: Client<BasicErrorResponse, BasicTokenResponse, BasicTokenIntrospectionResponse, StandardRevocableToken, BasicRevocationErrorResponse, EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>,
+    client: Client<BasicErrorResponse, StravaTokenResponse, BasicTokenIntrospectionResponse, StandardRevocableToken, BasicRevocationErrorResponse, EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>,
     scopes: Vec<String>,
     target: String, // URL to be redirected to after authentication – can be relative or absolute
-    state: Option<String>, // Holds the state between an auth-code request and entering the callback
-    token: Option<TokenHolder> // Holds the token issued by the IdP
+    states: HashMap<String, Instant>, // Holds the states between auth-code requests and entering the callback
+    tokens: BTreeMap<u64, TokenHolder>, // Holds the tokens issued by the IdP per athlete id
+    deauthorize_url: String, // Strava-specific endpoint next to the token endpoint
+    http_client: StravaClient
 }
 
 impl OAuthClient {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(client_id: String,
                client_secret: String,
                auth_url: String,
@@ -37,29 +47,34 @@ impl OAuthClient {
                target_url: String,
                redirect_url: String,
                scopes: Vec<String>,
+               http_client: StravaClient,
     ) -> Self {
-        let client = BasicClient::new(ClientId::new(client_id))
+        let client = Client::new(ClientId::new(client_id))
             .set_client_secret(ClientSecret::new(client_secret.to_string()))
             .set_auth_uri(AuthUrl::new(auth_url.to_string()).unwrap()) // Panic accepted
             .set_token_uri(TokenUrl::new(token_url.to_string()).unwrap())
             .set_redirect_uri(RedirectUrl::new(redirect_url).unwrap())
             .set_auth_type(AuthType::RequestBody);
+        let deauthorize_url = Self::deauthorize_url(&token_url);
+
+        Self { client, scopes, target: target_url, states: HashMap::new(), tokens: BTreeMap::new(), deauthorize_url, http_client }
+    }
 
-        Self { client, scopes, target: target_url, state: None, token: None }
+    /// Derives the deauthorization URL from the token URL, e.g. https://www.strava.com/oauth/deauthorize
+    /// from https://www.strava.com/oauth/token
+    fn deauthorize_url(token_url: &str) -> String {
+        let base_url = token_url.rsplit_once('/').map_or(token_url, |(base_url, _)| base_url);
+        format!("{base_url}/deauthorize")
     }
 
     #[allow(dead_code)]
     pub async fn authorize_password_grant(&self, user: &str, pass: &str) -> TokenResult {
-        let http_client = reqwest::ClientBuilder::new()
-            .redirect(reqwest::redirect::Policy::none())
-            .build()?;
-
         let token = token::validate(self.client
             .exchange_password(
                 &ResourceOwnerUsername::new(user.to_string()),
                 &ResourceOwnerPassword::new(pass.to_string())
             )
-            .request_async(&http_client)
+            .request_async(&self.http_client)
             .await?)?;
 
         Ok(TokenHolder::new(token))
@@ -67,6 +82,7 @@ impl OAuthClient {
 
     /// Constructs and returns the authorization URL for the IdP.
     /// The caller should then redirect to that URL to start the auth-code flow.
+    /// States of abandoned flows are removed after ten minutes.
     pub fn authorize_auth_code_grant(&mut self) -> Url {
         // Transform Vec<String> to Vec<Scope>.
         // Note that cloning is needed anyway because Client.add_scopes() moves its argument.
@@ -76,26 +92,35 @@ impl OAuthClient {
             .add_scopes(scopes)
             .url();
 
-        self.state = Some(csrf_token.secret().clone());
+        self.states.retain(|_, created| created.elapsed() < STATE_LIFETIME);
+        self.states.insert(csrf_token.secret().clone(), Instant::now());
         auth_url
     }
 
     /// A function to be called in the callback handler for the auth-code flow.
     /// The function exchanges the passed auth code by a token by calling the IdP.
-    /// It returns the target URL (passed to the constructor of this class)
-    /// to be redirected after success.
-    pub async fn callback_auth_code_grant(&mut self, code: &str, state: &str) -> UrlResult {
+    /// It returns the id of the authorized athlete and the target URL (passed to
+    /// the constructor of this class) to be redirected after success.
+    /// Each state is accepted only once, even if the token exchange fails.
+    pub async fn callback_auth_code_grant(&mut self, code: &str, state: &str) -> CallbackResult {
         debug!("Authorized with code {}", code);
-        if self.state.is_none() || self.state.as_ref().unwrap() != state {
-            warn!("Received state {} does not match expected state {}", state,
-            self.state.as_ref().unwrap_or(&String::from("<null>")));
-            return Err("OAuth state does not match".into());
+        match self.states.remove(state) {
+            Some(created) if created.elapsed() < STATE_LIFETIME => {}
+            Some(_) => {
+                warn!("Received state {} is expired", state);
+                return Err("OAuth state is expired".into());
+            }
+            None => {
+                warn!("Received state {} does not match any expected state", state);
+                return Err("OAuth state does not match".into());
+            }
         }
         match self.exchange_code_for_token(code).await {
             Ok(token) => {
-                self.token = Some(token);
-                self.state = None;
-                Ok(self.target.clone())
+                let athlete_id = token.athlete_id().ok_or(TokenError::AthleteMissing)?;
+                info!("Authorized athlete {athlete_id}");
+                self.tokens.insert(athlete_id, token);
+                Ok((athlete_id, self.target.clone()))
             }
             Err(error) => {
                 warn!("Error: {:?}", error);
@@ -107,13 +132,9 @@ impl OAuthClient {
     async fn exchange_code_for_token(&self, code: &str) -> TokenResult {
         debug!("Obtain token for code {}", code);
 
-        let http_client = reqwest::ClientBuilder::new()
-            .redirect(reqwest::redirect::Policy::none())
-            .build()?;
-
         let token = token::validate(self.client
             .exchange_code(AuthorizationCode::new(code.to_string()))
-            .request_async(&http_client)
+            .request_async(&self.http_client)
             .await?)?;
 
         info!("Obtained token");
@@ -123,15 +144,32 @@ impl OAuthClient {
         Ok(TokenHolder::new(token))
     }
 
-    /// Returns the previously obtained token or [None] if none was acquired so far.
-    /// It the token is expired, it is refreshed before returning.
-    pub async fn get_bearer(&mut self) -> BearerResult {
-        match self.token.as_ref() {
+    /// Returns the ids of all athletes with a token in ascending order
+    pub fn athletes(&self) -> Vec<u64> {
+        self.tokens.keys().copied().collect()
+    }
+
+    /// Returns true if the athlete has a token that is valid or can be refreshed.
+    /// Unlike [Self::get_bearer], the token is neither refreshed nor removed.
+    pub fn has_usable_token(&self, athlete_id: u64) -> bool {
+        self.tokens.get(&athlete_id).is_some_and(|token_holder| token_holder.is_usable())
+    }
+
+    /// Returns the previously obtained token of the athlete or [None] if none was acquired so far.
+    /// It the token is expired, it is refreshed before returning. If the IdP rejects the refresh
+    /// (e.g. because the athlete revoked the access), the token is removed and [None] is returned.
+    pub async fn get_bearer(&mut self, athlete_id: u64) -> BearerResult {
+        match self.tokens.get(&athlete_id) {
             Some(token_holder) => {
                 if token::is_expired(token_holder) {
                     match self.refresh_token(token_holder).await {
                         Ok(token) => {
-                            self.token = Some(token);
+                            self.tokens.insert(athlete_id, token);
+                        }
+                        Err(error) if matches!(error.downcast_ref::<TokenError>(), Some(TokenError::Rejected(_))) => {
+                            warn!("Token refresh of athlete {athlete_id} failed: {error}, remove the token");
+                            self.remove_token(athlete_id);
+                            return Ok(None);
                         }
                         Err(error) => {
                             warn!("Error: {}", error);
@@ -139,7 +177,7 @@ impl OAuthClient {
                         }
                     }
                 }
-                let bearer = self.token.as_ref().expect("Missing token").bearer();
+                let bearer = self.tokens.get(&athlete_id).expect("Missing token").bearer();
                 Ok(Some(bearer.clone()))
             }
             None => {
@@ -151,25 +189,52 @@ impl OAuthClient {
     async fn refresh_token(&self, token_holder: &TokenHolder) -> TokenResult {
         debug!("Access token expired, refreshing ...");
 
-        let http_client = reqwest::ClientBuilder::new()
-            .redirect(reqwest::redirect::Policy::none())
-            .build()?;
-
-        let token = token::validate(self.client
+        let token = self.client
             .exchange_refresh_token(token_holder.token().refresh_token().unwrap())
-            .request_async(&http_client)
-            .await?)?;
+            .request_async(&self.http_client)
+            .await
+            .map_err(|error| match error {
+                RequestTokenError::Request(error) => BoxError::from(error), // Network error, worth a retry
+                error => TokenError::Rejected(error.to_string()).into()
+            })?;
+        let token = token::validate(token)?;
 
         info!("Refreshed token successfully");
         Ok(TokenHolder::new(token))
     }
+
+    /// Removes the token of the athlete. Returns false if there was no token.
+    pub fn remove_token(&mut self, athlete_id: u64) -> bool {
+        self.tokens.remove(&athlete_id).is_some()
+    }
+
+    /// Revokes the access of this application to the data of the athlete at Strava and removes
+    /// the token. Returns false if the athlete was not authorized.
+    pub async fn deauthorize(&mut self, athlete_id: u64) -> Result<bool, BoxError> {
+        let Some(token_holder) = self.tokens.get(&athlete_id) else {
+            return Ok(false)
+        };
+        let access_token = token_holder.token().access_token().secret().clone();
+        let result = self.http_client.deauthorize(&self.deauthorize_url, &access_token).await;
+        self.remove_token(athlete_id); // Remove the token anyway, it is useless if Strava rejects it
+        match result {
+            Ok(()) | Err(StravaError::Unauthorized(_)) => {
+                info!("Deauthorized athlete {athlete_id}");
+                Ok(true)
+            }
+            Err(error) => Err(error.into())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use oauth2::basic::BasicClient;
-    use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
-    use crate::oauth::oauth_client::OAuthClient;
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::{Duration, Instant};
+    use oauth2::{AuthUrl, Client, ClientId, ClientSecret, TokenUrl};
+    use crate::oauth::oauth_client::{OAuthClient, STATE_LIFETIME};
+    use crate::oauth::token::TokenHolder;
+    use crate::strava::strava_client::StravaClient;
     use wiremock::{MockServer, Mock, ResponseTemplate};
     use wiremock::matchers::{method, path, body_string_contains};
     use serde_json::json;
@@ -178,31 +243,47 @@ mod tests {
     impl OAuthClient {
         pub fn dummy() -> Self {
             let dummy_url = "https://dummy.org";
-            let client = BasicClient::new(ClientId::new("dummy-client".to_string()))
+            let client = Client::new(ClientId::new("dummy-client".to_string()))
                 .set_client_secret(ClientSecret::new("dummy-secret".to_string()))
                 .set_auth_uri(AuthUrl::new(dummy_url.to_string()).unwrap())
                 .set_token_uri(TokenUrl::new(dummy_url.to_string()).unwrap());
 
-            Self { client, scopes: vec![], state: None, target: dummy_url.to_string(), token: None }
+            Self { client, scopes: vec![], states: HashMap::new(), target: dummy_url.to_string(), tokens: BTreeMap::new(),
+                deauthorize_url: dummy_url.to_string(), http_client: StravaClient::default() }
+        }
+
+        /// Adds a token for the athlete, as if the athlete had authorized the application
+        pub fn add_dummy_athlete(&mut self, athlete_id: u64) {
+            let response = json!({
+                "access_token": "dummy_access_token",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "athlete": { "id": athlete_id }
+            });
+            let token = serde_json::from_value(response).expect("Invalid token response");
+            self.tokens.insert(athlete_id, TokenHolder::new(token));
         }
 
         // Test helper to inspect state
         fn get_state(&self) -> Option<&String> {
-            self.state.as_ref()
+            self.states.keys().next()
         }
 
         // Test helper to check if token exists
-        fn has_token(&self) -> bool {
-            self.token.is_some()
+        fn has_token(&self, athlete_id: u64) -> bool {
+            self.tokens.contains_key(&athlete_id)
         }
     }
 
+    const ATHLETE_ID: u64 = 4711;
+
     fn create_mock_token_response(include_refresh: bool) -> serde_json::Value {
         let mut response = json!({
             "access_token": "mock_access_token_12345",
             "token_type": "Bearer",
             "expires_in": 3600,
-            "scope": "read write"
+            "scope": "read write",
+            "athlete": { "id": ATHLETE_ID }
         });
         
         if include_refresh {
@@ -221,6 +302,7 @@ mod tests {
             "/dashboard".to_string(),
             format!("{}/callback", mock_server.uri()),
             vec!["read".to_string(), "write".to_string()],
+            StravaClient::default(),
         )
     }
 
@@ -279,13 +361,15 @@ mod tests {
 
         // Step 2: Simulate callback with auth code
         let state = client.get_state().unwrap().clone();
-        let redirect_url = client.callback_auth_code_grant("auth_code_xyz", &state).await;
-        assert!(redirect_url.is_ok());
-        assert_eq!(redirect_url.unwrap(), "/dashboard");
+        let result = client.callback_auth_code_grant("auth_code_xyz", &state).await;
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), (ATHLETE_ID, "/dashboard".to_string()));
+        assert_eq!(client.get_state(), None);
+        assert_eq!(client.athletes(), vec![ATHLETE_ID]);
 
         // Step 3: Get bearer token
-        assert!(client.has_token()); // Should already be there (no refresh needed)
-        let bearer = client.get_bearer().await;
+        assert!(client.has_token(ATHLETE_ID)); // Should already be there (no refresh needed)
+        let bearer = client.get_bearer(ATHLETE_ID).await;
         assert!(bearer.is_ok());
         assert!(bearer.unwrap().is_some());
     }
@@ -309,6 +393,48 @@ mod tests {
 
         let result = client.callback_auth_code_grant("invalid_code", &state).await;
         assert!(result.is_err());
+        assert_eq!(client.get_state(), None); // A retry needs a new authorization
+        let result = client.callback_auth_code_grant("invalid_code", &state).await;
+        assert_eq!(result.unwrap_err().to_string(), "OAuth state does not match");
+    }
+
+    #[tokio::test]
+    async fn test_expired_state() {
+        let mut client = OAuthClient::dummy();
+        let expired = Instant::now() - STATE_LIFETIME - Duration::from_secs(1);
+        client.states.insert("old".to_string(), expired);
+        let result = client.callback_auth_code_grant("test_code", "old").await;
+        assert_eq!(result.unwrap_err().to_string(), "OAuth state is expired");
+        assert_eq!(client.get_state(), None);
+
+        client.states.insert("old".to_string(), expired);
+        let _auth_url = client.authorize_auth_code_grant();
+        assert_eq!(client.states.len(), 1);
+        assert!(!client.states.contains_key("old"));
+    }
+
+    #[tokio::test]
+    async fn test_token_without_athlete() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "mock_access_token_12345",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": "mock_refresh_token_67890"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let mut client = create_mock_client(&mock_server);
+        let _auth_url = client.authorize_auth_code_grant();
+        let state = client.get_state().unwrap().clone();
+
+        let result = client.callback_auth_code_grant("test_code", &state).await;
+        assert!(result.is_err());
+        assert!(client.athletes().is_empty());
     }
 
     #[tokio::test]
@@ -321,7 +447,8 @@ mod tests {
             "token_type": "Bearer",
             "expires_in": 1,
             "refresh_token": "mock_refresh_token_67890",
-            "scope": "read"
+            "scope": "read",
+            "athlete": { "id": ATHLETE_ID }
         });
         
         // Refreshed token
@@ -361,7 +488,7 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
         // This should trigger a refresh
-        let result = client.get_bearer().await;
+        let result = client.get_bearer(ATHLETE_ID).await;
         
         assert!(result.is_ok());
         let bearer = result.unwrap();
@@ -370,4 +497,84 @@ mod tests {
         let bearer_string: String = bearer.unwrap().into();
         assert!(bearer_string.contains("new_access_token"));
     }
-}
\ No newline at end of file
+
+    async fn authorize_with_expired_token(mock_server: &MockServer) -> OAuthClient {
+        let expired_token = json!({
+            "access_token": "old_access_token",
+            "token_type": "Bearer",
+            "expires_in": 1,
+            "refresh_token": "mock_refresh_token_67890",
+            "athlete": { "id": ATHLETE_ID }
+        });
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=authorization_code"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(expired_token))
+            .mount(mock_server)
+            .await;
+
+        let mut client = create_mock_client(mock_server);
+        let _auth_url = client.authorize_auth_code_grant();
+        let state = client.get_state().unwrap().clone();
+        client.callback_auth_code_grant("test_code", &state).await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh_rejected() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "message": "Bad Request",
+                "errors": [{ "resource": "RefreshToken", "field": "refresh_token", "code": "invalid" }]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let mut client = authorize_with_expired_token(&mock_server).await;
+        assert!(client.has_usable_token(ATHLETE_ID)); // Has a refresh token
+        assert!(client.get_bearer(ATHLETE_ID).await.unwrap().is_none());
+        assert!(!client.has_usable_token(ATHLETE_ID));
+        assert!(!client.has_token(ATHLETE_ID));
+    }
+
+    #[test]
+    fn test_has_usable_token() {
+        let mut client = OAuthClient::dummy();
+        assert!(!client.has_usable_token(ATHLETE_ID));
+        client.add_dummy_athlete(ATHLETE_ID); // Not expired, but without refresh token
+        assert!(client.has_usable_token(ATHLETE_ID));
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh_unreachable() {
+        let mock_server = MockServer::start().await;
+        let mut client = authorize_with_expired_token(&mock_server).await;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_url = format!("http://{}/token", listener.local_addr().unwrap());
+        drop(listener); // Nobody listens at the port anymore
+        client.client = client.client.set_token_uri(TokenUrl::new(closed_url).unwrap());
+
+        assert!(client.get_bearer(ATHLETE_ID).await.is_err());
+        assert!(client.has_token(ATHLETE_ID)); // Kept for the next attempt
+    }
+
+    #[tokio::test]
+    async fn test_deauthorize() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/deauthorize"))
+            .and(body_string_contains("access_token=old_access_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": "old_access_token" })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut client = authorize_with_expired_token(&mock_server).await;
+        assert!(client.deauthorize(ATHLETE_ID).await.unwrap());
+        assert!(!client.has_token(ATHLETE_ID));
+        assert!(!client.deauthorize(ATHLETE_ID).await.unwrap());
+    }
+}
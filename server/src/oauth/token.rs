@@ -1,6 +1,7 @@
 use std::time::SystemTime;
-use oauth2::TokenResponse;
-use oauth2::basic::BasicTokenResponse;
+use oauth2::{ExtraTokenFields, StandardTokenResponse, TokenResponse};
+use oauth2::basic::BasicTokenType;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 // Number of seconds before expiry time an access token will be refreshed
@@ -21,15 +22,34 @@ impl From<Bearer> for String {
     }
 }
 
+/// Summary of the athlete who authorized the application, see
+/// https://developers.strava.com/docs/authentication/#tokenexchange
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AthleteSummary {
+    pub id: u64
+}
+
+/// Strava-specific fields of a token response. The athlete is only returned by the
+/// initial token exchange, but not by refresh requests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StravaTokenFields {
+    #[serde(default)]
+    pub athlete: Option<AthleteSummary>
+}
+
+impl ExtraTokenFields for StravaTokenFields {}
+
+pub type StravaTokenResponse = StandardTokenResponse<StravaTokenFields, BasicTokenType>;
+
 #[derive(Debug)]
 pub struct TokenHolder {
-    token: BasicTokenResponse,
+    token: StravaTokenResponse,
     bearer: Bearer, // Bearer token extracted from the access token
     expiry: Option<u64> // Expiry date in seconds since 1970
 }
 
 impl TokenHolder {
-    pub fn new(token: BasicTokenResponse) -> Self {
+    pub fn new(token: StravaTokenResponse) -> Self {
         let bearer = Bearer::from(format!("Bearer {}", token.access_token().secret()));
         let expiry = token.expires_in().map(|e| e.as_secs() + get_current_time());
         Self { token, bearer, expiry }
@@ -38,9 +58,19 @@ impl TokenHolder {
     pub fn bearer(&self) -> &Bearer {
         &self.bearer
     }
-    pub fn token(&self) -> &BasicTokenResponse {
+    pub fn token(&self) -> &StravaTokenResponse {
         &self.token
     }
+
+    /// Returns true if the token can still be used, i.e. it is not expired or it can be refreshed
+    pub fn is_usable(&self) -> bool {
+        self.token.refresh_token().is_some() || !is_expired(self)
+    }
+
+    /// Returns the id of the authorizing athlete (only known for tokens from an initial exchange)
+    pub fn athlete_id(&self) -> Option<u64> {
+        self.token.extra_fields().athlete.as_ref().map(|a| a.id)
+    }
 }
 
 pub fn is_expired(token_holder: &TokenHolder) -> bool {
@@ -54,10 +84,14 @@ fn get_current_time() -> u64 {
 #[derive(Error, Debug)]
 pub enum TokenError {
     #[error("Token returned from auth server does not contain a refresh token")]
-    RefreshTokenMissing
+    RefreshTokenMissing,
+    #[error("Token returned from auth server does not contain the athlete")]
+    AthleteMissing,
+    #[error("Token request rejected by auth server: {0}")]
+    Rejected(String)
 }
 
-pub fn validate(token: BasicTokenResponse) -> Result<BasicTokenResponse, TokenError> {
+pub fn validate(token: StravaTokenResponse) -> Result<StravaTokenResponse, TokenError> {
     if token.refresh_token().is_none() {
         return Err(TokenError::RefreshTokenMissing)
     }
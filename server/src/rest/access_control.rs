@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use axum::http::Method;
+use oauth2::CsrfToken;
+use crate::rest::rest_paths::{ACTIVITY_REFETCH, ATHLETES, AUTHORIZE, FILTER, METRICS, TRACKS_RETRY, AUTH_CALLBACK, DOWNLOAD_START, DOWNLOAD_STOP, EVENTS, HEALTHZ, LOGOUT, READYZ, STATUS, TOGGLE};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const SESSION_COOKIE: &str = "session";
+
+/// How the endpoints are protected
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AccessMode {
+    #[default]
+    None,
+    ApiKey(String), // Expected in header X-API-Key
+    Basic { username: String, password: String }, // HTTP basic auth
+    Session // Cookie issued after the Strava login, binds the client to the athlete who authorized
+}
+
+/// Result of an access check, see [AccessControl::check]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessDecision {
+    Granted,
+    Unauthenticated,
+    Forbidden,
+    Redirect(String) // Endpoint without athlete path, redirected to the endpoint of the session athlete
+}
+
+/// Credentials sent with a request, extracted by [crate::rest::access_layer::AccessLayer]
+#[derive(Debug, Default)]
+pub struct Credentials<'a> {
+    pub api_key: Option<&'a str>,
+    pub authorization: Option<&'a str>,
+    pub session: Option<&'a str>
+}
+
+/// Protects the endpoints according to the [AccessMode]. In mode [AccessMode::Session],
+/// it also holds the sessions, which are kept in memory only (like the tokens).
+pub struct AccessControl {
+    mode: AccessMode,
+    secure_cookie: bool, // Session cookie is sent over HTTPS only
+    metrics_token: Option<String>, // Bearer token granting access to the metrics in every mode
+    sessions: BTreeMap<String, u64> // Session id -> athlete id
+}
+
+impl AccessControl {
+    pub fn new(mode: AccessMode, secure_cookie: bool) -> Self {
+        Self { mode, secure_cookie, metrics_token: None, sessions: BTreeMap::new() }
+    }
+
+    /// Lets a scraper like Prometheus read the metrics with header `Authorization: Bearer <token>`,
+    /// which is the only way to read them in mode [AccessMode::Session]
+    pub fn with_metrics_token(mut self, metrics_token: Option<String>) -> Self {
+        self.metrics_token = metrics_token;
+        self
+    }
+
+    pub fn mode(&self) -> &AccessMode {
+        &self.mode
+    }
+
+    /// Decides about a request with the method to the given route (the path template of the router, or None for
+    /// static files of the console), where `path` is the actual path and `query` the query string.
+    pub fn check(&self, method: &Method, route: Option<&str>, path: &str, query: Option<&str>, credentials: &Credentials) -> AccessDecision {
+        if route == Some(AUTH_CALLBACK) {
+            return AccessDecision::Granted // Protected by the CSRF state of the auth-code flow
+        }
+        if route == Some(HEALTHZ) || route == Some(READYZ) {
+            return AccessDecision::Granted // Probes of the container orchestration do not authenticate
+        }
+        if route == Some(METRICS) && self.is_metrics_token(credentials) {
+            return AccessDecision::Granted
+        }
+        match &self.mode {
+            AccessMode::None => AccessDecision::Granted,
+            AccessMode::ApiKey(api_key) => match route {
+                None => AccessDecision::Granted,
+                Some(_) if credentials.api_key.is_some_and(|key| equals(key, api_key)) => AccessDecision::Granted,
+                Some(_) => AccessDecision::Unauthenticated
+            }
+            AccessMode::Basic { username, password } => {
+                let expected = format!("Basic {}", STANDARD.encode(format!("{username}:{password}")));
+                match credentials.authorization {
+                    Some(authorization) if equals(authorization, &expected) => AccessDecision::Granted,
+                    _ => AccessDecision::Unauthenticated
+                }
+            }
+            AccessMode::Session => match route {
+                None => AccessDecision::Granted,
+                Some(AUTHORIZE) => AccessDecision::Granted, // Login
+                Some(route) => match credentials.session.and_then(|session| self.sessions.get(session)) {
+                    None => AccessDecision::Unauthenticated,
+                    Some(athlete_id) => Self::check_athlete(*athlete_id, method, route, path, query)
+                }
+            }
+        }
+    }
+
+    fn check_athlete(athlete_id: u64, method: &Method, route: &str, path: &str, query: Option<&str>) -> AccessDecision {
+        // These endpoints concern all athletes: the athlete list, the metrics, and the (global) download filter
+        if route == ATHLETES || route == METRICS || (route == FILTER && method == Method::PUT) {
+            return AccessDecision::Forbidden
+        }
+        if matches!(route, STATUS | TOGGLE | LOGOUT | EVENTS | DOWNLOAD_START | DOWNLOAD_STOP | ACTIVITY_REFETCH | TRACKS_RETRY) {
+            // Each of these routes has an athlete route with the same path below /athletes/{athlete_id}
+            let location = format!("{ATHLETES}/{athlete_id}{path}");
+            return match query {
+                Some(query) => AccessDecision::Redirect(format!("{location}?{query}")),
+                None => AccessDecision::Redirect(location)
+            }
+        }
+        match path.strip_prefix(ATHLETES).and_then(|rest| rest.strip_prefix('/')) {
+            Some(rest) if rest.split('/').next() != Some(athlete_id.to_string().as_str()) => AccessDecision::Forbidden,
+            _ => AccessDecision::Granted
+        }
+    }
+
+    fn is_metrics_token(&self, credentials: &Credentials) -> bool {
+        match (&self.metrics_token, credentials.authorization) {
+            (Some(token), Some(authorization)) => equals(authorization, &format!("Bearer {token}")),
+            _ => false
+        }
+    }
+
+    /// Creates a session for the athlete if sessions are enabled, and returns the value of the Set-Cookie header
+    pub fn create_session(&mut self, athlete_id: u64) -> Option<String> {
+        if self.mode != AccessMode::Session {
+            return None
+        }
+        let session = CsrfToken::new_random().secret().clone();
+        self.sessions.insert(session.clone(), athlete_id);
+        let secure = if self.secure_cookie { "; Secure" } else { "" };
+        Some(format!("{SESSION_COOKIE}={session}; Path=/; HttpOnly; SameSite=Lax{secure}"))
+    }
+
+    /// Removes all sessions of the athlete
+    pub fn remove_sessions(&mut self, athlete_id: u64) {
+        self.sessions.retain(|_, id| *id != athlete_id);
+    }
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        Self::new(AccessMode::None, true)
+    }
+}
+
+/// Compares in constant time (for strings of equal length), so that secrets cannot be guessed by timing
+fn equals(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use crate::rest::access_control::{AccessControl, AccessDecision, AccessMode, Credentials};
+    use crate::rest::rest_paths::{ACTIVITY_REFETCH, ATHLETES, ATHLETE_GEAR, AUTHORIZE, FILTER, METRICS, TRACKS_RETRY, AUTH_CALLBACK, DOWNLOAD_START, EVENTS, READYZ, STATUS};
+
+    fn session_of(cookie: &str) -> &str {
+        cookie.split(';').next().unwrap().strip_prefix("session=").unwrap()
+    }
+
+    #[test]
+    fn test_none() {
+        let access = AccessControl::default();
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &Credentials::default()), AccessDecision::Granted);
+    }
+
+    #[test]
+    fn test_api_key() {
+        let access = AccessControl::new(AccessMode::ApiKey("secret".to_string()), true);
+        let valid = Credentials { api_key: Some("secret"), ..Credentials::default() };
+        let invalid = Credentials { api_key: Some("secreT"), ..Credentials::default() };
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &valid), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &invalid), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, Some(AUTHORIZE), AUTHORIZE, None, &Credentials::default()), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, Some(AUTH_CALLBACK), AUTH_CALLBACK, None, &Credentials::default()), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, None, "/index.html", None, &Credentials::default()), AccessDecision::Granted);
+    }
+
+    #[test]
+    fn test_basic() {
+        let access = AccessControl::new(AccessMode::Basic { username: "user".to_string(), password: "pass".to_string() }, true);
+        let valid = Credentials { authorization: Some("Basic dXNlcjpwYXNz"), ..Credentials::default() };
+        let invalid = Credentials { authorization: Some("Basic dXNlcjpwYXNx"), ..Credentials::default() };
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &valid), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &invalid), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, None, "/index.html", None, &Credentials::default()), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, Some(READYZ), READYZ, None, &Credentials::default()), AccessDecision::Granted);
+    }
+
+    #[test]
+    fn test_session() {
+        let mut access = AccessControl::new(AccessMode::Session, false);
+        let cookie = access.create_session(4711).unwrap();
+        assert!(cookie.ends_with("; Path=/; HttpOnly; SameSite=Lax"));
+        let valid = Credentials { session: Some(session_of(&cookie)), ..Credentials::default() };
+
+        assert_eq!(access.check(&Method::GET, Some(ATHLETE_GEAR), "/athletes/4711/gear", None, &valid), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, Some(ATHLETE_GEAR), "/athletes/4712/gear", None, &valid), AccessDecision::Forbidden);
+        assert_eq!(access.check(&Method::GET, Some(ATHLETES), ATHLETES, None, &valid), AccessDecision::Forbidden);
+        assert_eq!(access.check(&Method::GET, Some(METRICS), METRICS, None, &valid), AccessDecision::Forbidden);
+        assert_eq!(access.check(&Method::PUT, Some(FILTER), FILTER, None, &valid), AccessDecision::Forbidden);
+        assert_eq!(access.check(&Method::GET, Some(FILTER), FILTER, None, &valid), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, Some("x=1"), &valid), AccessDecision::Redirect("/athletes/4711/status?x=1".to_string()));
+        assert_eq!(access.check(&Method::GET, Some(DOWNLOAD_START), DOWNLOAD_START, Some("phase=tracks"), &valid),
+                   AccessDecision::Redirect("/athletes/4711/download/start?phase=tracks".to_string()));
+        assert_eq!(access.check(&Method::GET, Some(EVENTS), EVENTS, None, &valid), AccessDecision::Redirect("/athletes/4711/events".to_string()));
+        assert_eq!(access.check(&Method::GET, Some(ACTIVITY_REFETCH), "/activities/5/refetch", None, &valid),
+                   AccessDecision::Redirect("/athletes/4711/activities/5/refetch".to_string()));
+        assert_eq!(access.check(&Method::GET, Some(TRACKS_RETRY), TRACKS_RETRY, Some("reason=not_found"), &valid),
+                   AccessDecision::Redirect("/athletes/4711/tracks/retry?reason=not_found".to_string()));
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &Credentials::default()), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, Some(AUTHORIZE), AUTHORIZE, None, &Credentials::default()), AccessDecision::Granted);
+
+        access.remove_sessions(4711);
+        assert_eq!(access.check(&Method::GET, Some(ATHLETE_GEAR), "/athletes/4711/gear", None, &valid), AccessDecision::Unauthenticated);
+    }
+
+    #[test]
+    fn test_metrics_token() {
+        let access = AccessControl::new(AccessMode::Session, false).with_metrics_token(Some("scrape".to_string()));
+        let valid = Credentials { authorization: Some("Bearer scrape"), ..Credentials::default() };
+        let invalid = Credentials { authorization: Some("Bearer scrapE"), ..Credentials::default() };
+        assert_eq!(access.check(&Method::GET, Some(METRICS), METRICS, None, &valid), AccessDecision::Granted);
+        assert_eq!(access.check(&Method::GET, Some(METRICS), METRICS, None, &invalid), AccessDecision::Unauthenticated);
+        assert_eq!(access.check(&Method::GET, Some(STATUS), STATUS, None, &valid), AccessDecision::Unauthenticated);
+    }
+
+    #[test]
+    fn test_secure_cookie() {
+        let mut access = AccessControl::new(AccessMode::Session, true);
+        assert!(access.create_session(4711).unwrap().ends_with("; Secure"));
+        assert_eq!(AccessControl::default().create_session(4711), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use axum::extract::MatchedPath;
+use axum::http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
+use log::{debug, warn};
+use tower::{Layer, Service};
+use crate::rest::access_control::{AccessDecision, AccessMode, Credentials, API_KEY_HEADER, SESSION_COOKIE};
+use crate::state::shared_state::MutexSharedState;
+
+const REALM: &str = "Basic realm=\"Strava Activity Downloader\"";
+
+/// Rejects requests that are not permitted by the [crate::rest::access_control::AccessControl]
+/// of the shared state. Must be applied with `Router::layer`, so that the matched route is known.
+#[derive(Clone)]
+pub struct AccessLayer {
+    state: MutexSharedState
+}
+
+impl AccessLayer {
+    pub fn new(state: MutexSharedState) -> Self {
+        Self { state }
+    }
+}
+
+impl<S> Layer<S> for AccessLayer {
+    type Service = AccessMiddleware<S>;
+    fn layer(&self, service: S) -> Self::Service {
+        AccessMiddleware { service, state: self.state.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessMiddleware<S> {
+    service: S,
+    state: MutexSharedState
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessMiddleware<S>
+    where
+        S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+        S::Future: Send,
+        ReqBody: Send + 'static,
+        ResBody: Default
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The polled service must be used for the call, see https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        let clone = self.service.clone();
+        let mut service = std::mem::replace(&mut self.service, clone);
+        let state = self.state.clone();
+        Box::pin(async move {
+            let (decision, basic) = {
+                let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str());
+                let credentials = credentials(req.headers());
+                let guard = state.lock().await;
+                let decision = guard.access.check(req.method(), route, req.uri().path(), req.uri().query(), &credentials);
+                (decision, matches!(guard.access.mode(), AccessMode::Basic { .. }))
+            };
+            match decision {
+                AccessDecision::Granted => service.call(req).await,
+                AccessDecision::Unauthenticated => {
+                    debug!("Reject unauthenticated request to {}", req.uri());
+                    let mut response = status_response(StatusCode::UNAUTHORIZED);
+                    if basic {
+                        response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(REALM));
+                    }
+                    Ok(response)
+                }
+                AccessDecision::Forbidden => {
+                    warn!("Reject request to {} of another athlete", req.uri());
+                    Ok(status_response(StatusCode::FORBIDDEN))
+                }
+                AccessDecision::Redirect(location) => {
+                    let mut response = status_response(StatusCode::TEMPORARY_REDIRECT);
+                    if let Ok(location) = HeaderValue::try_from(location) {
+                        response.headers_mut().insert(header::LOCATION, location);
+                    }
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+fn status_response<B: Default>(status: StatusCode) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = status;
+    response
+}
+
+fn credentials(headers: &HeaderMap) -> Credentials<'_> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let session = headers.get_all(header::COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value);
+    Credentials {
+        api_key: header(API_KEY_HEADER),
+        authorization: header(header::AUTHORIZATION.as_str()),
+        session
+    }
+}
@@ -1,6 +1,6 @@
 use axum::Router;
 use axum::http::Method;
-use axum::routing::get;
+use axum::routing::{get, post, put};
 use log::{debug, info};
 use tokio::net::TcpListener;
 use tokio::sync::broadcast::Receiver;
@@ -8,9 +8,10 @@ use tokio::task::JoinHandle;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
-use crate::rest::rest_handlers::{status_handler, toggle_handler};
-use crate::rest::oauth_handlers::{authorize_handler, callback_handler};
-use crate::rest::rest_paths::{AUTH_CALLBACK, AUTHORIZE, STATUS, TOGGLE, CONSOLE_DIR};
+use crate::rest::rest_handlers::{refetch_handler, athlete_refetch_handler, retry_handler, athlete_retry_handler, athlete_best_efforts_handler, athlete_start_handler, athlete_stop_handler, start_handler, stop_handler, athlete_events_handler, events_handler, athlete_gear_handler, athlete_jobs_handler, athlete_segment_efforts_handler, athlete_status_handler, athlete_toggle_handler, athletes_handler, filter_handler, put_filter_handler, healthz_handler, metrics_handler, readyz_handler, status_handler, toggle_handler};
+use crate::rest::oauth_handlers::{athlete_logout_handler, authorize_handler, callback_handler, logout_handler};
+use crate::rest::rest_paths::{AUTH_CALLBACK, AUTHORIZE, STATUS, TOGGLE, FILTER, CONSOLE_DIR, METRICS, HEALTHZ, READYZ, ATHLETES, ATHLETE_STATUS, ATHLETE_TOGGLE, LOGOUT, ATHLETE_LOGOUT, ATHLETE_GEAR, ATHLETE_SEGMENT_EFFORTS, ATHLETE_BEST_EFFORTS, ATHLETE_JOBS, EVENTS, ATHLETE_EVENTS, DOWNLOAD_START, DOWNLOAD_STOP, ATHLETE_DOWNLOAD_START, ATHLETE_DOWNLOAD_STOP, ACTIVITY_REFETCH, ATHLETE_ACTIVITY_REFETCH, TRACKS_RETRY, ATHLETE_TRACKS_RETRY};
+use crate::rest::access_layer::AccessLayer;
 use crate::rest::timing_layer::TimingLayer;
 use crate::state::shared_state::MutexSharedState;
 
@@ -19,15 +20,40 @@ pub fn spawn_http_server(listener: TcpListener, state: MutexSharedState, mut rx_
     info!("Spawn HTTP server");
 
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::PUT])
+        .allow_methods([Method::GET, Method::PUT, Method::POST])
         .allow_origin(Any);
 
     let router = Router::new()
         .route(STATUS, get(status_handler))
         .route(TOGGLE, get(toggle_handler))
+        .route(DOWNLOAD_START, post(start_handler))
+        .route(DOWNLOAD_STOP, post(stop_handler))
+        .route(FILTER, get(filter_handler))
+        .route(FILTER, put(put_filter_handler))
+        .route(EVENTS, get(events_handler))
+        .route(ACTIVITY_REFETCH, post(refetch_handler))
+        .route(TRACKS_RETRY, post(retry_handler))
+        .route(METRICS, get(metrics_handler))
+        .route(HEALTHZ, get(healthz_handler))
+        .route(READYZ, get(readyz_handler))
+        .route(ATHLETES, get(athletes_handler))
+        .route(ATHLETE_STATUS, get(athlete_status_handler))
+        .route(ATHLETE_TOGGLE, get(athlete_toggle_handler))
+        .route(ATHLETE_DOWNLOAD_START, post(athlete_start_handler))
+        .route(ATHLETE_DOWNLOAD_STOP, post(athlete_stop_handler))
+        .route(ATHLETE_GEAR, get(athlete_gear_handler))
+        .route(ATHLETE_SEGMENT_EFFORTS, get(athlete_segment_efforts_handler))
+        .route(ATHLETE_BEST_EFFORTS, get(athlete_best_efforts_handler))
+        .route(ATHLETE_JOBS, get(athlete_jobs_handler))
+        .route(ATHLETE_EVENTS, get(athlete_events_handler))
+        .route(ATHLETE_ACTIVITY_REFETCH, post(athlete_refetch_handler))
+        .route(ATHLETE_TRACKS_RETRY, post(athlete_retry_handler))
         .route(AUTHORIZE, get(authorize_handler))
         .route(AUTH_CALLBACK, get(callback_handler))
+        .route(LOGOUT, post(logout_handler))
+        .route(ATHLETE_LOGOUT, post(athlete_logout_handler))
         .fallback_service(ServeDir::new(CONSOLE_DIR))
+        .layer(ServiceBuilder::new().layer(AccessLayer::new(state.clone())))
         .layer(ServiceBuilder::new().layer(cors))
         .layer(ServiceBuilder::new().layer(TimingLayer))
         .with_state(state);
@@ -1,5 +1,7 @@
 pub mod http_server;
 pub mod rest_paths;
+pub mod access_control;
 mod rest_handlers;
 mod oauth_handlers;
-mod timing_layer;
\ No newline at end of file
+mod timing_layer;
+mod access_layer;
\ No newline at end of file
@@ -1,20 +1,36 @@
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Redirect, Response};
+use axum::{BoxError, Json};
 use axum_macros::debug_handler;
 use log::{debug, info, warn};
 use serde::Deserialize;
+use crate::domain::download_state::DownloadState;
+use crate::rest::access_control::AccessMode;
 use crate::state::shared_state::MutexSharedState;
 
+#[derive(Deserialize)]
+pub struct AuthorizeQuery {
+    athlete: Option<u64>
+}
+
 #[derive(Deserialize)]
 pub struct CallbackQuery {
     code: String,
     state: String,
 }
 
-pub async fn authorize_handler(State(state): State<MutexSharedState>) -> Result<Response, StatusCode> {
+/// Starts an auth-code flow, unless the athlete passed as query parameter is already authorized.
+/// Without athlete parameter, a new flow is always started, because any athlete may authorize.
+/// With session cookies, the flow is always started, because it issues the cookie.
+pub async fn authorize_handler(State(state): State<MutexSharedState>, query: Query<AuthorizeQuery>) -> Result<Response, StatusCode> {
     let mut guard = state.lock().await;
-    match guard.oauth.get_bearer().await {
+    let athlete_id = query.athlete.filter(|_| *guard.access.mode() != AccessMode::Session);
+    let bearer = match athlete_id {
+        Some(athlete_id) => guard.oauth.get_bearer(athlete_id).await,
+        None => Ok(None)
+    };
+    match bearer {
         Ok(bearer) => {
             match bearer {
                 Some(_) => {
@@ -36,16 +52,88 @@ pub async fn authorize_handler(State(state): State<MutexSharedState>) -> Result<
 }
 
 #[debug_handler]
-pub async fn callback_handler(State(state): State<MutexSharedState>, query: Query<CallbackQuery>) -> Result<Redirect, StatusCode> {
+pub async fn callback_handler(State(state): State<MutexSharedState>, query: Query<CallbackQuery>) -> Result<Response, StatusCode> {
     debug!("Authorized with code {}", query.code);
     let mut guard = state.lock().await;
     match guard.oauth.callback_auth_code_grant(&query.code, &query.state).await {
-        Ok(uri) => {
+        Ok((athlete_id, uri)) => {
+            drop(guard);
+            if let Err(error) = adopt_legacy_data(&state, athlete_id).await {
+                warn!("Failed to assign legacy data to athlete {athlete_id}: {error}");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            // Pass the athlete to the console, so that it can use the athlete-specific endpoints
+            let separator = if uri.contains('?') { '&' } else { '?' };
+            let uri = format!("{uri}{separator}athlete={athlete_id}");
             debug!("Redirect to origin URL: {}", uri);
-            Ok(Redirect::temporary(uri.as_str()))
+            let mut response = Redirect::temporary(uri.as_str()).into_response();
+            if let Some(cookie) = state.lock().await.access.create_session(athlete_id) {
+                let cookie = cookie.parse().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                response.headers_mut().insert(header::SET_COOKIE, cookie);
+            }
+            Ok(response)
         }
         Err(_) => {
             Err(StatusCode::UNAUTHORIZED)
         }
     }
 }
+
+/// Assigns activities and tracks downloaded before multi-athlete support was added to the athlete
+/// configured as their owner. As any Strava user may authorize, they are never assigned to anybody else.
+async fn adopt_legacy_data(state: &MutexSharedState, athlete_id: u64) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    if guard.legacy_athlete != Some(athlete_id) {
+        return Ok(())
+    }
+    if guard.service.adopt_legacy_activities(athlete_id).await? {
+        guard.tracks.adopt_legacy_tracks(athlete_id)?;
+        if let Some(athlete) = guard.athletes.get_mut(&athlete_id) {
+            athlete.activity_stats = None; // Force reloading the stats
+        }
+    }
+    Ok(())
+}
+
+/// Logs out the default athlete, see [crate::state::shared_state::SharedState::default_athlete]
+#[debug_handler]
+pub async fn logout_handler(State(state): State<MutexSharedState>) -> Result<Json<DownloadState>, StatusCode> {
+    let athlete_id = state.lock().await.default_athlete();
+    match athlete_id {
+        Some(athlete_id) => logout(&state, athlete_id).await,
+        None => Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+#[debug_handler]
+pub async fn athlete_logout_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>)
+    -> Result<Json<DownloadState>, StatusCode> {
+    logout(&state, athlete_id).await
+}
+
+/// Revokes the access to the athlete's data at Strava, removes the token, and stops downloading.
+/// If Strava cannot be reached, the token is removed nevertheless, but the athlete should
+/// revoke the access in the Strava settings.
+async fn logout(state: &MutexSharedState, athlete_id: u64) -> Result<Json<DownloadState>, StatusCode> {
+    let mut guard = state.lock().await;
+    let result = guard.oauth.deauthorize(athlete_id).await;
+    if let Ok(false) = result {
+        info!("Athlete {athlete_id} not authorized, cannot log out");
+        return Err(StatusCode::UNAUTHORIZED)
+    }
+    guard.access.remove_sessions(athlete_id);
+    guard.set_download_state(athlete_id, DownloadState::Unauthorized);
+    if let Err(error) = guard.save_download_state(athlete_id).await {
+        warn!("Failed to store the download state of athlete {athlete_id}: {error}");
+    }
+    if let Err(error) = guard.send_server_status(athlete_id).await {
+        warn!("Failed to send the status of athlete {athlete_id}: {error}");
+    }
+    match result {
+        Ok(_) => Ok(Json(DownloadState::Unauthorized)),
+        Err(error) => {
+            warn!("Failed to deauthorize athlete {athlete_id}: {error}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
@@ -1,24 +1,45 @@
 use axum::{BoxError, Error, Json};
-use axum::extract::State;
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
 use axum::response::Sse;
 use axum::response::sse::Event;
 use axum::http::Uri;
 use axum_macros::debug_handler;
 use futures::Stream;
 use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
-use crate::domain::download_state::DownloadState;
+use crate::domain::download_event::DownloadEvent;
+use crate::domain::download_filter::DownloadFilter;
+use crate::domain::download_job::DownloadJob;
+use crate::domain::download_state::{DownloadState, StartPhase};
+use crate::domain::effort::{BestEffort, SegmentEffort};
+use crate::domain::gear::GearUsage;
+use crate::domain::missing_reason::MissingReason;
+use crate::domain::readiness::Readiness;
 use crate::domain::server_status::ServerStatus;
-use crate::state::shared_state::MutexSharedState;
+use crate::state::shared_state::{MutexSharedState, SharedState};
+use crate::util::iso8601;
+use crate::util::metrics::metrics;
 
-#[allow(dead_code)]
-fn reqwest_error(error: reqwest::Error) -> StatusCode {
-    warn!("{}", error);
-    // Need to map reqwest::StatusCode to axum::http::StatusCode.
-    // Note that both types are actually aliases of http::StatusCode, but Rust complains.
-    let status = error.status().map(|e| e.as_u16()).unwrap_or(500_u16);
-    StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+const MAX_EVENTS: u32 = 1000; // Events returned per request
+const DOWNLOAD_EVENT: &str = "download_event"; // SSE event type of the logged events
+
+#[derive(Deserialize)]
+pub struct StartQuery {
+    #[serde(default)]
+    phase: StartPhase
+}
+
+#[derive(Deserialize)]
+pub struct RetryQuery {
+    reason: Option<MissingReason> // All reasons if not given
+}
+
+#[derive(Deserialize)]
+pub struct EventQuery {
+    since: Option<String> // ISO 8601, inclusive
 }
 
 fn internal_server_error(error: BoxError) -> StatusCode {
@@ -26,35 +47,340 @@ fn internal_server_error(error: BoxError) -> StatusCode {
     StatusCode::INTERNAL_SERVER_ERROR
 }
 
+#[debug_handler]
+pub async fn athletes_handler(State(state): State<MutexSharedState>, uri: Uri) -> Json<Vec<u64>> {
+    debug!("Enter {uri}");
+    let guard = state.lock().await;
+    Json(guard.oauth.athletes())
+}
+
+/// Returns the metrics in the Prometheus text format
+#[debug_handler]
+pub async fn metrics_handler(State(state): State<MutexSharedState>) -> ([(header::HeaderName, &'static str); 1], String) {
+    let download_states: Vec<(u64, String)> = state.lock().await.athletes.iter()
+        .map(|(athlete_id, athlete)| (*athlete_id, format!("{:?}", athlete.download_state)))
+        .collect();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics().render(&download_states))
+}
+
+/// Liveness probe, answers as long as the process serves requests
+#[debug_handler]
+pub async fn healthz_handler() -> Json<&'static str> {
+    Json("alive")
+}
+
+/// Readiness probe, see [crate::state::shared_state::SharedState::check_readiness]
+#[debug_handler]
+pub async fn readyz_handler(State(state): State<MutexSharedState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = state.lock().await.check_readiness().await;
+    if readiness.is_ready() {
+        (StatusCode::OK, Json(readiness))
+    } else {
+        warn!("Not ready: {:?}", readiness);
+        (StatusCode::SERVICE_UNAVAILABLE, Json(readiness))
+    }
+}
+
+/// Toggles the download state of the default athlete, see [crate::state::shared_state::SharedState::default_athlete]
 #[debug_handler]
 pub async fn toggle_handler(State(state): State<MutexSharedState>, uri: Uri)
     -> Result<Json<DownloadState>, StatusCode> {
     debug!("Enter {uri}");
+    let athlete_id = state.lock().await.default_athlete();
+    match athlete_id {
+        Some(athlete_id) => toggle(&state, athlete_id).await,
+        None => {
+            info!("Unauthorized, cannot enable the download scheduler");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn athlete_toggle_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Json<DownloadState>, StatusCode> {
+    debug!("Enter {uri}");
+    toggle(&state, athlete_id).await
+}
+
+/// Starts downloading for the default athlete, see [start]
+#[debug_handler]
+pub async fn start_handler(State(state): State<MutexSharedState>, query: Query<StartQuery>, uri: Uri)
+    -> Result<Json<DownloadState>, StatusCode> {
+    debug!("Enter {uri}");
+    let athlete_id = default_athlete(&state).await?;
+    start(&state, athlete_id, query.phase).await
+}
+
+#[debug_handler]
+pub async fn athlete_start_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, query: Query<StartQuery>, uri: Uri)
+    -> Result<Json<DownloadState>, StatusCode> {
+    debug!("Enter {uri}");
+    start(&state, athlete_id, query.phase).await
+}
+
+/// Stops downloading for the default athlete, see [stop]
+#[debug_handler]
+pub async fn stop_handler(State(state): State<MutexSharedState>, uri: Uri) -> Result<Json<DownloadState>, StatusCode> {
+    debug!("Enter {uri}");
+    let athlete_id = default_athlete(&state).await?;
+    stop(&state, athlete_id).await
+}
+
+#[debug_handler]
+pub async fn athlete_stop_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Json<DownloadState>, StatusCode> {
+    debug!("Enter {uri}");
+    stop(&state, athlete_id).await
+}
+
+/// Returns the filter restricting the downloaded activities
+#[debug_handler]
+pub async fn filter_handler(State(state): State<MutexSharedState>, uri: Uri) -> Json<DownloadFilter> {
+    debug!("Enter {uri}");
+    let guard = state.lock().await;
+    Json(guard.filter.clone())
+}
+
+/// Replaces the filter restricting the downloaded activities. It applies to the next
+/// requests of an ongoing download.
+#[debug_handler]
+pub async fn put_filter_handler(State(state): State<MutexSharedState>, uri: Uri, Json(filter): Json<DownloadFilter>)
+    -> Result<Json<DownloadFilter>, StatusCode> {
+    debug!("Enter {uri}");
+    let filter = filter.normalized().map_err(|error| {
+        info!("{error}");
+        StatusCode::BAD_REQUEST
+    })?;
+    let mut guard = state.lock().await;
+    info!("Set download filter {filter:?}");
+    guard.filter = filter.clone();
+    Ok(Json(filter))
+}
+
+/// Returns the bikes and shoes of the athlete with the distance of the downloaded activities
+#[debug_handler]
+pub async fn athlete_gear_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Json<Vec<GearUsage>>, StatusCode> {
+    debug!("Enter {uri}");
+    let mut guard = state.lock().await;
+    let usage = guard.service.get_gear_usage(athlete_id).await.map_err(internal_server_error)?;
+    Ok(Json(usage))
+}
+
+/// Returns all efforts of the athlete on the segment in chronological order
+#[debug_handler]
+pub async fn athlete_segment_efforts_handler(State(state): State<MutexSharedState>, Path((athlete_id, segment_id)): Path<(u64, u64)>, uri: Uri)
+    -> Result<Json<Vec<SegmentEffort>>, StatusCode> {
+    debug!("Enter {uri}");
+    let mut guard = state.lock().await;
+    let efforts = guard.service.get_segment_efforts(athlete_id, segment_id).await.map_err(internal_server_error)?;
+    Ok(Json(efforts))
+}
+
+/// Returns the current best effort of the athlete for each distance (like "5k")
+#[debug_handler]
+pub async fn athlete_best_efforts_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Json<Vec<BestEffort>>, StatusCode> {
+    debug!("Enter {uri}");
+    let mut guard = state.lock().await;
+    let efforts = guard.service.get_best_efforts(athlete_id).await.map_err(internal_server_error)?;
+    Ok(Json(efforts))
+}
+
+/// Returns the pending download jobs of the athlete in the order of execution
+#[debug_handler]
+pub async fn athlete_jobs_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Json<Vec<DownloadJob>>, StatusCode> {
+    debug!("Enter {uri}");
+    let mut guard = state.lock().await;
+    let jobs = guard.service.get_jobs(athlete_id).await.map_err(internal_server_error)?;
+    Ok(Json(jobs))
+}
+
+/// Re-downloads the track of the activity, see [refetch]
+#[debug_handler]
+pub async fn refetch_handler(State(state): State<MutexSharedState>, Path(activity_id): Path<u64>, uri: Uri) -> StatusCode {
+    debug!("Enter {uri}");
+    refetch(&state, None, activity_id).await
+}
+
+#[debug_handler]
+pub async fn athlete_refetch_handler(State(state): State<MutexSharedState>, Path((athlete_id, activity_id)): Path<(u64, u64)>, uri: Uri)
+    -> StatusCode {
+    debug!("Enter {uri}");
+    refetch(&state, Some(athlete_id), activity_id).await
+}
+
+/// Retries the missing tracks of all athletes, see [retry]
+#[debug_handler]
+pub async fn retry_handler(State(state): State<MutexSharedState>, query: Query<RetryQuery>, uri: Uri) -> Result<Json<u64>, StatusCode> {
+    debug!("Enter {uri}");
+    retry(&state, None, query.reason).await
+}
+
+#[debug_handler]
+pub async fn athlete_retry_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, query: Query<RetryQuery>, uri: Uri)
+    -> Result<Json<u64>, StatusCode> {
+    debug!("Enter {uri}");
+    retry(&state, Some(athlete_id), query.reason).await
+}
+
+/// Returns the logged events of all athletes, see [events]
+#[debug_handler]
+pub async fn events_handler(State(state): State<MutexSharedState>, query: Query<EventQuery>, uri: Uri)
+    -> Result<Json<Vec<DownloadEvent>>, StatusCode> {
+    debug!("Enter {uri}");
+    events(&state, None, query.0).await
+}
+
+#[debug_handler]
+pub async fn athlete_events_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, query: Query<EventQuery>, uri: Uri)
+    -> Result<Json<Vec<DownloadEvent>>, StatusCode> {
+    debug!("Enter {uri}");
+    events(&state, Some(athlete_id), query.0).await
+}
+
+/// Returns the logged events of the download scheduler at or after the given time in the order
+/// of their creation, at most [MAX_EVENTS]. Without time, the events are returned from the beginning.
+async fn events(state: &MutexSharedState, athlete_id: Option<u64>, query: EventQuery) -> Result<Json<Vec<DownloadEvent>>, StatusCode> {
+    let since = match query.since {
+        Some(since) => iso8601::try_string_to_secs(&since).ok_or_else(|| {
+            info!("Invalid timestamp '{since}'");
+            StatusCode::BAD_REQUEST
+        })?,
+        None => 0
+    };
+    let mut guard = state.lock().await;
+    let events = guard.service.get_events(athlete_id, &iso8601::secs_to_string(since), MAX_EVENTS).await
+        .map_err(internal_server_error)?;
+    Ok(Json(events))
+}
+
+/// Resets the track of the activity (of the athlete, if given) and enqueues its download at high priority.
+/// The download of the tracks is started if necessary, but runs asynchronously, so the request is only accepted.
+async fn refetch(state: &MutexSharedState, athlete_id: Option<u64>, activity_id: u64) -> StatusCode {
+    let mut guard = state.lock().await;
+    let activity = match guard.service.get_by_id(activity_id).await {
+        Ok(Some(activity)) if athlete_id.is_none_or(|id| id == activity.athlete_id) => activity,
+        Ok(_) => {
+            info!("Activity {activity_id} not found");
+            return StatusCode::NOT_FOUND
+        }
+        Err(error) => return internal_server_error(error)
+    };
+    if let Err(error) = guard.refetch_track(&activity).await {
+        return internal_server_error(error)
+    }
+    send_server_status(&mut guard, activity.athlete_id).await;
+    StatusCode::ACCEPTED
+}
+
+/// Resets the missing tracks for the given reason (or all reasons) and enqueues their download.
+/// Returns the number of reset tracks, which are downloaded with the next download of the tracks.
+async fn retry(state: &MutexSharedState, athlete_id: Option<u64>, reason: Option<MissingReason>) -> Result<Json<u64>, StatusCode> {
+    let mut guard = state.lock().await;
+    let count = guard.retry_missing_tracks(athlete_id, reason).await.map_err(internal_server_error)?;
+    if let Some(athlete_id) = athlete_id {
+        send_server_status(&mut guard, athlete_id).await;
+    }
+    Ok(Json(count))
+}
+
+async fn toggle(state: &MutexSharedState, athlete_id: u64) -> Result<Json<DownloadState>, StatusCode> {
     let mut guard = state.lock().await;
-    match guard.oauth.get_bearer().await.map_err(internal_server_error)? {
+    match guard.oauth.get_bearer(athlete_id).await.map_err(internal_server_error)? {
         Some(_) => {
-            guard.download_state = guard.download_state.toggle();
-            Ok(Json(guard.download_state.clone()))
+            let download_state = guard.get_download_state(athlete_id).toggle();
+            guard.set_download_state(athlete_id, download_state.clone());
+            guard.save_download_state(athlete_id).await.map_err(internal_server_error)?;
+            Ok(Json(download_state))
         },
         None => {
-            info!("Unauthorized, cannot enable the download scheduler");
+            info!("Athlete {athlete_id} unauthorized, cannot enable the download scheduler");
             Err(StatusCode::UNAUTHORIZED)
         }
     }
 }
 
+async fn default_athlete(state: &MutexSharedState) -> Result<u64, StatusCode> {
+    state.lock().await.default_athlete().ok_or_else(|| {
+        info!("Unauthorized, no default athlete");
+        StatusCode::UNAUTHORIZED
+    })
+}
+
+/// Starts downloading in the given phase. Unlike [toggle], it is idempotent: if the athlete
+/// is downloading already, the download continues unchanged.
+async fn start(state: &MutexSharedState, athlete_id: u64, phase: StartPhase) -> Result<Json<DownloadState>, StatusCode> {
+    let mut guard = state.lock().await;
+    if guard.oauth.get_bearer(athlete_id).await.map_err(internal_server_error)?.is_none() {
+        info!("Athlete {athlete_id} unauthorized, cannot start downloading");
+        return Err(StatusCode::UNAUTHORIZED)
+    }
+    let download_state = guard.start_download(athlete_id, phase).await.map_err(internal_server_error)?;
+    send_server_status(&mut guard, athlete_id).await;
+    Ok(Json(download_state))
+}
+
+/// Stops downloading. Unlike [toggle], it is idempotent: a state that is not downloading is kept.
+async fn stop(state: &MutexSharedState, athlete_id: u64) -> Result<Json<DownloadState>, StatusCode> {
+    let mut guard = state.lock().await;
+    let download_state = guard.get_download_state(athlete_id).stop();
+    guard.set_download_state(athlete_id, download_state.clone());
+    guard.save_download_state(athlete_id).await.map_err(internal_server_error)?;
+    send_server_status(&mut guard, athlete_id).await;
+    Ok(Json(download_state))
+}
+
+/// Informs the SSE clients (like other browser tabs) about the changed download state
+async fn send_server_status(guard: &mut SharedState, athlete_id: u64) {
+    if let Err(error) = guard.send_server_status(athlete_id).await {
+        warn!("Failed to send the status of athlete {athlete_id}: {error}");
+    }
+}
+
+/// Streams the status of the default athlete, see [crate::state::shared_state::SharedState::default_athlete]
 #[debug_handler]
 pub async fn status_handler(State(state): State<MutexSharedState>, uri: Uri)
     -> Result<Sse<impl Stream<Item = Result<Event, Error>>>, StatusCode> {
     debug!("Enter {uri}");
-    let mut receiver = subscribe_and_send_first(&state).await.map_err(internal_server_error)?;
-    let mut rx_term = subscribe_term(&state).await;
+    let athlete_id = state.lock().await.default_athlete();
+    status_stream(&state, athlete_id).await
+}
+
+#[debug_handler]
+pub async fn athlete_status_handler(State(state): State<MutexSharedState>, Path(athlete_id): Path<u64>, uri: Uri)
+    -> Result<Sse<impl Stream<Item = Result<Event, Error>>>, StatusCode> {
+    debug!("Enter {uri}");
+    status_stream(&state, Some(athlete_id)).await
+}
+
+/// Returns an SSE stream with the status events of the athlete. If no athlete is given
+/// (because nobody has authorized yet), the events of all athletes are passed.
+/// The logged events of the download scheduler are passed as SSE events of type `download_event`.
+async fn status_stream(state: &MutexSharedState, athlete_id: Option<u64>)
+    -> Result<Sse<impl Stream<Item = Result<Event, Error>> + use<>>, StatusCode> {
+    let mut receiver = subscribe_and_send_first(state, athlete_id).await.map_err(internal_server_error)?;
+    let mut rx_events = subscribe_events(state).await;
+    let mut rx_term = subscribe_term(state).await;
     let stream = async_stream::stream! {
         loop {
             tokio::select! {
-                item = receiver.recv() => {
-                    yield Event::default().json_data(item.unwrap());
-                }
+                item = receiver.recv() => match item {
+                    Ok(item) => if athlete_id.is_none() || item.athlete_id() == athlete_id {
+                        yield Event::default().json_data(item);
+                    }
+                    Err(RecvError::Lagged(count)) => warn!("SSE client too slow, skipped {count} status events"),
+                    Err(RecvError::Closed) => return
+                },
+                event = rx_events.recv() => match event {
+                    Ok(event) => if athlete_id.is_none_or(|id| id == event.athlete_id) {
+                        yield Event::default().event(DOWNLOAD_EVENT).json_data(event);
+                    }
+                    Err(RecvError::Lagged(count)) => warn!("SSE client too slow, skipped {count} download events"),
+                    Err(RecvError::Closed) => return
+                },
                 _ = rx_term.recv() => {
                     debug!("Termination signal received, leave SSE handler");
                     return;
@@ -65,14 +391,19 @@ pub async fn status_handler(State(state): State<MutexSharedState>, uri: Uri)
     Ok(Sse::new(stream))
 }
 
-async fn subscribe_and_send_first(state: &MutexSharedState) -> Result<Receiver<ServerStatus>, BoxError> {
+async fn subscribe_and_send_first(state: &MutexSharedState, athlete_id: Option<u64>) -> Result<Receiver<ServerStatus>, BoxError> {
     let mut guard = state.lock().await;
     let receiver = guard.tx_data.subscribe();
-    let status = guard.get_server_status().await?;
+    let status = guard.get_server_status(athlete_id).await?;
     guard.tx_data.send(status)?;
     Ok(receiver)
 }
 
+async fn subscribe_events(state: &MutexSharedState) -> Receiver<DownloadEvent> {
+    let guard = state.lock().await;
+    guard.tx_events.subscribe()
+}
+
 async fn subscribe_term(state: &MutexSharedState) -> Receiver<()> {
     let guard = state.lock().await;
     guard.tx_term.subscribe()
@@ -1,8 +1,32 @@
 pub const AUTHORIZE : &str = "/authorize";
 pub const AUTH_CALLBACK : &str = "/auth-callback";
+pub const LOGOUT : &str = "/logout";
 
 pub const STATUS : &str = "/status";
 pub const TOGGLE : &str = "/toggle";
+pub const DOWNLOAD_START : &str = "/download/start";
+pub const DOWNLOAD_STOP : &str = "/download/stop";
+pub const FILTER : &str = "/filter";
+pub const EVENTS : &str = "/events";
+pub const ACTIVITY_REFETCH : &str = "/activities/{activity_id}/refetch";
+pub const TRACKS_RETRY : &str = "/tracks/retry";
+pub const METRICS : &str = "/metrics";
+pub const HEALTHZ : &str = "/healthz";
+pub const READYZ : &str = "/readyz";
+
+pub const ATHLETES : &str = "/athletes";
+pub const ATHLETE_STATUS : &str = "/athletes/{athlete_id}/status";
+pub const ATHLETE_TOGGLE : &str = "/athletes/{athlete_id}/toggle";
+pub const ATHLETE_DOWNLOAD_START : &str = "/athletes/{athlete_id}/download/start";
+pub const ATHLETE_DOWNLOAD_STOP : &str = "/athletes/{athlete_id}/download/stop";
+pub const ATHLETE_LOGOUT : &str = "/athletes/{athlete_id}/logout";
+pub const ATHLETE_GEAR : &str = "/athletes/{athlete_id}/gear";
+pub const ATHLETE_SEGMENT_EFFORTS : &str = "/athletes/{athlete_id}/segments/{segment_id}/efforts";
+pub const ATHLETE_BEST_EFFORTS : &str = "/athletes/{athlete_id}/best-efforts";
+pub const ATHLETE_JOBS : &str = "/athletes/{athlete_id}/jobs";
+pub const ATHLETE_EVENTS : &str = "/athletes/{athlete_id}/events";
+pub const ATHLETE_ACTIVITY_REFETCH : &str = "/athletes/{athlete_id}/activities/{activity_id}/refetch";
+pub const ATHLETE_TRACKS_RETRY : &str = "/athletes/{athlete_id}/tracks/retry";
 
 pub const CONSOLE_PATH: &str = "/console";
 pub const CONSOLE_DIR: &str = "../console/dist";
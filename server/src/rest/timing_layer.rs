@@ -1,5 +1,6 @@
 use std::future::Future;
 use std::pin::Pin;
+use axum::extract::MatchedPath;
 use axum::http::{Request, Response};
 use std::task::{Context, Poll, ready};
 use std::time::Instant;
@@ -7,6 +8,7 @@ use humantime::format_duration;
 use log::info;
 use pin_project_lite::pin_project;
 use tower::{Layer, Service};
+use crate::util::metrics::metrics;
 
 #[derive(Clone)]
 pub struct TimingLayer;
@@ -38,9 +40,11 @@ impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for TimingMiddleware<S>
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let path = req.uri().to_string();
+        let method = req.method().to_string();
+        let route = req.extensions().get::<MatchedPath>().map_or("fallback".to_string(), |route| route.as_str().to_string());
         let timer = Instant::now();
         let future = self.service.call(req);
-        TimingFuture { future, timer, path }
+        TimingFuture { future, timer, path, method, route }
     }
 }
 
@@ -49,7 +53,9 @@ pin_project! {
         #[pin]
         future: F,
         timer: Instant,
-        path: String
+        path: String,
+        method: String,
+        route: String // Path template of the router, for the metrics
     }
 }
 
@@ -63,6 +69,7 @@ impl<F, B, E> Future for TimingFuture<F>
         let this = self.project();
         let response: Response<B> = ready!(this.future.poll(cx))?;
         info!("{} took {}", this.path, format_duration(this.timer.elapsed()));
+        metrics().observe_http_request(this.method, this.route, *this.timer);
         Poll::Ready(Ok(response))
     }
 }
\ No newline at end of file
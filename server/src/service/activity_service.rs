@@ -1,14 +1,57 @@
 use axum::BoxError;
 use log::{debug, info, warn};
 use crate::database::activity_table::ActivityTable;
-use crate::database::db_types::DBPool;
+use crate::database::athlete_table::AthleteTable;
+use crate::database::athlete_totals_table::AthleteTotalsTable;
+use crate::database::athlete_zone_table::AthleteZoneTable;
+use crate::database::best_effort_table::BestEffortTable;
+use crate::database::comment_table::CommentTable;
+use crate::database::db_types::{DBConnection, DBPool};
+use crate::database::download_event_table::DownloadEventTable;
+use crate::database::download_job_table::DownloadJobTable;
+use crate::database::download_state_table::DownloadStateTable;
+use crate::database::gear_table::GearTable;
+use crate::database::kudoer_table::KudoerTable;
+use crate::database::lap_table::LapTable;
 use crate::database::maptile_table::MapTileTable;
-use crate::domain::activity::{Activity, ActivityVec};
+use crate::database::photo_table::PhotoTable;
+use crate::database::segment_effort_table::SegmentEffortTable;
+use crate::domain::activity::{Activity, ActivityVec, LEGACY_ATHLETE};
 use crate::domain::activity_stats::ActivityStats;
 use crate::domain::activity_stream::ActivityStream;
+use crate::domain::athlete::Athlete;
+use crate::domain::athlete_stats::AthleteStats;
+use crate::domain::athlete_zones::AthleteZones;
+use crate::domain::download_event::DownloadEvent;
+use crate::domain::download_filter::DownloadFilter;
+use crate::domain::download_job::{DownloadJob, JobType};
+use crate::domain::download_state::DownloadState;
+use crate::domain::effort::{ActivityEfforts, BestEffort, SegmentEffort};
+use crate::domain::gear::{Gear, GearUsage};
+use crate::domain::lap::{Lap, LapVec};
 use crate::domain::map_tile::MapTile;
+use crate::domain::missing_reason::MissingReason;
+use crate::domain::sensor_stream::SensorStream;
+use crate::domain::photo::Photo;
+use crate::domain::social::{Comment, CommentVec, KudoerVec, PersonName};
 use crate::domain::track_store_state::TrackStoreState;
 use crate::domain::map_zoom::MapZoom;
+use crate::track::track_storage::TrackStorage;
+use crate::util::metrics::metrics;
+
+/// Defines service methods that each observe their duration as database query,
+/// labeled by the method name (see [crate::util::metrics::Metrics::time_query])
+macro_rules! timed_methods {
+    ($($(#[$attr:meta])* $vis:vis async fn $name:ident(&mut $self:ident $(, $arg:ident: $arg_type:ty)*) -> $result:ty $body:block)*) => {
+        $(
+            $(#[$attr])*
+            $vis async fn $name(&mut $self $(, $arg: $arg_type)*) -> $result {
+                let _timer = metrics().time_query(stringify!($name));
+                $body
+            }
+        )*
+    }
+}
 
 pub struct ActivityService {
     pool: DBPool,
@@ -19,95 +62,501 @@ impl ActivityService {
     pub async fn new(db_path: &str, store_tiles: bool) -> Result<Self, BoxError> {
         let pool = DBPool::connect(db_path).await?;
         ActivityTable::create_table(&pool).await?;
+        ActivityTable::upgrade_table(&pool).await?;
+        AthleteTable::create_table(&pool).await?;
+        AthleteZoneTable::create_table(&pool).await?;
+        AthleteTotalsTable::create_table(&pool).await?;
+        GearTable::create_table(&pool).await?;
+        LapTable::create_table(&pool).await?;
+        LapTable::create_index(&pool).await?;
+        PhotoTable::create_table(&pool).await?;
+        PhotoTable::create_index(&pool).await?;
+        SegmentEffortTable::create_table(&pool).await?;
+        SegmentEffortTable::create_indexes(&pool).await?;
+        BestEffortTable::create_table(&pool).await?;
+        BestEffortTable::create_indexes(&pool).await?;
+        CommentTable::create_table(&pool).await?;
+        CommentTable::create_index(&pool).await?;
+        KudoerTable::create_table(&pool).await?;
+        DownloadJobTable::create_table(&pool).await?;
+        DownloadJobTable::create_index(&pool).await?;
+        DownloadEventTable::create_table(&pool).await?;
+        DownloadEventTable::create_index(&pool).await?;
+        DownloadStateTable::create_table(&pool).await?;
         if store_tiles {
             for zoom in MapZoom::VALUES {
+                MapTileTable::upgrade_table(&pool, zoom).await?;
                 MapTileTable::create_table(&pool, zoom).await?;
             }
         }
         Ok(Self{ pool, store_tiles })
     }
 
-    /// Adds all activities to the database and returns the computed [ActivityStats]
-    /// for **these** inserted activities (**not** for the entire database table).
-    pub async fn add(&mut self, activities: &ActivityVec) -> Result<ActivityStats, BoxError> {
-        info!("Add {} activities to database", activities.len());
-        let act_count = activities.len() as u32;
-        let mut min_time : Option<String> = None;
-        let mut max_time : Option<String> = None;
-        let mut tx = self.pool.begin().await?;
-        for activity in activities {
-            ActivityTable::insert(&mut *tx, activity).await?;
-            // std::cmp::min for Option treats None as minimal value, but we need the timestamp
-            min_time = Some(match min_time {
-                Some(time) => std::cmp::min(activity.start_date.clone(), time),
-                None => activity.start_date.clone()
-            });
-            max_time = std::cmp::max(Some(activity.start_date.clone()), max_time);
-        }
-        tx.commit().await?;
-        Ok(ActivityStats::new(act_count, min_time, max_time, 0, None))
+    /// Checks that the database is reachable
+    pub async fn ping(&mut self) -> Result<(), BoxError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
     }
 
-    pub async fn get_stats(&mut self) -> Result<ActivityStats, BoxError> {
-        let stats = ActivityTable::select_stats(&self.pool).await?;
-        debug!("Read activity stats {:?} from database", stats);
-        Ok(stats)
+    /// Writes the GPX file with the laps and photos visible in the transaction
+    async fn write_track(conn: &mut DBConnection, tracks: &TrackStorage, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
+        let laps = LapTable::select_for_activity(&mut *conn, activity.id).await?;
+        let photos = PhotoTable::select_for_activity(&mut *conn, activity.id).await?;
+        tracks.write(activity, stream, &laps, &photos)
     }
 
-    pub async fn get_earliest_without_track(&mut self) -> Result<Option<Activity>, BoxError> {
-        let activity = ActivityTable::select_earliest_without_track(&self.pool).await?;
-        debug!("Earliest activity without track: {:?}", activity);
-        Ok(activity)
+    /// Rewrites the GPX file (if the track is already stored) to include changed laps or photos
+    async fn rewrite_track(conn: &mut DBConnection, tracks: &TrackStorage, activity: &Activity) -> Result<(), BoxError> {
+        if ActivityTable::select_fetched_column(&mut *conn, activity.id).await? == Some(TrackStoreState::Stored) {
+            let stream = tracks.read(activity)?;
+            Self::write_track(conn, tracks, activity, &stream).await?;
+        }
+        Ok(())
     }
 
-    pub async fn get_all_with_track(&mut self) -> Result<ActivityVec, BoxError> {
-        let activities = ActivityTable::select_all_with_track(&self.pool).await?;
-        debug!("Number of activities with track: {:?}", activities.len());
-        Ok(activities)
+    pub fn is_storing_tiles(&self) -> bool {
+        self.store_tiles
     }
+}
 
-    pub async fn mark_fetched(&mut self, activity: &Activity, state: TrackStoreState) -> Result<(), BoxError> {
-        let result = ActivityTable::update_fetched_column(&self.pool, activity.id, state).await?;
-        debug!("Marked 'GPX fetched' for activity {} with result {result}", activity.id);
-        Ok(())
-    }
+impl ActivityService {
+    timed_methods! {
+        /// Adds all activities to the database and returns the computed [ActivityStats]
+        /// for **these** inserted activities (**not** for the entire database table).
+        pub async fn add(&mut self, activities: &ActivityVec) -> Result<ActivityStats, BoxError> {
+            info!("Add {} activities to database", activities.len());
+            let act_count = activities.len() as u32;
+            let mut min_time : Option<String> = None;
+            let mut max_time : Option<String> = None;
+            let mut tx = self.pool.begin().await?;
+            for activity in activities {
+                ActivityTable::insert(&mut *tx, activity).await?;
+                // std::cmp::min for Option treats None as minimal value, but we need the timestamp
+                min_time = Some(match min_time {
+                    Some(time) => std::cmp::min(activity.start_date.clone(), time),
+                    None => activity.start_date.clone()
+                });
+                max_time = std::cmp::max(Some(activity.start_date.clone()), max_time);
+            }
+            tx.commit().await?;
+            metrics().count_activities(act_count as u64);
+            Ok(ActivityStats::new(act_count, min_time, max_time, 0, None))
+        }
 
-    /// Derives and stores the tiles for all zoom levels from the given activity stream
-    pub async fn store_tiles(&mut self, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
-        if self.store_tiles {
-            for zoom in MapZoom::VALUES {
-                let tiles = stream.to_tiles(zoom)?;
-                self.put_tiles(zoom, activity.id, &tiles).await?;
+        pub async fn get_stats(&mut self, athlete_id: u64) -> Result<ActivityStats, BoxError> {
+            let stats = ActivityTable::select_stats(&self.pool, athlete_id).await?;
+            debug!("Read activity stats {:?} of athlete {} from database", stats, athlete_id);
+            Ok(stats)
+        }
+
+        /// Returns the number of remaining tracks (matching the filter) and missing tracks of the athlete
+        pub async fn get_track_counts(&mut self, athlete_id: u64, filter: &DownloadFilter) -> Result<(u64, u64), BoxError> {
+            Ok(ActivityTable::select_track_counts(&self.pool, athlete_id, filter).await?)
+        }
+
+        /// Returns the ids of all athletes owning activities (including [LEGACY_ATHLETE])
+        pub async fn get_athletes(&mut self) -> Result<Vec<u64>, BoxError> {
+            Ok(ActivityTable::select_athletes(&self.pool).await?)
+        }
+
+        /// If the database contains only activities downloaded before multi-athlete support was added,
+        /// then these activities and their tiles are assigned to the given athlete. Returns true if so.
+        pub async fn adopt_legacy_activities(&mut self, athlete_id: u64) -> Result<bool, BoxError> {
+            if self.get_athletes().await? != vec![LEGACY_ATHLETE] {
+                return Ok(false);
             }
+            let mut tx = self.pool.begin().await?;
+            let count = ActivityTable::update_athlete_column(&mut *tx, LEGACY_ATHLETE, athlete_id).await?;
+            if self.store_tiles {
+                for zoom in MapZoom::VALUES {
+                    MapTileTable::update_athlete_column(&mut *tx, zoom, LEGACY_ATHLETE, athlete_id).await?;
+                }
+            }
+            tx.commit().await?;
+            info!("Assigned {count} legacy activities to athlete {athlete_id}");
+            Ok(true)
         }
-        Ok(())
-    }
 
-    /// Stores tiles for the given zoom level
-    pub async fn put_tiles(&mut self, zoom: MapZoom, activity_id: u64, tiles: &Vec<MapTile>) -> Result<(), BoxError> {
-        if self.store_tiles {
+        /// Stores the athlete profile together with the summaries of its bikes and shoes
+        pub async fn put_athlete(&mut self, athlete: &Athlete) -> Result<(), BoxError> {
             let mut tx = self.pool.begin().await?;
-            debug!("Save {} tiles with zoom level {} for activity {}", tiles.len(), zoom.value(), activity_id);
-            for tile in tiles {
-                MapTileTable::upsert(&mut *tx, zoom, &tile, activity_id).await?;
+            AthleteTable::upsert(&mut *tx, athlete).await?;
+            for gear in athlete.bikes.iter().chain(athlete.shoes.iter()) {
+                GearTable::upsert(&mut *tx, athlete.id, gear).await?;
             }
             tx.commit().await?;
-        } else {
-            warn!("Tile storage disabled");
+            debug!("Stored athlete {} with {} bikes and {} shoes", athlete.id, athlete.bikes.len(), athlete.shoes.len());
+            Ok(())
         }
-        Ok(())
-    }
 
-    /// Deletes **all** tiles for all zoom levels
-    pub async fn delete_all_tiles(&mut self) -> Result<(), BoxError> {
-        if self.store_tiles {
-            for zoom in MapZoom::VALUES {
-                MapTileTable::delete_all(&self.pool, zoom).await?;
+        /// Replaces the heart-rate and power zones of the athlete
+        pub async fn put_athlete_zones(&mut self, athlete_id: u64, zones: &AthleteZones) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            AthleteZoneTable::delete_for_athlete(&mut *tx, athlete_id).await?;
+            for (zone_type, ranges) in zones.by_type() {
+                for (index, zone) in ranges.zones.iter().enumerate() {
+                    AthleteZoneTable::insert(&mut *tx, athlete_id, zone_type, index, zone).await?;
+                }
             }
-        } else {
-            warn!("Tile storage disabled");
+            tx.commit().await?;
+            debug!("Stored zones of athlete {athlete_id}");
+            Ok(())
+        }
+
+        /// Stores the totals and the records of the athlete. The records are only stored if the
+        /// athlete profile was stored before.
+        pub async fn put_athlete_stats(&mut self, athlete_id: u64, stats: &AthleteStats) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            for (period, sport, total) in stats.totals() {
+                AthleteTotalsTable::upsert(&mut *tx, athlete_id, period, sport, total).await?;
+            }
+            if !AthleteTable::update_biggest_columns(&mut *tx, athlete_id, stats).await? {
+                warn!("Records of unknown athlete {athlete_id} not stored");
+            }
+            tx.commit().await?;
+            debug!("Stored stats of athlete {athlete_id}");
+            Ok(())
+        }
+
+        pub async fn put_gear(&mut self, athlete_id: u64, gear: &Gear) -> Result<(), BoxError> {
+            GearTable::upsert(&self.pool, athlete_id, gear).await?;
+            debug!("Stored gear {} of athlete {athlete_id}", gear.id);
+            Ok(())
+        }
+
+        /// Returns the bikes and shoes of the athlete with the distance of the downloaded activities
+        pub async fn get_gear_usage(&mut self, athlete_id: u64) -> Result<Vec<GearUsage>, BoxError> {
+            Ok(GearTable::select_usage(&self.pool, athlete_id).await?)
+        }
+
+        pub async fn get_by_id(&mut self, id: u64) -> Result<Option<Activity>, BoxError> {
+            Ok(ActivityTable::select_by_id(&self.pool, id).await?)
+        }
+
+        pub async fn get_fetch_state(&mut self, id: u64) -> Result<Option<TrackStoreState>, BoxError> {
+            Ok(ActivityTable::select_fetched_column(&self.pool, id).await?)
+        }
+
+        pub async fn get_all_with_track(&mut self) -> Result<ActivityVec, BoxError> {
+            let activities = ActivityTable::select_all_with_track(&self.pool).await?;
+            debug!("Number of activities with track: {:?}", activities.len());
+            Ok(activities)
+        }
+
+        /// Returns the activities whose stream is stored as sensor data, see [TrackStoreState::Sensors]
+        pub async fn get_all_with_sensors(&mut self) -> Result<ActivityVec, BoxError> {
+            Ok(ActivityTable::select_all_with_sensors(&self.pool).await?)
+        }
+
+        pub async fn mark_fetched(&mut self, activity: &Activity, state: TrackStoreState) -> Result<(), BoxError> {
+            let result = ActivityTable::update_fetched_column(&self.pool, activity.id, state).await?;
+            debug!("Marked 'GPX fetched' for activity {} with result {result}", activity.id);
+            Ok(())
+        }
+
+        pub async fn mark_track_missing(&mut self, activity: &Activity, reason: MissingReason) -> Result<(), BoxError> {
+            let result = ActivityTable::update_missing_columns(&self.pool, activity.id, reason).await?;
+            debug!("Marked track of activity {} missing ({}) with result {result}", activity.id, reason.name());
+            Ok(())
+        }
+
+        /// Resets the missing tracks to pending, so they are downloaded again with the next enqueued jobs.
+        /// Without athlete, the tracks of all athletes are reset. Without reason, also the tracks
+        /// marked missing before the reason was recorded. Returns the number of reset tracks.
+        pub async fn retry_missing_tracks(&mut self, athlete_id: Option<u64>, reason: Option<MissingReason>) -> Result<u64, BoxError> {
+            let count = ActivityTable::reset_missing_tracks(&self.pool, athlete_id, reason).await?;
+            info!("Reset {count} missing tracks (athlete {athlete_id:?}, reason {reason:?})");
+            Ok(count)
+        }
+
+        /// Stores the track of an activity as GPX file, marks the activity as fetched, and (optionally)
+        /// stores its tiles. Already downloaded laps and photos are included in the GPX file.
+        /// The database changes are done in a single transaction, which is committed
+        /// only after the GPX file was written. If any step fails, the transaction is rolled back.
+        /// In the rare case that the commit fails after writing, the GPX file is simply overwritten
+        /// by the next download attempt of the still pending activity.
+        pub async fn store_track(&mut self, tracks: &TrackStorage, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            ActivityTable::update_fetched_column(&mut *tx, activity.id, TrackStoreState::Stored).await?;
+            let mut tile_count = 0;
+            if self.store_tiles {
+                for zoom in MapZoom::VALUES {
+                    let tiles = stream.to_tiles(zoom)?;
+                    debug!("Save {} tiles with zoom level {} for activity {}", tiles.len(), zoom.value(), activity.id);
+                    for tile in &tiles {
+                        MapTileTable::upsert(&mut *tx, zoom, tile, activity.athlete_id, activity.id).await?;
+                    }
+                    tile_count += tiles.len() as u64;
+                }
+            }
+            Self::write_track(&mut tx, tracks, activity, stream).await?;
+            tx.commit().await?;
+            metrics().count_tracks(1);
+            metrics().count_tiles(tile_count);
+            debug!("Stored track of activity {}", activity.id);
+            Ok(())
+        }
+
+        /// Stores the sensor data of an activity without coordinates as CSV file and marks the activity
+        /// accordingly. As in [ActivityService::store_track], the transaction is committed only after the file was written.
+        pub async fn store_sensors(&mut self, tracks: &TrackStorage, activity: &Activity, stream: &SensorStream) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            ActivityTable::update_fetched_column(&mut *tx, activity.id, TrackStoreState::Sensors).await?;
+            tracks.write_sensors(activity, stream)?;
+            tx.commit().await?;
+            debug!("Stored sensor data of activity {}", activity.id);
+            Ok(())
+        }
+
+        /// Resets the track of the activity to pending and enqueues its download before all other jobs,
+        /// e.g. after Strava corrected the track. The tiles of the stored track are removed, so they are
+        /// re-computed from the new track. If the stored track cannot be read, its tiles are kept.
+        /// Stored sensor data (of an activity without coordinates) is deleted after the commit.
+        pub async fn refetch_track(&mut self, tracks: &TrackStorage, activity: &Activity) -> Result<(), BoxError> {
+            let state = ActivityTable::select_fetched_column(&self.pool, activity.id).await?;
+            let stored = state == Some(TrackStoreState::Stored);
+            let mut tx = self.pool.begin().await?;
+            if self.store_tiles && stored {
+                match tracks.read(activity) {
+                    Ok(stream) => for zoom in MapZoom::VALUES {
+                        for tile in stream.to_tiles(zoom)? {
+                            MapTileTable::decrement(&mut *tx, zoom, &tile, activity.athlete_id).await?;
+                        }
+                        MapTileTable::delete_unused(&mut *tx, zoom, activity.athlete_id).await?;
+                    }
+                    Err(error) => warn!("Failed to read the track of activity {}: {}, keep its tiles", activity.id, error)
+                }
+            }
+            ActivityTable::update_fetched_column(&mut *tx, activity.id, TrackStoreState::Pending).await?;
+            DownloadJobTable::upsert_refetch(&mut *tx, activity.athlete_id, activity.id).await?;
+            tx.commit().await?;
+            if state == Some(TrackStoreState::Sensors) {
+                tracks.delete_sensors(activity)?;
+            }
+            info!("Enqueued the track download of activity {}", activity.id);
+            Ok(())
+        }
+
+        pub async fn get_laps(&mut self, activity_id: u64) -> Result<LapVec, BoxError> {
+            Ok(LapTable::select_for_activity(&self.pool, activity_id).await?)
+        }
+
+        /// Replaces the laps of an activity and marks the laps as fetched. If the track of the activity
+        /// is already stored, the GPX file is rewritten with one segment per lap. As in
+        /// [ActivityService::store_track], the transaction is committed only after the file was written.
+        pub async fn store_laps(&mut self, tracks: &TrackStorage, activity: &Activity, laps: &[Lap]) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            LapTable::delete_for_activity(&mut *tx, activity.id).await?;
+            for lap in laps {
+                LapTable::insert(&mut *tx, activity.id, lap).await?;
+            }
+            ActivityTable::update_laps_fetched_column(&mut *tx, activity.id, true).await?;
+            if !laps.is_empty() {
+                Self::rewrite_track(&mut tx, tracks, activity).await?;
+            }
+            tx.commit().await?;
+            debug!("Stored {} laps of activity {}", laps.len(), activity.id);
+            Ok(())
+        }
+
+        pub async fn get_photos(&mut self, activity_id: u64) -> Result<Vec<Photo>, BoxError> {
+            Ok(PhotoTable::select_for_activity(&self.pool, activity_id).await?)
+        }
+
+        /// Replaces the photos of an activity and marks the photos as fetched. The downloaded images
+        /// (`None` if the download failed) are stored next to the track, and the track is rewritten
+        /// with the photo locations as waypoints. See [ActivityService::store_laps] for the transaction handling.
+        pub async fn store_photos(&mut self, tracks: &TrackStorage, activity: &Activity, photos: &[(Photo, Option<Vec<u8>>)]) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            PhotoTable::delete_for_activity(&mut *tx, activity.id).await?;
+            for (photo, image) in photos {
+                let mut photo = photo.clone();
+                if let Some(image) = image {
+                    photo.file_name = Some(tracks.write_photo(activity, &photo, image)?);
+                }
+                PhotoTable::insert(&mut *tx, activity.id, &photo).await?;
+            }
+            ActivityTable::update_photos_fetched_column(&mut *tx, activity.id, true).await?;
+            if !photos.is_empty() {
+                Self::rewrite_track(&mut tx, tracks, activity).await?;
+            }
+            tx.commit().await?;
+            debug!("Stored {} photos of activity {}", photos.len(), activity.id);
+            Ok(())
+        }
+
+        /// Replaces the segment and best efforts of an activity and marks the efforts as fetched
+        pub async fn store_efforts(&mut self, activity: &Activity, efforts: &ActivityEfforts) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            SegmentEffortTable::delete_for_activity(&mut *tx, activity.id).await?;
+            BestEffortTable::delete_for_activity(&mut *tx, activity.id).await?;
+            for effort in &efforts.segment_efforts {
+                SegmentEffortTable::insert(&mut *tx, effort).await?;
+            }
+            for effort in &efforts.best_efforts {
+                BestEffortTable::insert(&mut *tx, effort).await?;
+            }
+            ActivityTable::update_efforts_fetched_column(&mut *tx, activity.id, true).await?;
+            tx.commit().await?;
+            debug!("Stored {} segment efforts and {} best efforts of activity {}",
+                efforts.segment_efforts.len(), efforts.best_efforts.len(), activity.id);
+            Ok(())
+        }
+
+        /// Returns all efforts of the athlete on the segment in chronological order
+        pub async fn get_segment_efforts(&mut self, athlete_id: u64, segment_id: u64) -> Result<Vec<SegmentEffort>, BoxError> {
+            Ok(SegmentEffortTable::select_for_segment(&self.pool, athlete_id, segment_id).await?)
+        }
+
+        /// Returns the fastest effort of the athlete for each distance (like "5k")
+        pub async fn get_best_efforts(&mut self, athlete_id: u64) -> Result<Vec<BestEffort>, BoxError> {
+            Ok(BestEffortTable::select_best(&self.pool, athlete_id).await?)
+        }
+
+        /// Replaces the comments and kudoers of an activity and marks them as fetched
+        pub async fn store_social(&mut self, activity: &Activity, comments: &[Comment], kudoers: &[PersonName]) -> Result<(), BoxError> {
+            let mut tx = self.pool.begin().await?;
+            CommentTable::delete_for_activity(&mut *tx, activity.id).await?;
+            KudoerTable::delete_for_activity(&mut *tx, activity.id).await?;
+            for comment in comments {
+                CommentTable::insert(&mut *tx, activity.id, comment).await?;
+            }
+            for (position, kudoer) in kudoers.iter().enumerate() {
+                KudoerTable::insert(&mut *tx, activity.id, position, kudoer).await?;
+            }
+            ActivityTable::update_social_fetched_column(&mut *tx, activity.id, true).await?;
+            tx.commit().await?;
+            debug!("Stored {} comments and {} kudoers of activity {}", comments.len(), kudoers.len(), activity.id);
+            Ok(())
+        }
+
+        pub async fn get_comments(&mut self, activity_id: u64) -> Result<CommentVec, BoxError> {
+            Ok(CommentTable::select_for_activity(&self.pool, activity_id).await?)
+        }
+
+        pub async fn get_kudoers(&mut self, activity_id: u64) -> Result<KudoerVec, BoxError> {
+            Ok(KudoerTable::select_for_activity(&self.pool, activity_id).await?)
+        }
+
+        /// Enqueues a download job of each given type for all activities of the athlete that match the filter
+        /// and miss the respective data. Pending jobs of other types are removed. Returns the number of new jobs.
+        pub async fn enqueue_jobs(&mut self, athlete_id: u64, job_types: &[JobType], filter: &DownloadFilter) -> Result<u64, BoxError> {
+            let mut count = 0;
+            let mut tx = self.pool.begin().await?;
+            for job_type in JobType::VALUES {
+                if job_types.contains(&job_type) {
+                    count += DownloadJobTable::insert_missing(&mut *tx, athlete_id, job_type, filter).await?;
+                } else {
+                    DownloadJobTable::delete_for_type(&mut *tx, athlete_id, job_type).await?;
+                }
+            }
+            tx.commit().await?;
+            info!("Enqueued {count} download jobs of athlete {athlete_id}");
+            Ok(count)
+        }
+
+        /// Returns the job of the athlete with the highest priority that is due at `now` (seconds since epoch).
+        /// Jobs of activities not matching the filter are skipped.
+        pub async fn get_next_job(&mut self, athlete_id: u64, now: i64, filter: &DownloadFilter) -> Result<Option<DownloadJob>, BoxError> {
+            Ok(DownloadJobTable::select_next(&self.pool, athlete_id, now, filter).await?)
+        }
+
+        /// Returns all pending jobs of the athlete in the order of execution
+        pub async fn get_jobs(&mut self, athlete_id: u64) -> Result<Vec<DownloadJob>, BoxError> {
+            Ok(DownloadJobTable::select_for_athlete(&self.pool, athlete_id).await?)
+        }
+
+        pub async fn complete_job(&mut self, job: &DownloadJob) -> Result<(), BoxError> {
+            DownloadJobTable::delete(&self.pool, job.id).await?;
+            Ok(())
+        }
+
+        /// Records the failed attempt and postpones the job until `next_run_at` (seconds since epoch)
+        pub async fn fail_job(&mut self, job: &DownloadJob, next_run_at: i64, error: &str) -> Result<(), BoxError> {
+            DownloadJobTable::update_failed(&self.pool, job.id, next_run_at, error).await?;
+            Ok(())
+        }
+
+        /// Records the failed attempt and marks the job as poisoned, so it is not executed anymore
+        pub async fn poison_job(&mut self, job: &DownloadJob, error: &str) -> Result<(), BoxError> {
+            DownloadJobTable::update_poisoned(&self.pool, job.id, error).await?;
+            Ok(())
+        }
+
+        /// Returns the number of jobs of the athlete matching the filter that are not poisoned, including the postponed ones
+        pub async fn get_pending_job_count(&mut self, athlete_id: u64, filter: &DownloadFilter) -> Result<u64, BoxError> {
+            Ok(DownloadJobTable::select_pending_count(&self.pool, athlete_id, filter).await?)
+        }
+
+        /// Appends the event to the log and returns it with the assigned id
+        pub async fn add_event(&mut self, event: DownloadEvent) -> Result<DownloadEvent, BoxError> {
+            let id = DownloadEventTable::insert(&self.pool, &event).await?;
+            Ok(DownloadEvent { id, ..event })
+        }
+
+        /// Returns at most `limit` events at or after the given time (ISO 8601), of the athlete or of all athletes
+        pub async fn get_events(&mut self, athlete_id: Option<u64>, since: &str, limit: u32) -> Result<Vec<DownloadEvent>, BoxError> {
+            Ok(DownloadEventTable::select_since(&self.pool, athlete_id, since, limit).await?)
+        }
+
+        /// Stores the download state of the athlete, see [Self::get_download_states]
+        pub async fn save_download_state(&mut self, athlete_id: u64, download_state: &DownloadState) -> Result<(), BoxError> {
+            Ok(DownloadStateTable::upsert(&self.pool, athlete_id, download_state).await?)
+        }
+
+        /// Returns the stored download states of all athletes, which are restored after a restart
+        pub async fn get_download_states(&mut self) -> Result<Vec<(u64, DownloadState)>, BoxError> {
+            Ok(DownloadStateTable::select_all(&self.pool).await?)
+        }
+
+        /// Returns the number of tiles and the sum of their activity counts for the given zoom level
+        pub async fn get_tile_stats(&mut self, zoom: MapZoom) -> Result<(u64, u64), BoxError> {
+            if self.store_tiles {
+                Ok(MapTileTable::select_stats(&self.pool, zoom).await?)
+            } else {
+                warn!("Tile storage disabled");
+                Ok((0, 0))
+            }
+        }
+
+        /// Derives and stores the tiles for all zoom levels from the given activity stream
+        pub async fn store_tiles(&mut self, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
+            if self.store_tiles {
+                for zoom in MapZoom::VALUES {
+                    let tiles = stream.to_tiles(zoom)?;
+                    self.put_tiles(zoom, activity, &tiles).await?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Stores tiles for the given zoom level
+        pub async fn put_tiles(&mut self, zoom: MapZoom, activity: &Activity, tiles: &Vec<MapTile>) -> Result<(), BoxError> {
+            if self.store_tiles {
+                let mut tx = self.pool.begin().await?;
+                debug!("Save {} tiles with zoom level {} for activity {}", tiles.len(), zoom.value(), activity.id);
+                for tile in tiles {
+                    MapTileTable::upsert(&mut *tx, zoom, tile, activity.athlete_id, activity.id).await?;
+                }
+                tx.commit().await?;
+                metrics().count_tiles(tiles.len() as u64);
+            } else {
+                warn!("Tile storage disabled");
+            }
+            Ok(())
+        }
+
+        /// Deletes **all** tiles for all zoom levels
+        pub async fn delete_all_tiles(&mut self) -> Result<(), BoxError> {
+            if self.store_tiles {
+                for zoom in MapZoom::VALUES {
+                    MapTileTable::delete_all(&self.pool, zoom).await?;
+                }
+            } else {
+                warn!("Tile storage disabled");
+            }
+            Ok(())
         }
-        Ok(())
     }
 }
 
@@ -116,12 +565,24 @@ mod tests {
     use axum::BoxError;
     use log::warn;
     use crate::database::maptile_table::MapTileTable;
-    use crate::domain::activity::{Activity, ActivityVec};
+    use crate::domain::activity::{Activity, ActivityVec, LEGACY_ATHLETE};
     use crate::domain::activity_stats::ActivityStats;
     use crate::domain::activity_stream::ActivityStream;
+    use crate::domain::athlete::Athlete;
+    use crate::domain::download_filter::DownloadFilter;
+    use crate::domain::download_job::{JobType, REFETCH_PRIORITY};
+    use crate::domain::effort::{ActivityEfforts, BestEffort, SegmentEffort};
+    use crate::domain::gear::Gear;
+    use crate::domain::lap::Lap;
     use crate::domain::map_tile::MapTile;
     use crate::domain::map_zoom::MapZoom;
+    use crate::domain::missing_reason::MissingReason;
+    use crate::domain::photo::Photo;
+    use crate::domain::sensor_stream::SensorStream;
+    use crate::domain::social::{Comment, PersonName};
+    use crate::domain::track_store_state::TrackStoreState;
     use crate::service::activity_service::ActivityService;
+    use crate::track::track_storage::TrackStorage;
 
     impl ActivityService {
         /// Returns all tiles for the given zoom level
@@ -188,6 +649,263 @@ mod tests {
         ]);
     }
 
+    #[tokio::test]
+    async fn test_store_track() {
+        let activity = Activity::dummy(5, "2018-02-20T18:02:13Z");
+        let stream = ActivityStream::new(vec![(1.0, 1.0)], vec![100.0], vec![0]);
+        let base_path = std::env::temp_dir().join(format!("strava-store-track-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+
+        let mut service = create_service().await;
+        assert!(service.add(&vec![activity.clone()]).await.is_ok());
+        assert!(service.store_track(&tracks, &activity, &stream).await.is_ok());
+
+        assert_eq!(service.get_fetch_state(5).await.unwrap(), Some(TrackStoreState::Stored));
+        assert_eq!(service.get_tiles(MapZoom::Level14).await.unwrap(), vec![MapTile::new(8237, 8146)]);
+        assert_eq!(tracks.read(&activity).unwrap(), stream);
+        assert!(tracks.list_temp_files().unwrap().is_empty());
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_track_rollback() {
+        let activity = Activity::dummy(5, "2018-02-20T18:02:13Z");
+        let stream = ActivityStream::new(vec![(1.0, 1.0)], vec![100.0], vec![0]);
+        // The base path is a file, so writing the track fails
+        let base_path = std::env::temp_dir().join(format!("strava-store-track-rollback-{}", std::process::id()));
+        std::fs::write(&base_path, "").unwrap();
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+
+        let mut service = create_service().await;
+        assert!(service.add(&vec![activity.clone()]).await.is_ok());
+        assert!(service.store_track(&tracks, &activity, &stream).await.is_err());
+
+        assert_eq!(service.get_fetch_state(5).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.get_tiles(MapZoom::Level14).await.unwrap(), vec![]);
+        std::fs::remove_file(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refetch_track() {
+        let activities = vec![
+            Activity::dummy(5, "2018-02-20T18:02:13Z"),
+            Activity::dummy(7, "2018-02-20T18:02:15Z")
+        ];
+        let stream1 = ActivityStream::new(vec![(1.0, 1.0), (3.0, 3.0)], vec![100.0, 200.0], vec![0, 10]);
+        let stream2 = ActivityStream::new(vec![(2.0, 2.0), (1.0, 1.0)], vec![100.0, 200.0], vec![0, 10]);
+        let base_path = std::env::temp_dir().join(format!("strava-refetch-track-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+
+        let mut service = create_service().await;
+        assert!(service.add(&activities).await.is_ok());
+        assert!(service.store_track(&tracks, &activities[0], &stream1).await.is_ok());
+        assert!(service.store_track(&tracks, &activities[1], &stream2).await.is_ok());
+        assert_eq!(service.get_tile_stats(MapZoom::Level14).await.unwrap(), (3, 4));
+        service.enqueue_jobs(Activity::DUMMY_ATHLETE, &[JobType::Laps], &DownloadFilter::default()).await.unwrap();
+
+        assert!(service.refetch_track(&tracks, &activities[0]).await.is_ok());
+        assert_eq!(service.get_fetch_state(5).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.get_tile_stats(MapZoom::Level14).await.unwrap(), (2, 2)); // Tile [3.0, 3.0] removed
+        let job = service.get_next_job(Activity::DUMMY_ATHLETE, 0, &DownloadFilter::default()).await.unwrap().unwrap();
+        assert_eq!((job.job_type, job.activity_id, job.priority), (JobType::Track, 5, REFETCH_PRIORITY));
+
+        assert!(service.store_track(&tracks, &activities[0], &stream1).await.is_ok());
+        assert_eq!(service.get_tile_stats(MapZoom::Level14).await.unwrap(), (3, 4));
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_adopt_legacy_activities() {
+        let activities = vec![
+            Activity::dummy_for(LEGACY_ATHLETE, 5, "2018-02-20T18:02:13Z"),
+            Activity::dummy_for(LEGACY_ATHLETE, 7, "2018-02-20T18:02:15Z")
+        ];
+        let stream = ActivityStream::new(vec![(1.0, 1.0)], vec![], vec![]);
+
+        let mut service = create_service().await;
+        assert!(service.add(&activities).await.is_ok());
+        assert!(service.store_tiles(&activities[0], &stream).await.is_ok());
+
+        let result = service.adopt_legacy_activities(3).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap());
+        assert_eq!(service.get_athletes().await.unwrap(), vec![3]);
+        assert_eq!(service.get_stats(3).await.unwrap().act_max_time_as_secs(), Some(1519149735));
+
+        // Legacy activities are adopted only once
+        assert!(service.add(&vec![Activity::dummy_for(LEGACY_ATHLETE, 9, "2018-02-20T18:02:17Z")]).await.is_ok());
+        let result = service.adopt_legacy_activities(4).await;
+        assert!(result.is_ok());
+        assert!(!result.unwrap());
+        assert_eq!(service.get_athletes().await.unwrap(), vec![LEGACY_ATHLETE, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_put_athlete_and_gear() {
+        let mut service = create_service().await;
+        let mut athlete = Athlete::dummy(7);
+        athlete.bikes.push(Gear::dummy("b1", 1000.0));
+        athlete.shoes.push(Gear::dummy("g2", 500.0));
+        assert!(service.put_athlete(&athlete).await.is_ok());
+        let mut details = Gear::dummy("b1", 1000.0);
+        details.model_name = Some("Teammachine".to_string());
+        assert!(service.put_gear(7, &details).await.is_ok());
+        let mut activity = Activity::dummy_for(7, 1, "2020-01-01T00:00:00Z");
+        activity.gear_id = Some("b1".to_string());
+        service.add(&vec![activity]).await.unwrap();
+
+        let result = service.get_gear_usage(7).await;
+        assert!(result.is_ok());
+        let usage = result.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].gear, details);
+        assert_eq!((usage[0].activity_count, usage[1].activity_count), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_store_laps() {
+        let base_path = std::env::temp_dir().join(format!("strava-store-laps-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let activities = vec![Activity::dummy(1, "2020-01-01T00:00:00Z"), Activity::dummy(2, "2020-01-02T00:00:00Z")];
+        let stream = ActivityStream::new(vec![(1.0, 1.0), (2.0, 2.0)], vec![100.0, 110.0], vec![0, 10]);
+        let laps = vec![Lap::dummy(11, 1, 0, 0), Lap::dummy(12, 2, 1, 1)];
+        let mut service = create_service().await;
+        service.add(&activities).await.unwrap();
+        service.store_track(&tracks, &activities[0], &stream).await.unwrap();
+
+        // Laps of an activity with stored track rewrite the GPX file
+        assert!(service.store_laps(&tracks, &activities[0], &laps).await.is_ok());
+        let gpx = std::fs::read_to_string(base_path.join("1/2020/01/1.gpx")).unwrap();
+        assert_eq!(gpx.matches("<trkseg>").count(), 2);
+        assert_eq!(service.get_laps(1).await.unwrap(), laps);
+
+        // Laps of an activity without track are stored only, a later track download uses them
+        assert!(service.store_laps(&tracks, &activities[1], &[Lap::dummy(21, 1, 0, 0)]).await.is_ok());
+        assert!(service.store_laps(&tracks, &activities[1], &[]).await.is_ok()); // Replaces the laps
+        assert!(service.get_laps(2).await.unwrap().is_empty());
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_efforts() {
+        let activity = Activity::dummy(1, "2020-01-01T00:00:00Z");
+        let efforts = ActivityEfforts {
+            segment_efforts: vec![SegmentEffort::dummy(11, 1, 7, "2020-01-01T00:00:00Z", 300)],
+            best_efforts: vec![BestEffort::dummy(21, 1, "5k", "2020-01-01T00:00:00Z", 1500)]
+        };
+        let mut service = create_service().await;
+        service.add(&vec![activity.clone()]).await.unwrap();
+        assert!(service.store_efforts(&activity, &efforts).await.is_ok());
+        assert!(service.store_efforts(&activity, &efforts).await.is_ok()); // Replaces the efforts
+
+        assert_eq!(service.get_segment_efforts(Activity::DUMMY_ATHLETE, 7).await.unwrap(), efforts.segment_efforts);
+        assert_eq!(service.get_best_efforts(Activity::DUMMY_ATHLETE).await.unwrap(), efforts.best_efforts);
+    }
+
+    #[tokio::test]
+    async fn test_store_photos() {
+        let base_path = std::env::temp_dir().join(format!("strava-store-photos-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let activity = Activity::dummy(1, "2020-01-01T00:00:00Z");
+        let stream = ActivityStream::new(vec![(1.0, 1.0), (2.0, 2.0)], vec![100.0, 110.0], vec![0, 10]);
+        let photos = vec![
+            (Photo::dummy("a1", Some((1.5, 1.5))), Some(b"jpeg".to_vec())),
+            (Photo::dummy("b2", Some((1.7, 1.7))), None) // Download failed
+        ];
+        let mut service = create_service().await;
+        service.add(&vec![activity.clone()]).await.unwrap();
+        service.store_track(&tracks, &activity, &stream).await.unwrap();
+
+        assert!(service.store_photos(&tracks, &activity, &photos).await.is_ok());
+        let dir = base_path.join(format!("{}/2020/01", Activity::DUMMY_ATHLETE));
+        assert_eq!(std::fs::read(dir.join("1/a1.jpg")).unwrap(), b"jpeg");
+        let gpx = std::fs::read_to_string(dir.join("1.gpx")).unwrap();
+        assert_eq!(gpx.matches("<wpt ").count(), 2);
+        assert!(gpx.contains(r#"<link href="1/a1.jpg">"#));
+
+        let stored = service.get_photos(1).await.unwrap();
+        assert_eq!(stored.iter().map(|p| p.file_name.clone()).collect::<Vec<_>>(), vec![Some("1/a1.jpg".to_string()), None]);
+
+        // A later track download keeps the waypoints
+        service.store_track(&tracks, &activity, &stream).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("1.gpx")).unwrap(), gpx);
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_social() {
+        let activity = Activity::dummy(1, "2020-01-01T00:00:00Z");
+        let comments = vec![Comment::dummy(11, "Nice")];
+        let kudoers = vec![PersonName::new("Foo", "B."), PersonName::new("Bar", "F.")];
+        let mut service = create_service().await;
+        service.add(&vec![activity.clone()]).await.unwrap();
+
+        assert!(service.store_social(&activity, &comments, &kudoers).await.is_ok());
+        assert!(service.store_social(&activity, &comments, &kudoers).await.is_ok()); // Replaces the data
+        assert_eq!(service.get_comments(1).await.unwrap(), comments);
+        assert_eq!(service.get_kudoers(1).await.unwrap(), kudoers);
+    }
+
+    #[tokio::test]
+    async fn test_download_jobs() {
+        let filter = DownloadFilter::default();
+        let mut service = create_service().await;
+        service.add(&vec![Activity::dummy(1, "2020-01-01T00:00:00Z"), Activity::dummy(2, "2020-02-01T00:00:00Z")]).await.unwrap();
+        service.mark_fetched(&Activity::dummy(1, "2020-01-01T00:00:00Z"), TrackStoreState::Missing).await.unwrap();
+
+        assert_eq!(service.enqueue_jobs(Activity::DUMMY_ATHLETE, &JobType::VALUES, &filter).await.unwrap(), 9); // Only activity 2 needs a track
+        assert_eq!(service.enqueue_jobs(Activity::DUMMY_ATHLETE, &[JobType::Track, JobType::Laps], &filter).await.unwrap(), 0);
+        let jobs = service.get_jobs(Activity::DUMMY_ATHLETE).await.unwrap();
+        assert_eq!(jobs.len(), 3);
+
+        let job = service.get_next_job(Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap().unwrap();
+        assert_eq!((job.job_type, job.activity_id), (JobType::Track, 2));
+        assert!(service.fail_job(&job, 10, "Server error").await.is_ok());
+        let next = service.get_next_job(Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap().unwrap();
+        assert_eq!((next.job_type, next.activity_id), (JobType::Laps, 1));
+        assert!(service.complete_job(&next).await.is_ok());
+        assert_eq!(service.get_jobs(Activity::DUMMY_ATHLETE).await.unwrap().len(), 2);
+        let next = service.get_next_job(Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap().unwrap();
+        assert!(service.poison_job(&next, "Bad request").await.is_ok());
+        assert_eq!(service.get_next_job(Activity::DUMMY_ATHLETE, 0, &filter).await.unwrap(), None);
+        assert_eq!(service.get_pending_job_count(Activity::DUMMY_ATHLETE, &filter).await.unwrap(), 1); // Postponed track job
+    }
+
+    #[tokio::test]
+    async fn test_store_sensors() {
+        let activity = Activity::dummy(1, "2020-01-01T00:00:00Z");
+        let stream: SensorStream = serde_json::from_str(r#"{"time":{"data":[0,1]},"heartrate":{"data":[90,95]}}"#).unwrap();
+        let base_path = std::env::temp_dir().join(format!("strava-store-sensors-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let path = base_path.join(format!("{}/2020/01/1.csv", Activity::DUMMY_ATHLETE));
+        let mut service = create_service().await;
+        service.add(&vec![activity.clone()]).await.unwrap();
+
+        assert!(service.store_sensors(&tracks, &activity, &stream).await.is_ok());
+        assert_eq!(service.get_fetch_state(1).await.unwrap(), Some(TrackStoreState::Sensors));
+        assert!(path.is_file());
+
+        assert!(service.refetch_track(&tracks, &activity).await.is_ok());
+        assert_eq!(service.get_fetch_state(1).await.unwrap(), Some(TrackStoreState::Pending));
+        assert!(!path.exists());
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_missing_tracks() {
+        let filter = DownloadFilter::default();
+        let activity = Activity::dummy(1, "2020-01-01T00:00:00Z");
+        let mut service = create_service().await;
+        service.add(&vec![activity.clone()]).await.unwrap();
+        service.mark_track_missing(&activity, MissingReason::NoLatlng).await.unwrap();
+        assert_eq!(service.enqueue_jobs(Activity::DUMMY_ATHLETE, &[JobType::Track], &filter).await.unwrap(), 0);
+
+        assert_eq!(service.retry_missing_tracks(Some(Activity::DUMMY_ATHLETE), Some(MissingReason::NotFound)).await.unwrap(), 0);
+        assert_eq!(service.retry_missing_tracks(Some(Activity::DUMMY_ATHLETE), Some(MissingReason::NoLatlng)).await.unwrap(), 1);
+        assert_eq!(service.get_fetch_state(1).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.enqueue_jobs(Activity::DUMMY_ATHLETE, &[JobType::Track], &filter).await.unwrap(), 1);
+    }
+
     async fn create_service() -> ActivityService {
         ActivityService::new("sqlite::memory:", true).await.unwrap()
     }
@@ -1,194 +1,649 @@
-use log::{debug, info, trace, warn};
-use std::time::Duration;
+use log::{debug, error, info, trace, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::future::Future;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use axum::BoxError;
+use humantime::format_duration;
+use reqwest::StatusCode;
 use tokio::sync::broadcast::Receiver;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinError, JoinHandle};
 use tokio::time;
+use tokio::time::Instant;
 use crate::domain::activity::{Activity, ActivityVec};
 use crate::domain::activity_stats::ActivityStats;
 use crate::domain::activity_stream::ActivityStream;
+use crate::domain::athlete::Athlete;
+use crate::domain::athlete_stats::AthleteStats;
+use crate::domain::athlete_zones::AthleteZones;
 use crate::domain::download_delay::DownloadDelay;
+use crate::domain::download_event::EventKind;
+use crate::domain::download_progress::CurrentActivity;
+use crate::domain::download_job::{DownloadJob, JobType};
 use crate::domain::download_state::DownloadState;
-use crate::domain::track_store_state::TrackStoreState;
+use crate::domain::effort::ActivityEfforts;
+use crate::domain::gear::Gear;
+use crate::domain::lap::LapVec;
+use crate::domain::missing_reason::MissingReason;
+use crate::domain::photo::{Photo, PhotoVec};
+use crate::domain::profile_step::ProfileStep;
+use crate::domain::retry_policy::RetryPolicy;
+use crate::domain::sensor_stream::{SensorStream, StreamSet};
+use crate::domain::social::{Comment, PersonName};
 use crate::oauth::token::Bearer;
 use crate::state::shared_state::MutexSharedState;
+use crate::strava::activity_query::ActivityQuery;
+use crate::strava::strava_client::StravaClient;
+use crate::strava::strava_error::StravaError;
+use crate::util::iso8601;
 
-async fn get_download_state(state: &MutexSharedState) -> DownloadState {
+const STABLE_RUN: Duration = Duration::from_secs(300); // Resets the restart backoff of the scheduler
+
+async fn set_download_state(state: &MutexSharedState, athlete_id: u64, download_state: DownloadState) {
+    let mut guard = state.lock().await;
+    guard.set_download_state(athlete_id, download_state);
+}
+
+/// Returns the next athlete with active download state after the previously served athlete
+/// (in ascending order of ids, wrapping around). This way, all athletes get a fair share of
+/// the Strava requests, whose rate limit applies to the entire application.
+/// Athletes waiting for the retry of a failed task are skipped.
+async fn get_next_athlete(state: &MutexSharedState, prev_athlete: Option<u64>) -> Option<(u64, DownloadState)> {
     let guard = state.lock().await;
-    guard.download_state.clone()
+    let athletes = guard.due_athletes(Instant::now());
+    let next = athletes.iter()
+        .find(|id| prev_athlete.is_none_or(|prev| **id > prev))
+        .or(athletes.first());
+    next.map(|id| (*id, guard.get_download_state(*id)))
+}
+
+/// Strava's rate limits apply to the application, so all athletes are affected
+async fn set_limit_reached(state: &MutexSharedState) -> Vec<u64> {
+    let mut guard = state.lock().await;
+    let athletes = guard.active_athletes();
+    for athlete_id in &athletes {
+        guard.set_download_state(*athlete_id, DownloadState::LimitReached);
+    }
+    athletes
+}
+
+/// Counts the failed task of the athlete. Returns the delay after which the task is retried,
+/// or [None] if the attempts are exhausted.
+async fn add_failure(state: &MutexSharedState, athlete_id: u64, retry: &RetryPolicy) -> Option<Duration> {
+    let mut guard = state.lock().await;
+    let failures = guard.add_failure(athlete_id);
+    if retry.is_exhausted(failures) {
+        return None
+    }
+    let delay = retry.delay(failures);
+    guard.postpone(athlete_id, Instant::now() + delay);
+    Some(delay)
+}
+
+async fn set_current_activity(state: &MutexSharedState, athlete_id: u64, activity: Option<CurrentActivity>) {
+    let mut guard = state.lock().await;
+    guard.set_current_activity(athlete_id, activity);
+}
+
+/// Appends an event to the download log. A failure is logged only, because the event log must not stop downloading.
+async fn add_event(state: &MutexSharedState, athlete_id: u64, kind: EventKind, activity_id: Option<u64>, details: String) {
+    let mut guard = state.lock().await;
+    if let Err(error) = guard.add_event(athlete_id, kind, activity_id, details, epoch_secs()).await {
+        warn!("Failed to log {kind:?} event of athlete {athlete_id}: {error}");
+    }
+}
+
+/// Shows the error in the server status and appends it to the download log
+async fn record_error(state: &MutexSharedState, athlete_id: u64, activity_id: Option<u64>, message: String) {
+    state.lock().await.set_last_error(athlete_id, message.clone(), epoch_secs());
+    add_event(state, athlete_id, EventKind::Error, activity_id, message).await;
+}
+
+async fn set_next_request(state: &MutexSharedState, period: Duration) {
+    let mut guard = state.lock().await;
+    guard.next_request_at = Some(epoch_secs() + period.as_secs() as i64);
+}
+
+async fn start_scheduled_downloads(state: &MutexSharedState) -> Vec<u64> {
+    let mut guard = state.lock().await;
+    guard.start_scheduled_downloads(epoch_secs())
+}
+
+async fn count_request(state: &MutexSharedState, athlete_id: u64) -> bool {
+    let mut guard = state.lock().await;
+    guard.count_request(athlete_id)
 }
 
-async fn set_download_state(state: &MutexSharedState, download_state: DownloadState) {
+async fn remove_token(state: &MutexSharedState, athlete_id: u64) {
     let mut guard = state.lock().await;
-    guard.download_state = download_state;
+    guard.oauth.remove_token(athlete_id);
 }
 
-async fn get_bearer(state: &MutexSharedState) -> Result<Option<Bearer>, BoxError> {
+async fn reset_failures(state: &MutexSharedState, athlete_id: u64) {
     let mut guard = state.lock().await;
-    guard.oauth.get_bearer().await
+    guard.reset_failures(athlete_id);
+}
+
+async fn get_strava_client(state: &MutexSharedState) -> StravaClient {
+    let guard = state.lock().await;
+    guard.strava.clone()
 }
 
-async fn get_query_params(state: &MutexSharedState) -> Result<(i64, i64), BoxError> {
+async fn get_bearer(state: &MutexSharedState, athlete_id: u64) -> Result<Option<Bearer>, BoxError> {
     let mut guard = state.lock().await;
-    let max_time = guard.get_activity_max_time().await?;
-    let per_page = guard.activities_per_page as i64;
-    Ok((max_time, per_page))
+    guard.oauth.get_bearer(athlete_id).await
 }
 
-async fn add_activities(state: &MutexSharedState, activities: &ActivityVec) -> Result<(), BoxError> {
+/// Returns the query of the activity list. The list starts after the latest stored activity,
+/// but not before the start of the time window of the download filter.
+async fn get_activity_query(state: &MutexSharedState, athlete_id: u64) -> Result<ActivityQuery, BoxError> {
+    let mut guard = state.lock().await;
+    let max_time = guard.get_activity_max_time(athlete_id).await?;
+    let after = guard.filter.after_secs().map_or(max_time, |after| max_time.max(after - 1)); // Strava's after is exclusive
+    Ok(ActivityQuery {
+        after: Some(after),
+        before: guard.filter.before_secs(),
+        per_page: Some(guard.activities_per_page),
+        ..ActivityQuery::default()
+    })
+}
+
+async fn add_activities(state: &MutexSharedState, athlete_id: u64, activities: &ActivityVec) -> Result<(), BoxError> {
     let mut guard = state.lock().await;
     let activity_stats = guard.service.add(activities).await?;
-    guard.merge_activity_stats(&activity_stats);
+    guard.merge_activity_stats(athlete_id, &activity_stats);
     Ok(())
 }
 
-async fn send_status_event(state: &MutexSharedState) -> Result<(), BoxError> {
+async fn save_download_state(state: &MutexSharedState, athlete_id: u64) -> Result<(), BoxError> {
     let mut guard = state.lock().await;
-    if guard.tx_data.receiver_count() > 0 {
-        let status = guard.get_server_status().await?;
-        guard.tx_data.send(status)?;
-    }
+    guard.save_download_state(athlete_id).await
+}
+
+async fn send_status_event(state: &MutexSharedState, athlete_id: u64) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.send_server_status(athlete_id).await
+}
+
+async fn next_profile_step(state: &MutexSharedState, athlete_id: u64) -> Option<ProfileStep> {
+    let mut guard = state.lock().await;
+    guard.next_profile_step(athlete_id)
+}
+
+/// Stores the athlete and schedules the download of the gear details
+async fn store_athlete(state: &MutexSharedState, athlete: &Athlete) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.put_athlete(athlete).await?;
+    guard.add_profile_steps(athlete.id, athlete.gear_ids().into_iter().map(ProfileStep::Gear));
     Ok(())
 }
 
-async fn get_earliest_activity_without_track(state: &MutexSharedState) -> Result<Option<Activity>, BoxError> {
+async fn store_athlete_zones(state: &MutexSharedState, athlete_id: u64, zones: &AthleteZones) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.put_athlete_zones(athlete_id, zones).await
+}
+
+async fn store_athlete_stats(state: &MutexSharedState, athlete_id: u64, stats: &AthleteStats) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.put_athlete_stats(athlete_id, stats).await
+}
+
+async fn store_gear(state: &MutexSharedState, athlete_id: u64, gear: &Gear) -> Result<(), BoxError> {
     let mut guard = state.lock().await;
-    guard.service.get_earliest_without_track().await
+    guard.service.put_gear(athlete_id, gear).await
 }
 
 async fn store_track(state: &MutexSharedState, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
     let mut guard = state.lock().await;
-    // Write the GPX file ...
-    guard.tracks.write(activity, stream)?;
-    // ... then mark the fetch status of the corresponding activity
-    guard.service.mark_fetched(activity, TrackStoreState::Stored).await?;
-    // ... next (and optionally) compute the tiles and store them
-    guard.service.store_tiles(activity, stream).await?;
-    // ... finally increase the in-memory stats to be sent to the UI
-    guard.merge_activity_stats(&ActivityStats::new(0, None, None, 1, Some(activity.start_date.clone())));
+    let guard = &mut *guard; // Reborrow to allow disjoint borrows of the fields
+    // Write the GPX file, mark the fetch status, and (optionally) store the tiles in one go ...
+    guard.service.store_track(&guard.tracks, activity, stream).await?;
+    // ... then increase the in-memory stats to be sent to the UI
+    guard.merge_activity_stats(activity.athlete_id, &ActivityStats::new(0, None, None, 1, Some(activity.start_date.clone())));
     Ok(())
 }
 
-async fn mark_track_missing(state: &MutexSharedState, activity: &Activity) -> Result<(), BoxError> {
+async fn store_laps(state: &MutexSharedState, activity: &Activity, laps: &LapVec) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    let guard = &mut *guard; // Reborrow to allow disjoint borrows of the fields
+    guard.service.store_laps(&guard.tracks, activity, laps).await
+}
+
+async fn store_efforts(state: &MutexSharedState, activity: &Activity, efforts: &ActivityEfforts) -> Result<(), BoxError> {
     let mut guard = state.lock().await;
-    guard.service.mark_fetched(activity, TrackStoreState::Missing).await?;
+    guard.service.store_efforts(activity, efforts).await
+}
+
+async fn store_photos(state: &MutexSharedState, activity: &Activity, photos: &[(Photo, Option<Vec<u8>>)]) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    let guard = &mut *guard; // Reborrow to allow disjoint borrows of the fields
+    guard.service.store_photos(&guard.tracks, activity, photos).await
+}
+
+async fn store_social(state: &MutexSharedState, activity: &Activity, comments: &[Comment], kudoers: &[PersonName]) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.store_social(activity, comments, kudoers).await
+}
+
+async fn enqueue_jobs(state: &MutexSharedState, athlete_id: u64) -> Result<u64, BoxError> {
+    let mut guard = state.lock().await;
+    guard.enqueue_jobs(athlete_id).await
+}
+
+async fn get_next_job(state: &MutexSharedState, athlete_id: u64) -> Result<Option<(DownloadJob, Activity)>, BoxError> {
+    let mut guard = state.lock().await;
+    let filter = guard.filter.clone();
+    let Some(job) = guard.service.get_next_job(athlete_id, epoch_secs(), &filter).await? else {
+        return Ok(None)
+    };
+    let activity = guard.service.get_by_id(job.activity_id).await?
+        .ok_or_else(|| format!("Activity {} of download job {} not found", job.activity_id, job.id))?;
+    Ok(Some((job, activity)))
+}
+
+async fn get_pending_job_count(state: &MutexSharedState, athlete_id: u64) -> Result<u64, BoxError> {
+    let mut guard = state.lock().await;
+    let filter = guard.filter.clone();
+    guard.service.get_pending_job_count(athlete_id, &filter).await
+}
+
+async fn complete_job(state: &MutexSharedState, job: &DownloadJob) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.complete_job(job).await
+}
+
+async fn fail_job(state: &MutexSharedState, job: &DownloadJob, delay: Duration, error: &str) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.fail_job(job, epoch_secs() + delay.as_secs() as i64, error).await
+}
+
+async fn poison_job(state: &MutexSharedState, job: &DownloadJob, error: &str) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.poison_job(job, error).await
+}
+
+fn epoch_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+async fn store_sensors(state: &MutexSharedState, activity: &Activity, stream: &SensorStream) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    let guard = &mut *guard;
+    guard.service.store_sensors(&guard.tracks, activity, stream).await?;
+    // Sensor data counts as track, otherwise the progress would never complete
+    guard.merge_activity_stats(activity.athlete_id, &ActivityStats::new(0, None, None, 1, Some(activity.start_date.clone())));
+    Ok(())
+}
+
+async fn mark_track_missing(state: &MutexSharedState, activity: &Activity, reason: MissingReason) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.service.mark_track_missing(activity, reason).await?;
     Ok(())
 }
 
 type TaskResult = Result<DownloadState, BoxError>;
 
-/// Downloads activities from Strava and stores them in the database
-async fn activity_task(state: &MutexSharedState, strava_url: &str, bearer: String) -> TaskResult {
-    let (max_time, per_page) = get_query_params(state).await?;
-    let query = vec![("after", max_time),("per_page", per_page)];
+/// Classification of the errors returned by the download tasks
+#[derive(Debug, PartialEq)]
+enum Failure {
+    Transient, // Timeouts, connection errors and 5xx status, worth a retry
+    Permanent, // Other errors of a single request, like 400 status or an unparsable response
+    Fatal      // Errors affecting all requests, like 401 status or database errors
+}
 
-    let response = reqwest::Client::new()
-        .get(format!("{strava_url}/athlete/activities"))
-        .header(reqwest::header::AUTHORIZATION, bearer)
-        .query(&query)
-        .send().await?
-        .error_for_status();
+fn classify(error: &BoxError) -> Failure {
+    match error.downcast_ref::<StravaError>() {
+        Some(StravaError::Unauthorized(_)) | None => Failure::Fatal,
+        Some(error) if error.is_transient() => Failure::Transient,
+        Some(_) => Failure::Permanent
+    }
+}
 
-    if let Err(error) = response.as_ref() {
-        if error.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
-            warn!("Strava API limits reached, stop downloading (can be re-enabled)");
-            return Ok(DownloadState::LimitReached)
+/// Downloads the next part of the athlete profile (see [ProfileStep]) from Strava and stores it in the database.
+/// If a part is not accessible (e.g. the zones require scope profile:read_all), it is skipped.
+async fn profile_task(state: &MutexSharedState, strava: &StravaClient, athlete_id: u64, bearer: &str) -> TaskResult {
+    let Some(step) = next_profile_step(state, athlete_id).await else {
+        info!("Profile of athlete {athlete_id} complete, start downloading activities");
+        return Ok(DownloadState::Activities)
+    };
+    let result = match &step {
+        ProfileStep::Athlete => match strava.get_athlete(bearer).await {
+            Ok(athlete) => Ok(store_athlete(state, &athlete).await?),
+            Err(error) => Err(error)
+        },
+        ProfileStep::Zones => match strava.get_athlete_zones(bearer).await {
+            Ok(zones) => Ok(store_athlete_zones(state, athlete_id, &zones).await?),
+            Err(error) => Err(error)
+        },
+        ProfileStep::Stats => match strava.get_athlete_stats(bearer, athlete_id).await {
+            Ok(stats) => Ok(store_athlete_stats(state, athlete_id, &stats).await?),
+            Err(error) => Err(error)
+        },
+        ProfileStep::Gear(id) => match strava.get_gear(bearer, id).await {
+            Ok(gear) => Ok(store_gear(state, athlete_id, &gear).await?),
+            Err(error) => Err(error)
+        }
+    };
+    match result {
+        Err(StravaError::RateLimited { reset }) => Ok(limit_reached(state, athlete_id, reset).await),
+        Err(error) if is_inaccessible(&step, &error) => {
+            warn!("{error} for {step:?} of athlete {athlete_id}, skip it");
+            Ok(DownloadState::Profile) // Downloading continues
+        }
+        result => {
+            result?;
+            Ok(DownloadState::Profile)
         }
-        warn!("Strava activities API returned status {:?}, stop downloading", error.status());
-        return Ok(DownloadState::RequestError)
     }
+}
+
+/// Returns true if a part of the profile is not accessible. Strava returns 401 for a missing scope,
+/// but only the zones require an extra scope, so 401 means a revoked token for all other parts.
+fn is_inaccessible(step: &ProfileStep, error: &StravaError) -> bool {
+    match error {
+        StravaError::Unauthorized(StatusCode::UNAUTHORIZED) => *step == ProfileStep::Zones,
+        StravaError::Unauthorized(_) | StravaError::NotFound => true,
+        _ => false
+    }
+}
+
+async fn limit_reached(state: &MutexSharedState, athlete_id: u64, reset: i64) -> DownloadState {
+    let reset = iso8601::secs_to_string(reset);
+    warn!("Strava API limits reached until {reset}, stop downloading (can be re-enabled)");
+    add_event(state, athlete_id, EventKind::RateLimited, None, format!("Strava API limits reached until {reset}")).await;
+    DownloadState::LimitReached
+}
 
-    let activities= response?.json::<ActivityVec>().await?;
+/// Downloads activities of an athlete from Strava and stores them in the database
+async fn activity_task(state: &MutexSharedState, strava: &StravaClient, athlete_id: u64, bearer: &str) -> TaskResult {
+    let query = get_activity_query(state, athlete_id).await?;
+
+    let activities = match strava.get_activities(bearer, &query).await {
+        Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, athlete_id, reset).await),
+        result => result?
+    };
+    let after = query.after.map(iso8601::secs_to_string).unwrap_or_default();
+    add_event(state, athlete_id, EventKind::PageFetched, None, format!("{} activities after {after}", activities.len())).await;
     if activities.is_empty() {
-        info!("No further activities, start downloading activity streams from oldest to youngest");
+        let count = enqueue_jobs(state, athlete_id).await?;
+        info!("No further activities of athlete {athlete_id}, enqueued {count} new download jobs");
         return Ok(DownloadState::Tracks)
     }
 
-    add_activities(state, &activities).await?;
+    add_activities(state, athlete_id, &activities).await?;
+    let first = activities.iter().map(|activity| &activity.start_date).min();
+    let last = activities.iter().map(|activity| &activity.start_date).max();
+    if let (Some(first), Some(last)) = (first, last) {
+        add_event(state, athlete_id, EventKind::ActivitiesAdded, None, format!("{} activities from {first} to {last}", activities.len())).await;
+    }
     Ok(DownloadState::Activities)
 }
 
-/// Downloads an activity stream from Strava, transforms it to a GPX track, and stores it as file
-async fn stream_task(state: &MutexSharedState, strava_url: &str, bearer: String) -> TaskResult {
-    match get_earliest_activity_without_track(state).await? {
-        Some(activity) => {
-            let url = format!("{strava_url}/activities/{}/streams?keys=time,latlng,altitude&key_by_type=true", activity.id);
-            let response = reqwest::Client::new()
-                .get(&url)
-                .header(reqwest::header::AUTHORIZATION, bearer)
-                .send().await?
-                .error_for_status();
-
-            if let Err(error) = response.as_ref() {
-                if error.status() == Some(reqwest::StatusCode::NOT_FOUND) {
-                    warn!("Activity {} has no track", activity.id);
-                    mark_track_missing(state, &activity).await?;
-                    return Ok(DownloadState::Tracks) // Downloading continues
-                }
-                if error.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
-                    warn!("Strava API limits reached, stop downloading (can be re-enabled)");
-                    return Ok(DownloadState::LimitReached)
+/// Downloads an activity stream from Strava, transforms it to a GPX track, and stores it as file.
+/// The stream of an activity without coordinates (like an indoor ride) is stored as CSV file instead.
+async fn stream_task(state: &MutexSharedState, strava: &StravaClient, activity: &Activity, bearer: &str) -> TaskResult {
+    match strava.get_activity_stream(bearer, activity.id).await {
+        Ok(StreamSet::Track(stream)) => {
+            store_track(state, activity, &stream).await?;
+            add_event(state, activity.athlete_id, EventKind::TrackStored, Some(activity.id), activity.name.clone()).await;
+        }
+        Ok(StreamSet::Sensors(stream)) if stream.has_data() => {
+            store_sensors(state, activity, &stream).await?;
+            add_event(state, activity.athlete_id, EventKind::TrackStored, Some(activity.id), format!("{} (sensor data without GPS)", activity.name)).await;
+        }
+        Ok(StreamSet::Sensors(_)) => {
+            warn!("Activity {} has neither coordinates nor sensor data", activity.id);
+            mark_track_missing(state, activity, MissingReason::NoLatlng).await?;
+            add_event(state, activity.athlete_id, EventKind::TrackMissing, Some(activity.id), "Activity stream without data".to_string()).await;
+        }
+        Err(StravaError::NotFound) => {
+            warn!("Activity {} has no track", activity.id);
+            mark_track_missing(state, activity, MissingReason::NotFound).await?;
+            add_event(state, activity.athlete_id, EventKind::TrackMissing, Some(activity.id), "No activity stream".to_string()).await;
+        }
+        Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+        Err(StravaError::Decode(error)) => {
+            warn!("Failed to parse the track of activity {}: {}", activity.id, error);
+            mark_track_missing(state, activity, MissingReason::ParseError).await?;
+            add_event(state, activity.athlete_id, EventKind::TrackMissing, Some(activity.id), format!("Unparsable activity stream: {error}")).await;
+        }
+        Err(error) => return Err(error.into())
+    }
+    Ok(DownloadState::Tracks) // Downloading continues
+}
+
+/// Downloads the laps of an activity from Strava and stores them in the database (and the GPX file)
+async fn lap_task(state: &MutexSharedState, strava: &StravaClient, activity: &Activity, bearer: &str) -> TaskResult {
+    let laps = match strava.get_laps(bearer, activity.id).await {
+        Err(StravaError::NotFound) => {
+            warn!("Activity {} has no laps", activity.id);
+            LapVec::new()
+        }
+        Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+        result => result?
+    };
+    store_laps(state, activity, &laps).await?;
+    Ok(DownloadState::Laps)
+}
+
+/// Downloads the detailed activity from Strava and stores its segment efforts and best efforts in the database
+async fn effort_task(state: &MutexSharedState, strava: &StravaClient, activity: &Activity, bearer: &str) -> TaskResult {
+    let efforts = match strava.get_efforts(bearer, activity.id).await {
+        Err(StravaError::NotFound) => {
+            warn!("Activity {} not found", activity.id);
+            ActivityEfforts::default()
+        }
+        Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+        result => result?
+    };
+    store_efforts(state, activity, &efforts).await?;
+    Ok(DownloadState::Efforts)
+}
+
+/// Lists the photos of an activity, downloads their images, and stores them next to the track.
+/// The images are served by a CDN and do not count against the Strava API rate limits.
+async fn photo_task(state: &MutexSharedState, strava: &StravaClient, activity: &Activity, bearer: &str) -> TaskResult {
+    let photo_list = match strava.get_photos(bearer, activity.id).await {
+        Err(StravaError::NotFound) => {
+            warn!("Activity {} has no photos", activity.id);
+            PhotoVec::new()
+        }
+        Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+        result => result?
+    };
+
+    let mut photos = Vec::new();
+    for photo in photo_list {
+        let image = match photo.url() {
+            Some(url) => strava.get_image(url).await
+                .inspect_err(|error| warn!("Failed to download photo {} of activity {}: {}", photo.unique_id, activity.id, error))
+                .ok(),
+            None => None
+        };
+        photos.push((photo, image));
+    }
+    store_photos(state, activity, &photos).await?;
+    Ok(DownloadState::Photos)
+}
+
+/// Downloads the comments and kudoers of an activity. If an activity does not exist, its lists are empty.
+async fn social_task(state: &MutexSharedState, strava: &StravaClient, activity: &Activity, bearer: &str) -> TaskResult {
+    let comments = match activity.comment_count {
+        Some(0) => Vec::new(),
+        _ => match strava.get_comments(bearer, activity.id).await {
+            Err(StravaError::NotFound) => Vec::new(),
+            Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+            result => result?
+        }
+    };
+    let kudoers = match activity.kudos_count {
+        0 => Vec::new(),
+        _ => match strava.get_kudoers(bearer, activity.id).await {
+            Err(StravaError::NotFound) => Vec::new(),
+            Err(StravaError::RateLimited { reset }) => return Ok(limit_reached(state, activity.athlete_id, reset).await),
+            result => result?
+        }
+    };
+    store_social(state, activity, &comments, &kudoers).await?;
+    Ok(DownloadState::Social)
+}
+
+/// Executes the next due download job of the athlete. A successful job is removed from the queue.
+/// If the Strava API limits are reached, the job is kept as is. A failed job stays in the queue
+/// with the error recorded: After a transient failure, it is postponed according to the retry policy.
+/// If it fails permanently or too often, it is poisoned. A fatal failure is returned as error.
+async fn job_task(state: &MutexSharedState, strava: &StravaClient, athlete_id: u64, download_state: &DownloadState,
+                  retry: &RetryPolicy, bearer: &str) -> TaskResult {
+    let Some((job, activity)) = get_next_job(state, athlete_id).await? else {
+        if get_pending_job_count(state, athlete_id).await? > 0 {
+            trace!("Download jobs of athlete {athlete_id} postponed, wait for the retry");
+            return Ok(download_state.clone())
+        }
+        info!("No further download jobs of athlete {athlete_id}, stop downloading (can be re-enabled)");
+        set_current_activity(state, athlete_id, None).await;
+        return Ok(DownloadState::NoResults)
+    };
+    let current = CurrentActivity {
+        id: activity.id,
+        name: activity.name.clone(),
+        start_date: activity.start_date.clone(),
+        job: format!("{:?}", job.job_type)
+    };
+    set_current_activity(state, athlete_id, Some(current)).await;
+    let result = match job.job_type {
+        JobType::Track => stream_task(state, strava, &activity, bearer).await,
+        JobType::Laps => lap_task(state, strava, &activity, bearer).await,
+        JobType::Efforts => effort_task(state, strava, &activity, bearer).await,
+        JobType::Photos => photo_task(state, strava, &activity, bearer).await,
+        JobType::Social => social_task(state, strava, &activity, bearer).await
+    };
+    match result {
+        Ok(DownloadState::LimitReached) => Ok(DownloadState::LimitReached),
+        Ok(new_state) => {
+            complete_job(state, &job).await?;
+            Ok(new_state)
+        }
+        Err(error) => {
+            let attempts = job.attempts + 1;
+            let message = format!("{:?} job of activity {} failed: {error}", job.job_type, activity.id);
+            match classify(&error) {
+                Failure::Transient if !retry.is_exhausted(attempts) => {
+                    let delay = retry.delay(attempts);
+                    warn!("{:?} job of activity {} failed: {}, retry in {}", job.job_type, activity.id, error, format_duration(delay));
+                    record_error(state, athlete_id, Some(activity.id), message).await;
+                    fail_job(state, &job, delay, &error.to_string()).await?;
+                    Ok(job.job_type.download_state())
                 }
-                warn!("Strava streams API returned status {:?}, stop downloading", error.status());
-                return Ok(DownloadState::RequestError)
-            }
-            // match response?.text().await {
-            match response?.json::<ActivityStream>().await {
-                Ok(stream) => {
-                    // info!("{:?}", stream);
-                    store_track(state, &activity, &stream).await?;
-                    Ok(DownloadState::Tracks)
+                Failure::Fatal => {
+                    fail_job(state, &job, Duration::ZERO, &error.to_string()).await?;
+                    Err(error) // Stops downloading, the error is recorded by handle_failure
                 }
-                Err(error) => {
-                    // A known case is that the activity stream does not contain a "latlon" array
-                    warn!("Failed to parse the track of activity {}: {}", activity.id, error);
-                    mark_track_missing(state, &activity).await?;
-                    Ok(DownloadState::Tracks) // Downloading continues
+                _ => {
+                    warn!("{:?} job of activity {} failed {} times: {}, poison it", job.job_type, activity.id, attempts, error);
+                    record_error(state, athlete_id, Some(activity.id), message).await;
+                    poison_job(state, &job, &error.to_string()).await?;
+                    Ok(job.job_type.download_state())
                 }
             }
         }
-        None => {
-            info!("No further activities without track, stop downloading (can be re-enabled)");
-            Ok(DownloadState::NoResults)
-        }
     }
 }
 
-async fn try_task(state: &MutexSharedState, strava_url: &str) -> Result<DownloadDelay, BoxError> {
-    let mut new_delay = DownloadDelay::Short;
-    let download_state = get_download_state(state).await;
-    if download_state.is_active() {
-        match get_bearer(state).await? {
-            Some(bearer) => {
-                let new_state= match download_state {
-                    DownloadState::Activities => activity_task(state, strava_url, bearer.into()).await?,
-                    DownloadState::Tracks => stream_task(state, strava_url, bearer.into()).await?,
-                    _ => download_state.clone()
-                };
-                new_delay = download_state.new_delay(&new_state);
-                set_download_state(state, new_state).await;
-                send_status_event(state).await?; // Send status event to update the frontend
-            }
-            None => {
-                // This should not happen because the REST API allows enabling the downloader only if
-                // authenticated. There is no way for the downloader to do an OAuth auth code flow.
-                warn!("Not authorized, skip execution of download task");
-            }
+/// Executes the download task matching the download state of the athlete
+async fn run_task(state: &MutexSharedState, strava: &StravaClient, athlete_id: u64, download_state: &DownloadState,
+                  retry: &RetryPolicy) -> TaskResult {
+    let Some(bearer) = get_bearer(state, athlete_id).await? else {
+        // The REST API allows enabling the downloader only if authenticated, so the token was removed
+        // in the meantime (refresh rejected or logout). There is no way for the downloader to do an
+        // OAuth auth code flow.
+        warn!("Athlete {athlete_id} not authorized anymore, stop downloading");
+        return Ok(DownloadState::Unauthorized)
+    };
+    let bearer: String = bearer.into();
+    match download_state {
+        DownloadState::Profile => profile_task(state, strava, athlete_id, &bearer).await,
+        DownloadState::Activities => activity_task(state, strava, athlete_id, &bearer).await,
+        job_phase if job_phase.is_job_phase() => job_task(state, strava, athlete_id, download_state, retry, &bearer).await,
+        _ => Ok(download_state.clone())
+    }
+}
+
+/// Handles a failed download task. If the error is transient, the task is retried after a delay
+/// according to the retry policy. Otherwise, or if the attempts are exhausted, downloading stops.
+/// If Strava rejects the token, the token is removed, so the athlete has to authorize again.
+async fn handle_failure(state: &MutexSharedState, athlete_id: u64, download_state: &DownloadState,
+                        retry: &RetryPolicy, error: BoxError) -> DownloadState {
+    record_error(state, athlete_id, None, format!("{download_state:?} task failed: {error}")).await;
+    if matches!(error.downcast_ref::<StravaError>(), Some(StravaError::Unauthorized(StatusCode::UNAUTHORIZED))) {
+        warn!("{download_state:?} task of athlete {athlete_id} failed: {error}, remove the token and stop downloading");
+        remove_token(state, athlete_id).await;
+        return DownloadState::Unauthorized
+    }
+    if classify(&error) == Failure::Transient {
+        if let Some(delay) = add_failure(state, athlete_id, retry).await {
+            warn!("{download_state:?} task of athlete {athlete_id} failed: {error}, retry in {}", format_duration(delay));
+            return download_state.clone()
         }
-    } else {
+    }
+    warn!("{download_state:?} task of athlete {athlete_id} failed: {error}, stop downloading (can be re-enabled)");
+    DownloadState::RequestError
+}
+
+/// Starts the scheduled downloads if due, then executes the download task of the next athlete
+/// with active download state. Updates `prev_athlete` to the served athlete.
+/// A scheduled download stops when its request budget is exhausted.
+async fn try_task(state: &MutexSharedState, retry: &RetryPolicy, prev_athlete: &mut Option<u64>)
+    -> Result<DownloadDelay, BoxError> {
+    for athlete_id in start_scheduled_downloads(state).await {
+        info!("Start scheduled download of athlete {athlete_id}");
+        save_download_state(state, athlete_id).await?;
+        send_status_event(state, athlete_id).await?;
+    }
+    let Some((athlete_id, download_state)) = get_next_athlete(state, *prev_athlete).await else {
         trace!("Download disabled, skip task execution");
+        return Ok(DownloadDelay::Short)
+    };
+    *prev_athlete = Some(athlete_id);
+    let strava = get_strava_client(state).await;
+    let new_state = match run_task(state, &strava, athlete_id, &download_state, retry).await {
+        Ok(new_state) => {
+            reset_failures(state, athlete_id).await;
+            new_state
+        }
+        Err(error) => handle_failure(state, athlete_id, &download_state, retry, error).await
+    };
+    let new_state = match count_request(state, athlete_id).await && new_state.is_active() {
+        true => {
+            info!("Requests of the scheduled download of athlete {athlete_id} exhausted, stop downloading");
+            DownloadState::Inactive
+        }
+        false => new_state
+    };
+    let new_delay = download_state.new_delay(&new_state);
+    let athletes = match new_state {
+        DownloadState::LimitReached => set_limit_reached(state).await,
+        _ => {
+            set_download_state(state, athlete_id, new_state).await;
+            vec![athlete_id]
+        }
+    };
+    for athlete_id in athletes {
+        save_download_state(state, athlete_id).await?; // Lets a restart resume the download
+        send_status_event(state, athlete_id).await?; // Send status event to update the frontend
     }
     Ok(new_delay)
 }
 
 // Must be async as required by tokio::select!
-async fn repeat(state: MutexSharedState, strava_url: &str, long_period: Duration, short_period: Duration, mut rx_term: Receiver<()>) {
+async fn repeat(state: MutexSharedState, retry: RetryPolicy, long_period: Duration, short_period: Duration, mut rx_term: Receiver<()>) {
     let mut curr_delay = DownloadDelay::Short;
+    let mut prev_athlete = None;
     let mut interval = time::interval(Duration::from_secs(1));
     loop {
         tokio::select! {
             _ = interval.tick() => {
-                match try_task(&state, strava_url).await {
+                match try_task(&state, &retry, &mut prev_athlete).await {
                     Ok(new_delay) => if new_delay != curr_delay {
                         match new_delay {
                             DownloadDelay::Long => {
@@ -204,10 +659,11 @@ async fn repeat(state: MutexSharedState, strava_url: &str, long_period: Duration
                         curr_delay = new_delay;
                     }
                     Err(e) => {
-                        warn!("Task failed: {:?}, leave downloader", e);
-                        break;
+                        warn!("Task failed: {:?}, continue downloading", e); // A single failure must not stop the downloader
+                        state.lock().await.scheduler_status.scheduler_error = Some(format!("Task failed: {e}"));
                     }
                 }
+                set_next_request(&state, interval.period()).await;
             },
             _ = rx_term.recv() => {
                 debug!("Termination signal received, leave downloader");
@@ -217,9 +673,150 @@ async fn repeat(state: MutexSharedState, strava_url: &str, long_period: Duration
     }
 }
 
-pub fn spawn_download_scheduler(state: MutexSharedState, rx_term: Receiver<()>, strava_url: String, period: Duration) -> JoinHandle<()> {
+/// Clears the running flag of the scheduler when dropped, which also happens when the task panics
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            error!("Download scheduler panicked");
+        }
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Runs the scheduler task until the termination signal. If the task panics, it is restarted after
+/// a backoff delay, which grows with each restart unless the task ran for [STABLE_RUN] before.
+async fn supervise<F, Fut>(state: MutexSharedState, mut rx_term: Receiver<()>, backoff: RetryPolicy, task: F)
+    where
+        F: Fn(MutexSharedState, Receiver<()>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static
+{
+    let mut failures = 0;
+    loop {
+        let (running, rx_task) = {
+            let guard = state.lock().await;
+            (guard.scheduler_running.clone(), guard.tx_term.subscribe())
+        };
+        let future = task(state.clone(), rx_task);
+        let started = Instant::now();
+        let result = tokio::spawn(async move {
+            running.store(true, Ordering::Relaxed);
+            let _guard = RunningGuard(running);
+            future.await
+        }).await;
+        let error = match result {
+            Ok(()) => break, // Termination signal received
+            Err(error) => panic_message(error)
+        };
+        failures = if started.elapsed() >= STABLE_RUN { 1 } else { failures + 1 };
+        let delay = backoff.delay(failures);
+        error!("Download scheduler failed: {error}, restart in {}", format_duration(delay));
+        if let Err(error) = record_restart(&state, error).await {
+            warn!("Failed to send the scheduler status: {error}");
+        }
+        tokio::select! {
+            _ = time::sleep(delay) => info!("Restart download scheduler"),
+            _ = rx_term.recv() => break
+        }
+    }
+}
+
+fn panic_message(error: JoinError) -> String {
+    match error.try_into_panic() {
+        Ok(payload) => match payload.downcast_ref::<&str>() {
+            Some(message) => format!("Panic: {message}"),
+            None => format!("Panic: {}", payload.downcast_ref::<String>().map_or("unknown", |m| m.as_str()))
+        }
+        Err(error) => error.to_string()
+    }
+}
+
+async fn record_restart(state: &MutexSharedState, error: String) -> Result<(), BoxError> {
+    let mut guard = state.lock().await;
+    guard.scheduler_status.scheduler_restarts += 1;
+    guard.scheduler_status.scheduler_error = Some(error);
+    for athlete_id in guard.oauth.athletes() {
+        guard.send_server_status(athlete_id).await?;
+    }
+    Ok(())
+}
+
+pub fn spawn_download_scheduler(state: MutexSharedState, rx_term: Receiver<()>, period: Duration, retry: RetryPolicy) -> JoinHandle<()> {
     info!("Spawn download scheduler");
+    let backoff = RetryPolicy::new(u32::MAX, Duration::from_secs(1), Duration::from_secs(300));
     tokio::spawn(async move {
-        repeat(state, &strava_url, period, Duration::from_millis(500), rx_term).await;
+        state.lock().await.request_period = period;
+        supervise(state, rx_term, backoff, move |state, rx_term| {
+            repeat(state, retry.clone(), period, Duration::from_millis(500), rx_term)
+        }).await
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use axum::BoxError;
+    use reqwest::StatusCode;
+    use crate::domain::retry_policy::RetryPolicy;
+    use crate::service::activity_service::ActivityService;
+    use crate::service::download_scheduler::{classify, supervise, Failure};
+    use crate::state::shared_state::SharedState;
+    use crate::strava::strava_error::StravaError;
+
+    #[tokio::test]
+    async fn test_supervise() {
+        let service = ActivityService::new(":memory:", false).await.unwrap();
+        let state = SharedState::dummy(service);
+        let rx_term = state.lock().await.tx_term.subscribe();
+        let backoff = RetryPolicy::new(u32::MAX, Duration::from_millis(1), Duration::from_millis(10));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let task_runs = runs.clone();
+        let supervisor = tokio::spawn(supervise(state.clone(), rx_term, backoff, move |_, mut rx_term| {
+            let run = task_runs.fetch_add(1, Ordering::Relaxed);
+            async move {
+                if run == 0 {
+                    panic!("boom");
+                }
+                rx_term.recv().await.unwrap();
+            }
+        }));
+        while runs.load(Ordering::Relaxed) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(state.lock().await.scheduler_running.load(Ordering::Relaxed));
+
+        let status = state.lock().await.scheduler_status.clone();
+        assert_eq!(status.scheduler_restarts, 1);
+        assert_eq!(status.scheduler_error, Some("Panic: boom".to_string()));
+
+        state.lock().await.tx_term.send(()).unwrap();
+        supervisor.await.unwrap();
+        assert_eq!(runs.load(Ordering::Relaxed), 2); // No restart after termination
+        assert!(!state.lock().await.scheduler_running.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_classify() {
+        let error: BoxError = "Database is locked".into();
+        assert_eq!(classify(&error), Failure::Fatal);
+
+        let error: BoxError = StravaError::from(reqwest::Client::new().get("no url").send().await.unwrap_err()).into();
+        assert_eq!(classify(&error), Failure::Permanent);
+
+        let error: BoxError = StravaError::from(reqwest::Client::new().get("http://127.0.0.1:1").send().await.unwrap_err()).into();
+        assert_eq!(classify(&error), Failure::Transient); // Connection refused
+
+        let error: BoxError = StravaError::Server(StatusCode::SERVICE_UNAVAILABLE).into();
+        assert_eq!(classify(&error), Failure::Transient);
+
+        let error: BoxError = StravaError::Unauthorized(StatusCode::UNAUTHORIZED).into();
+        assert_eq!(classify(&error), Failure::Fatal);
+
+        let error: BoxError = StravaError::NotFound.into();
+        assert_eq!(classify(&error), Failure::Permanent);
+    }
+}
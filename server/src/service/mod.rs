@@ -1,2 +1,3 @@
 pub mod activity_service;
 pub mod download_scheduler;
+pub mod track_verifier;
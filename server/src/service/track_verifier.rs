@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use axum::BoxError;
+use log::{info, warn};
+use crate::domain::map_tile::MapTile;
+use crate::domain::map_zoom::MapZoom;
+use crate::domain::track_store_state::TrackStoreState;
+use crate::service::activity_service::ActivityService;
+use crate::track::track_storage::TrackStorage;
+
+/// Inconsistencies between the GPX (or CSV) files and the activity database found by [verify_tracks]
+#[derive(Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    pub temp_files: Vec<PathBuf>,       // Leftovers of interrupted track writes
+    pub orphan_files: Vec<PathBuf>,     // GPX or CSV files without activity in the database (or outdated CSV files)
+    pub unmarked_files: Vec<u64>,       // GPX or CSV files of activities not marked as stored
+    pub missing_files: Vec<u64>,        // Activities marked as stored, but without GPX or CSV file
+    pub corrupt_files: Vec<u64>,        // Activities marked as stored, but with unreadable GPX or CSV file
+    pub unsynced_tiles: Vec<MapZoom>    // Zoom levels where the stored tiles deviate from the tracks
+}
+
+impl VerifyReport {
+    pub fn is_consistent(&self) -> bool {
+        *self == VerifyReport::default()
+    }
+}
+
+/// Checks that every GPX file belongs to an activity marked as stored (and vice versa),
+/// that all GPX files can be read, and that the tile tables match the stored tracks.
+/// The CSV files of activities with sensor data ([TrackStoreState::Sensors]) are checked alike.
+/// If `repair` is set, the inconsistencies are fixed:
+/// * Temporary and orphan files are deleted
+/// * Activities with a readable but unmarked GPX (or CSV) file are marked as stored
+/// * Activities with missing or corrupt GPX (or CSV) files are reset to pending, so they are downloaded again
+/// * Unsynced tiles are deleted and recomputed from the tracks
+pub async fn verify_tracks(service: &mut ActivityService, tracks: &TrackStorage, repair: bool) -> Result<VerifyReport, BoxError> {
+    let mut report = VerifyReport::default();
+
+    for path in tracks.list_temp_files()? {
+        warn!("Found temporary file {}", path.display());
+        if repair {
+            fs::remove_file(&path)?;
+        }
+        report.temp_files.push(path);
+    }
+
+    for (id, path) in tracks.list()? {
+        match service.get_fetch_state(id).await? {
+            None => {
+                warn!("Found GPX file {} without activity", path.display());
+                if repair {
+                    fs::remove_file(&path)?;
+                }
+                report.orphan_files.push(path);
+            }
+            Some(TrackStoreState::Stored) => {} // Checked below
+            Some(_) => {
+                warn!("Found GPX file {} of activity not marked as stored", path.display());
+                if repair {
+                    let activity = service.get_by_id(id).await?
+                        .ok_or_else(|| format!("Activity {id} vanished during the verification"))?;
+                    if tracks.read(&activity).is_ok() {
+                        service.mark_fetched(&activity, TrackStoreState::Stored).await?;
+                    }
+                }
+                report.unmarked_files.push(id);
+            }
+        }
+    }
+
+    for (id, path) in tracks.list_sensors()? {
+        match service.get_fetch_state(id).await? {
+            // The CSV file of an activity with GPX file is a leftover of a refetch
+            None | Some(TrackStoreState::Stored) => {
+                warn!("Found CSV file {} without activity with sensor data", path.display());
+                if repair {
+                    fs::remove_file(&path)?;
+                }
+                report.orphan_files.push(path);
+            }
+            Some(TrackStoreState::Sensors) => {} // Checked below
+            Some(_) => {
+                warn!("Found CSV file {} of activity not marked as stored", path.display());
+                if repair {
+                    let activity = service.get_by_id(id).await?
+                        .ok_or_else(|| format!("Activity {id} vanished during the verification"))?;
+                    if tracks.check_sensors(&activity).is_ok() {
+                        service.mark_fetched(&activity, TrackStoreState::Sensors).await?;
+                    }
+                }
+                report.unmarked_files.push(id);
+            }
+        }
+    }
+
+    for activity in service.get_all_with_sensors().await? {
+        if !tracks.sensors_exist(&activity) {
+            warn!("CSV file of activity {} is missing", activity.id);
+            if repair {
+                service.mark_fetched(&activity, TrackStoreState::Pending).await?;
+            }
+            report.missing_files.push(activity.id);
+        } else if let Err(error) = tracks.check_sensors(&activity) {
+            warn!("CSV file of activity {} is corrupt: {}", activity.id, error);
+            if repair {
+                tracks.delete_sensors(&activity)?;
+                service.mark_fetched(&activity, TrackStoreState::Pending).await?;
+            }
+            report.corrupt_files.push(activity.id);
+        }
+    }
+
+    // Tiles are stored per athlete, so the same tile may occur once for each athlete
+    let mut expected_tiles: HashMap<MapZoom, HashMap<(u64, MapTile), u64>> = HashMap::new();
+    for activity in service.get_all_with_track().await? {
+        if !tracks.exists(&activity)? {
+            warn!("GPX file of activity {} is missing", activity.id);
+            if repair {
+                service.mark_fetched(&activity, TrackStoreState::Pending).await?;
+            }
+            report.missing_files.push(activity.id);
+            continue;
+        }
+        match tracks.read(&activity) {
+            Ok(stream) => {
+                if service.is_storing_tiles() {
+                    for zoom in MapZoom::VALUES {
+                        let counts = expected_tiles.entry(zoom).or_default();
+                        for tile in stream.to_tiles(zoom)? {
+                            *counts.entry((activity.athlete_id, tile)).or_default() += 1;
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                warn!("GPX file of activity {} is corrupt: {}", activity.id, error);
+                if repair {
+                    tracks.delete(&activity)?;
+                    service.mark_fetched(&activity, TrackStoreState::Pending).await?;
+                }
+                report.corrupt_files.push(activity.id);
+            }
+        }
+    }
+
+    if service.is_storing_tiles() {
+        for zoom in MapZoom::VALUES {
+            let expected = expected_tiles.get(&zoom)
+                .map(|counts| (counts.len() as u64, counts.values().sum()))
+                .unwrap_or((0, 0));
+            let actual = service.get_tile_stats(zoom).await?;
+            if actual != expected {
+                warn!("Tiles for zoom level {} out of sync: expected {:?} (count, sum), found {:?}", zoom.value(), expected, actual);
+                report.unsynced_tiles.push(zoom);
+            }
+        }
+        if repair && !report.unsynced_tiles.is_empty() {
+            info!("Recompute all tiles");
+            service.delete_all_tiles().await?;
+            for activity in service.get_all_with_track().await? {
+                let stream = tracks.read(&activity)?;
+                service.store_tiles(&activity, &stream).await?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::domain::activity::Activity;
+    use crate::domain::activity_stream::ActivityStream;
+    use crate::domain::map_zoom::MapZoom;
+    use crate::domain::sensor_stream::SensorStream;
+    use crate::domain::track_store_state::TrackStoreState;
+    use crate::service::activity_service::ActivityService;
+    use crate::service::track_verifier::{verify_tracks, VerifyReport};
+    use crate::track::track_storage::TrackStorage;
+
+    #[tokio::test]
+    async fn test_verify_consistent() {
+        let base_path = create_base_path("verify_consistent");
+        let tracks = TrackStorage::new(&base_path);
+        let mut service = create_service(&[1, 2]).await;
+        service.store_track(&tracks, &Activity::dummy(1, "2020-01-01T00:00:00Z"), &get_stream()).await.unwrap();
+
+        let report = verify_tracks(&mut service, &tracks, false).await;
+        assert!(report.is_ok());
+        assert!(report.unwrap().is_consistent());
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_and_repair() {
+        let base_path = create_base_path("verify_and_repair");
+        let tracks = TrackStorage::new(&base_path);
+        let mut service = create_service(&[1, 2, 3, 4]).await;
+        let activities: Vec<Activity> = (1..=4).map(|id| Activity::dummy(id, "2020-01-01T00:00:00Z")).collect();
+        let dir = format!("{base_path}/{}/2020/01", Activity::DUMMY_ATHLETE);
+
+        // Activity 1 is consistent
+        service.store_track(&tracks, &activities[0], &get_stream()).await.unwrap();
+        // Activity 2 has a GPX file, but is not marked as stored
+        tracks.write(&activities[1], &get_stream(), &[], &[]).unwrap();
+        // Activity 3 is marked as stored, but has no file (its tiles are stored anyway)
+        let stream = ActivityStream::new(vec![(2.0, 2.0)], vec![100.0], vec![0]);
+        service.store_track(&tracks, &activities[2], &stream).await.unwrap();
+        tracks.delete(&activities[2]).unwrap();
+        // Activity 4 is marked as stored, but its file is corrupt
+        service.mark_fetched(&activities[3], TrackStoreState::Stored).await.unwrap();
+        fs::write(format!("{dir}/4.gpx"), "<gpx").unwrap();
+        // Orphan file and leftover of an interrupted write
+        fs::write(format!("{dir}/5.gpx"), "<gpx").unwrap();
+        fs::write(format!("{dir}/6.tmp"), "<gpx").unwrap();
+
+        let expected = VerifyReport {
+            temp_files: vec![Path::new(&dir).join("6.tmp")],
+            orphan_files: vec![Path::new(&dir).join("5.gpx")],
+            unmarked_files: vec![2],
+            missing_files: vec![3],
+            corrupt_files: vec![4],
+            unsynced_tiles: vec![MapZoom::Level14, MapZoom::Level17]
+        };
+        let report = verify_tracks(&mut service, &tracks, true).await;
+        assert!(report.is_ok());
+        assert_eq!(report.unwrap(), expected);
+
+        assert_eq!(service.get_fetch_state(2).await.unwrap(), Some(TrackStoreState::Stored));
+        assert_eq!(service.get_fetch_state(3).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.get_fetch_state(4).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.get_tile_stats(MapZoom::Level14).await.unwrap(), (1, 2));
+
+        let report = verify_tracks(&mut service, &tracks, false).await;
+        assert!(report.is_ok());
+        assert!(report.unwrap().is_consistent());
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_sensors() {
+        let base_path = create_base_path("verify_sensors");
+        let tracks = TrackStorage::new(&base_path);
+        let mut service = create_service(&[1, 2, 3, 4, 5]).await;
+        let activities: Vec<Activity> = (1..=5).map(|id| Activity::dummy(id, "2020-01-01T00:00:00Z")).collect();
+        let dir = format!("{base_path}/{}/2020/01", Activity::DUMMY_ATHLETE);
+        let sensors: SensorStream = serde_json::from_str(r#"{"time":{"data":[0,1]},"heartrate":{"data":[90,95]}}"#).unwrap();
+
+        // Activity 1 is consistent
+        service.store_sensors(&tracks, &activities[0], &sensors).await.unwrap();
+        // Activity 2 has a CSV file, but is not marked as stored
+        tracks.write_sensors(&activities[1], &sensors).unwrap();
+        // Activity 3 is marked as stored, but has no file
+        service.mark_fetched(&activities[2], TrackStoreState::Sensors).await.unwrap();
+        // Activity 4 is marked as stored, but its file is truncated
+        service.mark_fetched(&activities[3], TrackStoreState::Sensors).await.unwrap();
+        fs::write(format!("{dir}/4.csv"), "time,heartrate\n0,90\n1").unwrap();
+        // Activity 5 has a GPX file and an outdated CSV file
+        service.store_track(&tracks, &activities[4], &get_stream()).await.unwrap();
+        tracks.write_sensors(&activities[4], &sensors).unwrap();
+
+        let expected = VerifyReport {
+            orphan_files: vec![Path::new(&dir).join("5.csv")],
+            unmarked_files: vec![2],
+            missing_files: vec![3],
+            corrupt_files: vec![4],
+            ..VerifyReport::default()
+        };
+        let report = verify_tracks(&mut service, &tracks, true).await;
+        assert_eq!(report.unwrap(), expected);
+
+        assert_eq!(service.get_fetch_state(2).await.unwrap(), Some(TrackStoreState::Sensors));
+        assert_eq!(service.get_fetch_state(3).await.unwrap(), Some(TrackStoreState::Pending));
+        assert_eq!(service.get_fetch_state(4).await.unwrap(), Some(TrackStoreState::Pending));
+        assert!(!Path::new(&dir).join("4.csv").exists());
+
+        let report = verify_tracks(&mut service, &tracks, false).await;
+        assert!(report.unwrap().is_consistent());
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    fn get_stream() -> ActivityStream {
+        ActivityStream::new(vec![(1.0, 1.0)], vec![100.0], vec![0])
+    }
+
+    fn create_base_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("strava-{}-{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    async fn create_service(ids: &[u64]) -> ActivityService {
+        let mut service = ActivityService::new("sqlite::memory:", true).await.unwrap();
+        let activities = ids.iter().map(|id| Activity::dummy(*id, "2020-01-01T00:00:00Z")).collect();
+        service.add(&activities).await.unwrap();
+        service
+    }
+}
@@ -0,0 +1,20 @@
+use std::collections::VecDeque;
+use tokio::time::Instant;
+use crate::domain::activity_stats::ActivityStats;
+use crate::domain::download_progress::{CurrentActivity, DownloadError};
+use crate::domain::download_state::DownloadState;
+use crate::domain::profile_step::ProfileStep;
+
+/// Download state of a single athlete, part of the [crate::state::shared_state::SharedState]
+#[derive(Default)]
+pub struct AthleteState {
+    pub download_state: DownloadState,
+    pub saved_state: Option<DownloadState>,    // Download state last stored in the database
+    pub activity_stats: Option<ActivityStats>, // Holds last version of DB activity stats
+    pub profile_steps: VecDeque<ProfileStep>,  // Pending requests of the DownloadState::Profile phase
+    pub failures: u32,                         // Consecutive failed download tasks
+    pub retry_at: Option<Instant>,             // Download tasks are paused until then after a transient failure
+    pub request_budget: Option<u32>,           // Remaining requests of a scheduled download
+    pub current_activity: Option<CurrentActivity>, // Activity of the latest download job
+    pub last_error: Option<DownloadError>      // Latest failure of a download task
+}
@@ -1 +1,2 @@
-pub mod shared_state;
\ No newline at end of file
+pub mod athlete_state;
+pub mod shared_state;
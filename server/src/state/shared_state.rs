@@ -1,51 +1,289 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use axum::BoxError;
+use tokio::sync::broadcast;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::Mutex;
+use tokio::time::Instant;
+use crate::domain::activity::Activity;
 use crate::domain::activity_stats::ActivityStats;
-use crate::domain::download_state::DownloadState;
+use crate::domain::download_event::{DownloadEvent, EventKind};
+use crate::domain::download_filter::DownloadFilter;
+use crate::domain::download_progress::{CurrentActivity, DownloadError, DownloadProgress};
+use crate::domain::download_schedule::DownloadSchedule;
+use crate::domain::download_job::JobType;
+use crate::domain::download_state::{DownloadState, StartPhase};
+use crate::domain::missing_reason::MissingReason;
+use crate::domain::profile_step::ProfileStep;
+use crate::domain::readiness::{Readiness, ReadinessCheck};
+use crate::domain::scheduler_status::SchedulerStatus;
 use crate::domain::server_status::ServerStatus;
 use crate::oauth::oauth_client::OAuthClient;
+use crate::rest::access_control::AccessControl;
 use crate::service::activity_service::ActivityService;
+use crate::state::athlete_state::AthleteState;
+use crate::strava::strava_client::StravaClient;
 use crate::track::track_storage::TrackStorage;
+use crate::util::iso8601;
 
 /// State shared between axum handlers and downloader
 pub struct SharedState {
     pub oauth: OAuthClient,
+    pub strava: StravaClient, // Shared HTTP client for the Strava API
     pub service: ActivityService,
     pub tracks: TrackStorage,
     pub tx_data: Sender<ServerStatus>, // Broadcast sender used by the downloader to inform the SSE endpoint
     pub tx_term: Sender<()>,  // Broadcast sender used by the SSE handlers to inform about server termination
-    pub activity_stats: Option<ActivityStats>, // Holds last version of DB activity stats
-    pub download_state: DownloadState,
-    pub activities_per_page: u16
+    pub tx_events: Sender<DownloadEvent>, // Broadcast sender used by the downloader to pass the logged events to the SSE endpoint
+    pub athletes: BTreeMap<u64, AthleteState>, // Download state and cached stats per athlete
+    pub activities_per_page: u16,
+    pub download_photos: bool, // Enables the optional DownloadState::Photos phase
+    pub filter: DownloadFilter, // Restricts the downloaded activities, adjustable by REST
+    pub schedule: Option<DownloadSchedule>, // Starts downloads automatically
+    pub access: AccessControl, // Protects the endpoints, holds the sessions
+    pub scheduler_running: Arc<AtomicBool>, // Cleared when the download scheduler task ends, even by panic
+    pub scheduler_status: SchedulerStatus, // Restarts and latest error of the download scheduler
+    pub request_period: Duration, // Delay between two requests of the download scheduler
+    pub next_request_at: Option<i64>, // Next tick of the download scheduler (seconds since epoch)
+    pub legacy_athlete: Option<u64> // Owner of the data downloaded by single-athlete versions
 }
 
 pub type MutexSharedState = Arc<Mutex<SharedState>>;
 
 impl SharedState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(oauth: OAuthClient,
+               strava: StravaClient,
                service: ActivityService,
                tracks: TrackStorage,
                tx_data: Sender<ServerStatus>,
                tx_term: Sender<()>,
-               activities_per_page: u16) -> MutexSharedState {
+               activities_per_page: u16,
+               download_photos: bool) -> MutexSharedState {
         Arc::new(Mutex::new(Self {
             oauth,
+            strava,
             service,
             tracks,
             tx_data,
             tx_term,
-            activity_stats: None,
-            download_state: DownloadState::Inactive,
-            activities_per_page
+            tx_events: broadcast::channel(64).0,
+            athletes: BTreeMap::new(),
+            activities_per_page,
+            download_photos,
+            filter: DownloadFilter::default(),
+            schedule: None,
+            access: AccessControl::default(),
+            scheduler_running: Arc::new(AtomicBool::new(false)),
+            scheduler_status: SchedulerStatus::default(),
+            request_period: Duration::from_secs(10),
+            next_request_at: None,
+            legacy_athlete: None
         }))
     }
 
+    /// Returns the athlete addressed by the endpoints without athlete path,
+    /// which is the athlete with the lowest id among the authorized athletes.
+    pub fn default_athlete(&self) -> Option<u64> {
+        self.oauth.athletes().first().copied()
+    }
+
+    /// Returns the ids of all athletes with an active download state in ascending order
+    pub fn active_athletes(&self) -> Vec<u64> {
+        self.athletes.iter()
+            .filter(|(_, athlete)| athlete.download_state.is_active())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Returns the ids of all authorized athletes with an active download state whose download tasks are not
+    /// paused after a transient failure, in ascending order. Downloads restored after a restart wait for
+    /// the authorization, see [Self::restore_download_states].
+    pub fn due_athletes(&self, now: Instant) -> Vec<u64> {
+        let authorized = self.oauth.athletes();
+        self.athletes.iter()
+            .filter(|(id, _)| authorized.contains(id))
+            .filter(|(_, athlete)| athlete.download_state.is_active())
+            .filter(|(_, athlete)| athlete.retry_at.is_none_or(|retry_at| retry_at <= now))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn get_download_state(&self, athlete_id: u64) -> DownloadState {
+        self.athletes.get(&athlete_id)
+            .map(|athlete| athlete.download_state.clone())
+            .unwrap_or_default()
+    }
+
+    /// Entering [DownloadState::Profile] from another state (re)starts the profile download.
+    /// Any state change resets the failures. An inactive state ends a scheduled download.
+    pub fn set_download_state(&mut self, athlete_id: u64, download_state: DownloadState) {
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        if download_state == DownloadState::Profile && athlete.download_state != DownloadState::Profile {
+            athlete.profile_steps = ProfileStep::initial().into();
+        }
+        if download_state != athlete.download_state {
+            athlete.failures = 0;
+            athlete.retry_at = None;
+        }
+        if !download_state.is_active() {
+            athlete.request_budget = None;
+        }
+        athlete.download_state = download_state;
+    }
+
+    /// Stores the download state of the athlete if it changed since it was last stored
+    pub async fn save_download_state(&mut self, athlete_id: u64) -> Result<(), BoxError> {
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        if athlete.saved_state.as_ref() == Some(&athlete.download_state) {
+            return Ok(())
+        }
+        self.service.save_download_state(athlete_id, &athlete.download_state).await?;
+        athlete.saved_state = Some(athlete.download_state.clone());
+        Ok(())
+    }
+
+    /// Restores the stored download states, so that downloads resume where they stopped.
+    /// As the tokens are not stored, a download continues when the athlete authorizes again.
+    pub async fn restore_download_states(&mut self) -> Result<(), BoxError> {
+        for (athlete_id, download_state) in self.service.get_download_states().await? {
+            self.set_download_state(athlete_id, download_state.clone());
+            self.athletes.entry(athlete_id).or_default().saved_state = Some(download_state);
+        }
+        Ok(())
+    }
+
+    /// Starts downloading in the given phase, unless the athlete is downloading already. Starting with
+    /// the tracks enqueues the download jobs, which is otherwise done at the end of the activity list.
+    /// Returns the resulting download state.
+    pub async fn start_download(&mut self, athlete_id: u64, phase: StartPhase) -> Result<DownloadState, BoxError> {
+        let download_state = self.get_download_state(athlete_id);
+        if download_state.is_active() {
+            return Ok(download_state)
+        }
+        let download_state = phase.download_state();
+        if download_state == DownloadState::Tracks {
+            self.enqueue_jobs(athlete_id).await?;
+        }
+        self.set_download_state(athlete_id, download_state.clone());
+        self.save_download_state(athlete_id).await?;
+        Ok(download_state)
+    }
+
+    /// Enqueues the download jobs for all activities of the athlete that miss any data.
+    /// Photos are downloaded only if enabled. Returns the number of new jobs.
+    pub async fn enqueue_jobs(&mut self, athlete_id: u64) -> Result<u64, BoxError> {
+        let job_types: Vec<JobType> = JobType::VALUES.into_iter()
+            .filter(|job_type| *job_type != JobType::Photos || self.download_photos)
+            .collect();
+        self.service.enqueue_jobs(athlete_id, &job_types, &self.filter).await
+    }
+
+    /// Resets the track of the activity and enqueues its download at high priority, see
+    /// [ActivityService::refetch_track]. The activity stats are reloaded with the next status.
+    /// If the athlete is authorized, but not downloading, the download of the tracks is started.
+    pub async fn refetch_track(&mut self, activity: &Activity) -> Result<(), BoxError> {
+        self.service.refetch_track(&self.tracks, activity).await?;
+        self.athletes.entry(activity.athlete_id).or_default().activity_stats = None;
+        if self.oauth.has_usable_token(activity.athlete_id) {
+            self.start_download(activity.athlete_id, StartPhase::Tracks).await?;
+        }
+        Ok(())
+    }
+
+    /// Resets the missing tracks (of the athlete, or of all athletes) for the reason, if given, and
+    /// enqueues their download jobs, see [ActivityService::retry_missing_tracks]. Returns the number of reset tracks.
+    pub async fn retry_missing_tracks(&mut self, athlete_id: Option<u64>, reason: Option<MissingReason>) -> Result<u64, BoxError> {
+        let count = self.service.retry_missing_tracks(athlete_id, reason).await?;
+        let athlete_ids = match athlete_id {
+            Some(athlete_id) => vec![athlete_id],
+            None => self.service.get_athletes().await?
+        };
+        for athlete_id in athlete_ids {
+            self.enqueue_jobs(athlete_id).await?;
+        }
+        Ok(count)
+    }
+
+    /// Counts a failed download task of the athlete and returns the number of consecutive failures
+    pub fn add_failure(&mut self, athlete_id: u64) -> u32 {
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        athlete.failures += 1;
+        athlete.failures
+    }
+
+    /// Pauses the download tasks of the athlete until the given time
+    pub fn postpone(&mut self, athlete_id: u64, retry_at: Instant) {
+        self.athletes.entry(athlete_id).or_default().retry_at = Some(retry_at);
+    }
+
+    pub fn reset_failures(&mut self, athlete_id: u64) {
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        athlete.failures = 0;
+        athlete.retry_at = None;
+    }
+
+    /// If the scheduled download is due, starts the download of all authorized athletes
+    /// that are not downloading yet. Returns the started athletes.
+    pub fn start_scheduled_downloads(&mut self, now: i64) -> Vec<u64> {
+        let Some(schedule) = self.schedule.as_mut() else {
+            return Vec::new()
+        };
+        if !schedule.take_due_run(now) {
+            return Vec::new()
+        }
+        let max_requests = schedule.max_requests;
+        let athletes: Vec<u64> = self.oauth.athletes().into_iter()
+            .filter(|id| !self.get_download_state(*id).is_active())
+            .collect();
+        for athlete_id in &athletes {
+            self.set_download_state(*athlete_id, DownloadState::Profile);
+            self.athletes.entry(*athlete_id).or_default().request_budget = max_requests;
+        }
+        athletes
+    }
+
+    /// Counts a request of a scheduled download. Returns true if the request budget is exhausted.
+    pub fn count_request(&mut self, athlete_id: u64) -> bool {
+        match self.athletes.get_mut(&athlete_id).and_then(|athlete| athlete.request_budget.as_mut()) {
+            Some(budget) => {
+                *budget = budget.saturating_sub(1);
+                *budget == 0
+            }
+            None => false
+        }
+    }
+
+    fn next_scheduled_run(&self) -> Option<String> {
+        self.schedule.as_ref()
+            .and_then(|schedule| schedule.next_run)
+            .map(iso8601::secs_to_string)
+    }
+
+    /// Sends the server status of the athlete to the SSE handlers, if there are any
+    pub async fn send_server_status(&mut self, athlete_id: u64) -> Result<(), BoxError> {
+        if self.tx_data.receiver_count() > 0 {
+            let status = self.get_server_status(Some(athlete_id)).await?;
+            self.tx_data.send(status)?;
+        }
+        Ok(())
+    }
+
+    /// Removes and returns the next pending request of the profile download
+    pub fn next_profile_step(&mut self, athlete_id: u64) -> Option<ProfileStep> {
+        self.athletes.get_mut(&athlete_id).and_then(|athlete| athlete.profile_steps.pop_front())
+    }
+
+    pub fn add_profile_steps(&mut self, athlete_id: u64, steps: impl IntoIterator<Item = ProfileStep>) {
+        self.athletes.entry(athlete_id).or_default().profile_steps.extend(steps);
+    }
+
     /// Merge only if the activity stats are already loaded. There is no need of merging
     /// otherwise, as the stats will be loaded later, and will then contain other_stats.
-    pub fn merge_activity_stats(&mut self, other_stats: &ActivityStats) {
-        if let Some(stats) = self.activity_stats.as_mut() {
+    pub fn merge_activity_stats(&mut self, athlete_id: u64, other_stats: &ActivityStats) {
+        if let Some(stats) = self.athletes.get_mut(&athlete_id).and_then(|a| a.activity_stats.as_mut()) {
             stats.merge(other_stats);
         }
     }
@@ -53,30 +291,110 @@ impl SharedState {
     /// Try to take the time from the state object.
     /// If it is not part of the state (on server startup), then get it from database.
     /// If no activity records exist in the database, then return 0.
-    pub async fn get_activity_max_time(&mut self) -> Result<i64, BoxError> {
-        let activity_stats = self.get_activity_stats().await?;
+    pub async fn get_activity_max_time(&mut self, athlete_id: u64) -> Result<i64, BoxError> {
+        let activity_stats = self.get_activity_stats(athlete_id).await?;
         match activity_stats.act_max_time_as_secs() {
             Some(secs) => Ok(secs),
             None => Ok(0)
         }
     }
 
-    /// Returns a [ServerStatus] object. Takes the included [ActivityStats]
-    /// from the [SharedState] or fetches them from database.
-    pub async fn get_server_status(&mut self) -> Result<ServerStatus, BoxError> {
-        let authorized = self.oauth.get_bearer().await?.is_some();
-        let download_state = self.download_state.clone();
-        let activity_stats = self.get_activity_stats().await?;
-        Ok(ServerStatus::new(authorized, download_state, activity_stats))
+    /// Returns a [ServerStatus] object for the athlete. Takes the included [ActivityStats]
+    /// from the [SharedState] or fetches them from database. If no athlete is given,
+    /// the returned status is unauthorized and has empty stats.
+    pub async fn get_server_status(&mut self, athlete_id: Option<u64>) -> Result<ServerStatus, BoxError> {
+        match athlete_id {
+            Some(athlete_id) => {
+                let authorized = self.oauth.get_bearer(athlete_id).await?.is_some();
+                let download_state = self.get_download_state(athlete_id);
+                let activity_stats = self.get_activity_stats(athlete_id).await?;
+                let progress = self.get_progress(athlete_id, &download_state).await?;
+                Ok(ServerStatus::new(Some(athlete_id), authorized, download_state, activity_stats, progress,
+                                     self.next_scheduled_run(), self.scheduler_status.clone()))
+            }
+            None => {
+                let activity_stats = ActivityStats::new(0, None, None, 0, None);
+                Ok(ServerStatus::new(None, false, DownloadState::Inactive, activity_stats, DownloadProgress::default(),
+                                     self.next_scheduled_run(), self.scheduler_status.clone()))
+            }
+        }
+    }
+
+    /// Returns the [DownloadProgress] of the athlete. The ETA assumes one request per remaining track.
+    /// The next request is delayed if the athlete waits for the retry of a failed task.
+    async fn get_progress(&mut self, athlete_id: u64, download_state: &DownloadState) -> Result<DownloadProgress, BoxError> {
+        let (remaining_tracks, missing_tracks) = self.service.get_track_counts(athlete_id, &self.filter).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        let (eta, next_request) = match download_state.is_active() {
+            true => {
+                let eta = (remaining_tracks > 0).then(|| now + (remaining_tracks * self.request_period.as_secs()) as i64);
+                let retry_at = athlete.retry_at.map(|retry_at| now + retry_at.saturating_duration_since(Instant::now()).as_secs() as i64);
+                (eta, self.next_request_at.max(retry_at))
+            }
+            false => (None, None)
+        };
+        Ok(DownloadProgress {
+            current_activity: athlete.current_activity.clone(),
+            remaining_tracks,
+            missing_tracks,
+            eta: eta.map(iso8601::secs_to_string),
+            last_error: athlete.last_error.clone(),
+            next_request: next_request.map(iso8601::secs_to_string),
+            rate_limit: self.strava.rate_limit()
+        })
+    }
+
+    pub fn set_current_activity(&mut self, athlete_id: u64, activity: Option<CurrentActivity>) {
+        self.athletes.entry(athlete_id).or_default().current_activity = activity;
+    }
+
+    pub fn set_last_error(&mut self, athlete_id: u64, message: String, now: i64) {
+        let time = iso8601::secs_to_string(now);
+        self.athletes.entry(athlete_id).or_default().last_error = Some(DownloadError { message, time });
+    }
+
+    /// Appends an event to the log of the download scheduler and passes it to the SSE endpoint
+    pub async fn add_event(&mut self, athlete_id: u64, kind: EventKind, activity_id: Option<u64>, details: String, now: i64)
+        -> Result<(), BoxError> {
+        let time = iso8601::secs_to_string(now);
+        let event = self.service.add_event(DownloadEvent { id: 0, athlete_id, time, kind, activity_id, details }).await?;
+        let _ = self.tx_events.send(event); // Fails if no SSE client is connected
+        Ok(())
+    }
+
+    /// Checks whether the server is able to download: the database is reachable, the data directory
+    /// is writable, the download scheduler is running, and the tokens of all authorized athletes are
+    /// valid (or can be refreshed). No authorized athlete is required, because authorizing needs a ready server.
+    pub async fn check_readiness(&mut self) -> Readiness {
+        let database = self.service.ping().await.map(|_| None);
+        let data_dir = self.tracks.check_writable().map(|_| None);
+        let scheduler = match self.scheduler_running.load(Ordering::Relaxed) {
+            true => Ok(None),
+            false => Err("Download scheduler not running".into())
+        };
+        // Only inspects the tokens, as refreshing them would have side effects
+        let athletes = self.oauth.athletes();
+        let tokens = match athletes.iter().find(|athlete_id| !self.oauth.has_usable_token(**athlete_id)) {
+            Some(athlete_id) => Err(format!("Token of athlete {athlete_id} expired").into()),
+            None => Ok(Some(format!("{} athletes authorized", athletes.len())))
+        };
+        Readiness::new(vec![
+            ReadinessCheck::new("database", database),
+            ReadinessCheck::new("data_dir", data_dir),
+            ReadinessCheck::new("scheduler", scheduler),
+            ReadinessCheck::new("tokens", tokens)
+        ])
     }
 
     /// Returns the [ActivityStats], either from the cached value or else from the wrapped service.
-    async fn get_activity_stats(&mut self) -> Result<ActivityStats, BoxError> {
-        match self.activity_stats.as_ref() {
+    async fn get_activity_stats(&mut self, athlete_id: u64) -> Result<ActivityStats, BoxError> {
+        let athlete = self.athletes.entry(athlete_id).or_default();
+        match athlete.activity_stats.as_ref() {
             Some(stats) => Ok(stats.clone()),
             None => {
-                let activity_stats = self.service.get_stats().await?;
-                self.activity_stats = Some(activity_stats.clone());
+                let activity_stats = self.service.get_stats(athlete_id).await?;
+                self.athletes.entry(athlete_id).or_default().activity_stats = Some(activity_stats.clone());
                 Ok(activity_stats)
             }
         }
@@ -85,13 +403,21 @@ impl SharedState {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
     use tokio::sync::broadcast;
+    use tokio::time::Instant;
     use crate::domain::activity::Activity;
     use crate::domain::activity_stats::ActivityStats;
+    use crate::domain::download_progress::CurrentActivity;
+    use crate::domain::download_schedule::{CronSchedule, DownloadSchedule};
+    use crate::domain::download_state::DownloadState;
+    use crate::domain::profile_step::ProfileStep;
     use crate::domain::server_status::ServerStatus;
     use crate::oauth::oauth_client::OAuthClient;
     use crate::service::activity_service::ActivityService;
     use crate::state::shared_state::{MutexSharedState, SharedState};
+    use crate::strava::strava_client::StravaClient;
     use crate::track::track_storage::TrackStorage;
 
     impl SharedState {
@@ -100,10 +426,27 @@ mod tests {
             let tracks = TrackStorage::new("");
             let (tx_data, _) = broadcast::channel::<ServerStatus>(1);
             let (tx_term, _) = broadcast::channel(1);
-            SharedState::new(client, service, tracks, tx_data, tx_term, 0)
+            SharedState::new(client, StravaClient::default(), service, tracks, tx_data, tx_term, 0, false)
         }
     }
 
+    #[tokio::test]
+    async fn test_check_readiness() {
+        let base_path = std::env::temp_dir().join(format!("strava-readiness-{}", std::process::id()));
+        let service = ActivityService::new(":memory:", false).await.unwrap();
+        let state = SharedState::dummy(service);
+        let mut guard = state.lock().await;
+        guard.tracks = TrackStorage::new(base_path.to_str().unwrap());
+
+        let json = serde_json::to_string(&guard.check_readiness().await).unwrap();
+        assert_eq!(json, r#"{"ready":false,"checks":[{"name":"database","ok":true},{"name":"data_dir","ok":true},{"name":"scheduler","ok":false,"detail":"Download scheduler not running"},{"name":"tokens","ok":true,"detail":"0 athletes authorized"}]}"#);
+
+        guard.scheduler_running.store(true, Ordering::Relaxed);
+        assert!(guard.check_readiness().await.is_ready());
+        assert_eq!(std::fs::read_dir(&base_path).unwrap().count(), 0); // Probe file removed
+        std::fs::remove_dir_all(base_path).unwrap();
+    }
+
     #[tokio::test]
     async fn test_activity_max_time() {
         let activities = vec![Activity::dummy(5, "2018-02-20T18:02:13Z")];
@@ -113,7 +456,7 @@ mod tests {
         let state = SharedState::dummy(service);
 
         let mut guard = state.lock().await;
-        let max_time = guard.get_activity_max_time().await;
+        let max_time = guard.get_activity_max_time(Activity::DUMMY_ATHLETE).await;
         assert!(max_time.is_ok());
         assert_eq!(max_time.unwrap(), 1519149733);
     }
@@ -128,17 +471,17 @@ mod tests {
 
         let mut guard = state.lock().await;
         let new_stats = ActivityStats::new(1, None, Some("2018-02-20T18:02:13Z".to_string()), 0,  None);
-        assert!(guard.get_activity_stats().await.is_ok()); // Force loading stats from service
-        guard.merge_activity_stats(&new_stats);
+        assert!(guard.get_activity_stats(Activity::DUMMY_ATHLETE).await.is_ok()); // Force loading stats from service
+        guard.merge_activity_stats(Activity::DUMMY_ATHLETE, &new_stats);
 
-        let max_time = guard.get_activity_max_time().await;
+        let max_time = guard.get_activity_max_time(Activity::DUMMY_ATHLETE).await;
         assert!(max_time.is_ok());
         assert_eq!(max_time.unwrap(), 1519149733);
     }
 
     #[tokio::test]
     async fn test_server_status() {
-        let expected = r#"{"authorized":false,"download_state":"Inactive","activity_stats":{"act_count":2,"act_min_time":"2018-02-20T18:02:13Z","act_max_time":"2020-08-21T00:00:00Z","trk_count":0,"trk_max_time":null}}"#;
+        let expected = r#"{"athlete_id":1,"authorized":false,"download_state":"Inactive","activity_stats":{"act_count":2,"act_min_time":"2018-02-20T18:02:13Z","act_max_time":"2020-08-21T00:00:00Z","trk_count":0,"trk_max_time":null},"progress":{"current_activity":null,"remaining_tracks":2,"missing_tracks":0,"eta":null,"last_error":null,"next_request":null,"rate_limit":null},"next_scheduled_run":null,"scheduler_restarts":0,"scheduler_error":null}"#;
 
         let activities = vec![
             Activity::dummy(5, "2018-02-20T18:02:13Z"),
@@ -150,10 +493,155 @@ mod tests {
         let state = SharedState::dummy(service);
 
         let mut guard = state.lock().await;
-        let status = guard.get_server_status().await;
+        let status = guard.get_server_status(Some(Activity::DUMMY_ATHLETE)).await;
+        assert!(status.is_ok());
+        let result = serde_json::to_string::<ServerStatus>(&status.unwrap());
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_server_status_without_athlete() {
+        let expected = r#"{"athlete_id":null,"authorized":false,"download_state":"Inactive","activity_stats":{"act_count":0,"act_min_time":null,"act_max_time":null,"trk_count":0,"trk_max_time":null},"progress":{"current_activity":null,"remaining_tracks":0,"missing_tracks":0,"eta":null,"last_error":null,"next_request":null,"rate_limit":null},"next_scheduled_run":null,"scheduler_restarts":0,"scheduler_error":null}"#;
+
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        assert_eq!(guard.default_athlete(), None);
+        let status = guard.get_server_status(None).await;
         assert!(status.is_ok());
         let result = serde_json::to_string::<ServerStatus>(&status.unwrap());
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), expected);
     }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let mut service = ActivityService::new(":memory:", true).await.unwrap();
+        service.add(&vec![Activity::dummy(5, "2018-02-20T18:02:13Z")]).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        guard.set_current_activity(Activity::DUMMY_ATHLETE, Some(CurrentActivity {
+            id: 5, name: "Morning Run".to_string(), start_date: "2018-02-20T18:02:13Z".to_string(), job: "Track".to_string()
+        }));
+        guard.set_last_error(Activity::DUMMY_ATHLETE, "Timeout".to_string(), 3600);
+        guard.next_request_at = Some(7200);
+        let status = guard.get_server_status(Some(Activity::DUMMY_ATHLETE)).await.unwrap();
+        let progress = &serde_json::to_value(status).unwrap()["progress"];
+        assert_eq!(progress["current_activity"]["name"], "Morning Run");
+        assert_eq!(progress["remaining_tracks"], 1);
+        assert_eq!(progress["last_error"]["time"], "1970-01-01T01:00:00Z");
+        assert!(progress["eta"].is_null()); // Not downloading
+        assert!(progress["next_request"].is_null());
+
+        guard.set_download_state(Activity::DUMMY_ATHLETE, DownloadState::Tracks);
+        let status = guard.get_server_status(Some(Activity::DUMMY_ATHLETE)).await.unwrap();
+        let progress = &serde_json::to_value(status).unwrap()["progress"];
+        assert!(progress["eta"].is_string());
+        assert_eq!(progress["next_request"], "1970-01-01T02:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_active_athletes() {
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        guard.set_download_state(7, DownloadState::Tracks);
+        guard.set_download_state(3, DownloadState::Activities);
+        guard.set_download_state(5, DownloadState::LimitReached);
+        assert_eq!(guard.active_athletes(), vec![3, 7]);
+        assert_eq!(guard.get_download_state(5), DownloadState::LimitReached);
+        assert_eq!(guard.get_download_state(9), DownloadState::Inactive);
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        let now = Instant::now();
+        guard.oauth.add_dummy_athlete(3);
+        guard.oauth.add_dummy_athlete(7);
+        guard.set_download_state(3, DownloadState::Activities);
+        guard.set_download_state(7, DownloadState::Tracks);
+        assert_eq!(guard.add_failure(7), 1);
+        assert_eq!(guard.add_failure(7), 2);
+        guard.postpone(7, now + Duration::from_secs(10));
+        assert_eq!(guard.due_athletes(now), vec![3]);
+        assert_eq!(guard.due_athletes(now + Duration::from_secs(10)), vec![3, 7]);
+        assert_eq!(guard.active_athletes(), vec![3, 7]);
+
+        guard.set_download_state(7, DownloadState::Tracks); // Same state keeps the failures
+        assert_eq!(guard.add_failure(7), 3);
+        guard.set_download_state(7, DownloadState::Laps);
+        assert_eq!(guard.due_athletes(now), vec![3, 7]);
+        assert_eq!(guard.add_failure(7), 1);
+        guard.reset_failures(7);
+        assert_eq!(guard.add_failure(7), 1);
+    }
+
+    #[tokio::test]
+    async fn test_restore_download_states() {
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        guard.set_download_state(7, DownloadState::Laps);
+        guard.save_download_state(7).await.unwrap();
+        guard.set_download_state(3, DownloadState::Activities); // Not saved
+        guard.athletes.clear(); // As after a restart
+
+        guard.restore_download_states().await.unwrap();
+        assert_eq!(guard.get_download_state(7), DownloadState::Laps);
+        assert_eq!(guard.get_download_state(3), DownloadState::Inactive);
+        assert!(guard.due_athletes(Instant::now()).is_empty()); // Waits for the authorization
+        guard.oauth.add_dummy_athlete(7);
+        assert_eq!(guard.due_athletes(Instant::now()), vec![7]);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_downloads() {
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        guard.oauth.add_dummy_athlete(3);
+        guard.oauth.add_dummy_athlete(7);
+        guard.set_download_state(7, DownloadState::Tracks);
+        assert!(guard.start_scheduled_downloads(0).is_empty()); // No schedule
+        guard.schedule = Some(DownloadSchedule::new(CronSchedule::try_from("0 3 * * *").unwrap(), Some(2), 0));
+        let status = guard.get_server_status(None).await.unwrap();
+        assert_eq!(serde_json::to_value(status).unwrap()["next_scheduled_run"], "1970-01-01T03:00:00Z");
+        assert!(guard.start_scheduled_downloads(3 * 3600 - 1).is_empty());
+
+        assert_eq!(guard.start_scheduled_downloads(3 * 3600), vec![3]); // Athlete 7 is downloading already
+        assert_eq!(guard.get_download_state(3), DownloadState::Profile);
+        assert!(!guard.count_request(7)); // Unlimited
+        assert!(!guard.count_request(3));
+        assert!(guard.count_request(3));
+        guard.set_download_state(3, DownloadState::Inactive);
+        assert!(!guard.count_request(3));
+        assert_eq!(guard.schedule.as_ref().unwrap().next_run, Some(27 * 3600));
+    }
+
+    #[tokio::test]
+    async fn test_profile_steps() {
+        let service = ActivityService::new(":memory:", true).await.unwrap();
+        let state = SharedState::dummy(service);
+
+        let mut guard = state.lock().await;
+        assert_eq!(guard.next_profile_step(7), None);
+        guard.set_download_state(7, DownloadState::Profile);
+        assert_eq!(guard.next_profile_step(7), Some(ProfileStep::Athlete));
+        guard.add_profile_steps(7, vec![ProfileStep::Gear("b1".to_string())]);
+        guard.set_download_state(7, DownloadState::Profile); // Does not restart the ongoing profile download
+        assert_eq!(guard.next_profile_step(7), Some(ProfileStep::Zones));
+        assert_eq!(guard.next_profile_step(7), Some(ProfileStep::Stats));
+        assert_eq!(guard.next_profile_step(7), Some(ProfileStep::Gear("b1".to_string())));
+        assert_eq!(guard.next_profile_step(7), None);
+    }
 }
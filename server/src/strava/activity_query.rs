@@ -0,0 +1,15 @@
+use serde::Serialize;
+
+/// Query parameters of the activity list of the authorized athlete, see
+/// https://developers.strava.com/docs/reference/#api-Activities-getLoggedInAthleteActivities
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ActivityQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<i64>, // Seconds since epoch, exclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<i64>, // Seconds since epoch, exclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u16>
+}
@@ -0,0 +1,4 @@
+pub mod activity_query;
+pub mod rate_limit;
+pub mod strava_client;
+pub mod strava_error;
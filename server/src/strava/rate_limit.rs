@@ -0,0 +1,88 @@
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+
+const SECS_PER_QUARTER: i64 = 15 * 60;
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Limits and usage of the Strava API for the current 15 minutes and the current day (UTC),
+/// taken from the response headers, see https://developers.strava.com/docs/rate-limits/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RateLimit {
+    pub limit_15min: u32,
+    pub limit_daily: u32,
+    pub usage_15min: u32,
+    pub usage_daily: u32
+}
+
+impl RateLimit {
+    /// Parses the headers `X-ReadRateLimit-Limit` and `X-ReadRateLimit-Usage`, which apply to the
+    /// read requests of this application, or `X-RateLimit-Limit` and `X-RateLimit-Usage` as fallback.
+    /// Each header contains the 15-minute value and the daily value separated by a comma.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let (limit_15min, limit_daily) = Self::header_pair(headers, "x-readratelimit-limit")
+            .or_else(|| Self::header_pair(headers, "x-ratelimit-limit"))?;
+        let (usage_15min, usage_daily) = Self::header_pair(headers, "x-readratelimit-usage")
+            .or_else(|| Self::header_pair(headers, "x-ratelimit-usage"))?;
+        Some(Self { limit_15min, limit_daily, usage_15min, usage_daily })
+    }
+
+    fn header_pair(headers: &HeaderMap, name: &str) -> Option<(u32, u32)> {
+        let (first, second) = headers.get(name)?.to_str().ok()?.split_once(',')?;
+        Some((first.trim().parse().ok()?, second.trim().parse().ok()?))
+    }
+
+    pub fn is_daily_exhausted(&self) -> bool {
+        self.usage_daily >= self.limit_daily
+    }
+
+    /// Returns the time (in seconds since epoch) when the requests are allowed again: Strava resets
+    /// the 15-minute limit at the next quarter hour and the daily limit at midnight UTC.
+    pub fn reset_after(&self, now: i64) -> i64 {
+        match self.is_daily_exhausted() {
+            true => (now.div_euclid(SECS_PER_DAY) + 1) * SECS_PER_DAY,
+            false => Self::next_quarter(now)
+        }
+    }
+
+    pub fn next_quarter(now: i64) -> i64 {
+        (now.div_euclid(SECS_PER_QUARTER) + 1) * SECS_PER_QUARTER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use crate::strava::rate_limit::RateLimit;
+    use crate::util::iso8601::{secs_to_string, string_to_secs};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let expected = RateLimit { limit_15min: 100, limit_daily: 1000, usage_15min: 17, usage_daily: 412 };
+        let general = headers(&[("x-ratelimit-limit", "100,1000"), ("x-ratelimit-usage", "17,412")]);
+        assert_eq!(RateLimit::from_headers(&general), Some(expected));
+
+        let read = headers(&[("x-ratelimit-limit", "200,2000"), ("x-ratelimit-usage", "1,1"),
+            ("x-readratelimit-limit", "100,1000"), ("x-readratelimit-usage", "17,412")]);
+        assert_eq!(RateLimit::from_headers(&read), Some(expected));
+
+        assert_eq!(RateLimit::from_headers(&headers(&[("x-ratelimit-limit", "100")])), None);
+        assert_eq!(RateLimit::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_reset_after() {
+        let now = string_to_secs("2024-03-01T10:41:12Z");
+        let limit = RateLimit { limit_15min: 100, limit_daily: 1000, usage_15min: 100, usage_daily: 412 };
+        assert_eq!(secs_to_string(limit.reset_after(now)), "2024-03-01T10:45:00Z");
+        let limit = RateLimit { usage_daily: 1000, ..limit };
+        assert_eq!(secs_to_string(limit.reset_after(now)), "2024-03-02T00:00:00Z");
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use axum::BoxError;
+use log::debug;
+use oauth2::{AsyncHttpClient, HttpClientError, HttpRequest, HttpResponse};
+use reqwest::{Certificate, ClientBuilder, Proxy, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::domain::activity::ActivityVec;
+use crate::domain::athlete::Athlete;
+use crate::domain::athlete_stats::AthleteStats;
+use crate::domain::athlete_zones::AthleteZones;
+use crate::domain::effort::ActivityEfforts;
+use crate::domain::gear::Gear;
+use crate::domain::lap::LapVec;
+use crate::domain::photo::{PhotoVec, PHOTO_SIZE};
+use crate::domain::sensor_stream::StreamSet;
+use crate::domain::social::{Comment, PersonName};
+use crate::strava::activity_query::ActivityQuery;
+use crate::strava::rate_limit::RateLimit;
+use crate::strava::strava_error::StravaError;
+use crate::util::metrics::metrics;
+
+pub const DEFAULT_API_URL: &str = "https://www.strava.com/api/v3";
+const MAX_PAGE_SIZE: u16 = 200;
+const STREAM_KEYS: &str = "time,latlng,altitude,distance,heartrate,watts,cadence";
+
+pub type StravaResult<T> = Result<T, StravaError>;
+
+/// Settings of the HTTP client, see section `strava` of `conf/application.yaml`
+#[derive(Clone, Debug, PartialEq)]
+pub struct StravaClientConfig {
+    pub base_url: String,
+    pub connect_timeout: Duration,
+    pub timeout: Duration, // For an entire request including the response body
+    pub proxy: Option<String>, // Proxy URL for all requests, like http://proxy.example.org:3128
+    pub user_agent: String,
+    pub ca_certificate: Option<String>, // Path of an additional trusted root certificate (PEM)
+    pub accept_invalid_certs: bool // Only for tests against servers with self-signed certificates
+}
+
+impl Default for StravaClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_API_URL.to_string(),
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            proxy: None,
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string(),
+            ca_certificate: None,
+            accept_invalid_certs: false
+        }
+    }
+}
+
+/// HTTP client for all requests to Strava, shared by the download scheduler and the OAuth client.
+/// Cloning is cheap and keeps the connection pool, so the clones reuse the connections.
+/// The methods return typed responses or a [StravaError] the callers can react on.
+#[derive(Clone, Debug)]
+pub struct StravaClient {
+    base_url: String,
+    client: reqwest::Client,
+    oauth_client: reqwest::Client, // Does not follow redirects, as recommended for OAuth token requests
+    rate_limit: Arc<Mutex<Option<RateLimit>>> // As of the latest response, shared by all clones
+}
+
+impl StravaClient {
+    pub fn new(config: &StravaClientConfig) -> Result<Self, BoxError> {
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            client: Self::builder(config)?.build()?,
+            oauth_client: Self::builder(config)?.redirect(reqwest::redirect::Policy::none()).build()?,
+            rate_limit: Arc::new(Mutex::new(None))
+        })
+    }
+
+    fn builder(config: &StravaClientConfig) -> Result<ClientBuilder, BoxError> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.timeout)
+            .user_agent(config.user_agent.as_str())
+            .tls_danger_accept_invalid_certs(config.accept_invalid_certs);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        if let Some(path) = &config.ca_certificate {
+            builder = builder.tls_certs_merge([Certificate::from_pem(&fs::read(path)?)?]);
+        }
+        Ok(builder)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Returns the rate limit and its usage as of the latest response of the Strava API
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.lock().ok().and_then(|rate_limit| *rate_limit)
+    }
+
+    async fn get<T: DeserializeOwned, Q: Serialize + ?Sized>(&self, path: &str, bearer: &str, query: &Q)
+        -> StravaResult<T> {
+        debug!("Request {path}");
+        let response = self.client.get(format!("{}{path}", self.base_url))
+            .header(reqwest::header::AUTHORIZATION, bearer)
+            .query(query)
+            .send().await
+            .inspect_err(|_| metrics().count_strava_request(path, "error"))?;
+        metrics().count_strava_request(path, response.status().as_str());
+        if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+            metrics().set_rate_limit(rate_limit);
+            if let Ok(mut latest) = self.rate_limit.lock() {
+                *latest = Some(rate_limit);
+            }
+        }
+        let body = Self::body(response).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Returns the body of a successful response or the error matching the response status
+    async fn body(response: Response) -> StravaResult<Vec<u8>> {
+        let status = response.status();
+        if !status.is_success() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+            let reset = RateLimit::from_headers(response.headers())
+                .map_or(RateLimit::next_quarter(now), |limit| limit.reset_after(now));
+            return Err(StravaError::from_status(status, reset))
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    pub async fn get_athlete(&self, bearer: &str) -> StravaResult<Athlete> {
+        self.get("/athlete", bearer, &()).await
+    }
+
+    pub async fn get_athlete_zones(&self, bearer: &str) -> StravaResult<AthleteZones> {
+        self.get("/athlete/zones", bearer, &()).await
+    }
+
+    pub async fn get_athlete_stats(&self, bearer: &str, athlete_id: u64) -> StravaResult<AthleteStats> {
+        self.get(&format!("/athletes/{athlete_id}/stats"), bearer, &()).await
+    }
+
+    pub async fn get_gear(&self, bearer: &str, gear_id: &str) -> StravaResult<Gear> {
+        self.get(&format!("/gear/{gear_id}"), bearer, &()).await
+    }
+
+    /// Returns a page of the activities of the authorized athlete
+    pub async fn get_activities(&self, bearer: &str, query: &ActivityQuery) -> StravaResult<ActivityVec> {
+        self.get("/athlete/activities", bearer, query).await
+    }
+
+    /// Returns the track of the activity, or its sensor data if the activity has no coordinates
+    pub async fn get_activity_stream(&self, bearer: &str, activity_id: u64) -> StravaResult<StreamSet> {
+        let query = [("keys", STREAM_KEYS), ("key_by_type", "true")];
+        self.get(&format!("/activities/{activity_id}/streams"), bearer, &query).await
+    }
+
+    pub async fn get_laps(&self, bearer: &str, activity_id: u64) -> StravaResult<LapVec> {
+        self.get(&format!("/activities/{activity_id}/laps"), bearer, &()).await
+    }
+
+    /// Returns the segment efforts and best efforts of the detailed activity
+    pub async fn get_efforts(&self, bearer: &str, activity_id: u64) -> StravaResult<ActivityEfforts> {
+        let query = [("include_all_efforts", "true")];
+        self.get(&format!("/activities/{activity_id}"), bearer, &query).await
+    }
+
+    pub async fn get_photos(&self, bearer: &str, activity_id: u64) -> StravaResult<PhotoVec> {
+        let query = [("size", PHOTO_SIZE)];
+        self.get(&format!("/activities/{activity_id}/photos"), bearer, &query).await
+    }
+
+    pub async fn get_comments(&self, bearer: &str, activity_id: u64) -> StravaResult<Vec<Comment>> {
+        let query = [("per_page", MAX_PAGE_SIZE)];
+        self.get(&format!("/activities/{activity_id}/comments"), bearer, &query).await
+    }
+
+    pub async fn get_kudoers(&self, bearer: &str, activity_id: u64) -> StravaResult<Vec<PersonName>> {
+        let query = [("per_page", MAX_PAGE_SIZE)];
+        self.get(&format!("/activities/{activity_id}/kudos"), bearer, &query).await
+    }
+
+    /// Downloads an image from the Strava CDN, which does not require authorization
+    pub async fn get_image(&self, url: &str) -> StravaResult<Vec<u8>> {
+        Self::body(self.client.get(url).send().await?).await
+    }
+
+    /// Revokes the access of this application to the data of the athlete, see
+    /// https://developers.strava.com/docs/authentication/#deauthorization
+    pub async fn deauthorize(&self, url: &str, access_token: &str) -> StravaResult<()> {
+        let response = self.oauth_client.post(url).form(&[("access_token", access_token)]).send().await?;
+        Self::body(response).await.map(|_| ())
+    }
+}
+
+impl Default for StravaClient {
+    fn default() -> Self {
+        Self::new(&StravaClientConfig::default()).expect("Cannot build the default HTTP client")
+    }
+}
+
+/// Makes the client usable for the token requests of the [oauth2] crate, following its reqwest integration
+impl<'c> AsyncHttpClient<'c> for StravaClient {
+    type Error = HttpClientError<reqwest::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, Self::Error>> + Send + Sync + 'c>>;
+
+    fn call(&'c self, request: HttpRequest) -> Self::Future {
+        Box::pin(async move {
+            let response = self.oauth_client
+                .execute(request.try_into().map_err(Box::new)?)
+                .await
+                .map_err(Box::new)?;
+
+            let mut builder = oauth2::http::Response::builder()
+                .status(response.status())
+                .version(response.version());
+            for (name, value) in response.headers().iter() {
+                builder = builder.header(name, value);
+            }
+            builder
+                .body(response.bytes().await.map_err(Box::new)?.to_vec())
+                .map_err(HttpClientError::Http)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{header, method, path, query_param};
+    use crate::strava::strava_client::{StravaClient, StravaClientConfig, STREAM_KEYS};
+    use crate::strava::strava_error::StravaError;
+
+    fn client(server: &MockServer) -> StravaClient {
+        let config = StravaClientConfig {
+            base_url: format!("{}/", server.uri()),
+            user_agent: "test-agent".to_string(),
+            timeout: Duration::from_secs(1),
+            ..StravaClientConfig::default()
+        };
+        StravaClient::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_laps() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/activities/42/laps"))
+            .and(header("authorization", "Bearer token"))
+            .and(header("user-agent", "test-agent"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+            .mount(&server)
+            .await;
+
+        let laps = client(&server).get_laps("Bearer token", 42).await.unwrap();
+        assert!(laps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_comments_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/activities/42/comments"))
+            .and(query_param("per_page", "200"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).get_comments("Bearer token", 42).await.unwrap_err();
+        assert!(matches!(error, StravaError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]").set_delay(Duration::from_secs(3)))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).get_laps("Bearer token", 42).await.unwrap_err();
+        assert!(matches!(error, StravaError::Network(ref error) if error.is_timeout()));
+        assert!(error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429)
+                .insert_header("x-ratelimit-limit", "100,1000")
+                .insert_header("x-ratelimit-usage", "101,1000"))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).get_laps("Bearer token", 42).await.unwrap_err();
+        let StravaError::RateLimited { reset } = error else {
+            panic!("Unexpected error {error:?}")
+        };
+        assert_eq!(reset % (24 * 60 * 60), 0); // Daily limit exhausted, reset at midnight
+    }
+
+    #[tokio::test]
+    async fn test_decode_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/activities/42/streams"))
+            .and(query_param("keys", STREAM_KEYS))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"latlng":{"data":[[50.0,8.0]]}}"#))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).get_activity_stream("Bearer token", 42).await.unwrap_err();
+        assert!(matches!(error, StravaError::Decode(_)));
+    }
+
+    #[test]
+    fn test_invalid_proxy() {
+        let config = StravaClientConfig { proxy: Some("not a proxy".to_string()), ..StravaClientConfig::default() };
+        assert!(StravaClient::new(&config).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+use crate::util::iso8601;
+
+/// Errors of the requests to the Strava API, classified by the reaction they require
+#[derive(Error, Debug)]
+pub enum StravaError {
+    #[error("Not authorized by Strava (status {0})")]
+    Unauthorized(StatusCode), // 401 or 403, the token is invalid or lacks a scope
+    #[error("Strava API rate limit exceeded until {}", iso8601::secs_to_string(*reset))]
+    RateLimited { reset: i64 }, // Seconds since epoch when the limit resets
+    #[error("Resource not found by Strava")]
+    NotFound,
+    #[error("Strava server error (status {0})")]
+    Server(StatusCode), // 5xx or 408, worth a retry
+    #[error("Request rejected by Strava (status {0})")]
+    Rejected(StatusCode), // Other 4xx status, pointless to retry
+    #[error("Failed to decode the Strava response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error) // Connection errors and timeouts
+}
+
+impl StravaError {
+    /// Returns the error for a response status other than success. A rate-limit error carries
+    /// the reset time derived from the rate-limit headers (or the next quarter hour).
+    pub fn from_status(status: StatusCode, reset: i64) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => StravaError::Unauthorized(status),
+            StatusCode::TOO_MANY_REQUESTS => StravaError::RateLimited { reset },
+            StatusCode::NOT_FOUND => StravaError::NotFound,
+            StatusCode::REQUEST_TIMEOUT => StravaError::Server(status),
+            _ if status.is_server_error() => StravaError::Server(status),
+            _ => StravaError::Rejected(status)
+        }
+    }
+
+    /// Returns true for errors that may disappear on a retry
+    pub fn is_transient(&self) -> bool {
+        match self {
+            StravaError::Server(_) => true,
+            StravaError::Network(error) => !error.is_builder(), // Invalid URLs remain invalid
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use crate::strava::strava_error::StravaError;
+
+    #[test]
+    fn test_from_status() {
+        assert!(matches!(StravaError::from_status(StatusCode::UNAUTHORIZED, 0), StravaError::Unauthorized(_)));
+        assert!(matches!(StravaError::from_status(StatusCode::FORBIDDEN, 0), StravaError::Unauthorized(_)));
+        assert!(matches!(StravaError::from_status(StatusCode::TOO_MANY_REQUESTS, 900), StravaError::RateLimited { reset: 900 }));
+        assert!(matches!(StravaError::from_status(StatusCode::NOT_FOUND, 0), StravaError::NotFound));
+        assert!(StravaError::from_status(StatusCode::BAD_GATEWAY, 0).is_transient());
+        assert!(StravaError::from_status(StatusCode::REQUEST_TIMEOUT, 0).is_transient());
+        assert!(!StravaError::from_status(StatusCode::BAD_REQUEST, 0).is_transient());
+        assert_eq!(StravaError::from_status(StatusCode::TOO_MANY_REQUESTS, 900).to_string(),
+                   "Strava API rate limit exceeded until 1970-01-01T00:15:00Z");
+    }
+}
@@ -1,12 +1,25 @@
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use axum::BoxError;
-use log::info;
-use crate::domain::activity::Activity;
+use log::{debug, info, warn};
+use crate::domain::activity::{Activity, LEGACY_ATHLETE};
 use crate::domain::activity_stream::ActivityStream;
+use crate::domain::lap::Lap;
+use crate::domain::photo::Photo;
+use crate::domain::sensor_stream::SensorStream;
 
+const GPX_EXTENSION: &str = "gpx";
+const CSV_EXTENSION: &str = "csv";
+const PHOTO_EXTENSION: &str = "jpg";
+const TEMP_EXTENSION: &str = "tmp";
+const PROBE_FILE: &str = "readiness-probe";
+
+/// Stores the tracks of each athlete in a separate directory below the base path,
+/// grouped by year and month. Tracks of the [LEGACY_ATHLETE] are located directly
+/// in the base path (the layout before multi-athlete support was added).
+/// The photos of an activity are stored in a directory next to the track, named by the activity id.
 pub struct TrackStorage {
     base_path: String
 }
@@ -25,21 +38,306 @@ impl TrackStorage {
         ActivityStream::from_gpx(reader)
     }
 
-    pub fn write(&self, activity: &Activity, stream: &ActivityStream) -> Result<(), BoxError> {
+    /// Writes the track to a temporary file in the target directory and then renames it.
+    /// Because a rename within the same file system is atomic, readers either see the
+    /// complete new GPX file or no (respectively the previous) file, but never a partial one.
+    /// The laps (if any) split the track into segments, the photos are added as waypoints.
+    pub fn write(&self, activity: &Activity, stream: &ActivityStream, laps: &[Lap], photos: &[Photo]) -> Result<(), BoxError> {
         let path = self.get_path(activity)?;
         info!("Write track to {path}");
+        Self::write_atomically(Path::new(&path), |writer| {
+            stream.to_gpx(writer, activity.id, &activity.name, &activity.start_date, laps, photos)
+        })
+    }
+
+    /// Writes the sensor data of an activity without coordinates as CSV file next to
+    /// (the place of) the GPX file, atomically like [TrackStorage::write]
+    pub fn write_sensors(&self, activity: &Activity, stream: &SensorStream) -> Result<(), BoxError> {
+        let path = self.get_sensor_path(activity);
+        info!("Write sensor data to {path}");
+        Self::write_atomically(Path::new(&path), |writer| stream.to_csv(writer))
+    }
+
+    /// Returns true if the CSV file of the activity exists
+    pub fn sensors_exist(&self, activity: &Activity) -> bool {
+        Path::new(&self.get_sensor_path(activity)).is_file()
+    }
+
+    /// Checks that the CSV file of the activity can be read, see [SensorStream::check_csv]
+    pub fn check_sensors(&self, activity: &Activity) -> Result<(), BoxError> {
+        let file = File::open(self.get_sensor_path(activity))?;
+        SensorStream::check_csv(BufReader::new(file))
+    }
+
+    /// Deletes the CSV file of the activity (if it exists)
+    pub fn delete_sensors(&self, activity: &Activity) -> Result<(), BoxError> {
+        let path = self.get_sensor_path(activity);
         let path = Path::new(&path);
+        if path.is_file() {
+            info!("Delete sensor data {}", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the image of a photo (atomically, like [TrackStorage::write]) and returns
+    /// its path relative to the directory of the GPX file. The file is named by the id of the photo,
+    /// which is supplied by Strava, so it must not contain anything but letters, digits, `-` and `_`.
+    pub fn write_photo(&self, activity: &Activity, photo: &Photo, image: &[u8]) -> Result<String, BoxError> {
+        let valid = !photo.unique_id.is_empty() &&
+            photo.unique_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("Invalid id '{}' of photo of activity {}", photo.unique_id, activity.id).into())
+        }
+        let file_name = format!("{}/{}.{PHOTO_EXTENSION}", activity.id, photo.unique_id);
+        let path = format!("{}/{file_name}", self.get_dir(activity));
+        info!("Write photo to {path}");
+        Self::write_atomically(Path::new(&path), |writer| Ok(writer.write_all(image)?))?;
+        Ok(file_name)
+    }
+
+    /// Returns true if the GPX file of the activity exists
+    pub fn exists(&self, activity: &Activity) -> Result<bool, BoxError> {
+        let path = self.get_path(activity)?;
+        Ok(Path::new(&path).is_file())
+    }
+
+    /// Deletes the GPX file of the activity (if it exists)
+    pub fn delete(&self, activity: &Activity) -> Result<(), BoxError> {
+        let path = self.get_path(activity)?;
+        let path = Path::new(&path);
+        if path.is_file() {
+            info!("Delete track {}", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Walks through the athlete and year/month folders and returns all GPX files, together with
+    /// the activity ids derived from their names. Files with non-numeric names are skipped.
+    pub fn list(&self) -> Result<Vec<(u64, PathBuf)>, BoxError> {
+        self.list_activity_files(GPX_EXTENSION)
+    }
+
+    /// Returns all CSV files of sensor data like [TrackStorage::list]
+    pub fn list_sensors(&self) -> Result<Vec<(u64, PathBuf)>, BoxError> {
+        self.list_activity_files(CSV_EXTENSION)
+    }
+
+    fn list_activity_files(&self, extension: &str) -> Result<Vec<(u64, PathBuf)>, BoxError> {
+        let mut tracks = Vec::new();
+        for path in self.list_files(extension)? {
+            match path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                Some(id) => tracks.push((id, path)),
+                None => debug!("Skip file {}", path.display())
+            }
+        }
+        Ok(tracks)
+    }
+
+    /// Returns all leftovers of interrupted [TrackStorage::write] calls
+    pub fn list_temp_files(&self) -> Result<Vec<PathBuf>, BoxError> {
+        self.list_files(TEMP_EXTENSION)
+    }
+
+    /// Checks that the base path is writable by creating and deleting a probe file.
+    /// A leftover probe file counts as temp file and is deleted by the track verifier.
+    pub fn check_writable(&self) -> Result<(), BoxError> {
+        fs::create_dir_all(&self.base_path)?;
+        let path = Path::new(&self.base_path).join(PROBE_FILE).with_extension(TEMP_EXTENSION);
+        fs::write(&path, b"probe")?;
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    /// Moves the year folders of the [LEGACY_ATHLETE] (located directly in the base path)
+    /// to the folder of the given athlete. Returns the number of moved folders.
+    pub fn adopt_legacy_tracks(&self, athlete_id: u64) -> Result<usize, BoxError> {
+        let base_path = Path::new(&self.base_path);
+        if !base_path.is_dir() {
+            return Ok(0);
+        }
+        let athlete_path = base_path.join(athlete_id.to_string());
+        let mut count = 0;
+        for year in fs::read_dir(base_path)? {
+            let year = year?.path();
+            if Self::is_legacy_year_dir(&year)? {
+                let target = athlete_path.join(year.file_name().unwrap());
+                info!("Move {} to {}", year.display(), target.display());
+                fs::create_dir_all(&athlete_path)?;
+                fs::rename(&year, &target)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// A legacy year folder has a four-digit name and contains two-digit month folders
+    /// (athlete folders contain four-digit year folders instead)
+    fn is_legacy_year_dir(path: &Path) -> Result<bool, BoxError> {
+        let is_numeric = |path: &Path, len: usize| path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.len() == len && n.chars().all(|c| c.is_ascii_digit()));
+        if !path.is_dir() || !is_numeric(path, 4) {
+            return Ok(false);
+        }
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            if entry.is_dir() && is_numeric(&entry, 2) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn list_files(&self, extension: &str) -> Result<Vec<PathBuf>, BoxError> {
+        let mut files = Vec::new();
+        let base_path = Path::new(&self.base_path);
+        if base_path.is_dir() {
+            Self::collect_files(base_path, extension, &mut files)?;
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn collect_files(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<(), BoxError> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?.path();
+            if entry.is_dir() {
+                Self::collect_files(&entry, extension, files)?;
+            } else if entry.is_file() && entry.extension().is_some_and(|e| e == extension) {
+                files.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes to a temporary file and renames it afterward, see [TrackStorage::write]
+    fn write_atomically<F>(path: &Path, write: F) -> Result<(), BoxError>
+        where F: FnOnce(&mut BufWriter<File>) -> Result<(), BoxError> {
+        let temp_path = path.with_extension(TEMP_EXTENSION);
         fs::create_dir_all(path.parent().unwrap())?;
+        if let Err(error) = Self::write_file(&temp_path, write) {
+            Self::remove_quietly(&temp_path);
+            return Err(error);
+        }
+        if let Err(error) = fs::rename(&temp_path, path) {
+            Self::remove_quietly(&temp_path);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    fn write_file<F>(path: &Path, write: F) -> Result<(), BoxError>
+        where F: FnOnce(&mut BufWriter<File>) -> Result<(), BoxError> {
         let file = File::create(path)?;
-        let writer = BufWriter::new(file);
-        stream.to_gpx(writer, activity.id, &activity.name, &activity.start_date)?;
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
         Ok(())
     }
 
+    fn remove_quietly(path: &Path) {
+        if !path.exists() {
+            return;
+        }
+        if let Err(error) = fs::remove_file(path) {
+            warn!("Cannot remove {}: {}", path.display(), error);
+        }
+    }
+
     fn get_path(&self, activity: &Activity) -> Result<String, BoxError> {
-        let id = &activity.id;
+        Ok(format!("{}/{}.{GPX_EXTENSION}", self.get_dir(activity), activity.id))
+    }
+
+    fn get_sensor_path(&self, activity: &Activity) -> String {
+        format!("{}/{}.{CSV_EXTENSION}", self.get_dir(activity), activity.id)
+    }
+
+    /// Returns the directory of the GPX file
+    fn get_dir(&self, activity: &Activity) -> String {
         let year = &activity.start_date[..4];
         let month = &activity.start_date[5..7];
-        Ok(format!("{}/{year}/{month}/{id}.gpx", self.base_path))
+        format!("{}/{year}/{month}", self.get_athlete_path(activity.athlete_id))
+    }
+
+    fn get_athlete_path(&self, athlete_id: u64) -> String {
+        match athlete_id {
+            LEGACY_ATHLETE => self.base_path.clone(),
+            _ => format!("{}/{athlete_id}", self.base_path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::domain::activity::{Activity, LEGACY_ATHLETE};
+    use crate::domain::activity_stream::ActivityStream;
+    use crate::domain::photo::Photo;
+    use crate::domain::sensor_stream::SensorStream;
+    use crate::track::track_storage::TrackStorage;
+
+    #[test]
+    fn test_adopt_legacy_tracks() {
+        let base_path = std::env::temp_dir().join(format!("strava-adopt-legacy-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let stream = ActivityStream::new(vec![(1.0, 1.0)], vec![100.0], vec![0]);
+        let legacy1 = Activity::dummy_for(LEGACY_ATHLETE, 1, "2018-03-01T00:00:00Z");
+        let legacy2 = Activity::dummy_for(LEGACY_ATHLETE, 2, "2020-04-01T00:00:00Z");
+        let other = Activity::dummy_for(2019, 3, "2020-04-01T00:00:00Z"); // Athlete id looks like a year
+        tracks.write(&legacy1, &stream, &[], &[]).unwrap();
+        tracks.write(&legacy2, &stream, &[], &[]).unwrap();
+        tracks.write(&other, &stream, &[], &[]).unwrap();
+        assert!(base_path.join("2018/03/1.gpx").is_file());
+        assert!(base_path.join("2019/2020/04/3.gpx").is_file());
+
+        let result = tracks.adopt_legacy_tracks(7);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2); // Folder 2019 is an athlete folder and not moved
+        assert!(tracks.exists(&Activity::dummy_for(7, 1, "2018-03-01T00:00:00Z")).unwrap());
+        assert!(tracks.exists(&Activity::dummy_for(7, 2, "2020-04-01T00:00:00Z")).unwrap());
+        assert!(tracks.exists(&other).unwrap());
+        assert!(!Path::new(&base_path.join("2018")).exists());
+        assert_eq!(tracks.list().unwrap().len(), 3);
+        fs::remove_dir_all(base_path).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_sensors() {
+        let base_path = std::env::temp_dir().join(format!("strava-write-sensors-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let activity = Activity::dummy(6, "2020-04-01T00:00:00Z");
+        let stream: SensorStream = serde_json::from_str(r#"{"time":{"data":[0,1]},"watts":{"data":[150,160]}}"#).unwrap();
+
+        assert!(tracks.write_sensors(&activity, &stream).is_ok());
+        let path = base_path.join(format!("{}/2020/04/6.csv", Activity::DUMMY_ATHLETE));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "time,watts\n0,150\n1,160\n");
+        assert!(tracks.list().unwrap().is_empty()); // Sensor data is no track
+        assert!(tracks.delete_sensors(&activity).is_ok());
+        assert!(!path.exists());
+        fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_photo() {
+        let base_path = std::env::temp_dir().join(format!("strava-write-photo-{}", std::process::id()));
+        let tracks = TrackStorage::new(base_path.to_str().unwrap());
+        let activity = Activity::dummy(5, "2020-04-01T00:00:00Z");
+
+        let result = tracks.write_photo(&activity, &Photo::dummy("a1", None), b"jpeg");
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "5/a1.jpg");
+        let path = base_path.join(format!("{}/2020/04/5/a1.jpg", Activity::DUMMY_ATHLETE));
+        assert_eq!(fs::read(path).unwrap(), b"jpeg");
+        assert!(tracks.list().unwrap().is_empty()); // Photos are no tracks
+        assert!(tracks.list_temp_files().unwrap().is_empty());
+
+        for unique_id in ["../../x", "a/b", "", "a.b"] {
+            assert!(tracks.write_photo(&activity, &Photo::dummy(unique_id, None), b"jpeg").is_err());
+        }
+        fs::remove_dir_all(base_path).unwrap();
+    }
+}
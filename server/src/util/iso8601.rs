@@ -4,6 +4,11 @@ pub fn string_to_secs(str: &str) -> i64 {
     parse_internal(str).unwrap_or_else(|| panic!("Invalid timestamp: '{str}'"))
 }
 
+/// Same as [string_to_secs], but returns [None] for invalid timestamps
+pub fn try_string_to_secs(str: &str) -> Option<i64> {
+    parse_internal(str)
+}
+
 pub fn secs_to_string(secs: i64) -> String {
     format_internal(secs).unwrap_or_else(|| panic!("Cannot convert {secs} secs to timestamp"))
 }
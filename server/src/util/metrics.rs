@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use crate::strava::rate_limit::RateLimit;
+
+/// Upper bounds (in seconds) of the histogram buckets
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Returns the metrics of this process
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()], // Non-cumulative, the count of the bucket only
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Values {
+    http_requests: BTreeMap<(String, String), Histogram>, // Method and route
+    strava_requests: BTreeMap<(String, String), u64>, // Endpoint and status
+    rate_limit: Option<RateLimit>,
+    activities: u64,
+    tracks: u64,
+    tiles: u64,
+    db_queries: BTreeMap<&'static str, Histogram>
+}
+
+/// Metrics of the server in the Prometheus text format, see
+/// https://prometheus.io/docs/instrumenting/exposition_formats/
+#[derive(Debug, Default)]
+pub struct Metrics {
+    values: Mutex<Values>
+}
+
+impl Metrics {
+    fn update(&self, update: impl FnOnce(&mut Values)) {
+        if let Ok(mut values) = self.values.lock() {
+            update(&mut values);
+        }
+    }
+
+    pub fn observe_http_request(&self, method: &str, route: &str, start: Instant) {
+        let secs = start.elapsed().as_secs_f64();
+        self.update(|values| values.http_requests
+            .entry((method.to_string(), route.to_string())).or_default().observe(secs));
+    }
+
+    /// Counts a Strava request, where numeric path segments (ids) of the endpoint are replaced by `{id}`
+    pub fn count_strava_request(&self, path: &str, status: &str) {
+        let endpoint = path.split('/')
+            .map(|segment| if segment.chars().any(|c| c.is_ascii_digit()) { "{id}" } else { segment })
+            .collect::<Vec<&str>>()
+            .join("/");
+        self.update(|values| *values.strava_requests.entry((endpoint, status.to_string())).or_default() += 1);
+    }
+
+    pub fn set_rate_limit(&self, rate_limit: RateLimit) {
+        self.update(|values| values.rate_limit = Some(rate_limit));
+    }
+
+    pub fn count_activities(&self, count: u64) {
+        self.update(|values| values.activities += count);
+    }
+
+    pub fn count_tracks(&self, count: u64) {
+        self.update(|values| values.tracks += count);
+    }
+
+    pub fn count_tiles(&self, count: u64) {
+        self.update(|values| values.tiles += count);
+    }
+
+    /// Returns a timer that observes the duration of the database query when dropped
+    pub fn time_query(&'static self, query: &'static str) -> QueryTimer {
+        QueryTimer { metrics: self, query, start: Instant::now() }
+    }
+
+    /// Renders all metrics, with the download states of the athletes as given by the caller
+    pub fn render(&self, download_states: &[(u64, String)]) -> String {
+        let mut out = String::new();
+        let Ok(values) = self.values.lock() else {
+            return out
+        };
+        Self::header(&mut out, "http_request_duration_seconds", "histogram", "Duration of HTTP requests per route");
+        for ((method, route), histogram) in &values.http_requests {
+            histogram.render(&mut out, "http_request_duration_seconds", &format!("method=\"{method}\",route=\"{route}\""));
+        }
+        Self::header(&mut out, "strava_requests_total", "counter", "Requests to the Strava API per endpoint and status");
+        for ((endpoint, status), count) in &values.strava_requests {
+            let _ = writeln!(out, "strava_requests_total{{endpoint=\"{endpoint}\",status=\"{status}\"}} {count}");
+        }
+        if let Some(limit) = values.rate_limit {
+            Self::header(&mut out, "strava_rate_limit_usage", "gauge", "Usage of the Strava rate limit, as of the latest response");
+            let _ = writeln!(out, "strava_rate_limit_usage{{window=\"15min\"}} {}", limit.usage_15min);
+            let _ = writeln!(out, "strava_rate_limit_usage{{window=\"daily\"}} {}", limit.usage_daily);
+            Self::header(&mut out, "strava_rate_limit_limit", "gauge", "Strava rate limit, as of the latest response");
+            let _ = writeln!(out, "strava_rate_limit_limit{{window=\"15min\"}} {}", limit.limit_15min);
+            let _ = writeln!(out, "strava_rate_limit_limit{{window=\"daily\"}} {}", limit.limit_daily);
+        }
+        Self::header(&mut out, "activities_downloaded_total", "counter", "Activities added to the database");
+        let _ = writeln!(out, "activities_downloaded_total {}", values.activities);
+        Self::header(&mut out, "tracks_downloaded_total", "counter", "Tracks stored as GPX files");
+        let _ = writeln!(out, "tracks_downloaded_total {}", values.tracks);
+        Self::header(&mut out, "tiles_stored_total", "counter", "Map tiles stored in the database");
+        let _ = writeln!(out, "tiles_stored_total {}", values.tiles);
+        Self::header(&mut out, "download_state", "gauge", "Current download state per athlete");
+        for (athlete_id, state) in download_states {
+            let _ = writeln!(out, "download_state{{athlete_id=\"{athlete_id}\",state=\"{state}\"}} 1");
+        }
+        Self::header(&mut out, "db_query_duration_seconds", "histogram", "Duration of database operations");
+        for (query, histogram) in &values.db_queries {
+            histogram.render(&mut out, "db_query_duration_seconds", &format!("query=\"{query}\""));
+        }
+        out
+    }
+
+    fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {metric_type}");
+    }
+}
+
+/// Observes the duration of a database query, see [Metrics::time_query]
+pub struct QueryTimer {
+    metrics: &'static Metrics,
+    query: &'static str,
+    start: Instant
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        let secs = self.start.elapsed().as_secs_f64();
+        self.metrics.update(|values| values.db_queries.entry(self.query).or_default().observe(secs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strava::rate_limit::RateLimit;
+    use crate::util::metrics::{Histogram, Metrics};
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.003);
+        histogram.observe(0.2);
+        histogram.observe(20.0);
+        let mut out = String::new();
+        histogram.render(&mut out, "test", "route=\"/\"");
+        assert!(out.contains("test_bucket{route=\"/\",le=\"0.001\"} 0\n"));
+        assert!(out.contains("test_bucket{route=\"/\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("test_bucket{route=\"/\",le=\"0.25\"} 2\n"));
+        assert!(out.contains("test_bucket{route=\"/\",le=\"10\"} 2\n"));
+        assert!(out.contains("test_bucket{route=\"/\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("test_count{route=\"/\"} 3\n"));
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.count_strava_request("/activities/4711/streams", "200");
+        metrics.count_strava_request("/activities/4712/streams", "200");
+        metrics.count_strava_request("/gear/b12345", "404");
+        metrics.set_rate_limit(RateLimit { limit_15min: 100, limit_daily: 1000, usage_15min: 5, usage_daily: 50 });
+        metrics.count_tracks(2);
+        let out = metrics.render(&[(4711, "Tracks".to_string())]);
+        assert!(out.contains("strava_requests_total{endpoint=\"/activities/{id}/streams\",status=\"200\"} 2\n"));
+        assert!(out.contains("strava_requests_total{endpoint=\"/gear/{id}\",status=\"404\"} 1\n"));
+        assert!(out.contains("strava_rate_limit_usage{window=\"15min\"} 5\n"));
+        assert!(out.contains("tracks_downloaded_total 2\n"));
+        assert!(out.contains("download_state{athlete_id=\"4711\",state=\"Tracks\"} 1\n"));
+        assert!(out.contains("# TYPE db_query_duration_seconds histogram\n"));
+    }
+}
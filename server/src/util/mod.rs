@@ -1,2 +1,3 @@
 pub mod iso8601;
+pub mod metrics;
 pub mod shutdown_signal;
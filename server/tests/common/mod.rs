@@ -0,0 +1 @@
+pub mod strava_simulator;
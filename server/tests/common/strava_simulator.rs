@@ -0,0 +1,218 @@
+use serde_json::{json, Value};
+use strava_activity_downloader::util::iso8601::string_to_secs;
+use wiremock::matchers::{header, method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+pub const ACCESS_TOKEN: &str = "simulated-access-token";
+const API_PATH: &str = "/api/v3";
+
+/// Fake Strava server for offline end-to-end tests. It serves the OAuth endpoints and the
+/// API endpoints requested by the download scheduler for a single athlete with the given activities.
+/// Every API request must carry the access token issued by the token endpoint, otherwise it fails with 401.
+/// Individual activity streams can be made to fail with [StravaSimulator::stream_not_found],
+/// [StravaSimulator::stream_malformed], and [StravaSimulator::stream_rate_limited],
+/// or to lack coordinates with [StravaSimulator::stream_indoor].
+/// The athlete can withdraw the access with [StravaSimulator::revoke].
+pub struct StravaSimulator {
+    server: MockServer,
+    pub athlete_id: u64
+}
+
+impl StravaSimulator {
+    pub async fn start(athlete_id: u64, activities: Vec<Value>) -> Self {
+        let server = MockServer::start().await;
+        let simulator = Self { server, athlete_id };
+        simulator.mount_oauth().await;
+        simulator.mount_api(activities).await;
+        simulator
+    }
+
+    pub fn auth_url(&self) -> String {
+        format!("{}/oauth/authorize", self.server.uri())
+    }
+
+    pub fn token_url(&self) -> String {
+        format!("{}/oauth/token", self.server.uri())
+    }
+
+    pub fn api_url(&self) -> String {
+        format!("{}{API_PATH}", self.server.uri())
+    }
+
+    /// Returns an activity as listed by Strava
+    pub fn activity(athlete_id: u64, id: u64, start_date: &str) -> Value {
+        json!({
+            "id": id,
+            "athlete": { "id": athlete_id, "resource_state": 1 },
+            "name": format!("Activity {id}"),
+            "sport_type": "Ride",
+            "start_date": start_date,
+            "distance": 12345.6,
+            "moving_time": 3600,
+            "total_elevation_gain": 123.4,
+            "average_speed": 3.43,
+            "kudos_count": 0,
+            "comment_count": 0,
+            "total_photo_count": 0
+        })
+    }
+
+    /// Returns an activity stream with a short track
+    pub fn stream() -> Value {
+        json!({
+            "latlng": { "data": [[50.0, 8.0], [50.001, 8.001], [50.002, 8.002]] },
+            "altitude": { "data": [100.0, 101.0, 102.0] },
+            "time": { "data": [0, 10, 20] }
+        })
+    }
+
+    /// The authorization endpoint redirects back to the application at once, as if the athlete had agreed
+    async fn mount_oauth(&self) {
+        Mock::given(method("GET"))
+            .and(path("/oauth/authorize"))
+            .respond_with(|request: &Request| {
+                let param = |name: &str| request.url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default();
+                let location = format!("{}?code=simulated-code&state={}", param("redirect_uri"), param("state"));
+                ResponseTemplate::new(302).insert_header("location", location.as_str())
+            })
+            .mount(&self.server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "token_type": "Bearer",
+                "access_token": ACCESS_TOKEN,
+                "refresh_token": "simulated-refresh-token",
+                "expires_in": 21600,
+                "athlete": { "id": self.athlete_id }
+            })))
+            .mount(&self.server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/deauthorize"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": ACCESS_TOKEN })))
+            .mount(&self.server)
+            .await;
+    }
+
+    async fn mount_api(&self, activities: Vec<Value>) {
+        let bearer = format!("Bearer {ACCESS_TOKEN}");
+        let api = |path_pattern: &str| Mock::given(method("GET"))
+            .and(path_regex(format!("^{API_PATH}{path_pattern}$")))
+            .and(header("authorization", bearer.as_str()));
+
+        api("/athlete")
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": self.athlete_id, "firstname": "Simulated" })))
+            .mount(&self.server).await;
+        api("/athlete/zones")
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&self.server).await;
+        api(r"/athletes/\d+/stats")
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&self.server).await;
+        api("/athlete/activities")
+            .respond_with(move |request: &Request| ResponseTemplate::new(200)
+                .set_body_json(Self::activity_page(&activities, request)))
+            .mount(&self.server).await;
+        api(r"/activities/\d+/streams")
+            .respond_with(ResponseTemplate::new(200).set_body_json(Self::stream()))
+            .mount(&self.server).await;
+        api(r"/activities/\d+")
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&self.server).await;
+        api(r"/activities/\d+/(laps|photos|comments|kudos)")
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&self.server).await;
+
+        // Lowest priority, only reached without valid token
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({ "message": "Authorization Error" })))
+            .with_priority(u8::MAX)
+            .mount(&self.server).await;
+    }
+
+    /// Returns the activities matching the query parameters `after`, `before`, `page`, and `per_page`
+    /// in ascending order of start date
+    fn activity_page(activities: &[Value], request: &Request) -> Value {
+        let param = |name: &str| request.url.query_pairs()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.parse::<i64>().ok());
+        let start = |activity: &Value| string_to_secs(activity["start_date"].as_str().unwrap_or_default());
+        let mut matching: Vec<&Value> = activities.iter()
+            .filter(|activity| param("after").is_none_or(|after| start(activity) > after))
+            .filter(|activity| param("before").is_none_or(|before| start(activity) < before))
+            .collect();
+        matching.sort_by_key(|activity| start(activity));
+        let per_page = param("per_page").unwrap_or(30) as usize;
+        let page = param("page").unwrap_or(1).max(1) as usize;
+        let page: Vec<&Value> = matching.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+        json!(page)
+    }
+
+    async fn mount_stream(&self, activity_id: u64, response: ResponseTemplate, times: Option<u64>) {
+        let mock = Mock::given(method("GET"))
+            .and(path(format!("{API_PATH}/activities/{activity_id}/streams")))
+            .respond_with(response)
+            .with_priority(1);
+        match times {
+            Some(times) => mock.up_to_n_times(times).mount(&self.server).await,
+            None => mock.mount(&self.server).await
+        }
+    }
+
+    /// Lets the stream of the activity fail with 404, as for manually created activities
+    pub async fn stream_not_found(&self, activity_id: u64) {
+        let response = ResponseTemplate::new(404).set_body_json(json!({ "message": "Record Not Found" }));
+        self.mount_stream(activity_id, response, None).await;
+    }
+
+    /// Lets the stream of the activity contain neither coordinates nor sensor data
+    pub async fn stream_malformed(&self, activity_id: u64) {
+        let response = ResponseTemplate::new(200).set_body_json(json!({ "time": { "data": [0, 10, 20] } }));
+        self.mount_stream(activity_id, response, None).await;
+    }
+
+    /// Lets the stream of the activity contain sensor data, but no coordinates, as for indoor rides
+    pub async fn stream_indoor(&self, activity_id: u64) {
+        let stream = json!({ "time": { "data": [0, 10, 20] }, "heartrate": { "data": [90, 110, 120] }, "watts": { "data": [150, 200, 210] } });
+        self.mount_stream(activity_id, ResponseTemplate::new(200).set_body_json(stream), None).await;
+    }
+
+    /// Lets the next requests of the stream fail with 429 and exhausted 15-minute rate limit
+    pub async fn stream_rate_limited(&self, activity_id: u64, times: u64) {
+        let response = ResponseTemplate::new(429)
+            .insert_header("x-ratelimit-limit", "100,1000")
+            .insert_header("x-ratelimit-usage", "101,300")
+            .set_body_json(json!({ "message": "Rate Limit Exceeded" }));
+        self.mount_stream(activity_id, response, Some(times)).await;
+    }
+
+    /// Lets all API requests fail with 401, as after the athlete revoked the access in the Strava settings
+    pub async fn revoke(&self) {
+        Mock::given(method("GET"))
+            .and(path_regex(format!("^{API_PATH}/")))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({ "message": "Authorization Error" })))
+            .with_priority(1)
+            .mount(&self.server).await;
+    }
+
+    /// Returns the number of received deauthorization requests
+    pub async fn deauthorize_count(&self) -> usize {
+        self.server.received_requests().await.unwrap_or_default().iter()
+            .filter(|request| request.url.path() == "/oauth/deauthorize")
+            .count()
+    }
+
+    /// Returns the number of received requests of the activity stream
+    pub async fn stream_request_count(&self, activity_id: u64) -> usize {
+        let stream_path = format!("{API_PATH}/activities/{activity_id}/streams");
+        self.server.received_requests().await.unwrap_or_default().iter()
+            .filter(|request| request.url.path() == stream_path)
+            .count()
+    }
+}
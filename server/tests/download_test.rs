@@ -0,0 +1,368 @@
+mod common;
+
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs, process};
+use reqwest::{header, redirect, StatusCode};
+use serde_json::Value;
+use sqlx::{Row, SqlitePool};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use strava_activity_downloader::domain::download_event::EventKind;
+use strava_activity_downloader::domain::download_job::JobType;
+use strava_activity_downloader::domain::download_state::DownloadState;
+use strava_activity_downloader::domain::retry_policy::RetryPolicy;
+use strava_activity_downloader::domain::server_status::ServerStatus;
+use strava_activity_downloader::oauth::oauth_client::OAuthClient;
+use strava_activity_downloader::rest::access_control::{AccessControl, AccessMode};
+use strava_activity_downloader::rest::http_server::spawn_http_server;
+use strava_activity_downloader::rest::rest_paths::{AUTHORIZE, AUTH_CALLBACK, ATHLETES, DOWNLOAD_START, DOWNLOAD_STOP, EVENTS, HEALTHZ, LOGOUT, METRICS, READYZ, TOGGLE, TRACKS_RETRY};
+use strava_activity_downloader::service::activity_service::ActivityService;
+use strava_activity_downloader::service::download_scheduler::spawn_download_scheduler;
+use strava_activity_downloader::state::shared_state::{MutexSharedState, SharedState};
+use strava_activity_downloader::strava::strava_client::{StravaClient, StravaClientConfig};
+use strava_activity_downloader::track::track_storage::TrackStorage;
+use crate::common::strava_simulator::StravaSimulator;
+
+const ATHLETE_ID: u64 = 4711;
+const ACTIVITY_DB: &str = "activity.db";
+
+/// The downloader with HTTP server and download scheduler, connected to a [StravaSimulator]
+struct Downloader {
+    state: MutexSharedState,
+    url: String,
+    data_dir: PathBuf,
+    tx_term: Sender<()>,
+    tasks: Vec<JoinHandle<()>>
+}
+
+impl Downloader {
+    async fn start(simulator: &StravaSimulator, name: &str) -> Self {
+        let data_dir = env::temp_dir().join(format!("strava-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(&data_dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let strava = StravaClient::new(&StravaClientConfig {
+            base_url: simulator.api_url(),
+            timeout: Duration::from_secs(5),
+            ..StravaClientConfig::default()
+        }).unwrap();
+        let oauth = OAuthClient::new(
+            "simulated-client".to_string(),
+            "simulated-secret".to_string(),
+            simulator.auth_url(),
+            simulator.token_url(),
+            ATHLETES.to_string(),
+            format!("{url}{AUTH_CALLBACK}"),
+            vec!["activity:read_all".to_string()],
+            strava.clone());
+        let db_path = data_dir.join(ACTIVITY_DB);
+        fs::File::create(&db_path).unwrap();
+        let service = ActivityService::new(db_path.to_str().unwrap(), false).await.unwrap();
+        let tracks = TrackStorage::new(data_dir.to_str().unwrap());
+
+        let (tx_term, rx_term1) = broadcast::channel(1);
+        let rx_term2 = tx_term.subscribe();
+        let (tx_data, _) = broadcast::channel::<ServerStatus>(16);
+        let state = SharedState::new(oauth, strava, service, tracks, tx_data, tx_term.clone(), 2, false);
+
+        let retry = RetryPolicy::new(3, Duration::from_millis(10), Duration::from_millis(100));
+        let tasks = vec![
+            spawn_download_scheduler(state.clone(), rx_term1, Duration::from_millis(10), retry),
+            spawn_http_server(listener, state.clone(), rx_term2)
+        ];
+        Self { state, url, data_dir, tx_term, tasks }
+    }
+
+    /// Runs the auth-code flow through the HTTP server, which is redirected to the simulator and back.
+    /// The flow ends at the configured target, the list of authorized athletes.
+    async fn authorize(&self) -> Vec<u64> {
+        reqwest::get(format!("{}{AUTHORIZE}", self.url)).await.unwrap()
+            .error_for_status().unwrap()
+            .json().await.unwrap()
+    }
+
+    async fn toggle(&self) -> DownloadState {
+        reqwest::get(format!("{}{TOGGLE}", self.url)).await.unwrap()
+            .error_for_status().unwrap()
+            .json().await.unwrap()
+    }
+
+    /// Sends a POST request to the start or stop endpoint (with query) and returns the status and the download state
+    async fn post_download(&self, path: &str) -> (StatusCode, Option<DownloadState>) {
+        let response = reqwest::Client::new().post(format!("{}{path}", self.url)).send().await.unwrap();
+        (response.status(), response.json().await.ok())
+    }
+
+    async fn logout(&self) -> DownloadState {
+        reqwest::Client::new().post(format!("{}{LOGOUT}", self.url)).send().await.unwrap()
+            .error_for_status().unwrap()
+            .json().await.unwrap()
+    }
+
+    async fn athletes(&self) -> Vec<u64> {
+        reqwest::get(format!("{}{ATHLETES}", self.url)).await.unwrap()
+            .error_for_status().unwrap()
+            .json().await.unwrap()
+    }
+
+    /// Runs the auth-code flow like [Downloader::authorize], but follows the redirects manually
+    /// to obtain the session cookie set by the callback
+    async fn login(&self) -> String {
+        let client = reqwest::Client::builder().redirect(redirect::Policy::none()).build().unwrap();
+        let mut url = format!("{}{AUTHORIZE}", self.url);
+        loop {
+            let response = client.get(&url).send().await.unwrap();
+            if let Some(cookie) = response.headers().get(header::SET_COOKIE) {
+                return cookie.to_str().unwrap().split(';').next().unwrap().to_string()
+            }
+            let location = response.headers().get(header::LOCATION).expect("Redirect expected").to_str().unwrap();
+            url = if location.starts_with('/') { format!("{}{location}", self.url) } else { location.to_string() };
+        }
+    }
+
+    /// Returns the status code of a GET request with the given header
+    async fn get_status(&self, path: &str, header: Option<(header::HeaderName, &str)>) -> StatusCode {
+        let request = reqwest::Client::new().get(format!("{}{path}", self.url));
+        let request = match header {
+            Some((name, value)) => request.header(name, value),
+            None => request
+        };
+        request.send().await.unwrap().status()
+    }
+
+    /// Waits until the download state of the athlete is the expected one
+    async fn wait_for(&self, expected: DownloadState) {
+        for _ in 0..600 {
+            if self.state.lock().await.get_download_state(ATHLETE_ID) == expected {
+                return
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Download state {expected:?} not reached, state is {:?}", self.state.lock().await.get_download_state(ATHLETE_ID));
+    }
+
+    /// Returns the gpx_fetched value per activity id
+    async fn fetch_states(&self) -> Vec<(u64, i32)> {
+        let db_path = self.data_dir.join(ACTIVITY_DB);
+        let pool = SqlitePool::connect(db_path.to_str().unwrap()).await.unwrap();
+        let rows = sqlx::query("SELECT id, gpx_fetched FROM activity ORDER BY id").fetch_all(&pool).await.unwrap();
+        pool.close().await;
+        rows.iter().map(|row| (row.get::<i64, _>(0) as u64, row.get(1))).collect()
+    }
+
+    /// Returns the missing_reason per activity with missing track
+    async fn missing_reasons(&self) -> Vec<(u64, String)> {
+        let db_path = self.data_dir.join(ACTIVITY_DB);
+        let pool = SqlitePool::connect(db_path.to_str().unwrap()).await.unwrap();
+        let rows = sqlx::query("SELECT id, missing_reason FROM activity WHERE gpx_fetched = 2 ORDER BY id").fetch_all(&pool).await.unwrap();
+        pool.close().await;
+        rows.iter().map(|row| (row.get::<i64, _>(0) as u64, row.get(1))).collect()
+    }
+
+    /// Returns the ids of the activities with a GPX file
+    fn gpx_ids(&self) -> Vec<u64> {
+        let tracks = TrackStorage::new(self.data_dir.to_str().unwrap());
+        let mut ids: Vec<u64> = tracks.list().unwrap().into_iter().map(|(id, _)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    async fn stop(self) {
+        self.tx_term.send(()).unwrap();
+        for task in self.tasks {
+            task.await.unwrap();
+        }
+        fs::remove_dir_all(&self.data_dir).unwrap();
+    }
+}
+
+fn activities() -> Vec<Value> {
+    (1..=5)
+        .map(|id| StravaSimulator::activity(ATHLETE_ID, id, &format!("2024-03-0{id}T10:00:00Z")))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_download_all() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    simulator.stream_not_found(3).await;
+    simulator.stream_malformed(4).await;
+    simulator.stream_indoor(5).await;
+    let downloader = Downloader::start(&simulator, "download-all").await;
+
+    assert_eq!(downloader.authorize().await, vec![ATHLETE_ID]);
+    assert_eq!(downloader.toggle().await, DownloadState::Profile);
+    downloader.wait_for(DownloadState::NoResults).await;
+
+    // Page size 2 requires three pages and an empty one
+    assert_eq!(downloader.fetch_states().await, vec![(1, 1), (2, 1), (3, 2), (4, 2), (5, 3)]);
+    assert_eq!(downloader.gpx_ids(), vec![1, 2]);
+    let csv = fs::read_to_string(downloader.data_dir.join(format!("{ATHLETE_ID}/2024/03/5.csv"))).unwrap();
+    assert_eq!(csv, "time,heartrate,watts\n0,90,150\n10,110,200\n20,120,210\n");
+
+    let events: Vec<Value> = reqwest::get(format!("{}{EVENTS}?since=2000-01-01T00:00:00Z", downloader.url)).await.unwrap()
+        .json().await.unwrap();
+    let count = |kind: &str| events.iter().filter(|event| event["kind"] == kind).count();
+    assert_eq!((count("PageFetched"), count("ActivitiesAdded"), count("TrackStored"), count("TrackMissing")), (4, 3, 3, 2));
+    assert_eq!(downloader.get_status(&format!("{EVENTS}?since=yesterday"), None).await, StatusCode::BAD_REQUEST);
+
+    // Retrying by reason resets only the matching missing tracks
+    assert_eq!(downloader.missing_reasons().await, vec![(3, "not_found".to_string()), (4, "no_latlng".to_string())]);
+    let response = reqwest::Client::new().post(format!("{}{TRACKS_RETRY}?reason=no_latlng", downloader.url)).send().await.unwrap();
+    assert_eq!(response.json::<u64>().await.unwrap(), 1);
+    assert_eq!(downloader.missing_reasons().await, vec![(3, "not_found".to_string())]);
+    assert_eq!(downloader.post_download(&format!("{TRACKS_RETRY}?reason=foo")).await.0, StatusCode::BAD_REQUEST);
+
+    // The metrics are global, other tests may have added to them
+    let metrics = reqwest::get(format!("{}{METRICS}", downloader.url)).await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("strava_requests_total{endpoint=\"/activities/{id}/streams\",status=\"404\"}"));
+    assert!(metrics.contains("http_request_duration_seconds_count{method=\"GET\",route=\"/toggle\"}"));
+    assert!(metrics.contains("download_state{athlete_id=\"4711\",state=\"NoResults\"} 1"));
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    simulator.stream_rate_limited(1, 1).await;
+    let downloader = Downloader::start(&simulator, "rate-limit").await;
+
+    downloader.authorize().await;
+    downloader.toggle().await;
+    downloader.wait_for(DownloadState::LimitReached).await;
+
+    assert_eq!(downloader.fetch_states().await.iter().filter(|(_, fetched)| *fetched == 0).count(), 5);
+    let jobs = downloader.state.lock().await.service.get_jobs(ATHLETE_ID).await.unwrap();
+    let track_job = jobs.iter().find(|job| job.job_type == JobType::Track && job.activity_id == 1).unwrap();
+    assert_eq!((track_job.attempts, track_job.poisoned), (0, false)); // Kept for the next download
+    let events = downloader.state.lock().await.service.get_events(Some(ATHLETE_ID), "", 100).await.unwrap();
+    assert_eq!(events.last().map(|event| event.kind), Some(EventKind::RateLimited));
+
+    // Downloading again after the reset succeeds
+    assert_eq!(downloader.toggle().await, DownloadState::Profile);
+    downloader.wait_for(DownloadState::NoResults).await;
+    assert_eq!(simulator.stream_request_count(1).await, 2);
+    assert_eq!(downloader.gpx_ids(), vec![1, 2, 3, 4, 5]);
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_start_stop() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "start-stop").await;
+    assert_eq!(downloader.post_download(DOWNLOAD_START).await.0, StatusCode::UNAUTHORIZED);
+
+    downloader.authorize().await;
+    // Stopping and starting twice has the same effect as once
+    assert_eq!(downloader.post_download(DOWNLOAD_STOP).await, (StatusCode::OK, Some(DownloadState::Inactive)));
+    assert_eq!(downloader.post_download(DOWNLOAD_START).await, (StatusCode::OK, Some(DownloadState::Profile)));
+    assert_eq!(downloader.post_download(DOWNLOAD_START).await.1, Some(DownloadState::Profile));
+    assert_eq!(downloader.post_download(DOWNLOAD_STOP).await.1, Some(DownloadState::Inactive));
+    assert_eq!(downloader.post_download(DOWNLOAD_STOP).await.1, Some(DownloadState::Inactive));
+    assert_eq!(downloader.post_download(&format!("{DOWNLOAD_START}?phase=laps")).await.0, StatusCode::BAD_REQUEST);
+
+    // Without the activity list, there is nothing to download
+    assert_eq!(downloader.post_download(&format!("{DOWNLOAD_START}?phase=tracks")).await.1, Some(DownloadState::Tracks));
+    downloader.wait_for(DownloadState::NoResults).await;
+    assert!(downloader.fetch_states().await.is_empty());
+
+    assert_eq!(downloader.post_download(&format!("{DOWNLOAD_START}?phase=activities")).await.1, Some(DownloadState::Activities));
+    downloader.wait_for(DownloadState::NoResults).await;
+    assert_eq!(downloader.gpx_ids(), vec![1, 2, 3, 4, 5]);
+
+    // A refetched track is downloaded again at once, the download of the tracks is started for it
+    assert_eq!(downloader.post_download("/activities/9/refetch").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(downloader.post_download("/athletes/4712/activities/3/refetch").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(downloader.post_download("/activities/3/refetch").await.0, StatusCode::ACCEPTED);
+    downloader.wait_for(DownloadState::NoResults).await;
+    assert_eq!(downloader.fetch_states().await[2], (3, 1));
+    assert_eq!(downloader.post_download(DOWNLOAD_STOP).await.1, Some(DownloadState::NoResults));
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_revoked_access() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "revoked-access").await;
+
+    downloader.authorize().await;
+    simulator.revoke().await;
+    downloader.toggle().await;
+    downloader.wait_for(DownloadState::Unauthorized).await;
+    assert!(downloader.athletes().await.is_empty());
+
+    // Authorizing again allows downloading again
+    assert_eq!(downloader.authorize().await, vec![ATHLETE_ID]);
+    assert_eq!(downloader.toggle().await, DownloadState::Profile);
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_logout() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "logout").await;
+
+    downloader.authorize().await;
+    assert_eq!(downloader.logout().await, DownloadState::Unauthorized);
+    assert_eq!(simulator.deauthorize_count().await, 1);
+    assert!(downloader.athletes().await.is_empty());
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_session_access() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "session-access").await;
+    downloader.state.lock().await.access = AccessControl::new(AccessMode::Session, false);
+
+    assert_eq!(downloader.get_status(TOGGLE, None).await, StatusCode::UNAUTHORIZED);
+    let cookie = downloader.login().await;
+    assert_eq!(downloader.get_status(ATHLETES, Some((header::COOKIE, &cookie))).await, StatusCode::FORBIDDEN);
+    assert_eq!(downloader.get_status("/athletes/1/jobs", Some((header::COOKIE, &cookie))).await, StatusCode::FORBIDDEN);
+
+    // The endpoint without athlete path is redirected to the one of the session athlete
+    let state: DownloadState = reqwest::Client::new().get(format!("{}{TOGGLE}", downloader.url))
+        .header(header::COOKIE, &cookie)
+        .send().await.unwrap()
+        .error_for_status().unwrap()
+        .json().await.unwrap();
+    assert_eq!(state, DownloadState::Profile);
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_basic_access() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "basic-access").await;
+    let mode = AccessMode::Basic { username: "user".to_string(), password: "pass".to_string() };
+    downloader.state.lock().await.access = AccessControl::new(mode, true);
+
+    let response = reqwest::get(format!("{}{ATHLETES}", downloader.url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+    assert_eq!(downloader.get_status(ATHLETES, Some((header::AUTHORIZATION, "Basic dXNlcjpwYXNz"))).await, StatusCode::OK);
+    downloader.stop().await;
+}
+
+#[tokio::test]
+async fn test_health() {
+    let simulator = StravaSimulator::start(ATHLETE_ID, activities()).await;
+    let downloader = Downloader::start(&simulator, "health").await;
+    downloader.authorize().await;
+    // Probes are open in every access mode
+    downloader.state.lock().await.access = AccessControl::new(AccessMode::ApiKey("secret".to_string()), true);
+
+    assert_eq!(downloader.get_status(HEALTHZ, None).await, StatusCode::OK);
+    let readiness: Value = reqwest::get(format!("{}{READYZ}", downloader.url)).await.unwrap()
+        .error_for_status().unwrap()
+        .json().await.unwrap();
+    assert_eq!(readiness["ready"], true);
+    assert_eq!(readiness["checks"][3]["detail"], "1 athletes authorized");
+    downloader.stop().await;
+}